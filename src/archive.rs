@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lmdb::{Cursor, Environment, Transaction};
+
+use pi_db::db::Bin;
+
+use atom::Atom;
+
+use crate::chunk;
+use crate::lmdb_file::with_txn;
+use crate::pool::lookup_db;
+use crate::snapshot::{read_frame, Frame};
+use crate::tombstone;
+
+// 单个归档文件的最大大小，超过后写入新文件
+const MAX_ARCHIVE_FILE_SIZE: u64 = 64 * 1024 * 1024;
+// 每次归档默认处理的记录数
+const DEFAULT_BATCH: usize = 10000;
+// 索引文件名
+const INDEX_FILE: &str = "index";
+
+/**
+* 记录的时间，单位为毫秒的UNIX时间
+*/
+#[derive(Clone)]
+pub enum RecordAge {
+    KeyPrefix(usize),                                           //键在指定偏移处的8字节大端时间
+    Extract(Arc<Fn(&[u8], &[u8]) -> Option<u64> + Send + Sync>), //从键值中提取时间，返回None的记录不归档
+}
+
+impl RecordAge {
+    fn time_of(&self, key: &[u8], value: &[u8]) -> Option<u64> {
+        match self {
+            RecordAge::KeyPrefix(offset) => key.get(*offset..*offset + 8).map(|b| {
+                let mut t = [0u8; 8];
+                t.copy_from_slice(b);
+                u64::from_be_bytes(t)
+            }),
+            RecordAge::Extract(f) => f(key, value),
+        }
+    }
+}
+
+/**
+* 归档策略，早于截止时间的记录被移出库，写入只追加的归档文件
+*/
+#[derive(Clone)]
+pub struct ArchivePolicy {
+    pub tab: Atom,          //表名
+    pub age: RecordAge,     //记录时间的取得方式
+    pub max_age: Duration,  //超过该时间的记录被归档
+    pub batch: usize,       //每次归档最多处理的记录数，0表示默认值
+}
+
+// 表的归档目录
+fn tab_dir(dir: &str, tab: &Atom) -> PathBuf {
+    Path::new(dir).join(tab.as_str())
+}
+
+fn archive_file(dir: &Path, seq: u32) -> PathBuf {
+    dir.join(format!("{:08}.arc", seq))
+}
+
+// 最后一个归档文件的序号，没有文件返回0
+fn last_seq(dir: &Path) -> Result<u32, String> {
+    let mut last = 0;
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let name = entry.map_err(|e| e.to_string())?.file_name();
+        let name = name.to_string_lossy();
+        if name.ends_with(".arc") {
+            if let Ok(seq) = name.trim_end_matches(".arc").parse::<u32>() {
+                last = last.max(seq);
+            }
+        }
+    }
+    Ok(last)
+}
+
+/**
+* 将记录追加到归档文件，并在索引中记录键所在的文件和偏移
+* 记录的格式与快照的记录帧相同，索引的每项为4字节大端键长、键、4字节大端文件序号和8字节大端偏移
+*/
+fn append(dir: &Path, records: &[(Vec<u8>, Vec<u8>)]) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let mut seq = last_seq(dir)?.max(1);
+    let mut file = OpenOptions::new().create(true).append(true).open(archive_file(dir, seq)).map_err(|e| e.to_string())?;
+    let mut offset = file.metadata().map_err(|e| e.to_string())?.len();
+    let mut index = Vec::new();
+    for (k, v) in records {
+        if offset >= MAX_ARCHIVE_FILE_SIZE {
+            file.sync_all().map_err(|e| e.to_string())?;
+            seq += 1;
+            file = OpenOptions::new().create(true).append(true).open(archive_file(dir, seq)).map_err(|e| e.to_string())?;
+            offset = 0;
+        }
+        let frame = Frame::Record(Arc::new(k.clone()), Arc::new(v.clone())).encode();
+        file.write_all(&frame).map_err(|e| e.to_string())?;
+        index.extend_from_slice(&(k.len() as u32).to_be_bytes());
+        index.extend_from_slice(k);
+        index.extend_from_slice(&seq.to_be_bytes());
+        index.extend_from_slice(&offset.to_be_bytes());
+        offset += frame.len() as u64;
+    }
+    file.sync_all().map_err(|e| e.to_string())?;
+
+    // 索引在归档文件落盘后写入，索引中的记录一定可以读到
+    let mut idx = OpenOptions::new().create(true).append(true).open(dir.join(INDEX_FILE)).map_err(|e| e.to_string())?;
+    idx.write_all(&index).map_err(|e| e.to_string())?;
+    idx.sync_all().map_err(|e| e.to_string())
+}
+
+/**
+* 按策略归档一批过期的记录，先写入归档文件和索引，再在写线程中删除库中的记录
+* 删除前崩溃会导致记录同时存在于库和归档中，下次归档时重复写入，索引以最后写入的为准
+* @param env LMDB环境
+* @param env_id 环境id
+* @param dir 归档根目录，每个表一个子目录
+* @param policy 归档策略
+* @returns 返回归档的记录数，小于批量大小表示已没有过期的记录
+*/
+pub fn run(env: &Environment, env_id: u64, dir: &str, policy: &ArchivePolicy) -> Result<usize, String> {
+    let db = lookup_db(env_id, &policy.tab).ok_or_else(|| format!("tab not opened: {:?}", policy.tab.to_string()))?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+    let cutoff = now.saturating_sub(policy.max_age.as_millis() as u64);
+    let batch = if policy.batch == 0 { DEFAULT_BATCH } else { policy.batch };
+
+    let mut records = Vec::new();
+    {
+        let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+        {
+            let mut cursor = txn.open_ro_cursor(db).map_err(|e| e.to_string())?;
+            for (k, v) in cursor.iter_start() {
+                // 软删除的键不归档，由tombstone::purge清除
+                if tombstone::is_deleted(&policy.tab, v) {
+                    continue;
+                }
+                let v = chunk::read(&txn, env_id, &policy.tab, k, v).map_err(|e| e.to_string())?;
+                if policy.age.time_of(k, &v).map_or(false, |t| t < cutoff) {
+                    records.push((k.to_vec(), v));
+                    if records.len() >= batch {
+                        break;
+                    }
+                }
+            }
+        }
+        let _ = txn.commit();
+    }
+    if records.is_empty() {
+        return Ok(0);
+    }
+
+    append(&tab_dir(dir, &policy.tab), &records)?;
+
+    let keys: Vec<Vec<u8>> = records.into_iter().map(|(k, _)| k).collect();
+    let n = keys.len();
+    let tab = policy.tab.clone();
+    with_txn(&policy.tab, move |ops| {
+        for k in keys.iter() {
+            ops.del(&tab, k)?;
+        }
+        Ok(())
+    })?;
+    info!("archived {} records of tab: {:?}", n, policy.tab.to_string());
+    Ok(n)
+}
+
+/**
+* 归档的读取器，打开时载入索引，按键读取已归档的记录
+*/
+pub struct ArchiveReader {
+    dir: PathBuf,
+    index: BTreeMap<Bin, (u32, u64)>,   //键所在的文件序号和偏移
+}
+
+impl ArchiveReader {
+    pub fn open(dir: &str, tab: &Atom) -> Result<Self, String> {
+        let dir = tab_dir(dir, tab);
+        let mut index = BTreeMap::new();
+        let mut buf = Vec::new();
+        match File::open(dir.join(INDEX_FILE)) {
+            Ok(f) => {
+                BufReader::new(f).read_to_end(&mut buf).map_err(|e| e.to_string())?;
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.to_string()),
+        }
+
+        let mut pos = 0;
+        while pos + 4 <= buf.len() {
+            let mut len = [0u8; 4];
+            len.copy_from_slice(&buf[pos..pos + 4]);
+            let end = pos + 4 + u32::from_be_bytes(len) as usize;
+            // 不完整的索引项，只可能出现在写入索引时崩溃的末尾
+            if end + 12 > buf.len() {
+                warn!("archive index of tab: {:?} truncated at: {}", tab.to_string(), pos);
+                break;
+            }
+            let key = Arc::new(buf[pos + 4..end].to_vec());
+            let mut seq = [0u8; 4];
+            seq.copy_from_slice(&buf[end..end + 4]);
+            let mut offset = [0u8; 8];
+            offset.copy_from_slice(&buf[end + 4..end + 12]);
+            index.insert(key, (u32::from_be_bytes(seq), u64::from_be_bytes(offset)));
+            pos = end + 12;
+        }
+        Ok(ArchiveReader { dir, index })
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    // 归档中的键，按键的顺序
+    pub fn keys(&self) -> impl Iterator<Item = &Bin> {
+        self.index.keys()
+    }
+
+    // 读取已归档的值
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bin>, String> {
+        let (seq, offset) = match self.index.get(&key.to_vec()) {
+            Some(pos) => *pos,
+            None => return Ok(None),
+        };
+        let mut file = File::open(archive_file(&self.dir, seq)).map_err(|e| e.to_string())?;
+        file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+        match read_frame(&mut file)? {
+            Some(Frame::Record(k, v)) if k.as_slice() == key => Ok(Some(v)),
+            _ => Err(format!("archive record of key: {:?} corrupted", key)),
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+
+use pi_db::db::TabKV;
+
+use atom::Atom;
+
+use crate::changelog;
+
+// 当前写入的审计日志文件名，轮换后的文件依次加后缀.1、.2...
+const AUDIT_FILE: &str = "audit.log";
+// 默认的单个日志文件大小上限
+const DEFAULT_MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+// 默认保留的轮换文件数
+const DEFAULT_MAX_FILES: usize = 10;
+
+/**
+* 审计日志的设置
+*/
+#[derive(Debug, Clone)]
+pub struct AuditConfig {
+    pub dir: PathBuf,           //日志目录
+    pub max_file_size: u64,     //单个日志文件的大小上限，超过后轮换
+    pub max_files: usize,       //保留的轮换文件数，更早的文件被删除
+}
+
+impl AuditConfig {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        AuditConfig {
+            dir: dir.as_ref().to_path_buf(),
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_files: DEFAULT_MAX_FILES,
+        }
+    }
+}
+
+/**
+* 一条审计记录，对应一次已提交的写入或删除
+*/
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    pub time: u64,      //写入时间，单位毫秒
+    pub env_id: u64,    //环境id
+    pub session: u64,   //发起修改的会话或事务id，不属于事务的写入为0
+    pub tab: Atom,      //表名
+    pub key: Vec<u8>,   //键
+    pub size: usize,    //写入的值的大小，删除为0
+    pub deleted: bool,  //是否为删除
+    pub renamed_from: Option<Atom>, //重命名表的原表名，此时tab为新表名，key为空
+}
+
+impl AuditRecord {
+    // 一行文本: 时间 环境id 会话id 操作 表名 十六进制的键 值大小，以制表符分隔，重命名时键的位置为原表名
+    fn to_line(&self) -> String {
+        let (op, key) = match &self.renamed_from {
+            Some(old) => ("rename", old.to_string()),
+            None => (if self.deleted { "del" } else { "put" }, self.key.iter().map(|b| format!("{:02x}", b)).collect()),
+        };
+        format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                self.time, self.env_id, self.session, op,
+                self.tab.as_str(), key, self.size)
+    }
+}
+
+lazy_static! {
+    static ref AUDIT_ENABLED: AtomicBool = AtomicBool::new(false);
+    // 写日志线程的发送端
+    static ref AUDIT_SENDER: Mutex<Option<Sender<Vec<AuditRecord>>>> = Mutex::new(None);
+    // 各环境写线程当前处理的消息所属的会话id
+    static ref SESSIONS: Mutex<HashMap<u64, u64>> = Mutex::new(HashMap::new());
+    // 各环境写线程中未提交的修改
+    static ref PENDING: Mutex<HashMap<u64, Vec<AuditRecord>>> = Mutex::new(HashMap::new());
+}
+
+// 追加写入的日志文件，超过大小上限时轮换
+struct AuditLog {
+    config: AuditConfig,
+    out: BufWriter<File>,
+    size: u64,
+}
+
+impl AuditLog {
+    fn open(config: AuditConfig) -> Result<Self, String> {
+        fs::create_dir_all(&config.dir).map_err(|e| e.to_string())?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(config.dir.join(AUDIT_FILE))
+            .map_err(|e| e.to_string())?;
+        let size = file.metadata().map_err(|e| e.to_string())?.len();
+        Ok(AuditLog { config, out: BufWriter::new(file), size })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        self.config.dir.join(format!("{}.{}", AUDIT_FILE, n))
+    }
+
+    // 依次后移已轮换的文件，删除超出保留数的最早文件，再重新打开当前文件
+    fn rotate(&mut self) -> Result<(), String> {
+        self.out.flush().map_err(|e| e.to_string())?;
+        let max = self.config.max_files;
+        if max == 0 {
+            let _ = fs::remove_file(self.config.dir.join(AUDIT_FILE));
+        } else {
+            let _ = fs::remove_file(self.rotated(max));
+            for n in (1..max).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    fs::rename(&from, self.rotated(n + 1)).map_err(|e| e.to_string())?;
+                }
+            }
+            fs::rename(self.config.dir.join(AUDIT_FILE), self.rotated(1)).map_err(|e| e.to_string())?;
+        }
+        *self = AuditLog::open(self.config.clone())?;
+        Ok(())
+    }
+
+    fn write(&mut self, records: &[AuditRecord]) -> Result<(), String> {
+        for r in records {
+            let line = r.to_line();
+            if self.size > 0 && self.size + line.len() as u64 > self.config.max_file_size {
+                self.rotate()?;
+            }
+            self.out.write_all(line.as_bytes()).map_err(|e| e.to_string())?;
+            self.size += line.len() as u64;
+        }
+        self.out.flush().map_err(|e| e.to_string())
+    }
+}
+
+// 写日志线程，发送端关闭后退出
+fn run(mut log: AuditLog, receiver: Receiver<Vec<AuditRecord>>) {
+    for records in receiver.iter() {
+        if let Err(e) = log.write(&records) {
+            error!("write audit log failed, lost {} records: {:?}", records.len(), e);
+        }
+    }
+}
+
+/**
+* 开启审计日志，之后每次提交的写入和删除都记录到日志目录中的audit.log
+* 日志由单独的线程写入，不阻塞写线程，已开启时按新设置重新开启
+* @param config 日志设置
+*/
+pub fn enable_audit(config: AuditConfig) -> Result<(), String> {
+    let log = AuditLog::open(config)?;
+    let (sender, receiver) = unbounded();
+    thread::Builder::new()
+        .name("pi_store-audit".to_string())
+        .spawn(move || run(log, receiver))
+        .map_err(|e| e.to_string())?;
+    // 替换后旧线程写完剩余的记录后退出
+    *AUDIT_SENDER.lock().unwrap() = Some(sender);
+    AUDIT_ENABLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+// 关闭审计日志，已提交的记录仍会写完
+pub fn disable_audit() {
+    AUDIT_ENABLED.store(false, Ordering::SeqCst);
+    AUDIT_SENDER.lock().unwrap().take();
+}
+
+pub fn is_enabled() -> bool {
+    AUDIT_ENABLED.load(Ordering::SeqCst)
+}
+
+// 写线程处理消息前设置消息所属的会话id，之后记录的修改都属于该会话
+pub fn set_session(env_id: u64, session: u64) {
+    if !is_enabled() {
+        return;
+    }
+    SESSIONS.lock().unwrap().insert(env_id, session);
+}
+
+/**
+* 在写入时记录修改，提交成功后由publish写入日志
+* @param env_id 环境id
+* @param m 修改，值为None表示删除
+*/
+pub fn capture(env_id: u64, m: &TabKV) {
+    if !is_enabled() || changelog::is_internal(&m.tab) {
+        return;
+    }
+    let session = SESSIONS.lock().unwrap().get(&env_id).cloned().unwrap_or(0);
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    PENDING.lock().unwrap().entry(env_id).or_insert_with(Vec::new).push(AuditRecord {
+        time,
+        env_id,
+        session,
+        tab: m.tab.clone(),
+        key: m.key.to_vec(),
+        size: m.value.as_ref().map_or(0, |v| v.len()),
+        deleted: m.value.is_none(),
+        renamed_from: None,
+    });
+}
+
+// 记录表的重命名，整个重命名只记录一条
+pub fn capture_rename(env_id: u64, old: &Atom, new: &Atom) {
+    if !is_enabled() || changelog::is_internal(new) {
+        return;
+    }
+    let session = SESSIONS.lock().unwrap().get(&env_id).cloned().unwrap_or(0);
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    PENDING.lock().unwrap().entry(env_id).or_insert_with(Vec::new).push(AuditRecord {
+        time,
+        env_id,
+        session,
+        tab: new.clone(),
+        key: Vec::new(),
+        size: 0,
+        deleted: false,
+        renamed_from: Some(old.clone()),
+    });
+}
+
+// 读写事务提交成功后，将事务中的修改交给写日志线程
+pub fn publish(env_id: u64) {
+    let records = match PENDING.lock().unwrap().remove(&env_id) {
+        Some(records) if !records.is_empty() => records,
+        _ => return,
+    };
+    if let Some(sender) = AUDIT_SENDER.lock().unwrap().as_ref() {
+        if sender.send(records).is_err() {
+            warn!("audit log closed, drop commit of env: {:?}", env_id);
+        }
+    }
+}
+
+// 读写事务回滚后丢弃事务中的修改
+pub fn discard(env_id: u64) {
+    PENDING.lock().unwrap().remove(&env_id);
+}
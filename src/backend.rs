@@ -0,0 +1,519 @@
+use crossbeam_channel::{Receiver, Sender};
+use lmdb::WriteFlags;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, ThreadId};
+
+use worker::impls::cast_store_task;
+use worker::task::TaskType;
+
+use pi_db::db::{Bin, TabKV};
+
+use atom::Atom;
+
+use crate::merge;
+use crate::page::{self, Page};
+use crate::pool::{channel, reader_name, release_writer, writer_name, Condition, Precondition, ReaderMsg, StoreError, WriteCallback, WriterMsg};
+use crate::txn_stats::CommitStats;
+
+/**
+* 非LMDB的存储后端，与LMDB后端使用相同的读写消息协议
+* 修改在MODS中缓存，只在Commit时一次性写入后端
+*/
+pub trait Backend: Clone + Send + 'static {
+    // 后端名，用于日志
+    fn name(&self) -> &'static str;
+
+    // 批量查询，不存在的键返回值为None
+    fn query(&self, queries: &[TabKV]) -> Result<Vec<TabKV>, String>;
+
+    // 定位迭代的起始键，与LMDB后端的游标语义保持一致
+    fn first_key(&self, tab: &Atom, descending: bool, start_key: Option<Bin>) -> Option<Bin>;
+
+    // 取当前键的值以及迭代方向上的下一个键
+    fn next_item(&self, tab: &Atom, descending: bool, cur_key: &Bin) -> (Option<Bin>, Option<Bin>);
+
+    // 原子提交修改，值为None表示删除
+    fn commit(&self, modifies: &[TabKV]) -> Result<(), String>;
+}
+
+thread_local! {
+    // 同步执行模式下执行消息时产生的回调，消息执行完并释放锁后依次调用
+    static DEFERRED: RefCell<Option<Vec<Box<FnOnce()>>>> = RefCell::new(None);
+}
+
+/**
+* 同步执行消息的执行器，发送者发送消息后调用pump在自己的线程中执行
+*/
+pub trait Pump: Send + Sync {
+    // 按发送顺序执行所有待执行的消息
+    fn pump(&self);
+}
+
+// 可重入的执行锁，同一个线程在回调中再次发送消息时可以继续执行，其它线程等待
+#[derive(Default)]
+struct PumpLock {
+    owner: Mutex<(Option<ThreadId>, usize)>,
+    cond: Condvar,
+}
+
+impl PumpLock {
+    fn enter(&self) {
+        let me = thread::current().id();
+        let mut owner = self.owner.lock().unwrap();
+        loop {
+            match owner.0 {
+                None => {
+                    *owner = (Some(me), 1);
+                    return;
+                }
+                Some(id) if id == me => {
+                    owner.1 += 1;
+                    return;
+                }
+                _ => owner = self.cond.wait(owner).unwrap(),
+            }
+        }
+    }
+
+    fn exit(&self) {
+        let mut owner = self.owner.lock().unwrap();
+        owner.1 -= 1;
+        if owner.1 == 0 {
+            owner.0 = None;
+            self.cond.notify_all();
+        }
+    }
+}
+
+/**
+* 同步执行模式的后端，不启动工作线程，消息在发送者的线程中按发送顺序执行，回调也在发送者的线程中调用
+*/
+struct Inline<B: Backend> {
+    store: B,
+    env_id: u64,
+    reader: Receiver<ReaderMsg>,
+    writer: Receiver<WriterMsg>,
+    prepared: Mutex<HashMap<u64, Vec<TabKV>>>,
+    lock: PumpLock,
+}
+
+impl<B: Backend + Sync> Pump for Inline<B> {
+    fn pump(&self) {
+        self.lock.enter();
+        loop {
+            let outer = DEFERRED.with(|d| d.replace(Some(Vec::new())));
+            let handled = match self.writer.try_recv() {
+                Ok(msg) => {
+                    handle_writer(&self.store, self.env_id, &mut self.prepared.lock().unwrap(), msg);
+                    true
+                }
+                Err(_) => match self.reader.try_recv() {
+                    Ok(msg) => {
+                        handle_reader(&self.store, msg);
+                        true
+                    }
+                    Err(_) => false,
+                },
+            };
+            // 回调中可能再次发送消息，此时不能持有任何锁
+            let callbacks = DEFERRED.with(|d| d.replace(outer)).unwrap_or_else(Vec::new);
+            for f in callbacks {
+                f();
+            }
+            if !handled {
+                break;
+            }
+        }
+        self.lock.exit();
+    }
+}
+
+/**
+* 创建同步执行模式的后端
+* @returns 返回读消息和写消息的发送端，以及发送后需要调用的执行器
+*/
+pub fn inline<B: Backend + Sync>(store: B, env_id: u64) -> (Sender<ReaderMsg>, Sender<WriterMsg>, Arc<Pump>) {
+    let (reader_tx, reader) = channel(0);
+    let (writer_tx, writer) = channel(0);
+    let pump = Inline {
+        store,
+        env_id,
+        reader,
+        writer,
+        prepared: Mutex::new(HashMap::new()),
+        lock: PumpLock::default(),
+    };
+    (reader_tx, writer_tx, Arc::new(pump))
+}
+
+// 启动一个后端读线程
+pub fn spawn_reader<B: Backend>(store: B, i: usize, capacity: usize) -> Sender<ReaderMsg> {
+    let (tx, rx) = channel(capacity);
+
+    let _ = thread::Builder::new().name(reader_name(i)).spawn(move ||
+        loop {
+            if let Ok(msg) = rx.recv() {
+                handle_reader(&store, msg);
+            }
+        });
+
+    tx
+}
+
+// 执行读消息
+fn handle_reader<B: Backend>(store: &B, msg: ReaderMsg) {
+    match msg {
+        ReaderMsg::Query(queries, cb, token) => {
+            let r = match token {
+                Some(ref t) if t.is_cancelled() => Err(StoreError::Cancelled.to_string()),
+                _ => store.query(&queries),
+            };
+            debug!("{} query result: {:?}", store.name(), r);
+            callback(Atom::from("Backend reader query"), move || cb(r));
+        }
+        ReaderMsg::QueryVersioned(_, cb) => {
+            let name = store.name();
+            callback(Atom::from("Backend reader query versioned"), move || cb(Err(format!("{} backend not support key versions", name))));
+        }
+        ReaderMsg::QueryView(_, mut cb) => {
+            cb(Err(format!("{} backend not support value views", store.name())));
+        }
+        ReaderMsg::CreateItemIter(descending, tab, start_key, sndr) => {
+            let _ = sndr.send(store.first_key(&tab, descending, start_key));
+        }
+        ReaderMsg::NextItem(descending, tab, cur_key, cb, sndr) => {
+            if let Some(ck) = cur_key {
+                let (item, next) = store.next_item(&tab, descending, &ck);
+                if let Some(v) = item {
+                    callback(Atom::from("Backend reader get next item"), move || cb(Ok(Some((ck, v)))));
+                }
+                let _ = sndr.send(next);
+            }
+        }
+        ReaderMsg::NextMatch(descending, tab, cur_key, filter, cb, sndr) => {
+            let mut key = Some(cur_key);
+            let mut found = None;
+            while let Some(k) = key {
+                if filter.past_end(&k, descending) {
+                    key = None;
+                    break;
+                }
+                let (v, next) = store.next_item(&tab, descending, &k);
+                key = next;
+                if let Some(v) = v {
+                    if filter.matches(&k, &v) {
+                        found = Some((k, v));
+                        break;
+                    }
+                }
+            }
+            callback(Atom::from("Backend reader get next match"), move || cb(Ok(found)));
+            let _ = sndr.send(key);
+        }
+        ReaderMsg::NextKey(descending, tab, cur_key, sndr) => {
+            let _ = sndr.send(store.next_item(&tab, descending, &cur_key).1);
+        }
+        ReaderMsg::Seek(tab, key, for_prev, sndr) => {
+            let found = store.first_key(&tab, true, Some(key.clone()));
+            let k = match (found, for_prev) {
+                (found, false) => found,
+                (Some(k), true) if k == key => Some(k),
+                (Some(k), true) => store.next_item(&tab, false, &k).1,
+                (None, true) => store.first_key(&tab, false, None),
+            };
+            let _ = sndr.send(k);
+        }
+        ReaderMsg::ScanPage(tab, after, descending, limit, cb) => {
+            let items = scan_page(store, &tab, after, descending, limit);
+            let next = page::next_token(&items, descending, limit);
+            callback(Atom::from("Backend reader scan page"), move || cb(Ok(Page { items, next })));
+        }
+        ReaderMsg::TableSize(tab, cb) => {
+            let r = count_range(store, &tab, None, None);
+            callback(Atom::from("Backend reader table size"), move || cb(Ok(r)));
+        }
+        ReaderMsg::CountRange(tab, start, end, cb) => {
+            let r = count_range(store, &tab, start, end);
+            callback(Atom::from("Backend reader count range"), move || cb(Ok(r)));
+        }
+        ReaderMsg::Commit(cb) => {
+            ok(Atom::from("Backend reader commit"), cb);
+        }
+        ReaderMsg::Rollback(cb) => {
+            ok(Atom::from("Backend reader rollback"), cb);
+        }
+        ReaderMsg::OpenRo(sndr) => {
+            let _ = sndr.send(Err(format!("{} backend not support ro txns", store.name())));
+        }
+        ReaderMsg::RoQuery(_, _, cb) => {
+            let name = store.name();
+            callback(Atom::from("Backend reader ro txn query"), move || cb(Err(format!("{} backend not support ro txns", name))));
+        }
+        ReaderMsg::RoNext(_, _, _, _, cb) => {
+            let name = store.name();
+            callback(Atom::from("Backend reader ro txn next"), move || cb(Err(format!("{} backend not support ro txns", name))));
+        }
+        ReaderMsg::CloseRo(_) => {}
+    }
+}
+
+
+// 启动后端写线程
+pub fn spawn_writer<B: Backend>(store: B, env_id: u64, capacity: usize) -> Sender<WriterMsg> {
+    let (tx, rx) = channel(capacity);
+
+    let _ = thread::Builder::new().name(writer_name()).spawn(move || {
+        // 已预提交的修改，在提交时一起写入后端
+        let mut prepared: HashMap<u64, Vec<TabKV>> = HashMap::new();
+        loop {
+            if let Ok(msg) = rx.recv() {
+                handle_writer(&store, env_id, &mut prepared, msg);
+            }
+        }
+    });
+
+    tx
+}
+
+// 执行写消息
+fn handle_writer<B: Backend>(store: &B, env_id: u64, prepared: &mut HashMap<u64, Vec<TabKV>>, msg: WriterMsg) {
+    match msg {
+        WriterMsg::Query(queries, cb, token) => {
+            let r = match token {
+                Some(ref t) if t.is_cancelled() => Err(StoreError::Cancelled.to_string()),
+                _ => store.query(&queries),
+            };
+            debug!("{} rw query result: {:?}", store.name(), r);
+            callback(Atom::from("Backend writer query"), move || cb(r));
+        }
+        WriterMsg::CreateItemIter(descending, tab, start_key, sndr) => {
+            let _ = sndr.send(store.first_key(&tab, descending, start_key));
+        }
+        WriterMsg::NextItem(descending, tab, cur_key, cb, sndr) => {
+            if let Some(ck) = cur_key {
+                let (item, next) = store.next_item(&tab, descending, &ck);
+                if let Some(v) = item {
+                    callback(Atom::from("Backend writer get next item"), move || cb(Ok(Some((ck, v)))));
+                }
+                let _ = sndr.send(next);
+            }
+        }
+        WriterMsg::Modify(cb) => {
+            ok(Atom::from("Backend writer modify"), cb);
+        }
+        WriterMsg::Exec(txid, _, sndr) => {
+            let _ = sndr.send(Err(format!("{} backend not support txn closure", store.name())));
+            release_writer(env_id, txid);
+        }
+        WriterMsg::Merge(operands, cb) => {
+            // 后端没有读写事务，合并立即提交
+            let r = merge(store, &operands);
+            callback(Atom::from("Backend writer merge"), move || cb(r));
+        }
+        WriterMsg::Coalesce(modifies, cb) => {
+            // 后端的提交本身不需要合并，立即提交
+            let r = store.commit(&modifies);
+            callback(Atom::from("Backend writer coalesce"), move || cb(r));
+        }
+        WriterMsg::Put(modifies, flags, cb) => {
+            // 后端只支持NO_OVERWRITE，写线程是唯一的写者，检查后到提交前键不会被写入
+            let r = if flags.contains(WriteFlags::NO_OVERWRITE) {
+                store.query(&modifies).and_then(|qr| {
+                    if qr.iter().any(|q| q.value.is_some()) {
+                        Err(StoreError::KeyExist.to_string())
+                    } else {
+                        Ok(())
+                    }
+                })
+            } else {
+                Ok(())
+            };
+            let r = r.and_then(|_| store.commit(&modifies));
+            callback(Atom::from("Backend writer put"), move || cb(r));
+        }
+        WriterMsg::BulkLoad(tab, stream, every, cb) => {
+            let r = bulk_load(store, &tab, &stream, every.max(1));
+            callback(Atom::from("Backend writer bulk load"), move || cb(r));
+        }
+        WriterMsg::Prepare(txid, modifies, conditions, cb) => {
+            let r = check_conditions(store, &conditions);
+            match r {
+                Ok(_) => prepared.entry(txid).or_insert_with(Vec::new).extend(modifies.iter().cloned()),
+                Err(_) => {
+                    prepared.remove(&txid);
+                    release_writer(env_id, txid);
+                }
+            }
+            callback(Atom::from("Backend writer prepare"), move || cb(r));
+        }
+        WriterMsg::CommitPrepared(txid, cb) => {
+            let r = match prepared.remove(&txid) {
+                Some(modifies) => store.commit(&modifies),
+                None => Err(format!("no prepared txn for txid: {:?}", txid)),
+            };
+            callback(Atom::from("Backend writer commit prepared"), move || cb(r));
+            release_writer(env_id, txid);
+        }
+        WriterMsg::Commit(txid, modifies, conditions, cb) => {
+            let mut all = prepared.remove(&txid).unwrap_or_else(Vec::new);
+            all.extend(modifies.iter().cloned());
+            // 非LMDB后端不估计脏页数
+            let r = check_conditions(store, &conditions).and_then(|_| store.commit(&all)).map(|_| CommitStats::of(&all));
+            if let Err(e) = &r {
+                warn!("{} commit error: {:?}", store.name(), e);
+            }
+            callback(Atom::from("Backend writer commit"), move || cb(r));
+            release_writer(env_id, txid);
+        }
+        WriterMsg::Rollback(txid, cb) => {
+            prepared.remove(&txid);
+            ok(Atom::from("Backend writer rollback"), cb);
+            release_writer(env_id, txid);
+        }
+        WriterMsg::RenameDb(_, _, _, cb) => {
+            let r = Err(format!("{} backend not support rename db", store.name()));
+            callback(Atom::from("Backend writer rename db"), move || cb(r));
+        }
+        WriterMsg::CopyDb(_, _, _, cb) => {
+            let r = Err(format!("{} backend not support copy db", store.name()));
+            callback(Atom::from("Backend writer copy db"), move || cb(r));
+        }
+    }
+}
+
+
+// 批量导入，校验键的顺序后每every条提交一次，失败后丢弃流中剩余的记录，错误中包含已提交的记录数
+fn bulk_load<B: Backend>(store: &B, tab: &Atom, stream: &Receiver<(Bin, Bin)>, every: usize) -> Result<usize, String> {
+    let mut committed = 0;
+    bulk_append(store, tab, stream, every, &mut committed).map_err(|e| {
+        let _ = stream.iter().count();
+        format!("bulk load tab: {:?} failed after {} records: {}", tab.to_string(), committed, e)
+    })
+}
+
+fn bulk_append<B: Backend>(store: &B, tab: &Atom, stream: &Receiver<(Bin, Bin)>, every: usize, committed: &mut usize) -> Result<usize, String> {
+    let mut batch = Vec::with_capacity(every);
+    let mut last: Option<Bin> = None;
+    let mut count = 0;
+    for (key, value) in stream.iter() {
+        if last.as_ref().map_or(false, |l| key <= *l) {
+            return Err(format!("keys not ascending at record {}", count));
+        }
+        last = Some(key.clone());
+        batch.push(TabKV { ware: Atom::from(""), tab: tab.clone(), key, index: 0, value: Some(value) });
+        count += 1;
+        if batch.len() >= every {
+            store.commit(&batch)?;
+            batch.clear();
+            *committed = count;
+        }
+    }
+    store.commit(&batch)?;
+    *committed = count;
+    Ok(count)
+}
+
+// 校验前置条件，写线程是唯一的写者，校验后到提交前值不会改变
+fn check_conditions<B: Backend>(store: &B, conditions: &[Condition]) -> Result<(), String> {
+    if conditions.is_empty() {
+        return Ok(());
+    }
+    let queries = conditions.iter().map(|c| TabKV {
+        ware: Atom::from(""),
+        tab: c.tab.clone(),
+        key: c.key.clone(),
+        index: 0,
+        value: None,
+    }).collect::<Vec<TabKV>>();
+    let current = store.query(&queries)?;
+    for (c, kv) in conditions.iter().zip(current.iter()) {
+        if let Precondition::Version(_) = c.expect {
+            return Err(format!("{} backend not support key versions", store.name()));
+        }
+        if !c.check(kv.value.as_ref().map(|v| v.as_slice()), 0) {
+            return Err(StoreError::PreconditionFailed.to_string());
+        }
+    }
+    Ok(())
+}
+
+// 读取当前值，合并后提交，同一批中对同一个键的多次合并依次生效
+fn merge<B: Backend>(store: &B, operands: &[TabKV]) -> Result<(), String> {
+    let current = store.query(operands)?;
+    let mut merged: HashMap<(u64, Bin), Option<Bin>> = HashMap::new();
+    let mut modifies = Vec::with_capacity(operands.len());
+    for (m, c) in operands.iter().zip(current.into_iter()) {
+        let operand = m.value.as_ref().ok_or_else(|| format!("merge without operand: {:?}", m.tab.to_string()))?;
+        let k = (m.tab.get_hash() as u64, m.key.clone());
+        let cur = merged.get(&k).cloned().unwrap_or(c.value);
+        let value = merge::apply(&m.tab, cur.as_ref().map(|v| v.as_slice()), operand)?.map(Arc::new);
+        merged.insert(k, value.clone());
+        modifies.push(TabKV {
+            value,
+            ..m.clone()
+        });
+    }
+    store.commit(&modifies)
+}
+
+// 读取一页键值，不包括after
+fn scan_page<B: Backend>(store: &B, tab: &Atom, after: Option<Bin>, descending: bool, limit: usize) -> Vec<(Bin, Bin)> {
+    let mut key = match after {
+        // 先定位到第一个大于等于after的键，再按方向跳过after
+        Some(k) => match (store.first_key(tab, true, Some(k.clone())), descending) {
+            (Some(found), true) if found == k => store.next_item(tab, true, &found).1,
+            (Some(found), true) => Some(found),
+            (Some(found), false) => store.next_item(tab, false, &found).1,
+            (None, true) => None,
+            (None, false) => store.first_key(tab, false, None),
+        },
+        None => store.first_key(tab, descending, None),
+    };
+    let mut items = Vec::with_capacity(limit);
+    while let Some(k) = key {
+        if items.len() >= limit {
+            break;
+        }
+        let (v, next) = store.next_item(tab, descending, &k);
+        if let Some(v) = v {
+            items.push((k, v));
+        }
+        key = next;
+    }
+    items
+}
+
+// 从起始键按从小到大的顺序遍历，统计键在[start, end)范围内的记录数
+fn count_range<B: Backend>(store: &B, tab: &Atom, start: Option<Bin>, end: Option<Bin>) -> usize {
+    let mut count = 0;
+    let mut key = store.first_key(tab, true, start);
+    while let Some(k) = key {
+        if end.as_ref().map_or(false, |e| k.as_slice() >= e.as_slice()) {
+            break;
+        }
+        count += 1;
+        key = store.next_item(tab, true, &k).1;
+    }
+    count
+}
+
+// 异步执行回调，同步执行模式下在消息执行完后由执行器调用
+fn callback<F: FnOnce() + 'static>(info: Atom, f: F) {
+    let mut f = Some(f);
+    DEFERRED.with(|d| {
+        if let Some(deferred) = d.borrow_mut().as_mut() {
+            deferred.push(Box::new(f.take().unwrap()));
+        }
+    });
+    if let Some(f) = f {
+        let t = Box::new(move |_: Option<isize>| f());
+        cast_store_task(TaskType::Async(false), 100, None, t, info);
+    }
+}
+
+// 异步返回成功
+fn ok(info: Atom, cb: WriteCallback) {
+    callback(info, move || cb(Ok(())));
+}
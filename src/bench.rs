@@ -0,0 +1,228 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::bounded;
+use lmdb::{DatabaseFlags, Environment, EnvironmentFlags};
+use tempdir::TempDir;
+
+use pi_db::db::{Bin, TabKV};
+
+use atom::Atom;
+
+use crate::pool::{register_db, unregister_db, LmdbPool, LmdbService, ReaderMsg, WriterMsg};
+
+// 压测使用的表
+const BENCH_TAB: &str = "_bench";
+// 每次扫描读取的记录数
+const SCAN_LIMIT: usize = 100;
+
+/**
+* 压测负载，各比例为读、写、扫描操作的百分比
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Workload {
+    ReadHeavy,      //90%读，10%写
+    WriteHeavy,     //10%读，90%写
+    Scan,           //全部为扫描
+    Mixed,          //50%读，40%写，10%扫描
+}
+
+impl Workload {
+    fn ratio(&self) -> (u32, u32, u32) {
+        match self {
+            Workload::ReadHeavy => (90, 10, 0),
+            Workload::WriteHeavy => (10, 90, 0),
+            Workload::Scan => (0, 0, 100),
+            Workload::Mixed => (50, 40, 10),
+        }
+    }
+}
+
+/**
+* 压测配置
+*/
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub workload: Workload,     //负载
+    pub clients: usize,         //并发的客户端线程数
+    pub ops: usize,             //每个客户端执行的操作数
+    pub keys: usize,            //预先写入的键数量，读和写随机选择其中的键
+    pub value_size: usize,      //值的字节数
+    pub readers: usize,         //工作线程池的读线程数
+    pub map_size: usize,        //临时环境的内存映射大小
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig {
+            workload: Workload::Mixed,
+            clients: 4,
+            ops: 10000,
+            keys: 100000,
+            value_size: 128,
+            readers: 4,
+            map_size: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/**
+* 压测结果，延迟为从发送消息到回调被调用的时间
+*/
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    pub workload: Workload,
+    pub ops: usize,             //完成的操作总数
+    pub errors: usize,          //失败的操作数
+    pub elapsed: Duration,      //总耗时
+    pub throughput: f64,        //每秒完成的操作数
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+fn bench_key(i: usize) -> Bin {
+    Arc::new(format!("{:016}", i).into_bytes())
+}
+
+fn bench_kv(ware: &Atom, key: Bin, value: Option<Bin>) -> TabKV {
+    TabKV {
+        ware: ware.clone(),
+        tab: Atom::from(BENCH_TAB),
+        key,
+        index: 0,
+        value,
+    }
+}
+
+fn random_value(size: usize) -> Bin {
+    Arc::new((0..size).map(|_| rand::random::<u8>()).collect())
+}
+
+// 发送一次读、写或扫描并等待回调，返回是否成功
+fn run_op(service: &LmdbService, ware: &Atom, op: u32, config: &BenchConfig) -> bool {
+    let tab = Atom::from(BENCH_TAB);
+    let key = bench_key(rand::random::<usize>() % config.keys.max(1));
+    let (tx, rx) = bounded(1);
+    let (read, write, _) = config.workload.ratio();
+    let sent = if op < read {
+        let msg = ReaderMsg::Query(Arc::new(vec![bench_kv(ware, key, None)]), Arc::new(move |r| {
+            let _ = tx.send(r.is_ok());
+        }), None);
+        service.ro_sender(&tab).map_or(false, |s| s.send(msg).is_ok())
+    } else if op < read + write {
+        let msg = WriterMsg::Coalesce(Arc::new(vec![bench_kv(ware, key, Some(random_value(config.value_size)))]), Arc::new(move |r| {
+            let _ = tx.send(r.is_ok());
+        }));
+        service.rw_sender().map_or(false, |s| s.send(msg).is_ok())
+    } else {
+        let msg = ReaderMsg::ScanPage(tab.clone(), Some(key), true, SCAN_LIMIT, Arc::new(move |r| {
+            let _ = tx.send(r.is_ok());
+        }));
+        service.ro_sender(&tab).map_or(false, |s| s.send(msg).is_ok())
+    };
+    sent && rx.recv().unwrap_or(false)
+}
+
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::from_millis(0);
+    }
+    sorted[((sorted.len() - 1) * p / 100).min(sorted.len() - 1)]
+}
+
+/**
+* 在临时环境中执行压测，环境在压测结束后删除
+* 操作通过工作线程池的读写消息执行，结果反映了线程池和后端的整体性能
+* @param config 压测配置
+* @returns 返回压测结果
+*/
+pub fn run(config: BenchConfig) -> Result<BenchReport, String> {
+    let dir = TempDir::new("pi_store_bench").map_err(|e| e.to_string())?;
+    let path = dir.path().to_string_lossy().to_string();
+    let ware = Atom::from(path.as_str());
+    let env_id = ware.get_hash() as u64;
+    let env = Arc::new(
+        Environment::new()
+            .set_max_dbs(2)
+            .set_map_size(config.map_size)
+            .set_flags(EnvironmentFlags::NO_TLS)
+            .open(Path::new(&path))
+            .map_err(|e| e.to_string())?,
+    );
+    let db = env.create_db(Some(BENCH_TAB), DatabaseFlags::empty()).map_err(|e| e.to_string())?;
+    register_db(env_id, &Atom::from(BENCH_TAB), db)?;
+
+    // 预先写入所有键
+    let mut pool = LmdbPool::new();
+    let mut service = LmdbService::new(config.readers.max(1));
+    service.set_env(env);
+    pool.add_service(env_id, service);
+    {
+        let service = pool.service_by_env(env_id).ok_or_else(|| "bench service not started".to_string())?;
+        for start in (0..config.keys).step_by(10000) {
+            let batch = (start..(start + 10000).min(config.keys))
+                .map(|i| bench_kv(&ware, bench_key(i), Some(random_value(config.value_size))))
+                .collect::<Vec<TabKV>>();
+            let (tx, rx) = bounded(1);
+            let sender = service.rw_sender().ok_or_else(|| "bench writer not started".to_string())?;
+            let _ = sender.send(WriterMsg::Coalesce(Arc::new(batch), Arc::new(move |r| {
+                let _ = tx.send(r);
+            })));
+            rx.recv().map_err(|e| e.to_string())??;
+        }
+    }
+    let pool = Arc::new(pool);
+    let start = Instant::now();
+    let (tx, rx) = bounded(config.clients);
+    for _ in 0..config.clients {
+        let tx = tx.clone();
+        let pool = pool.clone();
+        let config = config.clone();
+        let ware = ware.clone();
+        let _ = thread::Builder::new().name("pi_store-bench".to_string()).spawn(move || {
+            let service = match pool.service_by_env(env_id) {
+                Some(service) => service,
+                None => return,
+            };
+            let mut latencies = Vec::with_capacity(config.ops);
+            let mut errors = 0;
+            for _ in 0..config.ops {
+                let op = rand::random::<u32>() % 100;
+                let op_start = Instant::now();
+                if !run_op(service, &ware, op, &config) {
+                    errors += 1;
+                }
+                latencies.push(op_start.elapsed());
+            }
+            let _ = tx.send((latencies, errors));
+        });
+    }
+    drop(tx);
+
+    let mut latencies = Vec::with_capacity(config.clients * config.ops);
+    let mut errors = 0;
+    for (l, e) in rx.iter() {
+        latencies.extend(l);
+        errors += e;
+    }
+    let elapsed = start.elapsed();
+
+    unregister_db(env_id, Atom::from(BENCH_TAB).get_hash() as u64);
+    latencies.sort();
+    let ops = latencies.len();
+    Ok(BenchReport {
+        workload: config.workload,
+        ops,
+        errors,
+        elapsed,
+        throughput: ops as f64 / elapsed.as_secs_f64().max(1e-9),
+        p50: percentile(&latencies, 50),
+        p95: percentile(&latencies, 95),
+        p99: percentile(&latencies, 99),
+        max: latencies.last().cloned().unwrap_or_else(|| Duration::from_millis(0)),
+    })
+}
@@ -0,0 +1,237 @@
+use std::hash::Hasher;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crossbeam_channel::bounded;
+use crc32fast::Hasher as Crc32;
+use fnv::FnvHasher;
+use lmdb::{Database, Error, RwTransaction, Transaction, WriteFlags};
+
+use pi_db::db::{Bin, TabKV};
+
+use atom::Atom;
+
+use crate::lmdb_file::{try_ro_send, with_txn};
+use crate::pool::{lookup_db, Priority, ReaderMsg};
+
+// 按内容寻址存放附件的表，每个环境一个
+pub const BLOBS_TAB: &str = "_$blobs";
+// 值的前缀，8字节大端引用计数
+const REFS_LEN: usize = 8;
+
+lazy_static! {
+    // 不小于该大小的值去重存储，为0时不去重
+    static ref DEDUP_THRESHOLD: AtomicUsize = AtomicUsize::new(0);
+}
+
+/**
+* 设置值的自动去重，写入时不小于阈值的值按内容存入附件表，原键只保存附件的地址
+* 相同的值只存一份，读取时自动取回内容，已去重的值不受阈值修改的影响
+* @param threshold 去重阈值，为0则关闭
+*/
+pub fn set_dedup(threshold: usize) {
+    DEDUP_THRESHOLD.store(threshold, Ordering::SeqCst);
+}
+
+// 值是否需要去重存储
+pub fn should_dedup(value: &[u8]) -> bool {
+    let threshold = DEDUP_THRESHOLD.load(Ordering::SeqCst);
+    threshold > 0 && value.len() >= threshold
+}
+
+/**
+* 内容的地址，由内容的FNV-1a哈希、CRC32和长度组成，相同内容的地址相同
+* 地址不是密码学哈希，写入时会与已有内容逐字节比较，不同内容的地址冲突时写入失败而不是共用
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlobId(pub [u8; 16]);
+
+impl BlobId {
+    pub fn of(content: &[u8]) -> Self {
+        let mut fnv = FnvHasher::default();
+        fnv.write(content);
+        let mut crc = Crc32::new();
+        crc.update(content);
+        let mut id = [0u8; 16];
+        id[..8].copy_from_slice(&fnv.finish().to_be_bytes());
+        id[8..12].copy_from_slice(&crc.finalize().to_be_bytes());
+        id[12..].copy_from_slice(&(content.len() as u32).to_be_bytes());
+        BlobId(id)
+    }
+
+    // 十六进制表示，用于在普通表中保存引用
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self, String> {
+        if hex.len() != 32 {
+            return Err(format!("invalid blob id: {:?}", hex));
+        }
+        let mut id = [0u8; 16];
+        for (i, b) in id.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| format!("invalid blob id: {:?}", hex))?;
+        }
+        Ok(BlobId(id))
+    }
+}
+
+// 解析值中的引用计数和内容
+fn decode(value: &[u8]) -> Result<(u64, &[u8]), String> {
+    if value.len() < REFS_LEN {
+        return Err("blob record corrupted".to_string());
+    }
+    let mut refs = [0u8; REFS_LEN];
+    refs.copy_from_slice(&value[..REFS_LEN]);
+    Ok((u64::from_be_bytes(refs), &value[REFS_LEN..]))
+}
+
+fn encode(refs: u64, content: &[u8]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(REFS_LEN + content.len());
+    value.extend_from_slice(&refs.to_be_bytes());
+    value.extend_from_slice(content);
+    value
+}
+
+fn blobs_db(env_id: u64) -> Option<Database> {
+    lookup_db(env_id, &Atom::from(BLOBS_TAB))
+}
+
+/**
+* 在读写事务中增加内容的引用，内容不存在则写入
+* @returns 返回内容的地址，地址已被不同的内容占用返回None，调用者应直接保存内容
+*/
+pub fn incref(txn: &mut RwTransaction, env_id: u64, content: &[u8]) -> Result<Option<BlobId>, Error> {
+    let db = blobs_db(env_id).ok_or(Error::NotFound)?;
+    let id = BlobId::of(content);
+    let refs = match txn.get(db, &id.0) {
+        Ok(value) => match decode(value) {
+            Ok((refs, stored)) if stored == content => refs,
+            Ok(_) => {
+                warn!("blob id collision: {}", id.to_hex());
+                return Ok(None);
+            }
+            Err(_) => return Err(Error::Corrupted),
+        },
+        Err(Error::NotFound) => 0,
+        Err(e) => return Err(e),
+    };
+    txn.put(db, &id.0, &encode(refs + 1, content), WriteFlags::empty())?;
+    Ok(Some(id))
+}
+
+// 在读写事务中减少内容的引用，减到0时删除内容
+pub fn decref(txn: &mut RwTransaction, env_id: u64, id: &BlobId) -> Result<(), Error> {
+    let db = blobs_db(env_id).ok_or(Error::NotFound)?;
+    let (refs, content) = match txn.get(db, &id.0) {
+        Ok(value) => match decode(value) {
+            Ok((refs, content)) => (refs, content.to_vec()),
+            Err(_) => return Err(Error::Corrupted),
+        },
+        Err(Error::NotFound) => {
+            warn!("unref missing blob: {}", id.to_hex());
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    if refs <= 1 {
+        txn.del(db, &id.0, None)
+    } else {
+        txn.put(db, &id.0, &encode(refs - 1, &content), WriteFlags::empty())
+    }
+}
+
+// 在事务中读取内容
+pub fn read<T: Transaction>(txn: &T, env_id: u64, id: &BlobId) -> Result<Vec<u8>, Error> {
+    let db = blobs_db(env_id).ok_or(Error::NotFound)?;
+    match decode(txn.get(db, &id.0)?) {
+        Ok((_, content)) => Ok(content.to_vec()),
+        Err(_) => Err(Error::Corrupted),
+    }
+}
+
+/**
+* 按内容寻址的附件存储，相同内容只存一份，用引用计数管理生命周期
+* 普通表中保存附件的地址，写入附件时增加引用，删除引用时减少引用，引用为0时删除内容
+* 附件存放在环境的附件表中，与普通表的修改不在同一个事务中，调用者需要先写入附件再写入引用
+* 所有操作在调用线程中同步执行，不能在存储的任务池中调用
+*/
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    tab: Atom,  //用于选择环境的表，附件存放在该表所在环境的附件表中
+}
+
+impl BlobStore {
+    /**
+    * @param tab 引用附件的表，附件与它在同一个环境中
+    */
+    pub fn new(tab: &Atom) -> Self {
+        BlobStore { tab: tab.clone() }
+    }
+
+    /**
+    * 写入附件，内容已存在时只增加引用计数
+    * @param content 附件内容
+    * @returns 返回附件的地址，地址冲突返回错误
+    */
+    pub fn put_blob(&self, content: Bin) -> Result<BlobId, String> {
+        let id = BlobId::of(&content);
+        let tab = Atom::from(BLOBS_TAB);
+        with_txn(&self.tab, move |ops| {
+            let refs = match ops.get(&tab, &id.0)? {
+                Some(value) => {
+                    let (refs, stored) = decode(&value)?;
+                    if stored != content.as_slice() {
+                        return Err(format!("blob id collision: {}", id.to_hex()));
+                    }
+                    refs
+                }
+                None => 0,
+            };
+            ops.put(&tab, &id.0, &encode(refs + 1, &content))
+        })?;
+        Ok(id)
+    }
+
+    /**
+    * 读取附件，在读线程中读取
+    * @returns 附件不存在返回None
+    */
+    pub fn get_blob(&self, id: &BlobId) -> Result<Option<Bin>, String> {
+        let query = TabKV {
+            ware: Atom::from(""),
+            tab: Atom::from(BLOBS_TAB),
+            key: Arc::new(id.0.to_vec()),
+            index: 0,
+            value: None,
+        };
+        let (s, r) = bounded(1);
+        try_ro_send(&self.tab, Priority::High, ReaderMsg::Query(Arc::new(vec![query]), Arc::new(move |res| {
+            let _ = s.send(res);
+        }), None)).map_err(|e| e.to_string())?;
+        match r.recv().map_err(|e| e.to_string())??.pop().and_then(|kv| kv.value) {
+            Some(value) => decode(&value).map(|(_, content)| Some(Arc::new(content.to_vec()))),
+            None => Ok(None),
+        }
+    }
+
+    /**
+    * 减少附件的引用计数，减到0时删除内容
+    * @returns 返回剩余的引用数，附件不存在返回错误
+    */
+    pub fn unref_blob(&self, id: &BlobId) -> Result<u64, String> {
+        let id = *id;
+        let tab = Atom::from(BLOBS_TAB);
+        with_txn(&self.tab, move |ops| {
+            let value = ops.get(&tab, &id.0)?.ok_or_else(|| format!("blob not found: {}", id.to_hex()))?;
+            let (refs, content) = decode(&value)?;
+            let refs = refs.saturating_sub(1);
+            if refs == 0 {
+                ops.del(&tab, &id.0)?;
+            } else {
+                ops.put(&tab, &id.0, &encode(refs, content))?;
+            }
+            Ok(refs)
+        })
+    }
+}
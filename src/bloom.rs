@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use lmdb::{Cursor, Database, Environment, Transaction};
+
+use pi_db::db::TabKV;
+
+// 每个键占用的位数，约1%的误判率
+const BITS_PER_KEY: usize = 10;
+// 哈希函数数量
+const HASH_COUNT: u64 = 7;
+
+lazy_static! {
+    // 每个表预期的键数量，为0时不启用
+    static ref EXPECTED_KEYS: AtomicUsize = AtomicUsize::new(0);
+    // 所有表的布隆过滤器，键为(环境id, 表名哈希)
+    static ref FILTERS: RwLock<HashMap<(u64, u64), BloomFilter>> = RwLock::new(HashMap::new());
+}
+
+/**
+* 布隆过滤器，只会误判存在，不会误判不存在
+* 删除不会更新过滤器，删除多的表需要重新打开才能恢复准确率
+*/
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    len: u64,
+}
+
+impl BloomFilter {
+    pub fn new(keys: usize) -> Self {
+        let len = (keys.max(1) * BITS_PER_KEY) as u64;
+        BloomFilter {
+            bits: vec![0; ((len + 63) / 64) as usize],
+            len,
+        }
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        let (h1, h2) = hash(key);
+        for i in 0..HASH_COUNT {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.len;
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        let (h1, h2) = hash(key);
+        (0..HASH_COUNT).all(|i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.len;
+            self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0
+        })
+    }
+}
+
+// 双重哈希
+fn hash(key: &[u8]) -> (u64, u64) {
+    let mut h = DefaultHasher::new();
+    h.write(key);
+    let h1 = h.finish();
+    h.write_u8(0xff);
+    let h2 = h.finish() | 1;
+    (h1, h2)
+}
+
+/**
+* 启用布隆过滤器，查询前先检查过滤器，确定不存在的键不再访问LMDB
+* 只对之后打开的表生效
+* @param expected_keys 每个表预期的键数量，为0则关闭
+*/
+pub fn set_bloom_filter(expected_keys: usize) {
+    EXPECTED_KEYS.store(expected_keys, Ordering::SeqCst);
+    if expected_keys == 0 {
+        FILTERS.write().unwrap().clear();
+    }
+}
+
+// 打开表时用表中所有的键重建过滤器
+pub fn rebuild(env_id: u64, tab: u64, env: &Environment, db: Database) {
+    let expected = EXPECTED_KEYS.load(Ordering::SeqCst);
+    if expected == 0 {
+        return;
+    }
+
+    let txn = match env.begin_ro_txn() {
+        Ok(txn) => txn,
+        Err(e) => {
+            warn!("rebuild bloom filter failed: {:?}", e.to_string());
+            return;
+        }
+    };
+    let filter = {
+        let mut cursor = match txn.open_ro_cursor(db) {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                warn!("rebuild bloom filter failed: {:?}", e.to_string());
+                return;
+            }
+        };
+        let count = cursor.iter_start().count();
+        let mut filter = BloomFilter::new(expected.max(count));
+        for (k, _) in cursor.iter_start() {
+            filter.insert(k);
+        }
+        filter
+    };
+    let _ = txn.commit();
+
+    FILTERS.write().unwrap().insert((env_id, tab), filter);
+}
+
+// 键是否可能存在，表没有过滤器时总是返回true
+pub fn may_contain(env_id: u64, tab: u64, key: &[u8]) -> bool {
+    match FILTERS.read().unwrap().get(&(env_id, tab)) {
+        Some(filter) => filter.may_contain(key),
+        None => true,
+    }
+}
+
+// 将写入的键加入过滤器，必须在修改提交之前调用
+pub fn insert(env_id: u64, modifies: &[TabKV]) {
+    let mut filters = FILTERS.write().unwrap();
+    if filters.is_empty() {
+        return;
+    }
+    for m in modifies.iter().filter(|m| m.value.is_some()) {
+        if let Some(filter) = filters.get_mut(&(env_id, m.tab.get_hash() as u64)) {
+            filter.insert(&m.key);
+        }
+    }
+}
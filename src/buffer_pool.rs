@@ -0,0 +1,82 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use pi_db::db::Bin;
+
+// 最小的尺寸等级，2^6 = 64字节
+const MIN_CLASS_SHIFT: usize = 6;
+// 尺寸等级数量，最大等级为2^(6 + 11 - 1) = 64K
+const CLASS_COUNT: usize = 11;
+// 每个尺寸等级最多缓存的缓冲区数量
+const MAX_PER_CLASS: usize = 64;
+
+lazy_static! {
+    // 是否启用缓冲池
+    static ref POOL_ENABLED: AtomicBool = AtomicBool::new(false);
+}
+
+thread_local! {
+    // 每个线程独立的缓冲池，按尺寸等级存放空闲的缓冲区，不需要加锁
+    static POOL: RefCell<Vec<Vec<Vec<u8>>>> = RefCell::new(vec![Vec::new(); CLASS_COUNT]);
+}
+
+/**
+* 设置是否启用值复制的缓冲池，启用后读线程复制值时从缓冲池取缓冲区
+* 调用者用完值后通过recycle归还，归还的缓冲区进入归还线程的缓冲池
+*/
+pub fn set_buffer_pool(enabled: bool) {
+    POOL_ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+// 长度所属的尺寸等级，超过最大等级返回None
+fn class_of(len: usize) -> Option<usize> {
+    let shift = len.max(1).next_power_of_two().trailing_zeros() as usize;
+    let class = shift.saturating_sub(MIN_CLASS_SHIFT);
+    if class < CLASS_COUNT {
+        Some(class)
+    } else {
+        None
+    }
+}
+
+/**
+* 复制值，启用缓冲池时优先使用缓冲池中同等级的缓冲区
+*/
+pub fn copy(value: &[u8]) -> Vec<u8> {
+    if !POOL_ENABLED.load(Ordering::Relaxed) {
+        return value.to_vec();
+    }
+    let mut buf = match class_of(value.len()) {
+        Some(class) => POOL
+            .with(|p| p.borrow_mut()[class].pop())
+            .unwrap_or_else(|| Vec::with_capacity(1 << (class + MIN_CLASS_SHIFT))),
+        None => Vec::with_capacity(value.len()),
+    };
+    buf.extend_from_slice(value);
+    buf
+}
+
+/**
+* 归还不再使用的值，只有调用者持有唯一引用时才能归还，否则忽略
+*/
+pub fn recycle(value: Bin) {
+    if !POOL_ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Ok(mut buf) = Arc::try_unwrap(value) {
+        // 只回收容量正好等于等级大小的缓冲区，保证取出后不需要再扩容
+        match class_of(buf.capacity()) {
+            Some(class) if buf.capacity() == 1 << (class + MIN_CLASS_SHIFT) => {
+                buf.clear();
+                POOL.with(|p| {
+                    let mut p = p.borrow_mut();
+                    if p[class].len() < MAX_PER_CLASS {
+                        p[class].push(buf);
+                    }
+                });
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use lmdb::{Database, Error, RwTransaction, Transaction};
+
+use pi_db::db::{Bin, TabKV};
+
+use atom::Atom;
+
+use crate::changelog;
+use crate::chunk;
+use crate::pool::channel;
+use crate::pubsub;
+
+/**
+* 一次提交中一个键的修改
+*/
+#[derive(Debug, Clone)]
+pub struct ChangeEvent {
+    pub env_id: u64,            //环境id
+    pub seq: u64,               //提交序号
+    pub tab: Atom,              //表名
+    pub key: Bin,               //键
+    pub old: Option<Bin>,       //修改前的值，None表示修改前不存在
+    pub new: Option<Bin>,       //修改后的值，None表示删除
+    pub renamed_from: Option<Atom>, //表重命名事件的原表名，此时tab为新表名，key为空，old和new都为None
+}
+
+/**
+* 修改的消费者，每次提交成功后收到该提交的所有修改
+* 消费者处理太慢导致通道满时，后续的修改被丢弃并计入dropped
+*/
+pub struct CdcConsumer {
+    id: u64,
+    receiver: Receiver<Arc<Vec<ChangeEvent>>>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl CdcConsumer {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn receiver(&self) -> &Receiver<Arc<Vec<ChangeEvent>>> {
+        &self.receiver
+    }
+
+    // 因通道满被丢弃的提交数量，不为0时消费者应重新同步
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for CdcConsumer {
+    fn drop(&mut self) {
+        unsubscribe(self.id);
+    }
+}
+
+lazy_static! {
+    static ref CONSUMER_ID: AtomicU64 = AtomicU64::new(1);
+    // 已注册的消费者
+    static ref CONSUMERS: Mutex<HashMap<u64, (Sender<Arc<Vec<ChangeEvent>>>, Arc<AtomicUsize>)>> = Mutex::new(HashMap::new());
+    // 已注册的消费者数量，没有消费者时不读取修改前的值
+    static ref CONSUMER_COUNT: AtomicUsize = AtomicUsize::new(0);
+    // 各环境写线程中未提交的修改
+    static ref PENDING: Mutex<HashMap<u64, Vec<ChangeEvent>>> = Mutex::new(HashMap::new());
+}
+
+/**
+* 注册修改的消费者，注册之后提交的修改才会发送给消费者
+* @param capacity 通道容量，为0则不限制
+* @returns 返回消费者，消费者被释放时自动注销
+*/
+pub fn subscribe(capacity: usize) -> CdcConsumer {
+    let id = CONSUMER_ID.fetch_add(1, Ordering::SeqCst);
+    let (sender, receiver) = channel(capacity);
+    let dropped = Arc::new(AtomicUsize::new(0));
+    CONSUMERS.lock().unwrap().insert(id, (sender, dropped.clone()));
+    CONSUMER_COUNT.fetch_add(1, Ordering::SeqCst);
+    CdcConsumer { id, receiver, dropped }
+}
+
+// 注销消费者
+pub fn unsubscribe(id: u64) {
+    if CONSUMERS.lock().unwrap().remove(&id).is_some() {
+        CONSUMER_COUNT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// 有消费者或键前缀订阅时才记录修改
+fn is_active() -> bool {
+    CONSUMER_COUNT.load(Ordering::SeqCst) > 0 || pubsub::has_subscribers()
+}
+
+/**
+* 在写入前记录键的修改，只在有消费者时读取修改前的值，提交成功后由publish发送
+* @param txn 读写事务
+* @param env_id 环境id
+* @param db 修改的表
+* @param m 修改，值为None表示删除
+*/
+pub fn capture(txn: &RwTransaction, env_id: u64, db: Database, m: &TabKV) -> Result<(), Error> {
+    if !is_active() || changelog::is_internal(&m.tab) {
+        return Ok(());
+    }
+    let old = match txn.get(db, &m.key.as_ref()) {
+        Ok(v) => Some(Arc::new(chunk::read(txn, env_id, &m.tab, &m.key, v)?)),
+        Err(Error::NotFound) => None,
+        Err(e) => return Err(e),
+    };
+    let seq = changelog::last_seq(txn, env_id)?;
+    PENDING.lock().unwrap().entry(env_id).or_insert_with(Vec::new).push(ChangeEvent {
+        env_id,
+        seq,
+        tab: m.tab.clone(),
+        key: m.key.clone(),
+        old,
+        new: m.value.clone(),
+        renamed_from: None,
+    });
+    Ok(())
+}
+
+/**
+* 记录表的重命名，重命名的记录不逐条产生修改，整个重命名只产生一个事件
+* @param txn 读写事务
+* @param env_id 环境id
+* @param old 原表名
+* @param new 新表名
+*/
+pub fn capture_rename(txn: &RwTransaction, env_id: u64, old: &Atom, new: &Atom) -> Result<(), Error> {
+    if !is_active() || changelog::is_internal(new) {
+        return Ok(());
+    }
+    let seq = changelog::last_seq(txn, env_id)?;
+    PENDING.lock().unwrap().entry(env_id).or_insert_with(Vec::new).push(ChangeEvent {
+        env_id,
+        seq,
+        tab: new.clone(),
+        key: Arc::new(Vec::new()),
+        old: None,
+        new: None,
+        renamed_from: Some(old.clone()),
+    });
+    Ok(())
+}
+
+// 读写事务提交成功后，将事务中的修改发送给所有消费者
+pub fn publish(env_id: u64) {
+    let events = match PENDING.lock().unwrap().remove(&env_id) {
+        Some(events) if !events.is_empty() => Arc::new(events),
+        _ => return,
+    };
+    pubsub::notify(&events);
+    let mut consumers = CONSUMERS.lock().unwrap();
+    let mut closed = Vec::new();
+    for (id, (sender, dropped)) in consumers.iter() {
+        match sender.try_send(events.clone()) {
+            Ok(_) => (),
+            Err(TrySendError::Full(_)) => {
+                dropped.fetch_add(1, Ordering::SeqCst);
+                warn!("cdc consumer {:?} is full, drop {:?} changes", id, events.len());
+            }
+            Err(TrySendError::Disconnected(_)) => closed.push(*id),
+        }
+    }
+    for id in closed {
+        consumers.remove(&id);
+        CONSUMER_COUNT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// 读写事务回滚后丢弃事务中的修改
+pub fn discard(env_id: u64) {
+    PENDING.lock().unwrap().remove(&env_id);
+}
@@ -0,0 +1,311 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lmdb::{Cursor, Database, Error, RwTransaction, Transaction, WriteFlags};
+use lmdb_sys as ffi;
+
+use pi_db::db::{Bin, TabKV};
+
+use atom::Atom;
+
+use crate::pool::lookup_db;
+use crate::schema::META_TAB;
+
+// 按提交序号记录修改的日志表，每个环境一个
+pub const CHANGELOG_TAB: &str = "_$changelog";
+// 在__meta表中记录最近提交序号的键
+const SEQ_KEY: &str = "__commit_seq";
+
+lazy_static! {
+    // 是否记录修改日志，关闭时仍然为每次提交分配序号
+    static ref CHANGELOG_ENABLED: AtomicBool = AtomicBool::new(false);
+}
+
+/**
+* 设置是否记录修改日志，日志中保存每次提交修改后的值，用于增量备份和按时间点恢复
+* 开启后每次写入会多写一份值，不需要时应关闭
+*/
+pub fn set_change_log(enabled: bool) {
+    CHANGELOG_ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+/**
+* 修改日志中的一条修改
+*/
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub seq: u64,               //提交序号
+    pub time: u64,              //写入时间，单位毫秒
+    pub tab: Atom,              //表名
+    pub key: Bin,               //键
+    pub value: Option<Bin>,     //修改后的值，None表示删除
+}
+
+impl Change {
+    pub fn to_tabkv(&self, ware: &Atom) -> TabKV {
+        TabKV {
+            ware: ware.clone(),
+            tab: self.tab.clone(),
+            key: self.key.clone(),
+            index: 0,
+            value: self.value.clone(),
+        }
+    }
+}
+
+fn db_of(env_id: u64, tab: &str) -> Option<Database> {
+    lookup_db(env_id, &Atom::from(tab))
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// 影子表和保留表不记录修改
+pub fn is_internal(tab: &Atom) -> bool {
+    tab.starts_with("_$") || tab.as_str() == META_TAB
+}
+
+// 解析__meta表中的序号记录: 提交序号 + 分配序号的LMDB事务id
+fn decode_seq(value: &[u8]) -> Result<(u64, u64), Error> {
+    if value.len() != 16 {
+        return Err(Error::Corrupted);
+    }
+    let mut seq = [0u8; 8];
+    seq.copy_from_slice(&value[0..8]);
+    let mut txnid = [0u8; 8];
+    txnid.copy_from_slice(&value[8..16]);
+    Ok((u64::from_be_bytes(seq), u64::from_be_bytes(txnid)))
+}
+
+/**
+* 读取最近一次提交的序号，没有提交过返回0
+*/
+pub fn last_seq<T: Transaction>(txn: &T, env_id: u64) -> Result<u64, Error> {
+    let db = match db_of(env_id, META_TAB) {
+        Some(db) => db,
+        None => return Ok(0),
+    };
+    match txn.get(db, &SEQ_KEY) {
+        Ok(v) => decode_seq(v).map(|(seq, _)| seq),
+        Err(Error::NotFound) => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+/**
+* 为读写事务分配提交序号，同一个读写事务多次调用返回同一个序号
+* 序号与事务一起提交，事务回滚则序号也回滚，因此已提交的序号单调递增且连续
+* 压缩复制后LMDB的事务id会重新计数，所以只用事务id判断是否同一个事务，不直接用作序号
+*/
+pub fn assign_seq(txn: &mut RwTransaction, env_id: u64) -> Result<u64, Error> {
+    let db = match db_of(env_id, META_TAB) {
+        Some(db) => db,
+        None => return Ok(0),
+    };
+    let txnid = unsafe { ffi::mdb_txn_id(txn.txn()) } as u64;
+    let (last, last_txnid) = match (&*txn).get(db, &SEQ_KEY) {
+        Ok(v) => decode_seq(v)?,
+        Err(Error::NotFound) => (0, 0),
+        Err(e) => return Err(e),
+    };
+    if last_txnid == txnid && last > 0 {
+        return Ok(last);
+    }
+
+    let seq = last + 1;
+    let mut v = Vec::with_capacity(16);
+    v.extend_from_slice(&seq.to_be_bytes());
+    v.extend_from_slice(&txnid.to_be_bytes());
+    txn.put(db, &SEQ_KEY, &v, WriteFlags::empty())?;
+    Ok(seq)
+}
+
+// 日志的键: 提交序号 + 表名哈希 + 原键，同一次提交中同一个键只保留最后的修改
+fn log_key(seq: u64, tab: &Atom, key: &[u8]) -> Vec<u8> {
+    let mut k = Vec::with_capacity(16 + key.len());
+    k.extend_from_slice(&seq.to_be_bytes());
+    k.extend_from_slice(&(tab.get_hash() as u64).to_be_bytes());
+    k.extend_from_slice(key);
+    k
+}
+
+// 日志的值: 写入时间 + 表名长度 + 表名 + 是否删除 + 值
+fn encode_change(time: u64, tab: &Atom, value: Option<&[u8]>) -> Vec<u8> {
+    let name = tab.as_bytes();
+    let mut v = Vec::with_capacity(13 + name.len() + value.map_or(0, |v| v.len()));
+    v.extend_from_slice(&time.to_be_bytes());
+    v.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    v.extend_from_slice(name);
+    match value {
+        Some(value) => {
+            v.push(1);
+            v.extend_from_slice(value);
+        }
+        None => v.push(0),
+    }
+    v
+}
+
+fn decode_change(key: &[u8], value: &[u8]) -> Result<Change, Error> {
+    if key.len() < 16 || value.len() < 13 {
+        return Err(Error::Corrupted);
+    }
+    let mut seq = [0u8; 8];
+    seq.copy_from_slice(&key[0..8]);
+    let mut time = [0u8; 8];
+    time.copy_from_slice(&value[0..8]);
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&value[8..12]);
+    let len = u32::from_be_bytes(len) as usize;
+    if value.len() < 13 + len {
+        return Err(Error::Corrupted);
+    }
+    let tab = Atom::from(String::from_utf8_lossy(&value[12..12 + len]).to_string());
+    let v = match value[12 + len] {
+        0 => None,
+        _ => Some(Arc::new(value[13 + len..].to_vec())),
+    };
+    Ok(Change {
+        seq: u64::from_be_bytes(seq),
+        time: u64::from_be_bytes(time),
+        tab,
+        key: Arc::new(key[16..].to_vec()),
+        value: v,
+    })
+}
+
+/**
+* 在读写事务中记录一条修改，为事务分配提交序号，未开启日志时只分配序号
+* @param txn 读写事务
+* @param env_id 环境id
+* @param m 修改，值为None表示删除
+*/
+pub fn record(txn: &mut RwTransaction, env_id: u64, m: &TabKV) -> Result<(), Error> {
+    if is_internal(&m.tab) {
+        return Ok(());
+    }
+    let seq = assign_seq(txn, env_id)?;
+    if !CHANGELOG_ENABLED.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    match db_of(env_id, CHANGELOG_TAB) {
+        Some(db) => txn.put(
+            db,
+            &log_key(seq, &m.tab, &m.key),
+            &encode_change(now_millis(), &m.tab, m.value.as_ref().map(|v| v.as_slice())),
+            WriteFlags::empty(),
+        ),
+        None => Ok(()),
+    }
+}
+
+/**
+* 按提交顺序读取指定序号之后的修改
+* @param txn 事务
+* @param env_id 环境id
+* @param since 起始序号，不包括该序号的提交
+* @param until 结束序号，包括该序号的提交
+*/
+pub fn changes<T: Transaction>(txn: &T, env_id: u64, since: u64, until: u64) -> Result<Vec<Change>, Error> {
+    let db = match db_of(env_id, CHANGELOG_TAB) {
+        Some(db) => db,
+        None => return Ok(Vec::new()),
+    };
+    let mut cursor = txn.open_ro_cursor(db)?;
+    let mut r = Vec::new();
+    for (k, v) in cursor.iter_from(&(since + 1).to_be_bytes()) {
+        let change = decode_change(k, v)?;
+        if change.seq > until {
+            break;
+        }
+        r.push(change);
+    }
+    Ok(r)
+}
+
+/**
+* 读取指定序号之后修改过的记录的最新值，用于增量备份
+* @returns 返回按表和键排序的修改，删除的键值为None
+*/
+pub fn changed_since<T: Transaction>(txn: &T, env_id: u64, since: u64) -> Result<Vec<Change>, Error> {
+    let mut latest = BTreeMap::new();
+    for change in changes(txn, env_id, since, u64::MAX)? {
+        latest.insert((change.tab.to_string(), change.key.clone()), change);
+    }
+    Ok(latest.into_iter().map(|(_, c)| c).collect())
+}
+
+/**
+* 删除指定序号及之前的日志，备份完成后调用以回收空间
+* @returns 返回删除的日志条数
+*/
+pub fn prune(txn: &mut RwTransaction, env_id: u64, until: u64) -> Result<usize, Error> {
+    let db = match db_of(env_id, CHANGELOG_TAB) {
+        Some(db) => db,
+        None => return Ok(0),
+    };
+    let keys = {
+        let mut cursor = txn.open_ro_cursor(db)?;
+        cursor
+            .iter_start()
+            .map(|(k, _)| k)
+            .take_while(|k| k.len() >= 8 && k[0..8] <= until.to_be_bytes()[..])
+            .map(|k| k.to_vec())
+            .collect::<Vec<Vec<u8>>>()
+    };
+    for k in keys.iter() {
+        txn.del(db, k, None)?;
+    }
+    Ok(keys.len())
+}
+
+/**
+* 读取日志中最大的提交序号，日志为空返回0
+*/
+pub fn last_logged_seq<T: Transaction>(txn: &T, env_id: u64) -> Result<u64, Error> {
+    let db = match db_of(env_id, CHANGELOG_TAB) {
+        Some(db) => db,
+        None => return Ok(0),
+    };
+    let cursor = txn.open_ro_cursor(db)?;
+    match cursor.get(None, None, ffi::MDB_LAST) {
+        Ok((Some(k), _)) if k.len() >= 8 => {
+            let mut seq = [0u8; 8];
+            seq.copy_from_slice(&k[0..8]);
+            Ok(u64::from_be_bytes(seq))
+        }
+        Ok(_) => Err(Error::Corrupted),
+        Err(Error::NotFound) => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+/**
+* 删除指定序号之后的日志，用于恢复时丢弃没有对应提交的日志
+* @returns 返回删除的日志条数
+*/
+pub fn truncate_after(txn: &mut RwTransaction, env_id: u64, seq: u64) -> Result<usize, Error> {
+    let db = match db_of(env_id, CHANGELOG_TAB) {
+        Some(db) => db,
+        None => return Ok(0),
+    };
+    let keys = {
+        let mut cursor = txn.open_ro_cursor(db)?;
+        cursor.iter_from(&(seq + 1).to_be_bytes()).map(|(k, _)| k.to_vec()).collect::<Vec<Vec<u8>>>()
+    };
+    for k in keys.iter() {
+        txn.del(db, k, None)?;
+    }
+    Ok(keys.len())
+}
+
+// 是否记录修改日志
+pub fn is_enabled() -> bool {
+    CHANGELOG_ENABLED.load(Ordering::SeqCst)
+}
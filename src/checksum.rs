@@ -0,0 +1,79 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crc32fast::Hasher;
+use lmdb::{Database, Error, RwTransaction, Transaction, WriteFlags};
+
+use atom::Atom;
+
+use crate::policy;
+use crate::pool::{lookup_db, StoreError};
+
+// 存放值校验和的影子表，每个环境一个
+pub const CHECKSUMS_TAB: &str = "_$checksums";
+
+lazy_static! {
+    // 写入时是否记录校验和
+    static ref CHECKSUMS_ENABLED: AtomicBool = AtomicBool::new(false);
+}
+
+/**
+* 设置是否为写入的值记录CRC32校验和，校验和保存在影子表中，不改变原表的数据格式
+* 查询时只要值有校验和就会校验，关闭后已记录的校验和仍然有效，设置了策略的表以策略为准
+*/
+pub fn set_value_checksums(enabled: bool) {
+    CHECKSUMS_ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+// 影子表的键: 表名哈希 + 原键
+fn shadow_key(tab: &Atom, key: &[u8]) -> Vec<u8> {
+    let mut k = Vec::with_capacity(8 + key.len());
+    k.extend_from_slice(&(tab.get_hash() as u64).to_be_bytes());
+    k.extend_from_slice(key);
+    k
+}
+
+fn checksums_db(env_id: u64) -> Option<Database> {
+    lookup_db(env_id, &Atom::from(CHECKSUMS_TAB))
+}
+
+fn crc32(value: &[u8]) -> [u8; 4] {
+    let mut h = Hasher::new();
+    h.update(value);
+    h.finalize().to_be_bytes()
+}
+
+// 记录值的校验和，未启用时删除旧的校验和，避免覆盖后的值校验失败
+pub fn put(txn: &mut RwTransaction, env_id: u64, tab: &Atom, key: &[u8], value: &[u8]) -> Result<(), Error> {
+    if !policy::checksum(tab).unwrap_or_else(|| CHECKSUMS_ENABLED.load(Ordering::SeqCst)) {
+        return del(txn, env_id, tab, key);
+    }
+    match checksums_db(env_id) {
+        Some(db) => txn.put(db, &shadow_key(tab, key), &crc32(value), WriteFlags::empty()),
+        None => Ok(()),
+    }
+}
+
+// 删除值的校验和
+pub fn del(txn: &mut RwTransaction, env_id: u64, tab: &Atom, key: &[u8]) -> Result<(), Error> {
+    match checksums_db(env_id) {
+        Some(db) => match txn.del(db, &shadow_key(tab, key), None) {
+            Ok(_) | Err(Error::NotFound) => Ok(()),
+            Err(e) => Err(e),
+        },
+        None => Ok(()),
+    }
+}
+
+// 校验读到的值，没有校验和的值视为正确
+pub fn verify<T: Transaction>(txn: &T, env_id: u64, tab: &Atom, key: &[u8], value: &[u8]) -> Result<(), StoreError> {
+    let db = match checksums_db(env_id) {
+        Some(db) => db,
+        None => return Ok(()),
+    };
+    match txn.get(db, &shadow_key(tab, key)) {
+        Ok(sum) if sum == &crc32(value)[..] => Ok(()),
+        Ok(_) => Err(StoreError::ChecksumMismatch),
+        Err(Error::NotFound) => Ok(()),
+        Err(e) => Err(StoreError::Internal(e.to_string())),
+    }
+}
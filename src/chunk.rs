@@ -0,0 +1,193 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use lmdb::{Database, Error, RwTransaction, Transaction, WriteFlags};
+
+use atom::Atom;
+
+use crate::blob::{self, BlobId, BLOBS_TAB};
+use crate::buffer_pool;
+use crate::dict;
+use crate::pool::lookup_db;
+use crate::schema::META_TAB;
+
+// 存放大值分块的表，每个环境一个
+pub const CHUNKS_TAB: &str = "_$chunks";
+// 分块清单的魔数
+const MANIFEST_MAGIC: &[u8] = b"\0pi_store_chunks";
+// 分块清单长度: 魔数 + 总长度(8字节) + 分块数(4字节)
+const MANIFEST_LEN: usize = 16 + 8 + 4;
+// 去重引用的魔数
+const BLOB_REF_MAGIC: &[u8] = b"\0pi_store_dedup\0";
+// 去重引用长度: 魔数 + 附件地址(16字节)
+const BLOB_REF_LEN: usize = 16 + 16;
+// 默认分块大小
+const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+lazy_static! {
+    // 超过该大小的值分块存储，为0时不分块
+    static ref CHUNK_THRESHOLD: AtomicUsize = AtomicUsize::new(0);
+    static ref CHUNK_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_CHUNK_SIZE);
+}
+
+/**
+* 设置大值分块存储，超过阈值的值被切分成固定大小的块写入分块表，原键只保存分块清单
+* 读取时自动重组，已分块的值不受阈值修改的影响
+* @param threshold 分块阈值，为0则关闭
+* @param chunk_size 分块大小，为0则使用默认的1M
+*/
+pub fn set_chunking(threshold: usize, chunk_size: usize) {
+    CHUNK_THRESHOLD.store(threshold, Ordering::SeqCst);
+    CHUNK_SIZE.store(if chunk_size == 0 { DEFAULT_CHUNK_SIZE } else { chunk_size }, Ordering::SeqCst);
+}
+
+// 分块的键: 表名哈希 + 原键 + 分块序号
+fn chunk_key(tab: &Atom, key: &[u8], index: u32) -> Vec<u8> {
+    let mut k = Vec::with_capacity(8 + key.len() + 4);
+    k.extend_from_slice(&(tab.get_hash() as u64).to_be_bytes());
+    k.extend_from_slice(key);
+    k.extend_from_slice(&index.to_be_bytes());
+    k
+}
+
+fn chunks_db(env_id: u64) -> Option<Database> {
+    lookup_db(env_id, &Atom::from(CHUNKS_TAB))
+}
+
+// 解析分块清单，返回总长度和分块数
+fn manifest(value: &[u8]) -> Option<(usize, u32)> {
+    if value.len() != MANIFEST_LEN || !value.starts_with(MANIFEST_MAGIC) {
+        return None;
+    }
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&value[16..24]);
+    let mut count = [0u8; 4];
+    count.copy_from_slice(&value[24..28]);
+    Some((u64::from_be_bytes(len) as usize, u32::from_be_bytes(count)))
+}
+
+// 解析去重引用，返回附件的地址
+fn blob_ref(value: &[u8]) -> Option<BlobId> {
+    if value.len() != BLOB_REF_LEN || !value.starts_with(BLOB_REF_MAGIC) {
+        return None;
+    }
+    let mut id = [0u8; 16];
+    id.copy_from_slice(&value[16..]);
+    Some(BlobId(id))
+}
+
+// 值是否是分块清单、去重引用或字典压缩的值，这样的值需要重组、取回或解压后才能读取
+pub fn is_chunked(value: &[u8]) -> bool {
+    manifest(value).is_some() || blob_ref(value).is_some() || dict::is_compressed(value)
+}
+
+// 删除键原有的分块，原有的值是去重引用则减少附件的引用
+fn remove_chunks(txn: &mut RwTransaction, env_id: u64, db: Database, tab: &Atom, key: &[u8]) -> Result<(), Error> {
+    let count = match txn.get(db, &key) {
+        Ok(v) => match (manifest(v), blob_ref(v)) {
+            (Some((_, count)), _) => count,
+            (None, Some(id)) => return blob::decref(txn, env_id, &id),
+            (None, None) => return Ok(()),
+        },
+        Err(Error::NotFound) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let chunks = chunks_db(env_id).ok_or(Error::NotFound)?;
+    for i in 0..count {
+        match txn.del(chunks, &chunk_key(tab, key, i), None) {
+            Ok(_) | Err(Error::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// 写入值，超过阈值的值分块写入
+pub fn put(txn: &mut RwTransaction, env_id: u64, db: Database, tab: &Atom, key: &[u8], value: &[u8]) -> Result<(), Error> {
+    put_with_flags(txn, env_id, db, tab, key, value, WriteFlags::empty())
+}
+
+// 按写入标志写入值，标志作用于原键，分块清单与普通值使用相同的标志
+pub fn put_with_flags(txn: &mut RwTransaction, env_id: u64, db: Database, tab: &Atom, key: &[u8], value: &[u8], flags: WriteFlags) -> Result<(), Error> {
+    // 附件表和保留表的内容直接读取，按原样保存，不分块、去重或压缩
+    if tab.as_str() == BLOBS_TAB || tab.as_str() == META_TAB {
+        return txn.put(db, &key, &value, flags);
+    }
+    if !flags.contains(WriteFlags::NO_OVERWRITE) {
+        remove_chunks(txn, env_id, db, tab, key)?;
+    }
+
+    // 去重优先于分块，附件表中的内容不分块
+    if blob::should_dedup(value) {
+        if let Some(id) = blob::incref(txn, env_id, value)? {
+            let mut r = Vec::with_capacity(BLOB_REF_LEN);
+            r.extend_from_slice(BLOB_REF_MAGIC);
+            r.extend_from_slice(&id.0);
+            return match txn.put(db, &key, &r, flags) {
+                // 以NO_OVERWRITE写入已存在的键，撤销增加的引用
+                Err(Error::KeyExist) => blob::decref(txn, env_id, &id).and(Err(Error::KeyExist)),
+                r => r,
+            };
+        }
+    }
+
+    // 表有字典时小值用字典压缩，小值不会分块
+    if let Some(packed) = dict::compress(txn, env_id, tab, value)? {
+        return txn.put(db, &key, &packed, flags);
+    }
+
+    let threshold = CHUNK_THRESHOLD.load(Ordering::SeqCst);
+    let chunks = match chunks_db(env_id) {
+        Some(chunks) if threshold > 0 && value.len() > threshold => chunks,
+        _ => return txn.put(db, &key, &value, flags),
+    };
+
+    let size = CHUNK_SIZE.load(Ordering::SeqCst);
+    let mut count = 0u32;
+    for chunk in value.chunks(size) {
+        txn.put(chunks, &chunk_key(tab, key, count), &chunk, WriteFlags::empty())?;
+        count += 1;
+    }
+
+    let mut m = Vec::with_capacity(MANIFEST_LEN);
+    m.extend_from_slice(MANIFEST_MAGIC);
+    m.extend_from_slice(&(value.len() as u64).to_be_bytes());
+    m.extend_from_slice(&count.to_be_bytes());
+    txn.put(db, &key, &m, flags)
+}
+
+// 按键的顺序追加写入新键，键必须大于表中已有的键，需要分块的值按普通写入处理
+pub fn append(txn: &mut RwTransaction, env_id: u64, db: Database, tab: &Atom, key: &[u8], value: &[u8]) -> Result<(), Error> {
+    let threshold = CHUNK_THRESHOLD.load(Ordering::SeqCst);
+    if threshold > 0 && value.len() > threshold || blob::should_dedup(value) {
+        return put(txn, env_id, db, tab, key, value);
+    }
+    match dict::compress(txn, env_id, tab, value)? {
+        Some(packed) => txn.put(db, &key, &packed, WriteFlags::APPEND),
+        None => txn.put(db, &key, &value, WriteFlags::APPEND),
+    }
+}
+
+// 删除值及其分块
+pub fn del(txn: &mut RwTransaction, env_id: u64, db: Database, tab: &Atom, key: &[u8]) -> Result<(), Error> {
+    remove_chunks(txn, env_id, db, tab, key)?;
+    txn.del(db, &key, None)
+}
+
+// 读取值，分块存储的值在同一个事务中重组，去重存储的值从附件表中取回
+pub fn read<T: Transaction>(txn: &T, env_id: u64, tab: &Atom, key: &[u8], value: &[u8]) -> Result<Vec<u8>, Error> {
+    let (len, count) = match (manifest(value), blob_ref(value)) {
+        (Some(m), _) => m,
+        (None, Some(id)) => return blob::read(txn, env_id, &id),
+        (None, None) if dict::is_compressed(value) => return dict::decompress(txn, env_id, tab, value),
+        (None, None) => return Ok(buffer_pool::copy(value)),
+    };
+    let chunks = chunks_db(env_id).ok_or(Error::NotFound)?;
+    let mut v = Vec::with_capacity(len);
+    for i in 0..count {
+        v.extend_from_slice(txn.get(chunks, &chunk_key(tab, key, i))?);
+    }
+    if v.len() != len {
+        return Err(Error::Corrupted);
+    }
+    Ok(v)
+}
@@ -0,0 +1,69 @@
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+
+use lmdb::{Environment, Error};
+use lmdb_sys as ffi;
+
+// LMDB的数据文件和锁文件
+const DATA_FILE: &str = "data.mdb";
+const LOCK_FILE: &str = "lock.mdb";
+// 等待替换的标记文件，内容为压缩副本所在的目录
+const PENDING_FILE: &str = "compact.pending";
+
+/**
+* 用MDB_CP_COMPACT复制环境，跳过空闲页并重新排列，得到去碎片的数据文件
+* 复制期间只持有一个读事务，不阻塞写线程
+* @param env LMDB环境
+* @param dest 副本所在的目录，必须存在且为空
+*/
+pub fn copy_compact(env: &Environment, dest: &str) -> Result<(), String> {
+    let path = CString::new(dest).map_err(|e| e.to_string())?;
+    match unsafe { ffi::mdb_env_copy2(env.env(), path.as_ptr(), ffi::MDB_CP_COMPACT) } {
+        ffi::MDB_SUCCESS => Ok(()),
+        code => Err(Error::from_err_code(code).to_string()),
+    }
+}
+
+/**
+* 记录压缩副本，下次打开库时用副本替换库的数据文件
+* @param db_path 库所在的目录
+* @param dest 压缩副本所在的目录
+*/
+pub fn mark_swap(db_path: &str, dest: &str) -> Result<(), String> {
+    fs::write(Path::new(db_path).join(PENDING_FILE), dest).map_err(|e| e.to_string())
+}
+
+/**
+* 打开环境前调用，存在等待替换的压缩副本时替换数据文件
+* 只有库已正常关闭时才能调用，此时没有其它进程或线程打开该环境
+* @param db_path 库所在的目录
+* @returns 替换了数据文件返回true
+*/
+pub fn swap_pending(db_path: &str) -> Result<bool, String> {
+    let pending = Path::new(db_path).join(PENDING_FILE);
+    if !pending.exists() {
+        return Ok(false);
+    }
+    let dest = fs::read_to_string(&pending).map_err(|e| e.to_string())?;
+    let copy = Path::new(dest.trim()).join(DATA_FILE);
+    if !copy.exists() {
+        // 副本已丢失，放弃替换
+        warn!("compacted copy not found: {:?}, swap cancelled", copy);
+        fs::remove_file(&pending).map_err(|e| e.to_string())?;
+        return Ok(false);
+    }
+
+    // 同一文件系统内rename是原子的，跨文件系统时先复制到库目录再rename
+    let target = Path::new(db_path).join(DATA_FILE);
+    if fs::rename(&copy, &target).is_err() {
+        let tmp = Path::new(db_path).join("data.mdb.compact");
+        fs::copy(&copy, &tmp).map_err(|e| e.to_string())?;
+        fs::rename(&tmp, &target).map_err(|e| e.to_string())?;
+        let _ = fs::remove_file(&copy);
+    }
+    // 旧的锁文件记录的是旧数据文件的读槽
+    let _ = fs::remove_file(Path::new(db_path).join(LOCK_FILE));
+    fs::remove_file(&pending).map_err(|e| e.to_string())?;
+    Ok(true)
+}
@@ -0,0 +1,266 @@
+use std::io::{BufRead, Write};
+use std::sync::Arc;
+
+use lmdb::Environment;
+
+use pi_db::db::Bin;
+
+use atom::Atom;
+
+use crate::snapshot::{self, Frame};
+
+const BASE64_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/**
+* 文本格式，字段含分隔符、引号或换行时用双引号包围，字段中的双引号写两次
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,    //逗号分隔
+    Tsv,    //制表符分隔
+}
+
+impl Format {
+    fn delimiter(&self) -> u8 {
+        match self {
+            Format::Csv => b',',
+            Format::Tsv => b'\t',
+        }
+    }
+}
+
+/**
+* 键或值在文本中的编码
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Hex,    //小写十六进制
+    Base64, //标准base64，带填充
+    Utf8,   //原样输出，不是合法UTF-8的键值导出失败
+}
+
+impl Encoding {
+    fn encode(&self, bytes: &[u8]) -> Result<String, String> {
+        match self {
+            Encoding::Hex => Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect()),
+            Encoding::Base64 => Ok(base64_encode(bytes)),
+            Encoding::Utf8 => String::from_utf8(bytes.to_vec()).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn decode(&self, text: &str) -> Result<Vec<u8>, String> {
+        match self {
+            Encoding::Hex => {
+                if text.len() % 2 != 0 {
+                    return Err(format!("invalid hex: {:?}", text));
+                }
+                (0..text.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| format!("invalid hex: {:?}", text)))
+                    .collect()
+            }
+            Encoding::Base64 => base64_decode(text),
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+        }
+    }
+}
+
+/**
+* CSV导出和导入的选项
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CsvOptions {
+    pub format: Format,     //分隔格式
+    pub key: Encoding,      //键的编码
+    pub value: Encoding,    //值的编码
+    pub header: bool,       //第一行是否为列名key和value
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            format: Format::Csv,
+            key: Encoding::Hex,
+            value: Encoding::Base64,
+            header: true,
+        }
+    }
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_CHARS[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim_end_matches('=');
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let v = BASE64_CHARS.iter().position(|b| *b == c).ok_or_else(|| format!("invalid base64: {:?}", text))? as u32;
+        acc = acc << 6 | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Ok(out)
+}
+
+// 按格式写出一个字段，需要时加引号
+fn write_field(out: &mut Vec<u8>, field: &str, delimiter: u8) {
+    if field.bytes().any(|b| b == delimiter || b == b'"' || b == b'\n' || b == b'\r') {
+        out.push(b'"');
+        out.extend_from_slice(field.replace('"', "\"\"").as_bytes());
+        out.push(b'"');
+    } else {
+        out.extend_from_slice(field.as_bytes());
+    }
+}
+
+fn write_row(out: &mut Vec<u8>, key: &str, value: &str, delimiter: u8) {
+    write_field(out, key, delimiter);
+    out.push(delimiter);
+    write_field(out, value, delimiter);
+    out.push(b'\n');
+}
+
+/**
+* 导出表的一致性快照为CSV或TSV，每行一条记录，第一列为键，第二列为值
+* @param env LMDB环境
+* @param env_id 环境id
+* @param tab 表名
+* @param out 输出
+* @param opts 格式和编码
+* @returns 返回导出的记录数
+*/
+pub fn export<W: Write>(env: &Environment, env_id: u64, tab: &Atom, out: &mut W, opts: &CsvOptions) -> Result<u64, String> {
+    let delimiter = opts.format.delimiter();
+    let mut row = Vec::new();
+    if opts.header {
+        write_row(&mut row, "key", "value", delimiter);
+        out.write_all(&row).map_err(|e| e.to_string())?;
+    }
+    let count = snapshot::frames(env, env_id, tab, |frame| {
+        if let Frame::Record(k, v) = frame {
+            let key = opts.key.encode(&k).map_err(|e| format!("encode key: {:?} failed, {}", k, e))?;
+            let value = opts.value.encode(&v).map_err(|e| format!("encode value of key: {:?} failed, {}", k, e))?;
+            row.clear();
+            write_row(&mut row, &key, &value, delimiter);
+            out.write_all(&row).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    })?;
+    out.flush().map_err(|e| e.to_string())?;
+    Ok(count)
+}
+
+// 读取一行记录的所有字段，引号中的字段可以跨行，输入已结束返回None
+fn read_row<R: BufRead>(input: &mut R, delimiter: u8) -> Result<Option<Vec<String>>, String> {
+    let mut line = Vec::new();
+    if input.read_until(b'\n', &mut line).map_err(|e| e.to_string())? == 0 {
+        return Ok(None);
+    }
+    let mut fields = Vec::new();
+    let mut field = Vec::new();
+    let mut quoted = false;
+    let mut pos = 0;
+    loop {
+        if pos == line.len() {
+            if !quoted {
+                break;
+            }
+            // 引号中的换行，继续读取下一行
+            if input.read_until(b'\n', &mut line).map_err(|e| e.to_string())? == 0 {
+                return Err("unterminated quoted field".to_string());
+            }
+            continue;
+        }
+        let b = line[pos];
+        pos += 1;
+        if quoted {
+            if b == b'"' {
+                if line.get(pos) == Some(&b'"') {
+                    field.push(b'"');
+                    pos += 1;
+                } else {
+                    quoted = false;
+                }
+            } else {
+                field.push(b);
+            }
+        } else if b == b'"' && field.is_empty() {
+            quoted = true;
+        } else if b == delimiter {
+            fields.push(String::from_utf8(field.split_off(0)).map_err(|e| e.to_string())?);
+        } else if b != b'\n' && b != b'\r' {
+            field.push(b);
+        }
+    }
+    fields.push(String::from_utf8(field).map_err(|e| e.to_string())?);
+    Ok(Some(fields))
+}
+
+/**
+* 将CSV或TSV的行转换为快照帧，以尾帧结束，用于按快照导入，见import.rs
+* 空行被忽略，每行必须恰好两列
+* @param input 输入
+* @param opts 格式和编码，与导出时相同
+*/
+pub fn frames<'a, R: BufRead>(input: &'a mut R, opts: CsvOptions) -> impl Iterator<Item = Result<Frame, String>> + 'a {
+    let delimiter = opts.format.delimiter();
+    let mut line = 0u64;
+    let mut count = 0u64;
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        loop {
+            line += 1;
+            let fields = match read_row(input, delimiter) {
+                Ok(Some(fields)) => fields,
+                Ok(None) => {
+                    done = true;
+                    return Some(Ok(Frame::End(count)));
+                }
+                Err(e) => {
+                    done = true;
+                    return Some(Err(format!("row {}: {}", line, e)));
+                }
+            };
+            if line == 1 && opts.header || fields.len() == 1 && fields[0].is_empty() {
+                continue;
+            }
+            let r = if fields.len() != 2 {
+                Err(format!("expect 2 fields, found {}", fields.len()))
+            } else {
+                opts.key.decode(&fields[0]).and_then(|k| opts.value.decode(&fields[1]).map(|v| (k, v)))
+            };
+            return Some(match r {
+                Ok((k, v)) => {
+                    count += 1;
+                    Ok(Frame::Record(Arc::new(k) as Bin, Arc::new(v)))
+                }
+                Err(e) => {
+                    done = true;
+                    Err(format!("row {}: {}", line, e))
+                }
+            });
+        }
+    })
+}
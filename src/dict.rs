@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+
+use lmdb::{Cursor, Database, Environment, Error, Transaction};
+use zstd::block::{Compressor, Decompressor};
+
+use atom::Atom;
+
+use crate::chunk;
+use crate::lmdb_file::with_txn;
+use crate::policy;
+use crate::pool::lookup_db;
+use crate::schema::META_TAB;
+
+// 字典压缩的值的魔数
+const DICT_MAGIC: &[u8] = b"\0pi_store_zdict\0";
+// 字典压缩的值头部长度: 魔数 + 字典id(4字节) + 原始长度(4字节)
+const DICT_HEADER_LEN: usize = 16 + 4 + 4;
+// 字典在保留表中的键前缀，键为前缀 + 表名哈希(8字节) + 字典id(4字节)，字典id为0的键保存当前字典的id
+const DICT_KEY_PREFIX: &[u8] = b"__dict\0";
+// 默认只压缩不大于该大小的值
+const DEFAULT_MAX_VALUE: usize = 1024;
+// 默认的压缩级别
+const DEFAULT_LEVEL: i32 = 3;
+
+// 表的字典，旧字典一直保留，用旧字典压缩的值仍然可以读取
+#[derive(Default)]
+struct TabDicts {
+    current: Option<u32>,               //写入时使用的字典
+    dicts: HashMap<u32, Arc<Vec<u8>>>,  //已载入的字典
+}
+
+lazy_static! {
+    // 已载入的表字典，键为环境id和表名哈希，未训练过字典的表也缓存，避免每次写入都查询保留表
+    static ref DICTS: RwLock<HashMap<(u64, u64), TabDicts>> = RwLock::new(HashMap::new());
+    static ref MAX_VALUE: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_VALUE);
+    static ref LEVEL: AtomicI32 = AtomicI32::new(DEFAULT_LEVEL);
+}
+
+/**
+* 设置字典压缩，只对已训练字典的表生效
+* @param max_value 只压缩不大于该大小的值，大值单独压缩的效果已经足够，为0则使用默认的1K
+* @param level zstd压缩级别，为0则使用默认级别
+*/
+pub fn set_dict_compression(max_value: usize, level: i32) {
+    MAX_VALUE.store(if max_value == 0 { DEFAULT_MAX_VALUE } else { max_value }, Ordering::SeqCst);
+    LEVEL.store(if level == 0 { DEFAULT_LEVEL } else { level }, Ordering::SeqCst);
+}
+
+fn dict_key(tab: u64, id: u32) -> Vec<u8> {
+    let mut k = Vec::with_capacity(DICT_KEY_PREFIX.len() + 12);
+    k.extend_from_slice(DICT_KEY_PREFIX);
+    k.extend_from_slice(&tab.to_be_bytes());
+    k.extend_from_slice(&id.to_be_bytes());
+    k
+}
+
+fn meta_db(env_id: u64) -> Option<Database> {
+    lookup_db(env_id, &Atom::from(META_TAB))
+}
+
+fn u32_of(value: &[u8]) -> Result<u32, Error> {
+    if value.len() != 4 {
+        return Err(Error::Corrupted);
+    }
+    let mut b = [0u8; 4];
+    b.copy_from_slice(value);
+    Ok(u32::from_be_bytes(b))
+}
+
+// 在事务中读取表的当前字典id
+fn current_id<T: Transaction>(txn: &T, db: Database, tab: u64) -> Result<Option<u32>, Error> {
+    match txn.get(db, &dict_key(tab, 0)) {
+        Ok(v) => u32_of(v).map(Some),
+        Err(Error::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// 取得表的字典，未载入则在事务中读取，id为None时取当前字典
+fn load<T: Transaction>(txn: &T, env_id: u64, tab: u64, id: Option<u32>) -> Result<Option<(u32, Arc<Vec<u8>>)>, Error> {
+    if let Some(dicts) = DICTS.read().unwrap().get(&(env_id, tab)) {
+        match id.or(dicts.current) {
+            Some(id) => {
+                if let Some(dict) = dicts.dicts.get(&id) {
+                    return Ok(Some((id, dict.clone())));
+                }
+            }
+            None => return Ok(None),
+        }
+    }
+
+    let db = match meta_db(env_id) {
+        Some(db) => db,
+        None => return Ok(None),
+    };
+    let current = current_id(txn, db, tab)?;
+    let id = match id.or(current) {
+        Some(id) => id,
+        None => {
+            DICTS.write().unwrap().entry((env_id, tab)).or_insert_with(TabDicts::default);
+            return Ok(None);
+        }
+    };
+    let dict = Arc::new(txn.get(db, &dict_key(tab, id))?.to_vec());
+    let mut all = DICTS.write().unwrap();
+    let dicts = all.entry((env_id, tab)).or_insert_with(TabDicts::default);
+    dicts.current = current;
+    dicts.dicts.insert(id, dict.clone());
+    Ok(Some((id, dict)))
+}
+
+/**
+* 用表的当前字典压缩小值，写入时在读写事务中调用
+* @returns 返回压缩后的值，表没有字典、策略关闭了压缩、值太大或压缩后没有变小返回None
+*/
+pub fn compress<T: Transaction>(txn: &T, env_id: u64, tab: &Atom, value: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    if value.is_empty() || value.len() > MAX_VALUE.load(Ordering::SeqCst) || !policy::compression(tab) {
+        return Ok(None);
+    }
+    let (id, dict) = match load(txn, env_id, tab.get_hash() as u64, None)? {
+        Some(d) => d,
+        None => return Ok(None),
+    };
+    let packed = Compressor::with_dict(dict.to_vec())
+        .compress(value, LEVEL.load(Ordering::SeqCst))
+        .map_err(|_| Error::Invalid)?;
+    if DICT_HEADER_LEN + packed.len() >= value.len() {
+        return Ok(None);
+    }
+    let mut v = Vec::with_capacity(DICT_HEADER_LEN + packed.len());
+    v.extend_from_slice(DICT_MAGIC);
+    v.extend_from_slice(&id.to_be_bytes());
+    v.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    v.extend_from_slice(&packed);
+    Ok(Some(v))
+}
+
+// 值是否是字典压缩的值
+pub fn is_compressed(value: &[u8]) -> bool {
+    value.len() > DICT_HEADER_LEN && value.starts_with(DICT_MAGIC)
+}
+
+/**
+* 解压字典压缩的值，使用压缩时的字典
+*/
+pub fn decompress<T: Transaction>(txn: &T, env_id: u64, tab: &Atom, value: &[u8]) -> Result<Vec<u8>, Error> {
+    let id = u32_of(&value[16..20])?;
+    let len = u32_of(&value[20..24])? as usize;
+    let (_, dict) = load(txn, env_id, tab.get_hash() as u64, Some(id))?.ok_or(Error::Corrupted)?;
+    Decompressor::with_dict(dict.to_vec())
+        .decompress(&value[DICT_HEADER_LEN..], len)
+        .map_err(|_| Error::Corrupted)
+}
+
+/**
+* 从表中采样用于训练字典的小值，按键的顺序取前count个不大于压缩上限的值
+* @param env LMDB环境
+* @param env_id 环境id
+* @param tab 表名
+* @param count 采样数
+*/
+pub fn sample(env: &Environment, env_id: u64, tab: &Atom, count: usize) -> Result<Vec<Vec<u8>>, String> {
+    let db = lookup_db(env_id, tab).ok_or_else(|| format!("tab not opened: {:?}", tab.to_string()))?;
+    let max_value = MAX_VALUE.load(Ordering::SeqCst);
+    let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+    let mut samples = Vec::with_capacity(count);
+    {
+        let mut cursor = txn.open_ro_cursor(db).map_err(|e| e.to_string())?;
+        for (k, v) in cursor.iter_start() {
+            if samples.len() >= count {
+                break;
+            }
+            let v = chunk::read(&txn, env_id, tab, k, v).map_err(|e| e.to_string())?;
+            if !v.is_empty() && v.len() <= max_value {
+                samples.push(v);
+            }
+        }
+    }
+    let _ = txn.commit();
+    Ok(samples)
+}
+
+/**
+* 从表中采样值训练zstd字典，保存到保留表并用于之后写入的小值，已写入的值不会重新压缩
+* 每次训练生成新的字典id，旧字典保留用于读取旧值
+* @param samples 采样的值，应来自同一个表
+* @param tab 表名
+* @param max_dict_size 字典的最大字节数
+* @returns 返回新字典的id
+*/
+pub fn train(tab: &Atom, samples: &[Vec<u8>], max_dict_size: usize) -> Result<u32, String> {
+    if samples.is_empty() {
+        return Err(format!("no samples to train dict for tab: {:?}", tab.to_string()));
+    }
+    let dict = Arc::new(zstd::dict::from_samples(samples, max_dict_size).map_err(|e| e.to_string())?);
+    let meta = Atom::from(META_TAB);
+    let tab_hash = tab.get_hash() as u64;
+    let dict1 = dict.clone();
+    let (env_id, id) = with_txn(tab, move |ops| {
+        let id = match ops.get(&meta, &dict_key(tab_hash, 0))? {
+            Some(v) => u32_of(&v).map_err(|e| e.to_string())? + 1,
+            None => 1,
+        };
+        ops.put(&meta, &dict_key(tab_hash, id), &dict1)?;
+        ops.put(&meta, &dict_key(tab_hash, 0), &id.to_be_bytes())?;
+        Ok((ops.env_id(), id))
+    })?;
+
+    // 提交后才切换当前字典
+    let mut all = DICTS.write().unwrap();
+    let dicts = all.entry((env_id, tab_hash)).or_insert_with(TabDicts::default);
+    dicts.current = Some(id);
+    dicts.dicts.insert(id, dict);
+    info!("tab: {:?} trained dict: {}, samples: {}", tab.to_string(), id, samples.len());
+    Ok(id)
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use lmdb::Environment;
+
+use pi_db::db::Bin;
+
+use atom::Atom;
+
+use crate::snapshot::{self, Frame};
+
+/**
+* 两个快照之间一个键的差异
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffEntry {
+    Added(Bin, Bin),            //只在新快照中存在的键和值
+    Removed(Bin, Bin),          //只在旧快照中存在的键和值
+    Changed(Bin, Bin, Bin),     //键、旧值和新值
+}
+
+/**
+* 差异统计
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DiffSummary {
+    pub added: u64,
+    pub removed: u64,
+    pub changed: u64,
+    pub unchanged: u64,
+}
+
+impl DiffSummary {
+    // 两边是否完全一致
+    pub fn is_same(&self) -> bool {
+        self.added == 0 && self.removed == 0 && self.changed == 0
+    }
+}
+
+// 对比的进行状态，旧快照全部载入内存，新快照逐条对比
+struct Differ<F: FnMut(DiffEntry)> {
+    old: BTreeMap<Bin, Bin>,
+    summary: DiffSummary,
+    emit: F,
+}
+
+impl<F: FnMut(DiffEntry)> Differ<F> {
+    fn new<R: Read>(old: &mut R, emit: F) -> Result<Self, String> {
+        let mut records = BTreeMap::new();
+        snapshot::read_snapshot(old, |k, v| {
+            records.insert(k, v);
+            Ok(())
+        })?;
+        Ok(Differ {
+            old: records,
+            summary: DiffSummary::default(),
+            emit,
+        })
+    }
+
+    fn next(&mut self, key: Bin, value: Bin) {
+        match self.old.remove(&key) {
+            Some(old) if old == value => self.summary.unchanged += 1,
+            Some(old) => {
+                self.summary.changed += 1;
+                (self.emit)(DiffEntry::Changed(key, old, value));
+            }
+            None => {
+                self.summary.added += 1;
+                (self.emit)(DiffEntry::Added(key, value));
+            }
+        }
+    }
+
+    // 剩下的键只在旧快照中存在
+    fn finish(mut self) -> DiffSummary {
+        for (key, value) in self.old {
+            self.summary.removed += 1;
+            (self.emit)(DiffEntry::Removed(key, value));
+        }
+        self.summary
+    }
+}
+
+/**
+* 对比两个快照，用于校验迁移或复制的结果，旧快照全部载入内存，新快照逐条读取
+* 新增和修改的键按新快照中的顺序给出，删除的键在最后按键的字节序给出
+* @param old 旧快照，快照文件或快照流
+* @param new 新快照
+* @param emit 每个有差异的键调用一次
+* @returns 返回差异统计
+*/
+pub fn diff_snapshots<A: Read, B: Read, F: FnMut(DiffEntry)>(old: &mut A, new: &mut B, emit: F) -> Result<DiffSummary, String> {
+    let mut differ = Differ::new(old, emit)?;
+    snapshot::read_snapshot(new, |k, v| {
+        differ.next(k, v);
+        Ok(())
+    })?;
+    Ok(differ.finish())
+}
+
+/**
+* 对比快照和表的当前内容，表在一个读事务中读取
+* @param old 旧快照
+* @param env LMDB环境
+* @param env_id 环境id
+* @param tab 表名
+* @param emit 每个有差异的键调用一次
+* @returns 返回差异统计
+*/
+pub fn diff_table<R: Read, F: FnMut(DiffEntry)>(old: &mut R, env: &Environment, env_id: u64, tab: &Atom, emit: F) -> Result<DiffSummary, String> {
+    let mut differ = Differ::new(old, emit)?;
+    snapshot::frames(env, env_id, tab, |frame| {
+        if let Frame::Record(k, v) = frame {
+            differ.next(k, v);
+        }
+        Ok(())
+    })?;
+    Ok(differ.finish())
+}
@@ -0,0 +1,75 @@
+use std::sync::Arc;
+
+use lmdb::{Cursor, Database, DatabaseFlags, Error, Transaction};
+use lmdb_sys as ffi;
+
+use pi_db::db::Bin;
+
+use atom::Atom;
+
+use crate::table_meta;
+
+const MDB_SET: u32 = 15;
+const MDB_GET_MULTIPLE: u32 = 5;
+const MDB_NEXT_MULTIPLE: u32 = 9;
+
+/**
+* 将表设置为定长重复值的表，一个键对应多个等长的值，如索引表中键到多个id的映射
+* 必须在表创建前设置，表中所有值的长度必须相同；同一个键写入不同的值会追加，删除键会删除所有值
+* @param tab 表名
+*/
+pub fn set_dup_fixed(tab: &Atom) {
+    table_meta::set_db_flags(tab, table_meta::db_flags(tab) | DatabaseFlags::DUP_SORT | DatabaseFlags::DUP_FIXED);
+}
+
+/**
+* 读取键的所有值，每次游标调用取回一整页的值，值按顺序返回
+* @param txn 事务
+* @param db 定长重复值的表
+* @param key 键
+* @param width 值的长度
+* @returns 返回键的所有值，键不存在返回空
+*/
+pub fn get_all<T: Transaction>(txn: &T, db: Database, key: &[u8], width: usize) -> Result<Vec<Bin>, Error> {
+    if width == 0 {
+        return Err(Error::BadValSize);
+    }
+    let mut values = Vec::new();
+    let mut cursor = txn.open_ro_cursor(db)?;
+    match cursor.get(Some(key), None, MDB_SET) {
+        Ok(_) => (),
+        Err(Error::NotFound) => return Ok(values),
+        Err(e) => return Err(e),
+    }
+    let mut r = cursor.get(None, None, MDB_GET_MULTIPLE);
+    loop {
+        match r {
+            Ok((_, page)) => {
+                if page.len() % width != 0 {
+                    return Err(Error::BadValSize);
+                }
+                values.extend(page.chunks(width).map(|v| Arc::new(v.to_vec())));
+            }
+            Err(Error::NotFound) => return Ok(values),
+            Err(e) => return Err(e),
+        }
+        // NEXT_MULTIPLE只在当前键的重复值中移动，没有更多的值时返回NotFound
+        r = cursor.get(None, None, MDB_NEXT_MULTIPLE);
+    }
+}
+
+// 键的值的数量
+pub fn count<T: Transaction>(txn: &T, db: Database, key: &[u8]) -> Result<usize, Error> {
+    let cursor = txn.open_ro_cursor(db)?;
+    match cursor.get(Some(key), None, MDB_SET) {
+        Ok(_) => {
+            let mut n: usize = 0;
+            match unsafe { ffi::mdb_cursor_count(cursor.cursor(), &mut n) } {
+                ffi::MDB_SUCCESS => Ok(n),
+                code => Err(Error::from_err_code(code)),
+            }
+        }
+        Err(Error::NotFound) => Ok(0),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,153 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lmdb::{Environment, EnvironmentFlags};
+
+use crate::blob::BLOBS_TAB;
+use crate::changelog::CHANGELOG_TAB;
+use crate::checksum::CHECKSUMS_TAB;
+use crate::chunk::CHUNKS_TAB;
+use crate::policy::EXPIRES_TAB;
+use crate::schema::META_TAB;
+use crate::table_meta::SINFO;
+use crate::versions::VERSIONS_TAB;
+
+// 默认的最大表数量
+pub const DEFAULT_MAX_DBS: u32 = 1024;
+// 默认的最大读事务数量，与LMDB的默认值相同
+pub const DEFAULT_MAX_READERS: u32 = 126;
+// 默认的数据文件权限
+pub const DEFAULT_FILE_MODE: u32 = 0o644;
+// 数据库文件的最小大小
+const MIN_MAP_SIZE: usize = 1024 * 1024;
+// 库内部使用的保留表，每个环境打开时创建
+pub const RESERVED_TABS: &[&str] = &[SINFO, META_TAB, CHUNKS_TAB, BLOBS_TAB, CHECKSUMS_TAB, VERSIONS_TAB, CHANGELOG_TAB, EXPIRES_TAB];
+// 保留表数量
+const RESERVED_DBS: u32 = RESERVED_TABS.len() as u32;
+
+/**
+* LMDB环境的构建器，集中设置和校验环境的参数，读写线程使用的环境都由它构建
+*/
+#[derive(Debug, Clone)]
+pub struct EnvBuilder {
+    path: PathBuf,                  //数据库目录
+    map_size: usize,                //数据库文件的最大大小
+    max_dbs: u32,                   //最大表数量，包括保留表
+    max_readers: u32,               //最大读事务数量
+    flags: EnvironmentFlags,        //环境标志
+    mode: u32,                      //数据文件权限
+}
+
+impl EnvBuilder {
+    /**
+    * 构建环境的构建器，其它参数使用默认值
+    * @param path 数据库目录，不存在时创建
+    * @param map_size 数据库文件的最大大小
+    */
+    pub fn new<P: AsRef<Path>>(path: P, map_size: usize) -> Self {
+        EnvBuilder {
+            path: path.as_ref().to_path_buf(),
+            map_size,
+            max_dbs: DEFAULT_MAX_DBS,
+            max_readers: DEFAULT_MAX_READERS,
+            flags: EnvironmentFlags::NO_TLS,
+            mode: DEFAULT_FILE_MODE,
+        }
+    }
+
+    pub fn map_size(mut self, map_size: usize) -> Self {
+        self.map_size = map_size;
+        self
+    }
+
+    pub fn max_dbs(mut self, max_dbs: u32) -> Self {
+        self.max_dbs = max_dbs;
+        self
+    }
+
+    pub fn max_readers(mut self, max_readers: u32) -> Self {
+        self.max_readers = max_readers;
+        self
+    }
+
+    // 设置环境标志，读写线程共享读事务，NO_TLS总是被设置
+    pub fn flags(mut self, flags: EnvironmentFlags) -> Self {
+        self.flags = flags | EnvironmentFlags::NO_TLS;
+        self
+    }
+
+    /**
+    * 设置是否使用LMDB的锁文件，多个进程打开同一个环境时必须使用
+    * 不使用锁文件时LMDB不做任何加锁，只有确定只有本进程访问环境时才能关闭
+    */
+    pub fn use_lock_file(mut self, enable: bool) -> Self {
+        if enable {
+            self.flags.remove(EnvironmentFlags::NO_LOCK);
+        } else {
+            self.flags.insert(EnvironmentFlags::NO_LOCK);
+        }
+        self
+    }
+
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get_map_size(&self) -> usize {
+        self.map_size
+    }
+
+    pub fn get_max_dbs(&self) -> u32 {
+        self.max_dbs
+    }
+
+    pub fn get_max_readers(&self) -> u32 {
+        self.max_readers
+    }
+
+    /**
+    * 校验参数，不创建目录和环境
+    * @returns 参数无效返回原因描述
+    */
+    pub fn validate(&self) -> Result<(), String> {
+        if self.map_size < MIN_MAP_SIZE {
+            return Err("DB size must greater than 1M".to_string());
+        }
+        if self.max_dbs <= RESERVED_DBS {
+            return Err(format!("max dbs must greater than {}", RESERVED_DBS));
+        }
+        if self.max_readers == 0 {
+            return Err("max readers must greater than 0".to_string());
+        }
+        if self.flags.contains(EnvironmentFlags::READ_ONLY) {
+            return Err("read only env not support writer".to_string());
+        }
+        if self.mode & 0o600 != 0o600 {
+            return Err(format!("file mode {:o} must be readable and writable by owner", self.mode));
+        }
+        Ok(())
+    }
+
+    /**
+    * 校验参数，创建数据库目录并打开环境
+    * @returns 返回LMDB环境，失败返回原因描述
+    */
+    pub fn build(&self) -> Result<Environment, String> {
+        self.validate()?;
+        if !self.path.exists() {
+            fs::create_dir_all(&self.path).map_err(|e| e.to_string())?;
+        }
+        Environment::new()
+            .set_max_dbs(self.max_dbs)
+            .set_max_readers(self.max_readers)
+            .set_map_size(self.map_size)
+            .set_flags(self.flags)
+            .open_with_permissions(&self.path, self.mode as _)
+            .map_err(|e| e.to_string())
+    }
+}
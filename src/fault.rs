@@ -0,0 +1,97 @@
+// 测试用的故障注入，只在开启fault_injection特性时生效，未开启时所有钩子都是空操作
+// 用于验证上层的重试和回滚逻辑，不能在生产环境开启
+#[cfg(feature = "fault_injection")]
+use std::sync::RwLock;
+#[cfg(feature = "fault_injection")]
+use std::thread;
+#[cfg(feature = "fault_injection")]
+use std::time::Duration;
+
+use lmdb::Error;
+
+// 注入故障时返回的错误码
+pub const FAULT_CODE: i32 = -30000;
+
+/**
+* 故障注入的配置，各概率取值范围为[0, 1]
+*/
+#[derive(Debug, Clone, Default)]
+pub struct FaultConfig {
+    pub put_failure: f64,       //写入键值失败的概率
+    pub commit_failure: f64,    //读写事务提交失败的概率，失败时事务被回滚
+    pub delay: f64,             //工作线程处理消息前延迟的概率，延迟会推迟消息的回调
+    pub delay_millis: u64,      //延迟的毫秒数
+    pub panic: f64,             //工作线程处理消息前崩溃的概率
+}
+
+#[cfg(feature = "fault_injection")]
+lazy_static! {
+    static ref FAULTS: RwLock<Option<FaultConfig>> = RwLock::new(None);
+}
+
+/**
+* 设置故障注入，为None则关闭
+*/
+#[cfg(feature = "fault_injection")]
+pub fn set_faults(config: Option<FaultConfig>) {
+    *FAULTS.write().unwrap() = config;
+}
+
+#[cfg(not(feature = "fault_injection"))]
+pub fn set_faults(_config: Option<FaultConfig>) {
+    warn!("fault injection requires the fault_injection feature");
+}
+
+#[cfg(feature = "fault_injection")]
+fn hit<F: Fn(&FaultConfig) -> f64>(f: F) -> bool {
+    match FAULTS.read().unwrap().as_ref() {
+        Some(config) => rand::random::<f64>() < f(config),
+        None => false,
+    }
+}
+
+// 写入键值前调用，返回注入的错误
+#[cfg(feature = "fault_injection")]
+pub fn on_put() -> Result<(), Error> {
+    if hit(|c| c.put_failure) {
+        return Err(Error::Other(FAULT_CODE));
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "fault_injection"))]
+#[inline]
+pub fn on_put() -> Result<(), Error> {
+    Ok(())
+}
+
+// 提交读写事务前调用，返回注入的错误
+#[cfg(feature = "fault_injection")]
+pub fn on_commit() -> Result<(), Error> {
+    if hit(|c| c.commit_failure) {
+        return Err(Error::Other(FAULT_CODE));
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "fault_injection"))]
+#[inline]
+pub fn on_commit() -> Result<(), Error> {
+    Ok(())
+}
+
+// 工作线程处理消息前调用，按配置延迟或崩溃
+#[cfg(feature = "fault_injection")]
+pub fn on_message(worker: &str) {
+    if hit(|c| c.panic) {
+        panic!("fault injection: worker {} panic", worker);
+    }
+    if hit(|c| c.delay) {
+        let millis = FAULTS.read().unwrap().as_ref().map_or(0, |c| c.delay_millis);
+        thread::sleep(Duration::from_millis(millis));
+    }
+}
+
+#[cfg(not(feature = "fault_injection"))]
+#[inline]
+pub fn on_message(_worker: &str) {}
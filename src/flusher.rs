@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use lmdb::Environment;
+
+/**
+* 后台刷盘策略，用于以NO_SYNC打开的环境，提交时不刷盘，由后台线程定期刷盘
+* 崩溃时最多丢失最近一个间隔或指定数量的提交
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct SyncPolicy {
+    pub interval: Duration,     //刷盘的最大间隔
+    pub commits: u64,           //累计多少次提交后立即刷盘，0表示只按间隔刷盘
+}
+
+// 环境的刷盘状态
+struct FlushState {
+    policy: SyncPolicy,
+    commits: AtomicU64,         //上次刷盘后的提交次数
+    wake: Mutex<bool>,          //提交次数达到阈值时唤醒刷盘线程
+    cond: Condvar,
+}
+
+lazy_static! {
+    // 开启后台刷盘的环境，键为环境id
+    static ref FLUSHERS: RwLock<HashMap<u64, Arc<FlushState>>> = RwLock::new(HashMap::new());
+}
+
+// 读写事务提交后调用，未开启后台刷盘的环境直接返回
+pub fn on_commit(env_id: u64) {
+    if let Some(state) = FLUSHERS.read().unwrap().get(&env_id) {
+        let n = state.commits.fetch_add(1, Ordering::SeqCst) + 1;
+        if state.policy.commits > 0 && n >= state.policy.commits {
+            *state.wake.lock().unwrap() = true;
+            state.cond.notify_one();
+        }
+    }
+}
+
+/**
+* 启动环境的后台刷盘线程，环境关闭后刷盘并退出
+* @param env_id 环境id
+* @param env LMDB环境
+* @param policy 刷盘策略
+*/
+pub fn spawn(env_id: u64, env: Arc<Environment>, policy: SyncPolicy) {
+    let state = Arc::new(FlushState {
+        policy,
+        commits: AtomicU64::new(0),
+        wake: Mutex::new(false),
+        cond: Condvar::new(),
+    });
+    FLUSHERS.write().unwrap().insert(env_id, state.clone());
+
+    let _ = thread::Builder::new().name("pi_store-fsync".to_string()).spawn(move || loop {
+        {
+            let wake = state.wake.lock().unwrap();
+            let (mut wake, _) = state.cond.wait_timeout_while(wake, policy.interval, |wake| !*wake).unwrap();
+            *wake = false;
+        }
+        // 只剩本线程持有环境，说明环境已关闭
+        let closed = Arc::strong_count(&env) == 1;
+        let n = state.commits.swap(0, Ordering::SeqCst);
+        if n > 0 {
+            match env.sync(true) {
+                Ok(_) => debug!("env: {:?} synced {} commits", env_id, n),
+                Err(e) => {
+                    // 刷盘失败时保留计数，下次继续刷盘
+                    state.commits.fetch_add(n, Ordering::SeqCst);
+                    error!("env: {:?} background sync failed: {:?}", env_id, e);
+                }
+            }
+        }
+        if closed {
+            FLUSHERS.write().unwrap().remove(&env_id);
+            break;
+        }
+    });
+}
@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+
+use lmdb::{Database, Environment, Transaction, WriteFlags};
+
+use atom::Atom;
+
+use crate::pool::lookup_db;
+use crate::schema::META_TAB;
+use crate::usage;
+
+// 探测写入的键，事务总是被回滚，不会留在库中
+const PROBE_KEY: &[u8] = b"__health_probe";
+
+/**
+* 健康检查的结果
+*/
+#[derive(Debug, Clone)]
+pub struct Health {
+    pub write_latency: Duration,    //打开读写事务并写入探测键的耗时，包括等待写锁的时间
+    pub read_latency: Duration,     //在同一事务中读回探测键的耗时
+    pub map_size: usize,            //内存映射的大小
+    pub map_used: usize,            //已使用的页占用的大小
+    pub utilization: f64,           //内存映射的使用率，接近1时写入会因MDB_MAP_FULL失败
+}
+
+fn meta_db(env_id: u64) -> Option<Database> {
+    lookup_db(env_id, &Atom::from(META_TAB))
+}
+
+/**
+* 执行一次写入、读回和回滚，检查环境是否可以正常读写
+* 写线程长时间持有读写事务时本函数会等待写锁，耗时会反映在写入延迟中
+* @param env LMDB环境
+* @param env_id 环境id
+*/
+pub fn check(env: &Environment, env_id: u64) -> Result<Health, String> {
+    let db = meta_db(env_id).ok_or_else(|| "meta table not opened".to_string())?;
+
+    let start = Instant::now();
+    let mut txn = env.begin_rw_txn().map_err(|e| e.to_string())?;
+    let probe = (start.elapsed().as_nanos() as u64).to_be_bytes();
+    if let Err(e) = txn.put(db, &PROBE_KEY, &probe, WriteFlags::empty()) {
+        txn.abort();
+        return Err(format!("health probe write failed: {:?}", e.to_string()));
+    }
+    let write_latency = start.elapsed();
+
+    let start = Instant::now();
+    let r = match txn.get(db, &PROBE_KEY) {
+        Ok(v) if v == &probe[..] => Ok(()),
+        Ok(_) => Err("health probe read mismatch".to_string()),
+        Err(e) => Err(format!("health probe read failed: {:?}", e.to_string())),
+    };
+    let read_latency = start.elapsed();
+    txn.abort();
+    r?;
+
+    let (map_size, page_size, pages) = usage::map_usage(env).map_err(|e| e.to_string())?;
+    let map_used = page_size * pages;
+    Ok(Health {
+        write_latency,
+        read_latency,
+        map_size,
+        map_used,
+        utilization: if map_size == 0 { 0.0 } else { map_used as f64 / map_size as f64 },
+    })
+}
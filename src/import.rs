@@ -0,0 +1,234 @@
+use std::io::Read;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use crossbeam_channel::bounded;
+use futures::channel::oneshot;
+use futures::io::{AsyncRead, AsyncReadExt};
+
+use pi_db::db::{Bin, TabKV};
+
+use atom::Atom;
+
+use crate::pool::{SendCallback, WriteCallback};
+use crate::snapshot::{read_frame, Frame, MAX_FRAME_LEN};
+
+// 每批默认导入的记录数
+const DEFAULT_BATCH: usize = 1000;
+// 每批默认导入的最大字节数
+const DEFAULT_BATCH_BYTES: usize = 4 * 1024 * 1024;
+
+/**
+* 导入的选项
+*/
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    pub batch: usize,               //每批导入的最大记录数，每批在一个读写事务中提交，0表示默认值
+    pub batch_bytes: usize,         //每批导入的最大字节数，0表示默认值
+    pub resume_after: Option<Bin>,  //从中断处继续导入，为上次导入最后一次进度中的键，流中该键及之前的记录被跳过
+}
+
+/**
+* 导入的进度，每提交一批调用一次进度回调，导入结束时返回最终进度
+* 导入中断后，以最后一次进度的键作为resume_after重新导入同一个流即可继续
+*/
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportProgress {
+    pub records: u64,       //本次已提交的记录数
+    pub bytes: u64,         //本次已提交的键和值的字节数
+    pub skipped: u64,       //继续导入时跳过的已导入记录数
+    pub key: Option<Bin>,   //最后提交的键
+}
+
+// 导入进度的回调
+pub type ProgressCallback = SendCallback<ImportProgress>;
+
+// 导入状态，同步和异步导入共用，由调用者读取帧并提交批次
+struct Import {
+    ware: Atom,
+    tab: Atom,
+    batch: usize,
+    batch_bytes: usize,
+    resume_after: Option<Bin>,  //尚未遇到的继续导入的键，遇到后置为None
+    progress: ImportProgress,
+    pending: Vec<TabKV>,
+    pending_bytes: usize,
+    finished: bool,             //是否已读到尾帧
+}
+
+impl Import {
+    fn new(ware: &Atom, tab: &Atom, opts: ImportOptions) -> Self {
+        Import {
+            ware: ware.clone(),
+            tab: tab.clone(),
+            batch: if opts.batch == 0 { DEFAULT_BATCH } else { opts.batch },
+            batch_bytes: if opts.batch_bytes == 0 { DEFAULT_BATCH_BYTES } else { opts.batch_bytes },
+            resume_after: opts.resume_after,
+            progress: ImportProgress::default(),
+            pending: Vec::new(),
+            pending_bytes: 0,
+            finished: false,
+        }
+    }
+
+    // 处理一个帧，返回已满需要提交的一批记录
+    fn push(&mut self, frame: Frame) -> Result<Option<Vec<TabKV>>, String> {
+        if self.finished {
+            return Err("snapshot frame after end".to_string());
+        }
+        match frame {
+            Frame::Header(tab, txnid) => {
+                info!("import tab: {:?} from snapshot of tab: {:?}, txnid: {}", self.tab.to_string(), tab.to_string(), txnid);
+                Ok(None)
+            }
+            Frame::Record(key, value) => {
+                if let Some(after) = &self.resume_after {
+                    if key == *after {
+                        self.resume_after = None;
+                    }
+                    self.progress.skipped += 1;
+                    return Ok(None);
+                }
+                self.pending_bytes += key.len() + value.len();
+                self.pending.push(TabKV {
+                    ware: self.ware.clone(),
+                    tab: self.tab.clone(),
+                    key,
+                    index: 0,
+                    value: Some(value),
+                });
+                if self.pending.len() >= self.batch || self.pending_bytes >= self.batch_bytes {
+                    Ok(self.take())
+                } else {
+                    Ok(None)
+                }
+            }
+            Frame::End(count) => {
+                if self.resume_after.is_some() {
+                    return Err("resume key not found in snapshot".to_string());
+                }
+                let seen = self.progress.records + self.progress.skipped + self.pending.len() as u64;
+                if seen != count {
+                    return Err(format!("snapshot incomplete, expect {} records, read {}", count, seen));
+                }
+                self.finished = true;
+                Ok(self.take())
+            }
+        }
+    }
+
+    fn take(&mut self) -> Option<Vec<TabKV>> {
+        self.pending_bytes = 0;
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(mem::replace(&mut self.pending, Vec::new()))
+        }
+    }
+
+    // 一批记录提交后更新进度
+    fn committed(&mut self, batch: &[TabKV], progress: &ProgressCallback) {
+        self.progress.records += batch.len() as u64;
+        self.progress.bytes += batch.iter().map(|kv| (kv.key.len() + kv.value.as_ref().map_or(0, |v| v.len())) as u64).sum::<u64>();
+        self.progress.key = batch.last().map(|kv| kv.key.clone());
+        progress(self.progress.clone());
+    }
+
+    // 流结束时检查是否读到了尾帧
+    fn finish(self) -> Result<ImportProgress, String> {
+        if !self.finished {
+            return Err(format!("snapshot truncated after {} records, resume after key: {:?}", self.progress.records, self.progress.key));
+        }
+        info!("import tab: {:?}, records: {}, bytes: {}, skipped: {}", self.tab.to_string(), self.progress.records, self.progress.bytes, self.progress.skipped);
+        Ok(self.progress)
+    }
+}
+
+/**
+* 从快照流导入表，每批记录在写线程的一个读写事务中写入，已存在的键被覆盖
+* 在调用线程中同步执行，等待每批提交后再读取下一批，不能在存储的任务池中调用
+* 导入失败时已提交的批次保留，以最后一次进度的键作为resume_after可以继续导入
+* @param ware 库名
+* @param tab 导入的表，可以与快照中的表名不同
+* @param input 快照流，格式见snapshot.rs
+* @param opts 导入选项
+* @param submit 提交一批记录，提交完成后调用回调
+* @param progress 每提交一批调用一次的进度回调
+* @returns 返回最终进度
+*/
+pub fn import<R, S>(ware: &Atom, tab: &Atom, input: &mut R, opts: ImportOptions, submit: S, progress: ProgressCallback) -> Result<ImportProgress, String>
+    where R: Read, S: Fn(Arc<Vec<TabKV>>, WriteCallback) {
+    import_frames(ware, tab, FrameIter(input), opts, submit, progress)
+}
+
+// 逐个读取快照流中的帧
+struct FrameIter<'a, R: Read>(&'a mut R);
+
+impl<'a, R: Read> Iterator for FrameIter<'a, R> {
+    type Item = Result<Frame, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        read_frame(self.0).transpose()
+    }
+}
+
+/**
+* 导入帧序列，序列必须以尾帧结束，用于从其它格式转换而来的记录，见import
+*/
+pub fn import_frames<I, S>(ware: &Atom, tab: &Atom, frames: I, opts: ImportOptions, submit: S, progress: ProgressCallback) -> Result<ImportProgress, String>
+    where I: Iterator<Item = Result<Frame, String>>, S: Fn(Arc<Vec<TabKV>>, WriteCallback) {
+    let mut import = Import::new(ware, tab, opts);
+    for frame in frames {
+        if let Some(batch) = import.push(frame?)? {
+            let batch = Arc::new(batch);
+            let (s, r) = bounded(1);
+            submit(batch.clone(), Arc::new(move |res| {
+                let _ = s.send(res);
+            }));
+            r.recv().map_err(|e| e.to_string())??;
+            import.committed(&batch, &progress);
+        }
+    }
+    import.finish()
+}
+
+/**
+* 从异步的快照流导入表，与import相同，但读取流和等待提交都不阻塞线程
+*/
+pub async fn import_async<R, S>(ware: &Atom, tab: &Atom, input: &mut R, opts: ImportOptions, submit: S, progress: ProgressCallback) -> Result<ImportProgress, String>
+    where R: AsyncRead + Unpin, S: Fn(Arc<Vec<TabKV>>, WriteCallback) {
+    let mut import = Import::new(ware, tab, opts);
+    while let Some(frame) = read_frame_async(input).await? {
+        if let Some(batch) = import.push(frame)? {
+            let batch = Arc::new(batch);
+            let (s, r) = oneshot::channel();
+            // 回调只会被调用一次，发送端只能取出一次
+            let s = Mutex::new(Some(s));
+            submit(batch.clone(), Arc::new(move |res| {
+                if let Some(s) = s.lock().unwrap().take() {
+                    let _ = s.send(res);
+                }
+            }));
+            r.await.map_err(|e| e.to_string())??;
+            import.committed(&batch, &progress);
+        }
+    }
+    import.finish()
+}
+
+// 从异步流中读取下一个帧，流已结束返回None
+async fn read_frame_async<R: AsyncRead + Unpin>(input: &mut R) -> Result<Option<Frame>, String> {
+    let mut len = [0u8; 4];
+    match input.read_exact(&mut len).await {
+        Ok(_) => (),
+        Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(format!("snapshot frame too large: {}", len));
+    }
+    let mut payload = vec![0u8; len];
+    input.read_exact(&mut payload).await.map_err(|e| e.to_string())?;
+    Frame::decode(&payload).map(Some)
+}
@@ -0,0 +1,48 @@
+use std::sync::Arc;
+
+use lmdb::DatabaseFlags;
+
+use pi_db::db::Bin;
+
+use atom::Atom;
+
+use crate::table_meta;
+
+/**
+* 将表设置为整数键的表，键按本机字节序的无符号整数比较，分支页更紧凑
+* 必须在表创建前设置，表中所有键的长度必须相同，为4字节或8字节
+* @param tab 表名
+*/
+pub fn set_integer_key(tab: &Atom) {
+    table_meta::set_db_flags(tab, table_meta::db_flags(tab) | DatabaseFlags::INTEGER_KEY);
+}
+
+// 整数键的表中u32的键
+pub fn u32_key(id: u32) -> Bin {
+    Arc::new(id.to_ne_bytes().to_vec())
+}
+
+// 整数键的表中u64的键
+pub fn u64_key(id: u64) -> Bin {
+    Arc::new(id.to_ne_bytes().to_vec())
+}
+
+// 解析u32的键，长度不符返回None
+pub fn decode_u32(key: &[u8]) -> Option<u32> {
+    if key.len() != 4 {
+        return None;
+    }
+    let mut b = [0u8; 4];
+    b.copy_from_slice(key);
+    Some(u32::from_ne_bytes(b))
+}
+
+// 解析u64的键，长度不符返回None
+pub fn decode_u64(key: &[u8]) -> Option<u64> {
+    if key.len() != 8 {
+        return None;
+    }
+    let mut b = [0u8; 8];
+    b.copy_from_slice(key);
+    Some(u64::from_ne_bytes(b))
+}
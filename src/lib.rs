@@ -6,4 +6,60 @@ extern crate lazy_static;
 extern crate log;
 
 pub mod log_store;
-pub mod log_file_db;
\ No newline at end of file
+pub mod log_file_db;
+pub mod lmdb_file;
+pub mod pool;
+pub mod archive;
+pub mod audit;
+pub mod backend;
+pub mod bench;
+pub mod blob;
+pub mod bloom;
+pub mod buffer_pool;
+pub mod cdc;
+pub mod changelog;
+pub mod checksum;
+pub mod chunk;
+pub mod compact;
+pub mod csv;
+pub mod dict;
+pub mod diff;
+pub mod dup_fixed;
+pub mod env_builder;
+pub mod fault;
+pub mod flusher;
+pub mod health;
+pub mod import;
+pub mod int_key;
+pub mod maintenance;
+pub mod mem_store;
+pub mod merge;
+pub mod merkle;
+pub mod migration;
+pub mod namespace;
+pub mod page;
+pub mod policy;
+pub mod process_lock;
+pub mod pubsub;
+pub mod quota;
+pub mod read_cache;
+pub mod readers;
+pub mod recovery;
+pub mod repair;
+pub mod restore;
+pub mod retry;
+pub mod rocks_store;
+pub mod scan_filter;
+pub mod schema;
+pub mod snapshot;
+pub mod stream;
+pub mod table_meta;
+pub mod throttle;
+pub mod tiering;
+pub mod tombstone;
+pub mod txn_stats;
+pub mod typed_table;
+pub mod usage;
+pub mod versions;
+pub mod view;
+pub mod ware_registry;
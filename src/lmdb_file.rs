@@ -1,18 +1,21 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
-use std::sync::{Arc, Mutex, RwLock};
-use std::sync::atomic::Ordering;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::mem;
+use std::sync::{Arc, Mutex, Once, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::thread;
+use std::time::Duration;
 
 use worker::impls::cast_store_task;
 use worker::task::TaskType;
 
+use futures::io::{AsyncRead, AsyncWrite};
 use crossbeam_channel::{bounded, unbounded, Receiver, Sender, TryRecvError};
 use atom::Atom;
 use apm::counter::{GLOBAL_PREF_COLLECT, PrefCounter};
-use bon::{Decode, Encode, ReadBuffer, WriteBuffer};
+use bon::{Encode, WriteBuffer};
 use guid::Guid;
 use pi_db::db::{Bin, CommitResult, DBResult, Filter, Iter, IterResult, KeyIterResult, MetaTxn, NextResult,OpenTab, SResult, Tab, TabKV, TabMeta, TabTxn, TxCallback, TxQueryCallback, TxState, Txn, Ware,WareSnapshot};
 use sinfo::EnumType;
@@ -20,9 +23,41 @@ use pi_db::tabs::{TabLog, Tabs};
 use pi_db::db::Event;
 use pi_db::mgr::{COMMIT_CHAN, CommitChan};
 use lmdb::{ Cursor, Database, DatabaseFlags, Environment, EnvironmentFlags, Transaction, WriteFlags};
-use crate::pool::{LmdbService, ReaderMsg, WriterMsg, OPENED_TABLES, IN_PROGRESS_TX};
+use crate::archive::{self, ArchivePolicy};
+use crate::blob::BLOBS_TAB;
+use crate::changelog::{self, CHANGELOG_TAB};
+use crate::checksum::CHECKSUMS_TAB;
+use crate::chunk::CHUNKS_TAB;
+use crate::compact;
+use crate::csv::{self, CsvOptions};
+use crate::dict;
+use crate::diff::{self, DiffEntry, DiffSummary};
+use crate::dup_fixed;
+use crate::env_builder::EnvBuilder;
+use crate::health::{self, Health};
+use crate::import::{self, ImportOptions, ImportProgress, ProgressCallback};
+use crate::merkle::{self, MerkleTree};
+use crate::migration::{self, Migration};
+use crate::page::{Page, PageToken};
+use crate::policy::{self, TablePolicy, EXPIRES_TAB};
+use crate::process_lock;
+use crate::scan_filter::ScanFilter;
+use crate::view::ViewCallback;
+use crate::quota::{self, TabUsage};
+use crate::readers::{self, LongReader, ReaderSlot};
+use crate::recovery;
+use crate::restore::{self, RestorePoint};
+use crate::snapshot::{self, SnapshotHeader};
+use crate::table_meta::{self, SINFO};
+use crate::throttle;
+use crate::tiering::{self, TierConfig};
+use crate::tombstone;
+use crate::schema::{self, TableVersion, META_TAB};
+use crate::txn_stats::{self, CommitStats, TabTxnStats};
+use crate::usage::{self, DiskUsage, FreelistStats};
+use crate::versions::VERSIONS_TAB;
+use crate::pool::{clear_timed_out, is_timed_out, CancelToken, Condition, Precondition, TxnFn, TxnHandle, TxnOps, LmdbPool, LmdbService, pi_db_callback, PoolStats, Priority, QueryCallback, ReaderMsg, RoTxn, SendCallback, StoreError, VersionedQueryCallback, WriteCallback, WorkerSender, WriterGuard, WriterMsg, lookup_db, register_db, writer_owner};
 
-const SINFO: &str = "_$sinfo";
 const MAX_DBS_PER_ENV: u32 = 1024;
 const TIMEOUT: usize = 100;
 
@@ -126,6 +161,8 @@ impl Tab for LmdbTable {
             remove_byte: GLOBAL_PREF_COLLECT.
                 new_dynamic_counter(
                     Atom::from(LMDB_TABLE_PREFIX.to_string() + tab + LMDB_TABLE_REMOVE_BYTE_COUNT_SUFFIX), 0).unwrap(),
+            handle: Mutex::new(None),
+            writer: Mutex::new(None),
         });
 
         t
@@ -146,6 +183,8 @@ pub struct LmdbTableTxn {
     write_byte:		PrefCounter,	//写字节
     remove_count:	PrefCounter,	//删除计数
     remove_byte:	PrefCounter,	//删除字节
+    handle:         Mutex<Option<TxnHandle>>,   //事务绑定的工作线程
+    writer:         Mutex<Option<WriterGuard>>, //写线程的使用权，表事务释放时归还
 }
 
 impl Txn for LmdbTableTxn {
@@ -157,27 +196,61 @@ impl Txn for LmdbTableTxn {
         }
     }
 
-    fn prepare(&self, _timeout: usize, _cb: TxCallback) -> DBResult {
+    // 预提交时将本表的修改写入写线程的读写事务，校验前置条件，元信息在最终提交时写入
+    fn prepare(&self, _timeout: usize, cb: TxCallback) -> DBResult {
         *self.state.lock().unwrap() = TxState::Preparing;
 
         self.prepare_count.sum(1);
 
-        Some(Ok(()))
+        if !self.writable {
+            return Some(Ok(()));
+        }
+        // 超时的事务已被回滚，没有修改的表也不能预提交成功
+        if is_timed_out(self.id) {
+            *self.state.lock().unwrap() = TxState::Err;
+            return Some(Err(StoreError::TxnTimedOut.to_string()));
+        }
+        if self.tab.as_str() == SINFO {
+            return Some(Ok(()));
+        }
+        let modifies = take_tab_items(&*MODS, self.id, |kv: &TabKV| kv.tab == self.tab);
+        let conditions = take_tab_items(&*CONDS, self.id, |c: &Condition| c.tab == self.tab);
+        if modifies.is_empty() && conditions.is_empty() {
+            return Some(Ok(()));
+        }
+        // 缓存的修改已取出，之前设置的保存点不能再回滚
+        *WRITTEN.lock().unwrap().entry(self.id).or_insert(0) += 1;
+
+        let handle = self.handle();
+        if !self.checkout_writer() {
+            *self.state.lock().unwrap() = TxState::Err;
+            return Some(Err("prepare timeout".to_string()));
+        }
+        let state = self.state.clone();
+        if let Err(e) = handle.prepare(Arc::new(modifies), Arc::new(conditions), pi_db_callback(Arc::new(move |r: SResult<()>| {
+            if r.is_err() {
+                *state.lock().unwrap() = TxState::Err;
+            }
+            cb(r)
+        }))) {
+            return Some(Err(e.to_string()));
+        }
+
+        None
     }
 
     fn commit(&self, cb: TxCallback) -> CommitResult {
         self.commit_count.sum(1);
 
+        // 提交线程已丢弃超时事务的修改，在这里返回失败并清除超时标记
+        if self.writable && clear_timed_out(self.id) {
+            *self.state.lock().unwrap() = TxState::CommitFail;
+            return Some(Err(StoreError::TxnTimedOut.to_string()));
+        }
         *self.state.lock().unwrap() = TxState::Committing;
         let state1 = self.state.clone();
 
-        let ro_sender = LMDB_SERVICE
-                        .lock()
-                        .unwrap()
-                        .ro_sender(&self.tab.clone())
-                        .expect(&format!("Fatal error: cannot get ro sender for {:?}", &self.tab.to_string()));
-
-        let _ = ro_sender.send(ReaderMsg::Commit(Arc::new(move |c| match c {
+        let _ = self.handle().commit(pi_db_callback(Arc::new(move |c: SResult<()>| match c {
             Ok(_) => {
                 *state1.lock().unwrap() = TxState::Commited;
                 cb(Ok(()));
@@ -196,20 +269,16 @@ impl Txn for LmdbTableTxn {
 
         *self.state.lock().unwrap() = TxState::Rollbacking;
         let state1 = self.state.clone();
-        let ro_sender = LMDB_SERVICE
-                        .lock()
-                        .unwrap()
-                        .ro_sender(&self.tab)
-                        .expect(&format!("Fatal error: cannot get ro sender for {:?}", &self.tab.to_string()));
 
-        // 删除未提交的修改
+        // 删除未提交的修改和前置条件
         match MODS.lock().unwrap().remove(&self.id) {
             Some(m) => debug!("rollback txid: {:?}, modifies: {:?}", self.id, m),
             None => {}
         }
+        CONDS.lock().unwrap().remove(&self.id);
+        clear_txn_state(self.id);
 
-        // 不管是读写事务还是只读事务，直接回滚，调用上层回调
-        let _ = ro_sender.send(ReaderMsg::Rollback(Arc::new(move |c| match c {
+        let rollback_cb = pi_db_callback(Arc::new(move |c: SResult<()>| match c {
             Ok(_) => {
                 *state1.lock().unwrap() = TxState::Rollbacked;
                 cb(Ok(()));
@@ -218,77 +287,369 @@ impl Txn for LmdbTableTxn {
                 *state1.lock().unwrap() = TxState::RollbackFail;
                 cb(Err(e.to_string()));
             }
-        })));
+        }));
+
+        // 读写事务需要由写线程回滚，并释放写线程的使用权
+        let _ = self.handle().rollback(rollback_cb);
 
         None
     }
 }
 
-impl TabTxn for LmdbTableTxn {
-    fn key_lock(
-        &self,
-        _arr: Arc<Vec<TabKV>>,
-        _lock_time: usize,
-        _readonly: bool,
-        _cb: TxCallback,
-    ) -> DBResult {
+impl LmdbTableTxn {
+    // 取得表所在环境的写线程的使用权，写线程已在超时后收回时重新等待
+    fn checkout_writer(&self) -> bool {
+        let mut writer = self.writer.lock().unwrap();
+        if writer.as_ref().map_or(false, |w| writer_owner(w.env_id()) == Some(self.id)) {
+            return true;
+        }
+        // 先释放已失效的守卫，再重新取得
+        writer.take();
+        *writer = WriterGuard::checkout(env_id(&self.tab), self.id);
+        writer.is_some()
+    }
+
+    // 事务绑定的工作线程，第一次使用时选择，之后事务的所有消息都发送到同一个工作线程
+    fn handle(&self) -> TxnHandle {
+        let mut handle = self.handle.lock().unwrap();
+        if handle.is_none() {
+            *handle = LMDB_POOL
+                .lock()
+                .unwrap()
+                .service(&self.tab)
+                .and_then(|s| s.txn_handle(&self.tab, self.id, self.writable));
+        }
+        handle.clone().expect(&format!("Fatal error: cannot get txn handle for {:?}", self.tab.to_string()))
+    }
+
+    /**
+    * 带前置条件的修改，提交时写线程先校验事务中所有的前置条件，任一条件不满足则整个事务不写入
+    * @param arr 修改的键值
+    * @param preconditions 每个修改对应的前置条件，None表示没有条件
+    * @param cb 修改回调
+    */
+    pub fn modify_if(&self, arr: Arc<Vec<TabKV>>, preconditions: Vec<Option<Precondition>>, cb: TxCallback) -> DBResult {
+        if arr.len() != preconditions.len() {
+            return Some(Err("preconditions not match modifies".to_string()));
+        }
+        let conditions = arr
+            .iter()
+            .zip(preconditions.into_iter())
+            .filter_map(|(kv, p)| p.map(|expect| Condition {
+                tab: kv.tab.clone(),
+                key: kv.key.clone(),
+                expect,
+            }))
+            .collect::<Vec<Condition>>();
+        CONDS.lock().unwrap()
+            .entry(self.id)
+            .or_insert_with(Vec::new)
+            .extend(conditions);
+
+        self.modify(arr, None, false, cb)
+    }
+
+    /**
+    * 版本匹配时写入，用于乐观并发控制，版本由query_versioned读取
+    * @param kv 修改的键值
+    * @param version 期望的键版本，0表示键不存在
+    * @param cb 修改回调，版本不匹配时事务提交失败
+    */
+    pub fn put_if_version(&self, kv: TabKV, version: u64, cb: TxCallback) -> DBResult {
+        self.modify_if(Arc::new(vec![kv]), vec![Some(Precondition::Version(version))], cb)
+    }
+
+    /**
+    * 查询键的值和版本，键每次写入版本加1，不存在的键版本为0
+    * @param arr 查询的键
+    * @param cb 查询回调，返回键值和对应的版本
+    */
+    pub fn query_versioned(&self, arr: Arc<Vec<TabKV>>, cb: VersionedQueryCallback) {
+        debug!("query versioned txid: {:?}, query item: {:?}", self.id, arr);
+        if let Err(e) = try_ro_send(&self.tab, Priority::High, ReaderMsg::QueryVersioned(arr, cb.clone())) {
+            cb(Err(e.to_string()));
+        }
+    }
+
+    /**
+    * 带过滤条件的迭代，过滤在读线程中执行，不满足条件的键值不会发送给调用者
+    * @param key 起始键，会根据过滤条件的前缀和键范围收窄
+    * @param descending 与iter相同，为true时从小到大迭代
+    * @param scan_filter 过滤条件
+    * @param cb 异步返回迭代器
+    */
+    pub fn iter_filtered(&self, key: Option<Bin>, descending: bool, scan_filter: ScanFilter, cb: Arc<Fn(IterResult)>) -> Option<IterResult> {
+        let (tx, rx) = bounded(1);
+        let start = scan_filter.start_key(key, descending);
+        if let Err(e) = try_ro_send(&self.tab, Priority::Low, ReaderMsg::CreateItemIter(descending, self.tab.clone(), start, tx.clone())) {
+            return Some(Err(e.to_string()));
+        }
+        match rx.recv() {
+            Ok(k) => {
+                let mut iter = LmdbItemsIter::new(self.id, descending, self.tab.clone(), k, tx, rx, None);
+                iter.scan_filter = Some(Arc::new(scan_filter));
+                Some(Ok(Box::new(iter)))
+            }
+            Err(e) => Some(Err(format!("create filtered iter failed: {:?}", e))),
+        }
+    }
+
+    /**
+    * 从表的最后一个键开始从大到小迭代，调用者不需要知道表中最大的键
+    * @param filter 过滤器
+    * @param cb 异步返回迭代器
+    */
+    pub fn iter_from_last(&self, filter: Filter, cb: Arc<Fn(IterResult)>) -> Option<IterResult> {
+        // 从大到小迭代且没有起始键时，读线程用MDB_LAST定位到最后一个键
+        self.iter(&self.tab, None, false, filter, cb)
+    }
+
+    /**
+    * 分页查询，不需要在读线程中保持迭代器，适合无状态的前端逐页读取
+    * @param token 上一页返回的令牌，为None则从表头或表尾开始
+    * @param descending 与iter相同，为true时从小到大迭代，有令牌时使用令牌中的方向
+    * @param limit 每页最多返回的键值数量
+    * @param cb 异步返回本页的键值和下一页的令牌
+    */
+    pub fn scan_page(&self, token: Option<Bin>, descending: bool, limit: usize, cb: SendCallback<SResult<Page>>) {
+        let (after, descending) = match token {
+            Some(t) => match PageToken::decode(&t) {
+                Ok(t) => (Some(t.last_key), t.descending),
+                Err(e) => return cb(Err(e)),
+            },
+            None => (None, descending),
+        };
+        if limit == 0 {
+            return cb(Err("page limit must greater than 0".to_string()));
+        }
+        if let Err(e) = try_ro_send(&self.tab, Priority::Low, ReaderMsg::ScanPage(self.tab.clone(), after, descending, limit, cb.clone())) {
+            cb(Err(e.to_string()));
+        }
+    }
+
+    /**
+    * 统计表中键在[start, end)范围内的记录数，由读线程遍历游标计算，用于分页显示总数
+    * @param start 起始键，包括该键，None表示从表头开始
+    * @param end 结束键，不包括该键，None表示到表尾
+    * @param cb 异步返回记录数
+    */
+    pub fn count_range(&self, start: Option<Bin>, end: Option<Bin>, cb: SendCallback<SResult<usize>>) {
+        if let Err(e) = try_ro_send(&self.tab, Priority::Low, ReaderMsg::CountRange(self.tab.clone(), start, end, cb.clone())) {
+            cb(Err(e.to_string()));
+        }
+    }
+
+    /**
+    * 零拷贝查询，回调在读线程的读事务中同步执行，值视图直接指向LMDB的内存映射
+    * 适合只检查大值头部少量字节的读路径，需要保留的值应在回调中复制
+    * @param arr 查询的键
+    * @param cb 回调，读事务在回调返回后结束
+    */
+    pub fn query_view(&self, arr: Arc<Vec<TabKV>>, cb: ViewCallback) {
+        debug!("query view txid: {:?}, query item: {:?}", self.id, arr);
+        let _ = ro_sender(&self.tab, Priority::High).send(ReaderMsg::QueryView(arr, cb));
+    }
+
+    /**
+    * 用表注册的合并函数将操作数合并到当前值，在写线程中原子地读取和写回
+    * 合并在事务提交时生效，先于事务中其它的修改写入
+    * @param arr 合并的键，value为操作数
+    * @param cb 合并回调
+    */
+    pub fn merge(&self, arr: Arc<Vec<TabKV>>, cb: TxCallback) -> DBResult {
+        debug!("merge txid: {:?}, tab: {:?}, items: {:?}", self.id, self.tab, arr);
+        if !self.writable {
+            return Some(Err("merge in readonly txn".to_string()));
+        }
+        if is_timed_out(self.id) {
+            return Some(Err(StoreError::TxnTimedOut.to_string()));
+        }
+        let rw_sender = rw_sender(&self.tab);
+        if self.checkout_writer() {
+            *WRITTEN.lock().unwrap().entry(self.id).or_insert(0) += 1;
+            let _ = rw_sender.send(WriterMsg::Merge(arr, pi_db_callback(cb)));
+        } else {
+            let t = Box::new(move |_| {
+                cb(Err("merge timeout".to_string()));
+            });
+            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("merge timeout callback"));
+        }
+
         None
     }
 
-    fn query(
-        &self,
-        arr: Arc<Vec<TabKV>>,
-        _lock_time: Option<usize>,
-        _readonly: bool,
-        cb: TxQueryCallback,
-    ) -> Option<SResult<Vec<TabKV>>> {
+    /**
+    * 不经过事务的合并写入，写线程累计多批写入后在一个读写事务中提交，提交后调用各批的回调
+    * 刷新策略由set_write_coalescing设置，未设置时每批立即提交；所有键必须属于同一个数据库
+    * @param arr 修改的键值，value为None表示删除
+    * @param cb 提交回调
+    */
+    pub fn write_coalesced(&self, arr: Arc<Vec<TabKV>>, cb: TxCallback) {
+        debug!("write coalesced txid: {:?}, tab: {:?}, items: {:?}", self.id, self.tab, arr);
+        if !self.writable {
+            let t = Box::new(move |_| {
+                cb(Err("write in readonly txn".to_string()));
+            });
+            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("write coalesced callback"));
+            return;
+        }
+        let _ = rw_sender(&self.tab).send(WriterMsg::Coalesce(arr, pi_db_callback(cb)));
+    }
+
+    /**
+    * 不经过事务按写入标志写入一批键值，在写线程的独立读写事务中提交
+    * NO_OVERWRITE用于只插入的写入，键已存在时整批失败并返回StoreError::KeyExist
+    * APPEND用于按键的顺序写入，键不大于表中最后的键时失败；APPEND_DUP用于允许重复键的表
+    * @param arr 修改的键值，value为None表示删除，删除时忽略写入标志
+    * @param flags 写入标志
+    * @param cb 提交回调
+    */
+    pub fn put_with_flags(&self, arr: Arc<Vec<TabKV>>, flags: WriteFlags, cb: TxCallback) {
+        debug!("put with flags txid: {:?}, tab: {:?}, flags: {:?}, items: {:?}", self.id, self.tab, flags, arr);
+        if !self.writable {
+            let t = Box::new(move |_| {
+                cb(Err("write in readonly txn".to_string()));
+            });
+            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("put with flags callback"));
+            return;
+        }
+        let _ = rw_sender(&self.tab).send(WriterMsg::Put(arr, flags, pi_db_callback(cb)));
+    }
+
+    /**
+    * 提交已预提交的事务，用于由外部协调者驱动的两阶段提交
+    * 事务管理器的最终提交同样会提交已预提交的修改
+    */
+    pub fn commit_prepared(&self, cb: TxCallback) -> DBResult {
+        match self.handle().commit_prepared(pi_db_callback(cb)) {
+            Ok(_) => None,
+            Err(e) => Some(Err(e.to_string())),
+        }
+    }
+
+    /**
+    * 设置保存点，之后的修改可以回滚到保存点而不回滚整个事务
+    * 保存点属于整个事务，同一事务的各个表事务共享
+    */
+    pub fn savepoint(&self) -> DBResult {
+        if !self.writable {
+            return Some(Err("savepoint in readonly txn".to_string()));
+        }
+        let mark = SavepointMark {
+            mods: MODS.lock().unwrap().get(&self.id).map_or(0, |m| m.len()),
+            conds: CONDS.lock().unwrap().get(&self.id).map_or(0, |c| c.len()),
+            written: WRITTEN.lock().unwrap().get(&self.id).cloned().unwrap_or(0),
+        };
+        SAVEPOINTS.lock().unwrap().entry(self.id).or_insert_with(Vec::new).push(mark);
+        Some(Ok(()))
+    }
+
+    /**
+    * 回滚到最近的保存点，并保留该保存点
+    * 合并和预提交的修改已直接写入读写事务，无法撤销，保存点之后有合并或预提交时返回错误
+    */
+    pub fn rollback_to_savepoint(&self) -> DBResult {
+        let savepoints = SAVEPOINTS.lock().unwrap();
+        let mark = match savepoints.get(&self.id).and_then(|s| s.last()) {
+            Some(mark) => mark,
+            None => return Some(Err("no savepoint".to_string())),
+        };
+        if WRITTEN.lock().unwrap().get(&self.id).cloned().unwrap_or(0) != mark.written {
+            return Some(Err("cannot rollback merged or prepared modifies to savepoint".to_string()));
+        }
+        if let Some(m) = MODS.lock().unwrap().get_mut(&self.id) {
+            debug!("rollback to savepoint txid: {:?}, modifies: {:?}", self.id, &m[mark.mods..]);
+            m.truncate(mark.mods);
+        }
+        if let Some(c) = CONDS.lock().unwrap().get_mut(&self.id) {
+            c.truncate(mark.conds);
+        }
+        Some(Ok(()))
+    }
+
+    // 释放最近的保存点，保存点之后的修改保留在事务中
+    pub fn release_savepoint(&self) -> DBResult {
+        let mut savepoints = SAVEPOINTS.lock().unwrap();
+        match savepoints.get_mut(&self.id).and_then(|s| s.pop()) {
+            Some(_) => Some(Ok(())),
+            None => Some(Err("no savepoint".to_string())),
+        }
+    }
+
+    /**
+    * 开始子事务，子事务只是缓存修改的写批次，不是LMDB的嵌套事务
+    * 修改在提交前不写入写线程的读写事务，子事务和父事务都读不到这些修改，提交后并入本事务，回滚只丢弃子事务的修改
+    */
+    pub fn begin_nested_txn(&self) -> Result<NestedTxn, String> {
+        if !self.writable {
+            return Err("nested txn in readonly txn".to_string());
+        }
+        Ok(NestedTxn {
+            parent: self.id,
+            mods: Mutex::new(Vec::new()),
+            conds: Mutex::new(Vec::new()),
+        })
+    }
+
+    /**
+    * 可取消的查询，用于大批量查询
+    * @param arr 查询的键
+    * @param cb 查询回调，取消后以Cancelled错误调用
+    * @returns 返回取消令牌和同步的查询结果
+    */
+    pub fn query_cancelable(&self, arr: Arc<Vec<TabKV>>, cb: TxQueryCallback) -> (CancelToken, Option<SResult<Vec<TabKV>>>) {
+        let token = CancelToken::new();
+        let r = self.query_with_token(arr, cb, Some(token.clone()));
+        (token, r)
+    }
+
+    fn query_with_token(&self, arr: Arc<Vec<TabKV>>, cb: TxQueryCallback, token: Option<CancelToken>) -> Option<SResult<Vec<TabKV>>> {
         debug!("query txid: {:?}, query item: {:?}", self.id, arr);
         let read_byte = self.read_byte.clone();
+        if self.writable && is_timed_out(self.id) {
+            return Some(Err(StoreError::TxnTimedOut.to_string()));
+        }
         match self.writable {
             true => {
-                let rw_sender = LMDB_SERVICE.lock().unwrap().rw_sender().unwrap();
-                let mut retry = 250;
-                let cb1= cb.clone();
-                while retry > 0 {
-                    if IN_PROGRESS_TX.load(Ordering::SeqCst) == self.id || IN_PROGRESS_TX.compare_and_swap(0, self.id, Ordering::SeqCst) == 0 {
-                        let _ = rw_sender.send(WriterMsg::Query(
-                            arr.clone(),
-                            Arc::new(move |q| match q {
-                                Ok(v) => {
-                                    read_byte.sum(v.len());
-
-                                    cb(Ok(v))
-                                },
-                                Err(e) => cb(Err(e.to_string())),
-                            }),
-                        ));
-                        break;
-                    }
-                    thread::sleep_ms(20);
-                    retry -= 1;
-                }
-
-                if retry == 0 {
+                let handle = self.handle();
+                if self.checkout_writer() {
+                    let _ = handle.query(
+                        arr.clone(),
+                        pi_db_callback(Arc::new(move |q: SResult<Vec<TabKV>>| match q {
+                            Ok(v) => {
+                                read_byte.sum(v.len());
+
+                                cb(Ok(v))
+                            },
+                            Err(e) => cb(Err(e.to_string())),
+                        })),
+                        token,
+                    );
+                } else {
                     let t = Box::new(move |_| {
-                        cb1(Err("query timeout".to_string()));
+                        cb(Err("query timeout".to_string()));
                     });
                     cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("query timeout callback"));
                 }
             }
 
             false => {
-                let sender = LMDB_SERVICE.lock().unwrap().ro_sender(&self.tab).unwrap();
-                let _ = sender.send(ReaderMsg::Query(
+                let r = self.handle().query(
                     arr,
-                    Arc::new(move |q| match q {
+                    pi_db_callback(Arc::new(move |q: SResult<Vec<TabKV>>| match q {
                         Ok(v) => {
                             read_byte.sum(v.len());
 
                             cb(Ok(v))
                         },
                         Err(e) => cb(Err(e.to_string())),
-                    }),
-                ));
+                    })),
+                    token,
+                );
+                // 读线程队列已满时直接返回Busy，由调用者决定是否重试
+                if let Err(e) = r {
+                    return Some(Err(e.to_string()));
+                }
             }
         }
 
@@ -296,6 +657,80 @@ impl TabTxn for LmdbTableTxn {
 
         None
     }
+}
+
+/**
+* 子事务，缓存一批推测性的修改，失败时只丢弃这批修改
+* 子事务没有自己的读视图，也不能查询，修改提交前只缓存在内存中，通过父事务的查询读不到，包括子事务自己的修改
+* 提交后修改并入父事务的缓存，与父事务自己的修改一样在预提交时写入，前置条件也在预提交时校验
+*/
+pub struct NestedTxn {
+    parent: u64,
+    mods: Mutex<Vec<TabKV>>,
+    conds: Mutex<Vec<Condition>>,
+}
+
+impl NestedTxn {
+    // 缓存修改，不写入读写事务
+    pub fn modify(&self, arr: Arc<Vec<TabKV>>) {
+        self.mods.lock().unwrap().extend(arr.iter().cloned());
+    }
+
+    // 缓存带前置条件的修改，条件在父事务提交时校验
+    pub fn modify_if(&self, arr: Arc<Vec<TabKV>>, preconditions: Vec<Option<Precondition>>) -> SResult<()> {
+        if arr.len() != preconditions.len() {
+            return Err("preconditions not match modifies".to_string());
+        }
+        self.conds.lock().unwrap().extend(arr
+            .iter()
+            .zip(preconditions.into_iter())
+            .filter_map(|(kv, p)| p.map(|expect| Condition {
+                tab: kv.tab.clone(),
+                key: kv.key.clone(),
+                expect,
+            })));
+        self.modify(arr);
+        Ok(())
+    }
+
+    // 将子事务的修改并入父事务
+    pub fn commit(self) {
+        let mods = self.mods.into_inner().unwrap();
+        debug!("nested txn commit to txid: {:?}, modifies: {:?}", self.parent, mods);
+        MODS.lock().unwrap().entry(self.parent).or_insert_with(Vec::new).extend(mods);
+        let conds = self.conds.into_inner().unwrap();
+        if !conds.is_empty() {
+            CONDS.lock().unwrap().entry(self.parent).or_insert_with(Vec::new).extend(conds);
+        }
+    }
+
+    // 丢弃子事务的修改
+    pub fn abort(self) {
+        debug!("nested txn abort for txid: {:?}, modifies: {:?}", self.parent, self.mods.lock().unwrap());
+    }
+}
+
+impl TabTxn for LmdbTableTxn {
+    fn key_lock(
+        &self,
+        _arr: Arc<Vec<TabKV>>,
+        _lock_time: usize,
+        _readonly: bool,
+        _cb: TxCallback,
+    ) -> DBResult {
+        // 写线程是唯一的写者，读写事务天然串行，不需要键锁
+        Some(Ok(()))
+    }
+
+    fn query(
+        &self,
+        arr: Arc<Vec<TabKV>>,
+        _lock_time: Option<usize>,
+        _readonly: bool,
+        cb: TxQueryCallback,
+    ) -> Option<SResult<Vec<TabKV>>> {
+        self.query_with_token(arr, cb, None)
+    }
 
     fn modify(
         &self,
@@ -306,11 +741,24 @@ impl TabTxn for LmdbTableTxn {
     ) -> DBResult {
         debug!("MODIFY: txid: {:?}, tab: {:?}, len: {:?}", self.id, self.tab, arr);
 
-        let sender = LMDB_SERVICE.lock().unwrap().rw_sender().unwrap();
-        let _ = sender.send(WriterMsg::Modify(Arc::new(move |m| match m {
-            Ok(_) => cb(Ok(())),
-            Err(e) => cb(Err(e.to_string())),
-        })));
+        let cb = pi_db_callback(cb);
+        let handle = self.handle();
+        // 超出写入限速时推迟确认修改，调用者在确认后才能继续修改或提交
+        let bytes = arr.iter().map(|kv| kv.key.len() + kv.value.as_ref().map_or(0, |v| v.len())).sum::<usize>();
+        match throttle::reserve(bytes as u64) {
+            Some(delay) => {
+                throttle::after(delay, move || {
+                    if let Err(e) = handle.modify(cb.clone()) {
+                        cb(Err(e.to_string()));
+                    }
+                });
+            }
+            None => {
+                if let Err(e) = handle.modify(cb.clone()) {
+                    return Some(Err(e.to_string()));
+                }
+            }
+        }
 
         let data = arr.iter().cloned().collect::<Vec<TabKV>>();
 
@@ -349,36 +797,31 @@ impl TabTxn for LmdbTableTxn {
         cb: Arc<Fn(IterResult)>,
     ) -> Option<IterResult> {
         debug!("create iter for txid: {:?}, tab: {:?}, key: {:?}, descending: {:?}", self.id, self.tab, key, descending);
+        if self.writable && is_timed_out(self.id) {
+            return Some(Err(StoreError::TxnTimedOut.to_string()));
+        }
         let (tx, rx) = bounded(1);
         match self.writable {
             true => {
-                let rw_sender = LMDB_SERVICE.lock().unwrap().rw_sender().unwrap();
-                let mut retry = 250;
-                while retry > 0 {
-                    if IN_PROGRESS_TX.load(Ordering::SeqCst) == self.id || IN_PROGRESS_TX.compare_and_swap(0, self.id, Ordering::SeqCst) == 0 {
-                        let _ = rw_sender.send(WriterMsg::CreateItemIter(
-                            descending,
-                            tab.clone(),
-                            key.clone(),
-                            tx.clone(),
-                        ));
-                        break;
-                    }
-                    thread::sleep_ms(20);
-                    retry -= 1;
-                }
-
-                if retry == 0 {
+                let rw_sender = rw_sender(tab);
+                if self.checkout_writer() {
+                    let _ = rw_sender.send(WriterMsg::CreateItemIter(
+                        descending,
+                        tab.clone(),
+                        key.clone(),
+                        tx.clone(),
+                    ));
+                } else {
                     let t = Box::new(move |_| {
                         cb(Err("create iter timeout".to_string()));
                     });
                     cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("create iter timeout callback"));
+                    return None;
                 }
             }
 
             false => {
-                let ro_sender = LMDB_SERVICE.lock().unwrap().ro_sender(&tab).unwrap();
-                let _ = ro_sender.send(ReaderMsg::CreateItemIter(
+                let _ = ro_sender(tab, Priority::Low).send(ReaderMsg::CreateItemIter(
                     descending,
                     tab.clone(),
                     key.clone(),
@@ -405,14 +848,29 @@ impl TabTxn for LmdbTableTxn {
         }
     }
 
+    // 只迭代键的游标，不读取值，适合值很大的表
     fn key_iter(
         &self,
         key: Option<Bin>,
         descending: bool,
-        filter: Filter,
+        _filter: Filter,
         _cb: Arc<Fn(KeyIterResult)>,
     ) -> Option<KeyIterResult> {
-        None
+        debug!("create key iter for txid: {:?}, tab: {:?}, key: {:?}, descending: {:?}", self.id, self.tab, key, descending);
+        let (tx, rx) = bounded(1);
+        if let Err(e) = try_ro_send(&self.tab, Priority::Low, ReaderMsg::CreateItemIter(descending, self.tab.clone(), key, tx.clone())) {
+            return Some(Err(e.to_string()));
+        }
+        match rx.recv() {
+            Ok(k) => Some(Ok(Box::new(LmdbKeysIter {
+                desc: descending,
+                tab: self.tab.clone(),
+                cur_key: k,
+                sender: tx,
+                receiver: rx,
+            }))),
+            Err(e) => Some(Err(format!("create key iter failed: {:?}", e))),
+        }
     }
 
     fn index(
@@ -424,10 +882,14 @@ impl TabTxn for LmdbTableTxn {
         _filter: Filter,
         _cb: Arc<Fn(IterResult)>,
     ) -> Option<IterResult> {
-        None
+        Some(Err("lmdb tab not support index".to_string()))
     }
 
+    // 只统计本表的记录数，不包括环境中的其它表
     fn tab_size(&self, cb: Arc<Fn(SResult<usize>)>) -> Option<SResult<usize>> {
+        if let Err(e) = try_ro_send(&self.tab, Priority::Low, ReaderMsg::TableSize(self.tab.clone(), pi_db_callback(cb.clone()))) {
+            cb(Err(e.to_string()));
+        }
         None
     }
 }
@@ -441,6 +903,8 @@ pub struct LmdbItemsIter {
     sender: Sender<Option<Bin>>,
     receiver: Receiver<Option<Bin>>,
     _filter: Filter,
+    cancel: CancelToken,            //取消令牌
+    scan_filter: Option<Arc<ScanFilter>>,   //在读线程中执行的过滤条件
     iter_count:		PrefCounter,	//迭代计数
     iter_byte:		PrefCounter,	//迭代字节
 }
@@ -463,6 +927,8 @@ impl LmdbItemsIter {
             sender,
             receiver,
             _filter,
+            cancel: CancelToken::new(),
+            scan_filter: None,
             iter_count: GLOBAL_PREF_COLLECT.
                 new_dynamic_counter(
                     Atom::from(LMDB_TABLE_PREFIX.to_string() + &tab + LMDB_TABLE_ITER_COUNT_SUFFIX), 0).unwrap(),
@@ -473,6 +939,49 @@ impl LmdbItemsIter {
     }
 }
 
+impl LmdbItemsIter {
+    // 获取迭代器的取消令牌，取消后的next以Cancelled错误调用回调
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /**
+    * 将迭代器定位到第一个大于等于键的位置，下一次next从该位置开始，迭代方向不变
+    * @param key 定位的键
+    * @param cb 定位回调，同步完成时不调用
+    * @returns 返回定位结果
+    */
+    pub fn seek(&mut self, key: Bin, cb: Arc<Fn(SResult<()>)>) -> Option<SResult<()>> {
+        self.seek_to(key, false, cb)
+    }
+
+    /**
+    * 将迭代器定位到最后一个小于等于键的位置，下一次next从该位置开始，迭代方向不变
+    * @param key 定位的键
+    * @param cb 定位回调，同步完成时不调用
+    * @returns 返回定位结果
+    */
+    pub fn seek_for_prev(&mut self, key: Bin, cb: Arc<Fn(SResult<()>)>) -> Option<SResult<()>> {
+        self.seek_to(key, true, cb)
+    }
+
+    fn seek_to(&mut self, key: Bin, for_prev: bool, _cb: Arc<Fn(SResult<()>)>) -> Option<SResult<()>> {
+        if self.cancel.is_cancelled() {
+            return Some(Err(StoreError::Cancelled.to_string()));
+        }
+        if let Err(e) = try_ro_send(&self.tab, Priority::High, ReaderMsg::Seek(self.tab.clone(), key, for_prev, self.sender.clone())) {
+            return Some(Err(e.to_string()));
+        }
+        match self.receiver.recv() {
+            Ok(k) => {
+                self.cur_key = k;
+                Some(Ok(()))
+            }
+            Err(e) => Some(Err(e.to_string())),
+        }
+    }
+}
+
 impl Iter for LmdbItemsIter {
     type Item = (Bin, Bin);
 
@@ -481,32 +990,50 @@ impl Iter for LmdbItemsIter {
 
         debug!("next item: txid: {:?}, tab: {:?}, cur_key: {:?}, descending: {:?}", self.txid, self.tab, self.cur_key, self.desc);
 
-        if self.cur_key.is_none() {
+        if self.cancel.is_cancelled() {
+            cb(Err(StoreError::Cancelled.to_string()))
+        } else if self.cur_key.is_none() {
             cb(Ok(None))
         } else {
-            let sender = LMDB_SERVICE.lock().unwrap().ro_sender(&self.tab).unwrap();
+            let sender = ro_sender(&self.tab, Priority::Low);
 
             let iter_byte = self.iter_byte.clone();
 
-            let _ = sender.send(ReaderMsg::NextItem(
-                self.desc,
-                self.tab.clone(),
-                self.cur_key.clone(),
-                Arc::new(move |item| match item {
-                    Ok(Some(v)) => {
-                        iter_byte.sum(v.0.len() + v.1.len());
+            let cb = pi_db_callback(Arc::new(move |item: NextResult<Self::Item>| match item {
+                Ok(Some(v)) => {
+                    iter_byte.sum(v.0.len() + v.1.len());
 
-                        cb(Ok(Some(v)));
-                    }
-                    Ok(None) => {
-                        cb(Ok(None));
-                    }
-                    Err(e) => {
-                        cb(Err(e.to_string()));
-                    }
-                }),
-                self.sender.clone(),
-            ));
+                    cb(Ok(Some(v)));
+                }
+                Ok(None) => {
+                    cb(Ok(None));
+                }
+                Err(e) => {
+                    cb(Err(e.to_string()));
+                }
+            }));
+            // 软删除的表按空过滤条件迭代，跳过墓碑
+            let filter = match &self.scan_filter {
+                None if policy::soft_delete(&self.tab) => Some(Arc::new(ScanFilter::default())),
+                filter => filter.clone(),
+            };
+            let _ = match &filter {
+                Some(filter) => sender.send(ReaderMsg::NextMatch(
+                    self.desc,
+                    self.tab.clone(),
+                    self.cur_key.clone().unwrap(),
+                    filter.clone(),
+                    cb,
+                    self.sender.clone(),
+                )),
+                None => sender.send(ReaderMsg::NextItem(
+                    self.desc,
+                    self.tab.clone(),
+                    self.cur_key.clone(),
+                    cb,
+                    self.sender.clone(),
+                )),
+            };
 
             match self.receiver.recv() {
                 Ok(v) => self.cur_key = v,
@@ -518,39 +1045,92 @@ impl Iter for LmdbItemsIter {
     }
 }
 
+/// lmdb iterator that only navigate keys
+pub struct LmdbKeysIter {
+    desc: bool,
+    tab: Atom,
+    cur_key: Option<Bin>,
+    sender: Sender<Option<Bin>>,
+    receiver: Receiver<Option<Bin>>,
+}
+
+impl Iter for LmdbKeysIter {
+    type Item = Bin;
+
+    // 当前键已经在迭代器中，只需要读线程移动游标取得下一个键，同步返回
+    fn next(&mut self, _cb: Arc<Fn(NextResult<Self::Item>)>) -> Option<NextResult<Self::Item>> {
+        let cur = match self.cur_key.take() {
+            Some(k) => k,
+            None => return Some(Ok(None)),
+        };
+        if let Err(e) = try_ro_send(&self.tab, Priority::Low, ReaderMsg::NextKey(self.desc, self.tab.clone(), cur.clone(), self.sender.clone())) {
+            self.cur_key = Some(cur);
+            return Some(Err(e.to_string()));
+        }
+        match self.receiver.recv() {
+            Ok(k) => self.cur_key = k,
+            Err(_) => (),
+        }
+        Some(Ok(Some(cur)))
+    }
+}
+
 #[derive(Clone)]
-pub struct LmdbMetaTxn(Arc<TabTxn>);
+pub struct LmdbMetaTxn(Arc<TabTxn>, Atom, Arc<Mutex<Vec<TableOp>>>);
 
 impl LmdbMetaTxn {
-    //tab_txn 必须是Arc<FileTabTxn>
-    fn new(tab_txn: Arc<TabTxn>) -> LmdbMetaTxn {
-        LmdbMetaTxn(tab_txn)
+    //tab_txn 必须是Arc<FileTabTxn>，ware 为所属的库名
+    fn new(tab_txn: Arc<TabTxn>, ware: Atom) -> LmdbMetaTxn {
+        LmdbMetaTxn(tab_txn, ware, Arc::new(Mutex::new(Vec::new())))
     }
 }
 
-fn create_table_in_lmdb(tab: &Atom) {
-    let env = LMDB_SERVICE.lock().unwrap().get_env();
-    OPENED_TABLES
-        .write()
-        .unwrap()
-        .entry(tab.get_hash() as u64)
-        .or_insert_with(|| {
-            env
-            .as_ref()
-            .create_db(Some(tab.as_str()), DatabaseFlags::empty())
-            .expect("Fatal error: open table failed")
-        });
+// 元信息事务中复制或重命名表的操作，元信息事务提交后才在写线程中依次执行，回滚时丢弃
+enum TableOp {
+    Copy(Atom, Atom, Arc<Vec<TabKV>>),
+    Rename(Atom, Atom, Arc<Vec<TabKV>>),
+}
+
+// 依次执行表操作，任一操作失败时停止，之后的操作不再执行
+fn run_table_ops(ware: Atom, ops: Arc<Vec<TableOp>>, index: usize, cb: TxCallback) {
+    let op = match ops.get(index) {
+        Some(op) => op,
+        None => return cb(Ok(())),
+    };
+    let (ware1, ops1, cb1) = (ware.clone(), ops.clone(), cb.clone());
+    let next: TxCallback = Arc::new(move |r: SResult<()>| match r {
+        Ok(_) => run_table_ops(ware1.clone(), ops1.clone(), index + 1, cb1.clone()),
+        Err(e) => cb1(Err(e)),
+    });
+    match op {
+        TableOp::Copy(from, tab, extra) => send_table_op(&ware, from, tab, next, |cb| WriterMsg::CopyDb(from.clone(), tab.clone(), extra.clone(), cb)),
+        TableOp::Rename(tab, new_name, extra) => send_table_op(&ware, tab, new_name, next, |cb| WriterMsg::RenameDb(tab.clone(), new_name.clone(), extra.clone(), cb)),
+    }
+}
+
+// 将表绑定到库所在的环境，表在元信息提交的读写事务中创建，见table_meta.rs
+fn bind_table_to_env(ware: &Atom, tab: &Atom) {
+    LMDB_POOL.lock().unwrap().bind_tab(tab, ware.get_hash() as u64);
 }
 
 impl MetaTxn for LmdbMetaTxn {
     // 创建表、修改指定表的元数据
     fn alter(&self, tab: &Atom, meta: Option<Arc<TabMeta>>, cb: TxCallback) -> DBResult {
         debug!("META TXN: alter tab: {:?}", tab);
-        create_table_in_lmdb(tab);
+        bind_table_to_env(&self.1, tab);
         let mut key = WriteBuffer::new();
         tab.encode(&mut key);
         let key = Arc::new(key.unwrap());
 
+        // 新建的表记录初始版本和创建时间
+        let version = match meta {
+            Some(_) => match table_version(&self.1, tab) {
+                Ok(None) if lmdb_env(&self.1).is_some() => Some(schema::version_kv(&self.1, tab, &TableVersion::new(0))),
+                _ => None,
+            },
+            None => None,
+        };
+
         let value = match meta {
             Some(v) => {
                 let mut value = WriteBuffer::new();
@@ -561,22 +1141,39 @@ impl MetaTxn for LmdbMetaTxn {
         };
 
         let tabkv = TabKV {
-            ware: Atom::from("file"), // hard code
+            ware: self.1.clone(), // 提交时按库名路由到对应环境的 SINFO表
             tab: Atom::from(SINFO), // 元信息写入 SINFO表中
             key: key,
             index: 0,
             value: value,
         };
 
-        self.0.modify(Arc::new(vec![tabkv]), None, false, cb)
+        let mut modifies = vec![tabkv];
+        modifies.extend(version);
+        self.0.modify(Arc::new(modifies), None, false, cb)
     }
 
-    // 快照拷贝表
-    fn snapshot(&self, _tab: &Atom, _from: &Atom, _cb: TxCallback) -> DBResult {
+    // 快照拷贝表，元信息事务提交后在写线程中复制，回滚时不复制
+    fn snapshot(&self, tab: &Atom, from: &Atom, _cb: TxCallback) -> DBResult {
+        let meta = match stored_meta(&self.1, from) {
+            Ok(Some(meta)) => meta,
+            Ok(None) => return Some(Err(format!("tab not found: {:?}", from.to_string()))),
+            Err(e) => return Some(Err(e)),
+        };
+        let extra = copy_meta_kvs(&self.1, from, tab, meta, false);
+        self.2.lock().unwrap().push(TableOp::Copy(from.clone(), tab.clone(), Arc::new(extra)));
         Some(Ok(()))
     }
-    // 修改指定表的名字
-    fn rename(&self, _tab: &Atom, _new_name: &Atom, _cb: TxCallback) -> DBResult {
+    // 修改指定表的名字，元信息事务提交后在写线程中重命名，回滚时不重命名
+    fn rename(&self, tab: &Atom, new_name: &Atom, _cb: TxCallback) -> DBResult {
+        let meta = match stored_meta(&self.1, tab) {
+            Ok(Some(meta)) => meta,
+            Ok(None) => return Some(Err(format!("tab not found: {:?}", tab.to_string()))),
+            Err(e) => return Some(Err(e)),
+        };
+        let mut extra = vec![sinfo_kv(&self.1, tab, None)];
+        extra.extend(copy_meta_kvs(&self.1, tab, new_name, meta, true));
+        self.2.lock().unwrap().push(TableOp::Rename(tab.clone(), new_name.clone(), Arc::new(extra)));
         Some(Ok(()))
     }
 }
@@ -590,12 +1187,27 @@ impl Txn for LmdbMetaTxn {
     fn prepare(&self, timeout: usize, cb: TxCallback) -> DBResult {
         self.0.prepare(timeout, cb)
     }
-    // 提交一个事务
+    // 提交一个事务，元信息提交成功后再执行事务中的表操作
     fn commit(&self, cb: TxCallback) -> CommitResult {
-        self.0.commit(cb)
+        let ops = Arc::new(mem::replace(&mut *self.2.lock().unwrap(), Vec::new()));
+        if ops.is_empty() {
+            return self.0.commit(cb);
+        }
+        let (ware, ops1, cb1) = (self.1.clone(), ops.clone(), cb.clone());
+        match self.0.commit(Arc::new(move |r: SResult<()>| match r {
+            Ok(_) => run_table_ops(ware.clone(), ops1.clone(), 0, cb1.clone()),
+            Err(e) => cb1(Err(e)),
+        })) {
+            Some(Ok(_)) => {
+                run_table_ops(self.1.clone(), ops, 0, cb);
+                None
+            }
+            r => r,
+        }
     }
-    // 回滚一个事务
+    // 回滚一个事务，丢弃事务中的表操作
     fn rollback(&self, cb: TxCallback) -> DBResult {
+        self.2.lock().unwrap().clear();
         self.0.rollback(cb)
     }
 }
@@ -617,23 +1229,76 @@ impl DB {
     * @returns 返回Lmdb数据库，失败返回原因描述
     */
     pub fn new(name: Atom, db_size: usize) -> Result<Self, String> {
-        debug!("create new db: {:?}, db_size: {:?}", name, db_size);
-        if !Path::new(&name.to_string()).exists() {
-            let _ = fs::create_dir(name.to_string());
-        }
-
-        if db_size < 1024 * 1024 {
-            return Err("DB size must greater than 1M".to_string());
-        }
+        DB::new_with_service(name, db_size, LmdbService::new(17))
+    }
 
-        let env = Arc::new(
-            Environment::new()
-                .set_max_dbs(MAX_DBS_PER_ENV)
-                .set_map_size(db_size)
-                .set_flags(EnvironmentFlags::NO_TLS)
-                .open(Path::new(&name.to_string()))
-                .map_err(|e| e.to_string())?,
-        );
+    /**
+    * 使用指定的服务构建Lmdb数据库，每个库是一个独立的环境，拥有独立的读写线程
+    * 同一进程中可以构建多个库，库之间的表名不能重复
+    * @param name 数据库路径
+    * @param db_size 数据库文件的最大大小
+    * @param service 已配置好的服务，环境由本函数设置
+    * @returns 返回Lmdb数据库，失败返回原因描述
+    */
+    pub fn new_with_service(name: Atom, db_size: usize, service: LmdbService) -> Result<Self, String> {
+        DB::open(EnvBuilder::new(name.as_str(), db_size), service, &[])
+    }
+
+    /**
+    * 使用环境构建器构建Lmdb数据库，可以设置最大表数量、最大读事务数量、环境标志和文件权限
+    * 数据库的名称为构建器的路径
+    * @param builder 环境构建器
+    * @param service 已配置好的服务，环境由本函数设置
+    * @returns 返回Lmdb数据库，参数无效或打开失败返回原因描述
+    */
+    pub fn new_with_env(builder: EnvBuilder, service: LmdbService) -> Result<Self, String> {
+        DB::open(builder, service, &[])
+    }
+
+    /**
+    * 构建Lmdb数据库，并设置每个读写线程通道的容量
+    * 容量足够时事务可以一次发出所有消息而不必在每一步之间等待工作线程
+    * @param name 数据库路径
+    * @param db_size 数据库文件的最大大小
+    * @param capacity 每个读写线程通道的容量，0表示不限制
+    * @returns 返回Lmdb数据库，失败返回原因描述
+    */
+    pub fn new_with_queue_capacity(name: Atom, db_size: usize, capacity: usize) -> Result<Self, String> {
+        let mut service = LmdbService::new(17);
+        service.set_queue_capacity(capacity);
+        DB::new_with_service(name, db_size, service)
+    }
+
+    /**
+    * 构建Lmdb数据库，并在启动读写线程前执行未执行过的迁移
+    * @param name 数据库路径
+    * @param db_size 数据库文件的最大大小
+    * @param migrations 所有迁移，已执行的迁移会被跳过
+    * @returns 返回Lmdb数据库，失败返回原因描述
+    */
+    pub fn new_with_migrations(name: Atom, db_size: usize, migrations: &[Box<Migration>]) -> Result<Self, String> {
+        DB::open(EnvBuilder::new(name.as_str(), db_size), LmdbService::new(17), migrations)
+    }
+
+    fn open(builder: EnvBuilder, mut service: LmdbService, migrations: &[Box<Migration>]) -> Result<Self, String> {
+        let name = Atom::from(builder.path().to_string_lossy().as_ref());
+        debug!("create new db: {:?}, db_size: {:?}", name, builder.get_map_size());
+        builder.validate()?;
+        // 另一个进程正在写入同一个库时立即返回错误，而不是在第一个写事务时阻塞
+        let writer_lock = process_lock::acquire(builder.path())?;
+
+        // 上次关闭前压缩过的库，用压缩副本替换数据文件
+        if compact::swap_pending(&name.to_string())? {
+            info!("db: {:?} swapped in compacted copy", name);
+        }
+
+        let env = Arc::new(builder.build()?);
+        // 清理已退出的进程遗留在锁文件中的读槽
+        match readers::reader_check(&env) {
+            Ok(0) => (),
+            Ok(dead) => warn!("db: {:?} cleared {} stale reader slots on open", name, dead),
+            Err(e) => warn!("db: {:?} reader check failed: {:?}", name, e),
+        }
 
         // retrive meta table info of a DB
         let db = match env.open_db(Some(SINFO)) {
@@ -641,76 +1306,878 @@ impl DB {
             Err(_) => env.create_db(Some(SINFO), DatabaseFlags::empty()).expect("Failed to open db to retrive meta table"),
         };
 
-        OPENED_TABLES.write().unwrap().insert(Atom::from(SINFO.to_string()).get_hash() as u64, db);
+        // 按内容寻址的附件表
+        let blobs = env.create_db(Some(BLOBS_TAB), DatabaseFlags::empty()).map_err(|e| e.to_string())?;
+        // 大值的分块表
+        let chunks = env.create_db(Some(CHUNKS_TAB), DatabaseFlags::empty()).map_err(|e| e.to_string())?;
+        // 值校验和的影子表
+        let checksums = env.create_db(Some(CHECKSUMS_TAB), DatabaseFlags::empty()).map_err(|e| e.to_string())?;
+        // 表版本的保留表
+        let meta = env.create_db(Some(META_TAB), DatabaseFlags::empty()).map_err(|e| e.to_string())?;
+        // 键版本的影子表
+        let versions = env.create_db(Some(VERSIONS_TAB), DatabaseFlags::empty()).map_err(|e| e.to_string())?;
+        // 按提交序号记录修改的日志表
+        let changes = env.create_db(Some(CHANGELOG_TAB), DatabaseFlags::empty()).map_err(|e| e.to_string())?;
+        // 键过期时间的影子表
+        let expires = env.create_db(Some(EXPIRES_TAB), DatabaseFlags::empty()).map_err(|e| e.to_string())?;
+
+        let version = migration::run(&env, meta, migrations)?;
+        debug!("db: {:?} migrated to version: {:?}", name, version);
+
+        let env_id = name.get_hash() as u64;
+        process_lock::hold(env_id, writer_lock);
+        register_db(env_id, &Atom::from(SINFO), db)?;
+        register_db(env_id, &Atom::from(CHUNKS_TAB), chunks)?;
+        register_db(env_id, &Atom::from(BLOBS_TAB), blobs)?;
+        register_db(env_id, &Atom::from(CHECKSUMS_TAB), checksums)?;
+        register_db(env_id, &Atom::from(META_TAB), meta)?;
+        register_db(env_id, &Atom::from(VERSIONS_TAB), versions)?;
+        register_db(env_id, &Atom::from(CHANGELOG_TAB), changes)?;
+        register_db(env_id, &Atom::from(EXPIRES_TAB), expires)?;
+
+        // 打开表前载入表策略，表按策略中的比较方式创建
+        let policies = policy::load(&env, meta)?;
+        debug!("db: {:?} loaded {} table policies", name, policies);
+        // 启动工作线程前打开元信息中的所有表
+        let metas = table_meta::load(&env, env_id, db)?;
+        // 其余已存在的命名表也在启动时打开，工作线程共享表句柄
+        table_meta::preopen(&env, env_id, builder.get_max_dbs())?;
+        // 接受读写前校验表和修改日志，必要时重放或丢弃日志
+        if recovery::verify_on_open() {
+            let tabs: Vec<Atom> = metas.iter().map(|(tab, _)| tab.clone()).collect();
+            recovery::verify(&env, env_id, &tabs)?;
+        }
+
+        let mut tabs: Tabs<LmdbTable> = Tabs::new();
 
+        service.set_env(env.clone());
+        let mut pool = LMDB_POOL.lock().unwrap();
+        pool.add_service(env_id, service);
+
+        for (tab, meta) in metas {
+            pool.bind_tab(&tab, env_id);
+            tabs.set_tab_meta(tab, meta);
+        }
+        std::mem::drop(pool);
+
+        spawn_commit_thread();
+
+        tabs.set_tab_meta(
+            Atom::from(SINFO),
+            Arc::new(TabMeta::new(EnumType::Str, EnumType::Bool)),
+        );
+        tabs.set_tab_meta(
+            Atom::from(META_TAB),
+            Arc::new(TabMeta::new(EnumType::Str, EnumType::Bin)),
+        );
+
+        LMDB_WARE_CREATE_COUNT.sum(1);
+
+        Ok(DB {
+            name: name,
+            tabs: Arc::new(RwLock::new(tabs)),
+        })
+    }
+
+    /**
+    * 读取表的数据格式版本
+    * @param tab 表名
+    * @returns 返回表的版本，没有记录返回None，失败返回原因描述
+    */
+    pub fn table_version(&self, tab: &Atom) -> Result<Option<TableVersion>, String> {
+        table_version(&self.name, tab)
+    }
+
+    /**
+    * 构建修改表版本的修改，保留表的创建时间，需要在读写事务中修改__meta表提交
+    * @param tab 表名
+    * @param version 新的版本
+    * @returns 返回需要提交的修改，失败返回原因描述
+    */
+    pub fn table_version_kv(&self, tab: &Atom, version: u32) -> Result<TabKV, String> {
+        let v = match self.table_version(tab)? {
+            Some(old) => TableVersion { version, created_at: old.created_at },
+            None => TableVersion::new(version),
+        };
+        Ok(schema::version_kv(&self.name, tab, &v))
+    }
+
+    // 库所在环境的id
+    pub fn env_id(&self) -> u64 {
+        self.name.get_hash() as u64
+    }
+
+    /**
+    * 读取库最近一次提交的序号，每次写入用户表的提交分配一个单调递增的序号
+    * @returns 返回提交序号，没有提交过返回0
+    */
+    pub fn commit_seq(&self) -> Result<u64, String> {
+        let env = match lmdb_env(&self.name) {
+            Some(env) => env,
+            None => return Ok(0),
+        };
         let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
-        let mut cursor = txn.open_ro_cursor(db).map_err(|e| e.to_string())?;
+        let r = changelog::last_seq(&txn, self.name.get_hash() as u64).map_err(|e| e.to_string());
+        let _ = txn.commit();
+        r
+    }
+
+    /**
+    * 导出指定提交序号之后修改过的记录，用于增量备份，需要开启修改日志
+    * @param since 上次备份时的提交序号，为0则导出日志中的所有修改
+    * @param cb 异步返回导出时的提交序号和修改过的记录，删除的记录值为None
+    */
+    pub fn export_since(&self, since: u64, cb: Arc<Fn(SResult<(u64, Vec<TabKV>)>)>) {
+        let name = self.name.clone();
+        let env = lmdb_env(&self.name);
+        let t = Box::new(move |_| {
+            let r = match &env {
+                Some(env) => export_since(env, &name, since),
+                None => Err("export only supported by lmdb".to_string()),
+            };
+            cb(r);
+        });
+        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb export since"));
+    }
+
+    /**
+    * 删除指定提交序号及之前的修改日志，增量备份完成后调用
+    * @param until 已备份的提交序号
+    * @returns 返回删除的日志条数
+    */
+    pub fn prune_change_log(&self, until: u64) -> Result<usize, String> {
+        let env = match lmdb_env(&self.name) {
+            Some(env) => env,
+            None => return Ok(0),
+        };
+        let mut txn = env.begin_rw_txn().map_err(|e| e.to_string())?;
+        let n = changelog::prune(&mut txn, self.name.get_hash() as u64, until).map_err(|e| e.to_string())?;
+        txn.commit().map_err(|e| e.to_string())?;
+        Ok(n)
+    }
+
+    /**
+    * 重建表在历史时间点的内容，用于从错误的写入中恢复数据，需要开启修改日志
+    * 重建的记录通过回调返回，由调用者决定写回原表还是写入新表
+    * @param tab 表名
+    * @param point 恢复到的提交序号或时间
+    * @param base compact生成的全量备份目录或write_snapshot生成的快照文件，为None则从空表开始重放全部日志
+    * @param cb 异步返回表在该时间点的所有记录
+    */
+    pub fn restore_to(&self, tab: &Atom, point: RestorePoint, base: Option<String>, cb: Arc<Fn(SResult<Vec<TabKV>>)>) {
+        let name = self.name.clone();
+        let tab = tab.clone();
+        let env = lmdb_env(&self.name);
+        let _ = thread::Builder::new().name("pi_store-restore".to_string()).spawn(move || {
+            let r = match &env {
+                Some(env) => restore::restore_to(env, &name, &tab, point, base.as_ref().map(|b| b.as_str()), MAX_DBS_PER_ENV),
+                None => Err("restore only supported by lmdb".to_string()),
+            };
+            let t = Box::new(move |_| {
+                cb(r.clone());
+            });
+            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb restore to"));
+        });
+    }
+
+    /**
+    * 获取库所在环境的工作线程池统计，用于监控和诊断，只短暂持有全局锁
+    * @returns 返回线程池统计，库未打开时返回None
+    */
+    pub fn pool_stats(&self) -> Option<PoolStats> {
+        LMDB_POOL.lock().unwrap().service_by_env(self.name.get_hash() as u64).map(|s| s.stats())
+    }
+
+    /**
+    * 打开独立的读事务，读事务由表所在环境的一个读线程持有，该读线程同时处理其它消息和读事务
+    * @param tab 表名，用于选择读线程
+    * @returns 返回读事务，释放时结束
+    */
+    pub fn open_ro(&self, tab: &Atom) -> Result<RoTxn, String> {
+        let reader = LMDB_POOL
+            .lock()
+            .unwrap()
+            .service(tab)
+            .and_then(|s| s.ro_sender(tab))
+            .ok_or_else(|| format!("no env for tab: {:?}", tab.to_string()))?;
+        RoTxn::open(reader)
+    }
+
+    /**
+    * 获取库中各表的读写事务失败统计，包括回滚、写入失败、MDB_MAP_FULL和重试的次数
+    * @returns 返回发生过失败的表的统计，按回滚次数从多到少排列
+    */
+    pub fn txn_stats(&self) -> Vec<TabTxnStats> {
+        txn_stats::stats(self.name.get_hash() as u64)
+    }
+
+    /**
+    * 列出库所在环境的读槽，包括其它进程的读槽，用于排查长时间打开的读事务
+    * @param cb 异步返回读槽列表，非LMDB后端返回错误
+    */
+    pub fn readers(&self, cb: Arc<Fn(SResult<Vec<ReaderSlot>>)>) {
+        let env_id = self.name.get_hash() as u64;
+        let env = lmdb_env(&self.name);
+        let t = Box::new(move |_| {
+            let r = match &env {
+                Some(env) => readers::reader_list(env_id, env).map_err(|e| e.to_string()),
+                None => Err("readers only supported by lmdb".to_string()),
+            };
+            cb(r);
+        });
+        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb readers"));
+    }
+
+    /**
+    * 清理库所在环境中已崩溃进程遗留的读槽
+    * @returns 返回清理的读槽数量，失败返回原因描述
+    */
+    pub fn reader_check(&self) -> Result<usize, String> {
+        match lmdb_env(&self.name) {
+            Some(env) => readers::reader_check(&env).map_err(|e| e.to_string()),
+            None => Ok(0),
+        }
+    }
+
+    /**
+    * 获取库所在环境中超过告警时间的读事务，由清理失效读槽的线程定期检查，见readers::set_long_reader_policy
+    * @returns 返回最近一次检查的结果，未设置告警策略时为空
+    */
+    pub fn long_readers(&self) -> Vec<LongReader> {
+        readers::long_readers(self.name.get_hash() as u64)
+    }
+
+    /**
+    * 健康检查，在独立线程中执行一次写入、读回和回滚，返回读写延迟和内存映射使用率
+    * 适合作为存活和就绪探针，不会修改库中的数据
+    * @param cb 检查完成的回调，非LMDB后端返回错误
+    */
+    pub fn health_check(&self, cb: Arc<Fn(SResult<Health>)>) {
+        let env = match lmdb_env(&self.name) {
+            Some(env) => env,
+            None => return cb(Err("health check only supported by lmdb".to_string())),
+        };
+        let env_id = self.name.get_hash() as u64;
+        let _ = thread::Builder::new().name("pi_store-health".to_string()).spawn(move || {
+            let r = health::check(&env, env_id);
+            debug!("health check result: {:?}", r);
+            let t = Box::new(move |_| {
+                cb(r.clone());
+            });
+            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb health check"));
+        });
+    }
+
+    /**
+    * 获取库的磁盘和内存映射使用情况，用于在MDB_MAP_FULL之前告警
+    * @returns 返回使用情况，非LMDB后端返回错误
+    */
+    pub fn disk_usage(&self) -> Result<DiskUsage, String> {
+        match lmdb_env(&self.name) {
+            Some(env) => usage::disk_usage(&env, &self.name.to_string()),
+            None => Err("disk usage only supported by lmdb".to_string()),
+        }
+    }
+
+    /**
+    * 获取库的空闲页列表统计，空闲页列表持续增大是空闲页回收出现问题的最早信号
+    * @returns 返回空闲页列表统计，非LMDB后端返回错误
+    */
+    pub fn freelist_stats(&self) -> Result<FreelistStats, String> {
+        match lmdb_env(&self.name) {
+            Some(env) => usage::freelist_stats(&env),
+            None => Err("freelist stats only supported by lmdb".to_string()),
+        }
+    }
+
+    /**
+    * 获取库中各表的记录数、占用的字节数和配额，在调用线程中同步执行，只持有一个读事务
+    * @returns 返回各表的使用情况，非LMDB后端返回错误
+    */
+    pub fn quota_usage(&self) -> Result<Vec<TabUsage>, String> {
+        let env = match lmdb_env(&self.name) {
+            Some(env) => env,
+            None => return Err("quota usage only supported by lmdb".to_string()),
+        };
+        let env_id = self.name.get_hash() as u64;
+        let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+        let r = self.list()
+            .filter_map(|tab| lookup_db(env_id, &tab).map(|db| (tab, db)))
+            .map(|(tab, db)| quota::usage(&txn, db, &tab).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<TabUsage>, String>>();
+        let _ = txn.commit();
+        r
+    }
+
+    /**
+    * 导出表的一致性快照到输出，格式为长度前缀的二进制帧，见snapshot.rs
+    * 在调用线程中同步执行，导出期间持有读事务，不阻塞写入
+    * @param tab 表名
+    * @param out 输出，如文件或网络连接
+    * @returns 返回导出的记录数
+    */
+    pub fn export_snapshot<W: Write>(&self, tab: &Atom, out: &mut W) -> Result<u64, String> {
+        match lmdb_env(&self.name) {
+            Some(env) => snapshot::export(&env, self.name.get_hash() as u64, tab, out),
+            None => Err("snapshot export only supported by lmdb".to_string()),
+        }
+    }
+
+    /**
+    * 将表的一致性快照写入快照文件，文件带格式版本、记录数和校验和，见snapshot.rs
+    * 快照文件与LMDB的版本无关，可以代替compact的副本作为restore_to的全量备份
+    * @param tab 表名
+    * @param path 快照文件路径，已存在则覆盖
+    * @returns 返回快照的文件头
+    */
+    pub fn write_snapshot(&self, tab: &Atom, path: &str) -> Result<SnapshotHeader, String> {
+        let env = match lmdb_env(&self.name) {
+            Some(env) => env,
+            None => return Err("snapshot only supported by lmdb".to_string()),
+        };
+        let mut file = fs::File::create(path).map_err(|e| e.to_string())?;
+        let header = snapshot::write_snapshot(&env, self.name.get_hash() as u64, tab, &mut file)?;
+        file.sync_all().map_err(|e| e.to_string())?;
+        Ok(header)
+    }
+
+    /**
+    * 读取快照文件并导入表，校验通过后才开始写入，导入的表可以与快照中的表不同
+    * 快照中的记录全部载入内存，大表应使用import_snapshot分批导入
+    * @param tab 导入的表，必须已打开
+    * @param path 快照文件路径
+    * @returns 返回快照的文件头
+    */
+    pub fn read_snapshot(&self, tab: &Atom, path: &str) -> Result<SnapshotHeader, String> {
+        let mut input = BufReader::new(fs::File::open(path).map_err(|e| e.to_string())?);
+        let mut records = Vec::new();
+        let header = snapshot::read_snapshot(&mut input, |key, value| {
+            records.push(TabKV {
+                ware: self.name.clone(),
+                tab: tab.clone(),
+                key,
+                index: 0,
+                value: Some(value),
+            });
+            Ok(())
+        })?;
+        let (s, r) = bounded(1);
+        let _ = rw_sender(tab).send(WriterMsg::Put(Arc::new(records), WriteFlags::empty(), Arc::new(move |res| {
+            let _ = s.send(res);
+        })));
+        r.recv().map_err(|e| e.to_string())??;
+        Ok(header)
+    }
+
+    /**
+    * 对比快照文件和表的当前内容，用于校验迁移或复制的结果，在调用线程中同步执行
+    * @param tab 表名
+    * @param path 旧快照文件路径，也可以是export_snapshot导出的快照流
+    * @param emit 每个新增、删除或修改的键调用一次
+    * @returns 返回差异统计
+    */
+    pub fn diff_snapshot<F: FnMut(DiffEntry)>(&self, tab: &Atom, path: &str, emit: F) -> Result<DiffSummary, String> {
+        let env = match lmdb_env(&self.name) {
+            Some(env) => env,
+            None => return Err("snapshot diff only supported by lmdb".to_string()),
+        };
+        let mut input = BufReader::new(fs::File::open(path).map_err(|e| e.to_string())?);
+        diff::diff_table(&mut input, &env, self.name.get_hash() as u64, tab, emit)
+    }
+
+    /**
+    * 计算表内容的Merkle树，副本之间对比根哈希和子树哈希即可定位不一致的键范围
+    * 在调用线程中同步执行，读取全表但不保留记录
+    * @param tab 表名
+    * @param depth 树的深度，叶子数为2^depth，最大为16
+    */
+    pub fn merkle(&self, tab: &Atom, depth: u8) -> Result<MerkleTree, String> {
+        match lmdb_env(&self.name) {
+            Some(env) => merkle::build(&env, self.name.get_hash() as u64, tab, depth),
+            None => Err("merkle only supported by lmdb".to_string()),
+        }
+    }
+
+    /**
+    * 从表中采样小值训练zstd字典，之后写入该表的小值用字典压缩，见dict.rs
+    * 在调用线程中同步执行，训练完成后在写线程中保存字典
+    * @param tab 表名
+    * @param samples 采样的值数量
+    * @param max_dict_size 字典的最大字节数
+    * @returns 返回新字典的id
+    */
+    pub fn train_dict(&self, tab: &Atom, samples: usize, max_dict_size: usize) -> Result<u32, String> {
+        let env = match lmdb_env(&self.name) {
+            Some(env) => env,
+            None => return Err("dict compression only supported by lmdb".to_string()),
+        };
+        let samples = dict::sample(&env, self.name.get_hash() as u64, tab, samples)?;
+        dict::train(tab, &samples, max_dict_size)
+    }
+
+    /**
+    * 设置表的存储策略，保存在表所在环境中，重新打开后仍然有效，见policy.rs
+    * 比较方式只对之后创建的表生效，其余设置对之后的写入生效
+    * 在调用线程中同步执行，等待写线程提交，不能在存储的任务池中调用
+    * @param tab 表名
+    * @param policy 策略
+    */
+    pub fn set_table_policy(&self, tab: &Atom, policy: TablePolicy) -> Result<(), String> {
+        policy::set_policy(tab, policy)
+    }
+
+    // 表的存储策略，没有设置返回None，使用全局设置
+    pub fn table_policy(&self, tab: &Atom) -> Option<TablePolicy> {
+        policy::policy(tab)
+    }
+
+    /**
+    * 删除表中已过期的键，键的存活时间由表策略设置，过期的键在删除前仍可读到
+    * 在调用线程中同步执行，等待写线程提交，不能在存储的任务池中调用
+    * @param tab 表名
+    * @param limit 最多删除的键数，0表示默认值
+    * @returns 返回删除的键数
+    */
+    pub fn expire(&self, tab: &Atom, limit: usize) -> Result<usize, String> {
+        match lmdb_env(&self.name) {
+            Some(env) => policy::expire(&env, self.name.get_hash() as u64, tab, limit),
+            None => Err("expire only supported by lmdb".to_string()),
+        }
+    }
+
+    /**
+    * 永久删除软删除的表中超过保留时间的墓碑，墓碑在删除前查询不到，但仍占用空间
+    * 在调用线程中同步执行，等待写线程提交，不能在存储的任务池中调用
+    * @param tab 表名
+    * @param retention 墓碑的保留时间
+    * @param limit 最多删除的键数，0表示默认值
+    * @returns 返回删除的键数
+    */
+    pub fn purge_tombstones(&self, tab: &Atom, retention: Duration, limit: usize) -> Result<usize, String> {
+        match lmdb_env(&self.name) {
+            Some(env) => tombstone::purge(&env, self.name.get_hash() as u64, tab, retention, limit),
+            None => Err("tombstones only supported by lmdb".to_string()),
+        }
+    }
+
+    /**
+    * 异步导出表的一致性快照，读事务不能跨线程，返回的future只能在当前线程中执行
+    * @param batch 每批缓冲后写出的帧数
+    */
+    pub async fn export_snapshot_async<W: AsyncWrite + Unpin>(&self, tab: &Atom, out: &mut W, batch: usize) -> Result<u64, String> {
+        match lmdb_env(&self.name) {
+            Some(env) => snapshot::export_async(&env, self.name.get_hash() as u64, tab, out, batch).await,
+            None => Err("snapshot export only supported by lmdb".to_string()),
+        }
+    }
+
+    /**
+    * 从快照流导入表，分批在写线程中提交，每提交一批调用一次进度回调
+    * 在调用线程中同步执行，等待每批提交，不能在存储的任务池中调用
+    * @param tab 导入的表，必须已打开
+    * @param input 快照流，如export_snapshot导出的文件
+    * @param opts 导入选项，中断后以最后一次进度的键作为resume_after继续导入
+    * @param progress 进度回调
+    * @returns 返回最终进度
+    */
+    pub fn import_snapshot<R: Read>(&self, tab: &Atom, input: &mut R, opts: ImportOptions, progress: ProgressCallback) -> Result<ImportProgress, String> {
+        let sender = rw_sender(tab);
+        import::import(&self.name, tab, input, opts, move |batch, cb| {
+            if sender.send(WriterMsg::Put(batch, WriteFlags::empty(), cb.clone())).is_err() {
+                cb(Err(StoreError::Busy.to_string()));
+            }
+        }, progress)
+    }
+
+    /**
+    * 从异步的快照流导入表，与import_snapshot相同，但不阻塞线程
+    */
+    pub async fn import_snapshot_async<R: AsyncRead + Unpin>(&self, tab: &Atom, input: &mut R, opts: ImportOptions, progress: ProgressCallback) -> Result<ImportProgress, String> {
+        let sender = rw_sender(tab);
+        import::import_async(&self.name, tab, input, opts, move |batch, cb| {
+            if sender.send(WriterMsg::Put(batch, WriteFlags::empty(), cb.clone())).is_err() {
+                cb(Err(StoreError::Busy.to_string()));
+            }
+        }, progress).await
+    }
+
+    /**
+    * 导出表的一致性快照为CSV或TSV，键和值按选项编码，如十六进制或base64，便于用其它工具分析
+    * 在调用线程中同步执行，导出期间持有读事务
+    * @param tab 表名
+    * @param out 输出
+    * @param opts 格式和编码
+    * @returns 返回导出的记录数
+    */
+    pub fn export_csv<W: Write>(&self, tab: &Atom, out: &mut W, opts: &CsvOptions) -> Result<u64, String> {
+        match lmdb_env(&self.name) {
+            Some(env) => csv::export(&env, self.name.get_hash() as u64, tab, out, opts),
+            None => Err("csv export only supported by lmdb".to_string()),
+        }
+    }
+
+    /**
+    * 从CSV或TSV导入表，与import_snapshot相同，分批提交并调用进度回调
+    * @param opts 格式和编码，与导出时相同
+    */
+    pub fn import_csv<R: BufRead>(&self, tab: &Atom, input: &mut R, opts: CsvOptions, import_opts: ImportOptions, progress: ProgressCallback) -> Result<ImportProgress, String> {
+        let sender = rw_sender(tab);
+        import::import_frames(&self.name, tab, csv::frames(input, opts), import_opts, move |batch, cb| {
+            if sender.send(WriterMsg::Put(batch, WriteFlags::empty(), cb.clone())).is_err() {
+                cb(Err(StoreError::Busy.to_string()));
+            }
+        }, progress)
+    }
+
+    /**
+    * 重命名表，在写线程的一个读写事务中创建新表、复制所有记录并删除旧表，表的元信息和版本一起迁移
+    * 重命名期间占用写线程，其它读写事务等待
+    * @param old 原表名
+    * @param new 新表名，不能已存在
+    * @param cb 重命名完成的回调
+    */
+    pub fn rename_table(&self, old: &Atom, new: &Atom, cb: TxCallback) {
+        let meta = match self.tabs.read().unwrap().get(old) {
+            Some(meta) => meta,
+            None => return cb(Err(format!("tab not found: {:?}", old.to_string()))),
+        };
+        if self.tabs.read().unwrap().get(new).is_some() {
+            return cb(Err(format!("tab already exists: {:?}", new.to_string())));
+        }
+
+        let mut extra = vec![sinfo_kv(&self.name, old, None)];
+        extra.extend(copy_meta_kvs(&self.name, old, new, encode_meta(&meta), true));
+        let (db, new_tab) = (self.clone(), new.clone());
+        let cb = Arc::new(move |r: SResult<()>| {
+            if r.is_ok() {
+                db.tabs.write().unwrap().set_tab_meta(new_tab.clone(), meta.clone());
+            }
+            cb(r);
+        });
+        send_table_op(&self.name, old, new, cb, |cb| WriterMsg::RenameDb(old.clone(), new.clone(), Arc::new(extra), cb));
+    }
+
+    /**
+    * 复制表，在写线程的一个读写事务中将源表的所有记录按顺序追加写入目标表，用于制作测试数据或迁移前备份单个表
+    * 目标表不存在则按源表的元信息创建，已存在则必须为空
+    * @param src 源表名
+    * @param dst 目标表名
+    * @param cb 复制完成的回调
+    */
+    pub fn copy_table(&self, src: &Atom, dst: &Atom, cb: TxCallback) {
+        let meta = match self.tabs.read().unwrap().get(src) {
+            Some(meta) => meta,
+            None => return cb(Err(format!("tab not found: {:?}", src.to_string()))),
+        };
+        let created = self.tabs.read().unwrap().get(dst).is_none();
+        let extra = if created { copy_meta_kvs(&self.name, src, dst, encode_meta(&meta), false) } else { Vec::new() };
+        let (db, dst_tab) = (self.clone(), dst.clone());
+        let cb = Arc::new(move |r: SResult<()>| {
+            if r.is_ok() && created {
+                db.tabs.write().unwrap().set_tab_meta(dst_tab.clone(), meta.clone());
+            }
+            cb(r);
+        });
+        send_table_op(&self.name, src, dst, cb, |cb| WriterMsg::CopyDb(src.clone(), dst.clone(), Arc::new(extra), cb));
+    }
+
+    /**
+    * 开启表的分层存储，长时间未被访问的键可以迁移到压缩的冷表，查询时自动检查冷表
+    * 分层状态不持久化，库重新打开后需要再次开启
+    * @param tab 表名
+    * @param config 分层配置
+    */
+    pub fn enable_tiering(&self, tab: &Atom, config: TierConfig) -> Result<(), String> {
+        let env = lmdb_env(&self.name).ok_or_else(|| "tiering only supported by lmdb".to_string())?;
+        tiering::enable(&env, self.name.get_hash() as u64, tab, config)
+    }
+
+    /**
+    * 将表中超过空闲时间未被访问的键迁移到冷表，迁移期间占用写线程
+    * @param tab 表名
+    * @returns 返回迁移的键数量
+    */
+    pub fn migrate_cold(&self, tab: &Atom) -> Result<usize, String> {
+        self.with_tier_txn(tab, |txn, env_id, db| tiering::migrate(txn, env_id, tab, db))
+    }
+
+    /**
+    * 将冷表中的键全部迁回后关闭表的分层存储
+    * @returns 返回迁回的键数量
+    */
+    pub fn disable_tiering(&self, tab: &Atom) -> Result<usize, String> {
+        let n = self.with_tier_txn(tab, |txn, env_id, db| tiering::restore_all(txn, env_id, tab, db))?;
+        tiering::disable(self.name.get_hash() as u64, tab);
+        Ok(n)
+    }
+
+    /**
+    * 按策略将过期的记录移出库，写入归档目录中只追加的归档文件，用ArchiveReader读取
+    * 每次最多归档一批记录，可以在维护任务中反复调用直到返回0
+    * @param dir 归档根目录
+    * @param policy 归档策略
+    * @returns 返回归档的记录数
+    */
+    pub fn archive(&self, dir: &str, policy: &ArchivePolicy) -> Result<usize, String> {
+        match lmdb_env(&self.name) {
+            Some(env) => archive::run(&env, self.name.get_hash() as u64, dir, policy),
+            None => Err("archive only supported by lmdb".to_string()),
+        }
+    }
+
+    /**
+    * 读取定长重复值的表中键的所有值，见dup_fixed::set_dup_fixed
+    * @param tab 表名
+    * @param key 键
+    * @param width 值的长度
+    * @returns 返回键的所有值，键不存在返回空
+    */
+    pub fn get_dup_fixed(&self, tab: &Atom, key: &[u8], width: usize) -> Result<Vec<Bin>, String> {
+        let env = lmdb_env(&self.name).ok_or_else(|| "dup fixed only supported by lmdb".to_string())?;
+        let db = lookup_db(self.name.get_hash() as u64, tab)
+            .ok_or_else(|| format!("tab not opened: {:?}", tab.to_string()))?;
+        let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+        let r = dup_fixed::get_all(&txn, db, key, width).map_err(|e| e.to_string());
+        let _ = txn.commit();
+        r
+    }
+
+    // 取得写线程的使用权后在独立的读写事务中迁移分层数据
+    fn with_tier_txn<F>(&self, tab: &Atom, f: F) -> Result<usize, String>
+        where F: FnOnce(&mut lmdb::RwTransaction, u64, Database) -> Result<usize, String> {
+        let env = lmdb_env(&self.name).ok_or_else(|| "tiering only supported by lmdb".to_string())?;
+        let env_id = self.name.get_hash() as u64;
+        let db = lookup_db(env_id, tab).ok_or_else(|| format!("tab not opened: {:?}", tab.to_string()))?;
+        let txid = WITH_TXN_ID.fetch_add(1, Ordering::SeqCst);
+        let _writer = WriterGuard::checkout(env_id, txid).ok_or_else(|| "acquire writer timeout".to_string())?;
+        env.begin_rw_txn().map_err(|e| e.to_string()).and_then(|mut txn| {
+            let n = f(&mut txn, env_id, db)?;
+            txn.commit().map(|_| n).map_err(|e| e.to_string())
+        })
+    }
+
+    /**
+    * 批量导入按键升序排列的键值，比逐批修改快得多，用于大表的初始导入
+    * 导入期间占用写线程，键必须严格升序且大于表中已有的键，否则导入停止并返回错误，已提交的记录保留
+    * 导入失败后丢弃流中剩余的记录，发送端关闭后才回调，错误中包含已提交的记录数
+    * @param tab 表名
+    * @param stream 键值的通道，发送端关闭后导入结束
+    * @param commit_every 每导入多少条记录提交一次
+    * @param cb 导入完成的回调，参数为导入的记录数
+    */
+    pub fn bulk_load(&self, tab: &Atom, stream: Receiver<(Bin, Bin)>, commit_every: usize, cb: SendCallback<SResult<usize>>) {
+        let txid = WITH_TXN_ID.fetch_add(1, Ordering::SeqCst);
+        let writer = match WriterGuard::checkout(env_id(tab), txid) {
+            Some(writer) => writer,
+            None => return cb(Err("acquire writer timeout".to_string())),
+        };
+        // 导入结束后回调释放时归还写线程
+        let cb: SendCallback<SResult<usize>> = Arc::new(move |r| {
+            let _ = &writer;
+            cb(r);
+        });
+        let _ = rw_sender(tab).send(WriterMsg::BulkLoad(tab.clone(), stream, commit_every, cb));
+    }
+
+    /**
+    * 压缩复制库，生成去碎片的数据文件，回收已删除表和空闲页占用的空间
+    * 复制在独立线程中执行，不阻塞读写
+    * @param dest 副本所在的目录，不存在则创建，必须为空
+    * @param swap 是否在库正常关闭后下次打开时用副本替换数据文件
+    * @param cb 复制完成的回调
+    */
+    pub fn compact(&self, dest: &str, swap: bool, cb: Arc<Fn(SResult<()>)>) {
+        let env = match lmdb_env(&self.name) {
+            Some(env) => env,
+            None => return cb(Err("compact only supported by lmdb".to_string())),
+        };
+        let name = self.name.clone();
+        let dest = dest.to_string();
+        let _ = thread::Builder::new().name("pi_store-compact".to_string()).spawn(move || {
+            let r = fs::create_dir_all(&dest)
+                .map_err(|e| e.to_string())
+                .and_then(|_| compact::copy_compact(&env, &dest))
+                .and_then(|_| if swap { compact::mark_swap(&name.to_string(), &dest) } else { Ok(()) });
+            debug!("compact db: {:?} to {:?}, result: {:?}", name, dest, r);
+            let t = Box::new(move |_| {
+                cb(r.clone());
+            });
+            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb compact"));
+        });
+    }
+
+    /**
+    * 构建纯内存数据库，与Lmdb数据库使用相同的消息协议，不创建任何文件
+    * @param name 数据库名
+    * @returns 返回内存数据库
+    */
+    pub fn new_in_memory(name: Atom) -> Result<Self, String> {
+        debug!("create new memory db: {:?}", name);
+        let mut service = LmdbService::new(17);
+        service.use_mem_store();
+        DB::new_with_backend(name, service)
+    }
+
+    /**
+    * 构建同步执行的纯内存数据库，不启动工作线程，消息和回调在发送者的线程中按发送顺序执行
+    * 用于编写确定性的测试，回调中可以继续操作数据库
+    * @param name 数据库名
+    * @returns 返回内存数据库
+    */
+    pub fn new_inline(name: Atom) -> Result<Self, String> {
+        debug!("create new inline memory db: {:?}", name);
+        let mut service = LmdbService::new(1);
+        service.use_mem_store();
+        service.set_inline(true);
+        DB::new_with_backend(name, service)
+    }
+
+    /**
+    * 构建基于RocksDB的数据库，与Lmdb数据库使用相同的消息协议
+    * @param name 数据库路径
+    * @returns 返回RocksDB数据库，失败返回原因描述
+    */
+    pub fn new_rocksdb(name: Atom) -> Result<Self, String> {
+        debug!("create new rocksdb: {:?}", name);
+        let mut service = LmdbService::new(17);
+        service.use_rocks_store(&name.to_string())?;
+        DB::new_with_backend(name, service)
+    }
+
+    // 启动已选定的非LMDB后端
+    fn new_with_backend(name: Atom, service: LmdbService) -> Result<Self, String> {
+        LMDB_POOL.lock().unwrap().add_service(name.get_hash() as u64, service);
+        spawn_commit_thread();
 
         let mut tabs: Tabs<LmdbTable> = Tabs::new();
+        tabs.set_tab_meta(
+            Atom::from(SINFO),
+            Arc::new(TabMeta::new(EnumType::Str, EnumType::Bool)),
+        );
 
-        LMDB_SERVICE.lock().unwrap().set_env(env.clone());
-        LMDB_SERVICE.lock().unwrap().start();
+        LMDB_WARE_CREATE_COUNT.sum(1);
 
-        let rw_sender = LMDB_SERVICE.lock().unwrap().rw_sender().unwrap();
+        Ok(DB {
+            name: name,
+            tabs: Arc::new(RwLock::new(tabs)),
+        })
+    }
+}
 
+// 启动最终提交线程，接收事务管理器的提交通知并交给各环境的写线程，整个进程只启动一次
+fn spawn_commit_thread() {
+    COMMIT_THREAD.call_once(|| {
         let _ = thread::spawn(move || {
             debug!("start thread serving for the finally commit");
             loop {
                 match COMMIT_CHAN.1.recv() {
                     Ok(CommitChan(txid, sndr)) => {
                         debug!("receive commit notification for txid: {:?} ", txid.time());
-                        match MODS.lock().unwrap().remove(&txid.time()) {
-                            Some(v) => {
-                                debug!("modifications to be committed: {:?}", v);
-                                let _ = rw_sender.send(WriterMsg::Commit(Arc::new(v.clone()), Arc::new(move |c| match c {
-                                    Ok(_) => {
-                                        debug!("txid: {:?} finnaly committed", txid.time());
-                                        let _ = sndr.send(Arc::new(v.clone()));
-                                    }
-                                    Err(e) =>{
-                                        warn!("txid: {:?} commit failed {:?}", txid.time(), e);
-                                    }
-                                })));
-                            }
-                            None => {
-                                let _ = rw_sender.send(WriterMsg::Commit(Arc::new(vec![]), Arc::new(move |c| match c {
-                                    Ok(_) => {
-                                        debug!("non write txid: {:?} finnaly committed", txid.time());
-                                        let _ = sndr.send(Arc::new(vec![]));
-                                    }
-                                    Err(e) =>{
-                                        warn!("non write txid: {:?} commit failed {:?}", txid.time(), e);
-                                    }
-                                })));
-                            }
+                        // 超时的事务已被回滚，丢弃超时前后的所有修改，回复空的修改使事务管理器结束等待
+                        // 超时标记保留到表事务提交，由表事务的提交回调返回失败
+                        if is_timed_out(txid.time()) {
+                            MODS.lock().unwrap().remove(&txid.time());
+                            CONDS.lock().unwrap().remove(&txid.time());
+                            clear_txn_state(txid.time());
+                            warn!("txid: {:?} commit failed {:?}", txid.time(), StoreError::TxnTimedOut);
+                            let _ = sndr.send(Arc::new(Vec::new()));
+                            continue;
                         }
+                        let v = MODS.lock().unwrap().remove(&txid.time()).unwrap_or_else(Vec::new);
+                        let conditions = CONDS.lock().unwrap().remove(&txid.time()).unwrap_or_else(Vec::new);
+                        clear_txn_state(txid.time());
+                        debug!("modifications to be committed: {:?}", v);
+                        commit_to_envs(txid.time(), v, conditions, Arc::new(move |v| {
+                            let _ = sndr.send(v);
+                        }));
                     }
                     Err(_) => {}
                 }
             }
         });
+    });
+}
 
-        for kv in cursor.iter() {
-            tabs.set_tab_meta(
-                Atom::decode(&mut ReadBuffer::new(kv.0, 0)).unwrap(),
-                Arc::new(TabMeta::decode(&mut ReadBuffer::new(kv.1, 0)).unwrap()),
-            );
+/**
+* 按环境分组提交修改，每个环境的写线程都会收到提交消息(可能为空)，用于结束该环境中打开的读写事务
+* 所有环境都提交成功后才通知事务管理器；跨环境的提交不是原子的，某个环境失败时其它环境的修改不会回滚
+* 前置条件只在所属的环境中原子地校验
+*/
+fn commit_to_envs(txid: u64, modifies: Vec<TabKV>, conditions: Vec<Condition>, notify: Arc<Fn(Arc<Vec<TabKV>>)>) {
+    let pool = LMDB_POOL.lock().unwrap();
+    let mut groups: HashMap<u64, Vec<TabKV>> = pool.services().map(|(env_id, _)| (*env_id, vec![])).collect();
+    let mut cond_groups: HashMap<u64, Vec<Condition>> = HashMap::new();
+    for c in conditions.into_iter() {
+        match pool.env_of(&c.tab) {
+            Some(env_id) => cond_groups.entry(env_id).or_insert_with(Vec::new).push(c),
+            None => warn!("txid: {:?} no env for tab: {:?}, precondition ignored", txid, c.tab),
         }
+    }
+    for kv in modifies.iter() {
+        // 元信息和表版本按库名路由，其它表按表所属的环境路由
+        let env_id = if kv.tab.as_str() == SINFO || kv.tab.as_str() == META_TAB {
+            Some(kv.ware.get_hash() as u64)
+        } else {
+            pool.env_of(&kv.tab)
+        };
+        match env_id.and_then(|id| groups.get_mut(&id)) {
+            Some(group) => group.push(kv.clone()),
+            None => warn!("txid: {:?} no env for tab: {:?}, modify ignored", txid, kv.tab),
+        }
+    }
 
-        tabs.set_tab_meta(
-            Atom::from(SINFO),
-            Arc::new(TabMeta::new(EnumType::Str, EnumType::Bool)),
-        );
-
-        std::mem::drop(cursor);
-        let _ = txn.commit().unwrap();
-
-        LMDB_WARE_CREATE_COUNT.sum(1);
+    // 同步执行模式下发送时会执行消息，发送前释放全局锁
+    let mut senders: HashMap<u64, WorkerSender<WriterMsg>> = groups
+        .keys()
+        .filter_map(|env_id| pool.service_by_env(*env_id).and_then(|s| s.rw_sender()).map(|s| (*env_id, s)))
+        .collect();
+    drop(pool);
+
+    // 只等待有写线程的环境回复，有修改的环境没有写线程时整个提交失败
+    let failed = Arc::new(AtomicBool::new(false));
+    groups.retain(|env_id, group| {
+        if senders.contains_key(env_id) {
+            return true;
+        }
+        if !group.is_empty() {
+            warn!("txid: {:?} no writer for env: {:?}, commit failed", txid, env_id);
+            failed.store(true, Ordering::SeqCst);
+        }
+        false
+    });
+    let modifies = Arc::new(modifies);
+    if groups.is_empty() {
+        if !failed.load(Ordering::SeqCst) {
+            notify(modifies);
+        }
+        return;
+    }
 
-        Ok(DB {
-            name: name,
-            tabs: Arc::new(RwLock::new(tabs)),
-        })
+    let remaining = Arc::new(AtomicUsize::new(groups.len()));
+    let total = Arc::new(Mutex::new(CommitStats::default()));
+    for (env_id, group) in groups {
+        let rw_sender = senders.remove(&env_id).unwrap();
+        let remaining = remaining.clone();
+        let failed = failed.clone();
+        let total = total.clone();
+        let modifies = modifies.clone();
+        let notify = notify.clone();
+        let conditions = cond_groups.remove(&env_id).unwrap_or_else(Vec::new);
+        let _ = rw_sender.send(WriterMsg::Commit(txid, Arc::new(group), Arc::new(conditions), pi_db_callback(Arc::new(move |c: SResult<CommitStats>| {
+            match c {
+                Ok(stats) => total.lock().unwrap().merge(&stats),
+                Err(e) => {
+                    failed.store(true, Ordering::SeqCst);
+                    warn!("txid: {:?} commit failed in env: {:?}, {:?}", txid, env_id, e);
+                }
+            }
+            if remaining.fetch_sub(1, Ordering::SeqCst) == 1 && !failed.load(Ordering::SeqCst) {
+                let stats = *total.lock().unwrap();
+                debug!("txid: {:?} finnaly committed, {:?}", txid, stats);
+                txn_stats::observe_commit(txid, &stats);
+                notify(modifies.clone());
+            }
+        }))));
     }
 }
 
@@ -786,6 +2253,7 @@ impl WareSnapshot for LmdbSnapshot {
             self.tab_txn(&Atom::from(SINFO), id, true, Box::new(|_r| {}))
                 .unwrap()
                 .expect("meta_txn"),
+            (self.0).name.clone(),
         ))
     }
     // 元信息预提交
@@ -808,7 +2276,339 @@ impl WareSnapshot for LmdbSnapshot {
     fn notify(&self, evt: Event) {}
 }
 
+/**
+* 在表所在环境的写线程中执行事务闭包，闭包返回成功则提交，返回错误则回滚
+* 写线程忙时自动重试，闭包可能被执行多次，等待写线程使用权超时则直接返回错误
+* @param tab 表名，用于选择环境，闭包可以读写同一环境中的其它表
+* @param f 事务闭包
+* @returns 返回闭包的结果，失败返回原因描述
+*/
+pub fn with_txn<T, F>(tab: &Atom, f: F) -> Result<T, String>
+    where T: Send + 'static,
+          F: Fn(&mut TxnOps) -> Result<T, String> + Send + Sync + 'static {
+    let f = Arc::new(f);
+    let mut last_error = String::new();
+    for _ in 0..WITH_TXN_RETRY {
+        let txid = WITH_TXN_ID.fetch_add(1, Ordering::SeqCst);
+        // 等待使用权已到超时时间，不再重试
+        let _writer = WriterGuard::checkout(env_id(tab), txid).ok_or_else(|| "acquire writer timeout".to_string())?;
+
+        let result = Arc::new(Mutex::new(None));
+        let (f1, result1) = (f.clone(), result.clone());
+        let (tx, rx) = bounded(1);
+        let exec: TxnFn = Box::new(move |ops| {
+            let t = f1(ops)?;
+            *result1.lock().unwrap() = Some(t);
+            Ok(())
+        });
+        let _ = rw_sender(tab).send(WriterMsg::Exec(txid, exec, tx));
+
+        match rx.recv() {
+            Ok(Ok(_)) => return result.lock().unwrap().take().ok_or_else(|| "txn closure without result".to_string()),
+            Ok(Err(ref e)) if *e == StoreError::Busy.to_string() => last_error = e.clone(),
+            Ok(Err(e)) => return Err(e),
+            Err(e) => return Err(e.to_string()),
+        }
+        thread::sleep(WITH_TXN_RETRY_INTERVAL);
+    }
+    Err(last_error)
+}
+
+/**
+* 会话，在一系列操作期间独占表所在环境的写线程和它的读写事务
+* 会话中的修改直接写入写线程的读写事务，之后的查询在同一个读写事务中执行，总能读到会话自己未提交的修改
+* 会话持有写线程期间其它写入都在等待，应尽快提交或回滚，未结束的会话被丢弃时自动回滚
+*/
+pub struct Session {
+    id: u64,
+    sender: WorkerSender<WriterMsg>,
+    writer: WriterGuard,        //写线程的使用权，提交或回滚完成后归还
+    failed: Arc<AtomicBool>,    //修改失败后读写事务已回滚，会话不能继续使用
+    used: AtomicBool,           //是否已打开读写事务
+    finished: AtomicBool,       //已提交或回滚
+}
+
+impl Session {
+    /**
+    * 开始会话，等待获取表所在环境的写线程
+    * @param tab 表名，用于选择环境，会话可以读写同一环境中的其它表
+    * @returns 返回会话，获取写线程超时返回错误
+    */
+    pub fn begin(tab: &Atom) -> Result<Session, String> {
+        let id = WITH_TXN_ID.fetch_add(1, Ordering::SeqCst);
+        let writer = WriterGuard::checkout(env_id(tab), id).ok_or_else(|| "acquire writer timeout".to_string())?;
+        Ok(Session {
+            id,
+            sender: rw_sender(tab),
+            writer,
+            failed: Arc::new(AtomicBool::new(false)),
+            used: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+        })
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    // 会话是否还可以继续使用，失败时返回原因
+    fn check(&self) -> SResult<()> {
+        if is_timed_out(self.id) {
+            self.failed.store(true, Ordering::SeqCst);
+            return Err(StoreError::TxnTimedOut.to_string());
+        }
+        if self.failed.load(Ordering::SeqCst) {
+            return Err("session aborted by previous error".to_string());
+        }
+        Ok(())
+    }
+
+    /**
+    * 将修改写入会话的读写事务，修改失败时整个会话回滚
+    * @param arr 修改的键值，value为None表示删除
+    * @param cb 写入完成的回调
+    */
+    pub fn modify(&self, arr: Arc<Vec<TabKV>>, cb: WriteCallback) {
+        if let Err(e) = self.check() {
+            return cb(Err(e));
+        }
+        self.used.store(true, Ordering::SeqCst);
+        let failed = self.failed.clone();
+        let _ = self.sender.send(WriterMsg::Prepare(self.id, arr, Arc::new(Vec::new()), Arc::new(move |r: SResult<()>| {
+            if r.is_err() {
+                failed.store(true, Ordering::SeqCst);
+            }
+            cb(r)
+        })));
+    }
+
+    /**
+    * 在会话的读写事务中查询，结果包括会话自己未提交的修改
+    * @param arr 查询的键
+    * @param cb 查询回调
+    */
+    pub fn query(&self, arr: Arc<Vec<TabKV>>, cb: QueryCallback) {
+        if let Err(e) = self.check() {
+            return cb(Err(e));
+        }
+        self.used.store(true, Ordering::SeqCst);
+        let _ = self.sender.send(WriterMsg::Query(arr, cb, None));
+    }
+
+    /**
+    * 提交会话的所有修改并释放写线程
+    * @param cb 提交完成的回调
+    */
+    pub fn commit(self, cb: WriteCallback) {
+        if let Err(e) = self.check() {
+            return self.rollback(Arc::new(move |_| cb(Err(e.clone()))));
+        }
+        // 没有打开读写事务，只需要释放写线程
+        if !self.used.load(Ordering::SeqCst) {
+            return self.rollback(cb);
+        }
+        self.finished.store(true, Ordering::SeqCst);
+        let _ = self.sender.send(WriterMsg::CommitPrepared(self.id, cb));
+    }
+
+    /**
+    * 回滚会话的所有修改并释放写线程
+    * @param cb 回滚完成的回调
+    */
+    pub fn rollback(self, cb: WriteCallback) {
+        self.finished.store(true, Ordering::SeqCst);
+        let _ = self.sender.send(WriterMsg::Rollback(self.id, cb));
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if !self.finished.load(Ordering::SeqCst) {
+            warn!("session: {:?} dropped without commit, rollback", self.id);
+            let _ = self.sender.send(WriterMsg::Rollback(self.id, Arc::new(|_| {})));
+        }
+    }
+}
+
+// 表的元信息以表名为键保存在SINFO表中
+fn sinfo_kv(ware: &Atom, tab: &Atom, value: Option<Bin>) -> TabKV {
+    let mut key = WriteBuffer::new();
+    tab.encode(&mut key);
+    TabKV {
+        ware: ware.clone(),
+        tab: Atom::from(SINFO),
+        key: Arc::new(key.unwrap()),
+        index: 0,
+        value,
+    }
+}
+
+fn encode_meta(meta: &Arc<TabMeta>) -> Bin {
+    let mut value = WriteBuffer::new();
+    meta.encode(&mut value);
+    Arc::new(value.unwrap())
+}
+
+// 读取已提交的表元信息
+fn stored_meta(ware: &Atom, tab: &Atom) -> Result<Option<Bin>, String> {
+    let env = lmdb_env(ware).ok_or_else(|| "table meta only supported by lmdb".to_string())?;
+    let db = lookup_db(ware.get_hash() as u64, &Atom::from(SINFO))
+        .ok_or_else(|| "meta table not opened".to_string())?;
+    let key = sinfo_kv(ware, tab, None).key;
+    let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+    let r = match txn.get(db, key.as_ref()) {
+        Ok(v) => Ok(Some(Arc::new(v.to_vec()))),
+        Err(lmdb::Error::NotFound) => Ok(None),
+        Err(e) => Err(e.to_string()),
+    };
+    let _ = txn.commit();
+    r
+}
+
+// 将源表的元信息和版本写到目标表的修改，remove为真时同时删除源表的版本
+fn copy_meta_kvs(ware: &Atom, from: &Atom, to: &Atom, meta: Bin, remove: bool) -> Vec<TabKV> {
+    let mut kvs = vec![sinfo_kv(ware, to, Some(meta))];
+    if let Ok(Some(v)) = table_version(ware, from) {
+        kvs.push(schema::version_kv(ware, to, &v));
+        if remove {
+            kvs.push(TabKV { value: None, ..schema::version_kv(ware, from, &v) });
+        }
+    }
+    kvs
+}
+
+// 在写线程中执行表的重命名或复制，执行期间占用写线程，成功后将目标表绑定到库所在的环境
+fn send_table_op<F>(ware: &Atom, src: &Atom, dst: &Atom, cb: TxCallback, msg: F)
+    where F: FnOnce(WriteCallback) -> WriterMsg {
+    let txid = WITH_TXN_ID.fetch_add(1, Ordering::SeqCst);
+    let writer = match WriterGuard::checkout(env_id(src), txid) {
+        Some(writer) => writer,
+        None => return cb(Err("acquire writer timeout".to_string())),
+    };
+    let (ware, dst) = (ware.clone(), dst.clone());
+    let cb = pi_db_callback(Arc::new(move |r: SResult<()>| {
+        // 执行完成后回调释放时归还写线程
+        let _ = &writer;
+        if r.is_ok() {
+            LMDB_POOL.lock().unwrap().bind_tab(&dst, ware.get_hash() as u64);
+        }
+        cb(r);
+    }));
+    let _ = rw_sender(src).send(msg(cb));
+}
+
+// 库所在的LMDB环境，非LMDB后端返回None
+fn lmdb_env(ware: &Atom) -> Option<Arc<Environment>> {
+    match LMDB_POOL.lock().unwrap().service_by_env(ware.get_hash() as u64) {
+        Some(service) if service.is_lmdb() => Some(service.get_env()),
+        _ => None,
+    }
+}
+
+// 在同一个读事务中读取提交序号和之后的修改，保证导出的记录与序号一致
+fn export_since(env: &Environment, ware: &Atom, since: u64) -> Result<(u64, Vec<TabKV>), String> {
+    let env_id = ware.get_hash() as u64;
+    let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+    let r = changelog::last_seq(&txn, env_id)
+        .and_then(|seq| changelog::changed_since(&txn, env_id, since).map(|c| (seq, c)))
+        .map(|(seq, c)| (seq, c.iter().map(|c| c.to_tabkv(ware)).collect()))
+        .map_err(|e| e.to_string());
+    let _ = txn.commit();
+    r
+}
+
+// 读取库中表的版本
+fn table_version(ware: &Atom, tab: &Atom) -> Result<Option<TableVersion>, String> {
+    let env = match lmdb_env(ware) {
+        Some(env) => env,
+        None => return Ok(None),
+    };
+    let db = match lookup_db(ware.get_hash() as u64, &Atom::from(META_TAB)) {
+        Some(db) => db,
+        None => return Ok(None),
+    };
+    let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+    let r = schema::read(&txn, db, tab);
+    let _ = txn.commit();
+    r
+}
+
+// 取出事务中满足条件的缓存项，其余的保留
+fn take_tab_items<T, F: Fn(&T) -> bool>(items: &Arc<Mutex<HashMap<u64, Vec<T>>>>, txid: u64, f: F) -> Vec<T> {
+    let mut items = items.lock().unwrap();
+    match items.get_mut(&txid) {
+        Some(v) => {
+            let (taken, rest): (Vec<T>, Vec<T>) = v.drain(..).partition(|t| f(t));
+            *v = rest;
+            taken
+        }
+        None => Vec::new(),
+    }
+}
+
+// 保存点，记录设置保存点时事务中缓存的修改、前置条件的数量和直接写入读写事务的次数
+struct SavepointMark {
+    mods: usize,
+    conds: usize,
+    written: usize,
+}
+
+// 清理事务结束后不再需要的状态
+fn clear_txn_state(txid: u64) {
+    SAVEPOINTS.lock().unwrap().remove(&txid);
+    WRITTEN.lock().unwrap().remove(&txid);
+}
+
+// 提交线程只启动一次
+static COMMIT_THREAD: Once = Once::new();
+
+// 事务闭包的重试次数和间隔
+const WITH_TXN_RETRY: usize = 3;
+const WITH_TXN_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+// 表所在的环境
+fn env_id(tab: &Atom) -> u64 {
+    LMDB_POOL.lock().unwrap().env_of(tab).unwrap_or(0)
+}
+
+// 表所在环境的写线程
+fn rw_sender(tab: &Atom) -> WorkerSender<WriterMsg> {
+    LMDB_POOL
+        .lock()
+        .unwrap()
+        .service(tab)
+        .and_then(|s| s.rw_sender())
+        .expect(&format!("Fatal error: cannot get rw sender for {:?}", tab.to_string()))
+}
+
+// 表所在环境的读线程
+fn ro_sender(tab: &Atom, priority: Priority) -> WorkerSender<ReaderMsg> {
+    LMDB_POOL
+        .lock()
+        .unwrap()
+        .service(tab)
+        .and_then(|s| s.ro_sender_with_priority(tab, priority))
+        .expect(&format!("Fatal error: cannot get ro sender for {:?}", tab.to_string()))
+}
+
+// 非阻塞地发送给表所在环境的读线程，同步执行模式下发送时会执行消息，不能持有全局锁
+pub(crate) fn try_ro_send(tab: &Atom, priority: Priority, msg: ReaderMsg) -> Result<(), StoreError> {
+    let sender = match LMDB_POOL.lock().unwrap().service(tab) {
+        Some(service) => service.ro_sender_with_priority(tab, priority).ok_or(StoreError::Busy)?,
+        None => return Err(StoreError::Internal(format!("no env for tab: {:?}", tab.to_string()))),
+    };
+    sender.try_send(msg).map_err(|_| StoreError::Busy)
+}
+
 lazy_static! {
-    static ref LMDB_SERVICE: Arc<Mutex<LmdbService>> = Arc::new(Mutex::new(LmdbService::new(17)));
+    static ref LMDB_POOL: Arc<Mutex<LmdbPool>> = Arc::new(Mutex::new(LmdbPool::new()));
     static ref MODS: Arc<Mutex<HashMap<u64, Vec<TabKV>>>> = Arc::new(Mutex::new(HashMap::new()));
+    // 事务提交时需要校验的前置条件
+    static ref CONDS: Arc<Mutex<HashMap<u64, Vec<Condition>>>> = Arc::new(Mutex::new(HashMap::new()));
+    // 事务的保存点栈
+    static ref SAVEPOINTS: Mutex<HashMap<u64, Vec<SavepointMark>>> = Mutex::new(HashMap::new());
+    // 事务中直接写入写线程读写事务的次数，包括合并和预提交
+    static ref WRITTEN: Mutex<HashMap<u64, usize>> = Mutex::new(HashMap::new());
+    // 事务闭包的事务id，从最高位开始分配，不与事务管理器的事务id冲突
+    static ref WITH_TXN_ID: AtomicU64 = AtomicU64::new(1 << 63);
 }
@@ -0,0 +1,162 @@
+use std::fs;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crossbeam_channel::bounded;
+
+use atom::Atom;
+
+use crate::lmdb_file::DB;
+use crate::pool::WriterGuard;
+
+// 维护任务占用写线程时使用的事务id，不与事务管理器和事务闭包的事务id冲突
+const MAINTENANCE_TXID: u64 = u64::MAX;
+// 每天的分钟数
+const MINUTES_PER_DAY: u64 = 24 * 60;
+// 默认检查是否进入维护窗口的间隔
+const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/**
+* 维护任务，在维护窗口中按顺序执行
+*/
+#[derive(Clone)]
+pub enum MaintenanceTask {
+    Compact(String),                                            //压缩复制到指定目录，库下次打开时用副本替换数据文件
+    Backup(String),                                             //压缩复制到指定目录作为全量备份，目录名后附加执行的日期
+    PruneChangeLog(u64),                                        //删除修改日志，只保留最近的指定次数的提交
+    Sweep(Atom, Arc<Fn(&DB) -> Result<(), String> + Send + Sync>), //自定义的清理，如删除过期的记录，参数为名称和清理函数
+}
+
+impl MaintenanceTask {
+    fn name(&self) -> String {
+        match self {
+            MaintenanceTask::Compact(_) => "compact".to_string(),
+            MaintenanceTask::Backup(_) => "backup".to_string(),
+            MaintenanceTask::PruneChangeLog(_) => "prune change log".to_string(),
+            MaintenanceTask::Sweep(name, _) => format!("sweep {}", name.as_str()),
+        }
+    }
+}
+
+/**
+* 每天的维护窗口，以UTC零点起的分钟数表示，开始大于结束表示窗口跨越零点
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaintenanceWindow {
+    pub start: u32,     //窗口开始的分钟，包括该分钟
+    pub end: u32,       //窗口结束的分钟，不包括该分钟
+}
+
+impl MaintenanceWindow {
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            minute >= self.start && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+/**
+* 维护调度器的句柄，停止后调度线程在下次检查时退出，不会中断正在执行的任务
+*/
+#[derive(Clone)]
+pub struct MaintenanceHandle(Arc<AtomicBool>);
+
+impl MaintenanceHandle {
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// 当前的UTC天数和当天的分钟
+fn now_day_minute() -> (u64, u32) {
+    let minutes = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() / 60).unwrap_or(0);
+    (minutes / MINUTES_PER_DAY, (minutes % MINUTES_PER_DAY) as u32)
+}
+
+// 调用异步的压缩复制并等待完成
+fn compact(db: &DB, dest: &str, swap: bool) -> Result<(), String> {
+    let (tx, rx) = bounded(1);
+    db.compact(dest, swap, Arc::new(move |r| {
+        let _ = tx.send(r);
+    }));
+    rx.recv().map_err(|e| e.to_string())?
+}
+
+fn run_task(db: &DB, task: &MaintenanceTask, day: u64) -> Result<(), String> {
+    match task {
+        MaintenanceTask::Compact(dest) => {
+            // 压缩目录必须为空，清除上次遗留的副本
+            let _ = fs::remove_dir_all(dest);
+            compact(db, dest, true)
+        }
+        MaintenanceTask::Backup(dest) => compact(db, &format!("{}_{}", dest, day), false),
+        MaintenanceTask::PruneChangeLog(keep) => {
+            let seq = db.commit_seq()?;
+            if seq > *keep {
+                db.prune_change_log(seq - keep).map(|n| debug!("maintenance pruned {} change logs", n))
+            } else {
+                Ok(())
+            }
+        }
+        MaintenanceTask::Sweep(_, f) => f(db),
+    }
+}
+
+/**
+* 执行一轮维护，先取得写线程的使用权，等待进行中的读写事务结束并阻止新的读写事务开始
+* @returns 返回各任务的名称和执行结果，取得写线程超时返回错误
+*/
+pub fn run_once(db: &DB, tasks: &[MaintenanceTask]) -> Result<Vec<(String, Result<(), String>)>, String> {
+    // 维护结束后守卫释放时归还写线程
+    let _writer = WriterGuard::checkout(db.env_id(), MAINTENANCE_TXID)
+        .ok_or_else(|| "maintenance acquire writer timeout".to_string())?;
+    let (day, _) = now_day_minute();
+    let results = tasks
+        .iter()
+        .map(|task| {
+            let r = run_task(db, task, day);
+            match &r {
+                Ok(_) => info!("maintenance {} ok", task.name()),
+                Err(e) => warn!("maintenance {} failed: {:?}", task.name(), e),
+            }
+            (task.name(), r)
+        })
+        .collect();
+    Ok(results)
+}
+
+/**
+* 启动维护调度器，每天进入维护窗口后执行一轮维护，同一天只执行一次
+* 维护期间其它事务无法取得写线程，应选择流量较低的时段作为窗口
+* @param db 数据库
+* @param window 维护窗口
+* @param tasks 维护任务，按顺序执行
+* @returns 返回调度器的句柄
+*/
+pub fn schedule(db: DB, window: MaintenanceWindow, tasks: Vec<MaintenanceTask>) -> MaintenanceHandle {
+    let handle = MaintenanceHandle(Arc::new(AtomicBool::new(false)));
+    let stopped = handle.clone();
+    let _ = thread::Builder::new().name("pi_store-maintenance".to_string()).spawn(move || {
+        let mut last_day = None;
+        while !stopped.is_stopped() {
+            let (day, minute) = now_day_minute();
+            if window.contains(minute) && last_day != Some(day) {
+                match run_once(&db, &tasks) {
+                    Ok(_) => last_day = Some(day),
+                    // 取得写线程超时，在窗口内下次检查时重试
+                    Err(e) => warn!("{}", e),
+                }
+            }
+            thread::sleep(DEFAULT_CHECK_INTERVAL);
+        }
+    });
+    handle
+}
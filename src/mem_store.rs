@@ -0,0 +1,91 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound::{Excluded, Included, Unbounded};
+use std::sync::{Arc, RwLock};
+
+use pi_db::db::{Bin, TabKV};
+
+use atom::Atom;
+
+use crate::backend::Backend;
+
+// 单表数据，按键有序
+type MemTab = BTreeMap<Vec<u8>, Bin>;
+
+/**
+* 纯内存存储后端，与LMDB后端使用相同的读写消息协议，用于测试和CI
+*/
+#[derive(Clone, Default)]
+pub struct MemStore(Arc<RwLock<HashMap<u64, MemTab>>>);
+
+impl MemStore {
+    pub fn new() -> Self {
+        MemStore(Arc::new(RwLock::new(HashMap::new())))
+    }
+}
+
+impl Backend for MemStore {
+    fn name(&self) -> &'static str {
+        "Mem"
+    }
+
+    fn query(&self, queries: &[TabKV]) -> Result<Vec<TabKV>, String> {
+        let tabs = self.0.read().unwrap();
+        Ok(queries.iter().map(|q| TabKV {
+            ware: q.ware.clone(),
+            tab: q.tab.clone(),
+            key: q.key.clone(),
+            index: q.index,
+            value: tabs
+                .get(&(q.tab.get_hash() as u64))
+                .and_then(|t| t.get(q.key.as_ref()))
+                .cloned(),
+        }).collect())
+    }
+
+    fn first_key(&self, tab: &Atom, descending: bool, start_key: Option<Bin>) -> Option<Bin> {
+        let tabs = self.0.read().unwrap();
+        let t = tabs.get(&(tab.get_hash() as u64))?;
+        let key = match (descending, start_key) {
+            (true, None) => t.keys().next(),
+            (true, Some(sk)) => t.range::<[u8], _>((Included(sk.as_slice()), Unbounded)).next().map(|(k, _)| k),
+            (false, Some(sk)) => t
+                .range::<[u8], _>((Included(sk.as_slice()), Unbounded))
+                .next()
+                .map(|(k, _)| k)
+                .or_else(|| t.keys().next_back()),
+            (false, None) => t.keys().next_back(),
+        };
+        key.map(|k| Arc::new(k.clone()))
+    }
+
+    fn next_item(&self, tab: &Atom, descending: bool, cur_key: &Bin) -> (Option<Bin>, Option<Bin>) {
+        let tabs = self.0.read().unwrap();
+        let t = match tabs.get(&(tab.get_hash() as u64)) {
+            Some(t) => t,
+            None => return (None, None),
+        };
+        let ck = cur_key.as_slice();
+        let next = if descending {
+            t.range::<[u8], _>((Excluded(ck), Unbounded)).next()
+        } else {
+            t.range::<[u8], _>((Unbounded, Excluded(ck))).next_back()
+        };
+        (t.get(ck).cloned(), next.map(|(k, _)| Arc::new(k.clone())))
+    }
+
+    fn commit(&self, modifies: &[TabKV]) -> Result<(), String> {
+        let mut tabs = self.0.write().unwrap();
+        for m in modifies.iter() {
+            let t = tabs.entry(m.tab.get_hash() as u64).or_insert_with(BTreeMap::new);
+            match &m.value {
+                Some(v) => {
+                    t.insert(m.key.to_vec(), v.clone());
+                }
+                None => {
+                    t.remove(m.key.as_ref());
+                }
+            }
+        }
+        Ok(())
+    }
+}
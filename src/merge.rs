@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use atom::Atom;
+
+/**
+* 合并函数，参数为当前值(不存在为None)和操作数，返回合并后的值，返回None表示删除
+*/
+pub type MergeFn = Arc<Fn(Option<&[u8]>, &[u8]) -> Option<Vec<u8>> + Send + Sync>;
+
+lazy_static! {
+    // 各表注册的合并函数，键为表名哈希
+    static ref MERGE_OPS: RwLock<HashMap<u64, MergeFn>> = RwLock::new(HashMap::new());
+}
+
+/**
+* 为表注册合并函数，合并在写线程中读取当前值并写回，不需要往返读取
+* @param tab 表名
+* @param f 合并函数，为None则取消注册
+*/
+pub fn register_merge(tab: &Atom, f: Option<MergeFn>) {
+    let mut ops = MERGE_OPS.write().unwrap();
+    match f {
+        Some(f) => {
+            ops.insert(tab.get_hash() as u64, f);
+        }
+        None => {
+            ops.remove(&(tab.get_hash() as u64));
+        }
+    }
+}
+
+// 表的合并函数
+pub fn merge_fn(tab: &Atom) -> Option<MergeFn> {
+    MERGE_OPS.read().unwrap().get(&(tab.get_hash() as u64)).cloned()
+}
+
+// 用表的合并函数合并值，表没有注册合并函数时返回错误
+pub fn apply(tab: &Atom, current: Option<&[u8]>, operand: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    match merge_fn(tab) {
+        Some(f) => Ok(f(current, operand)),
+        None => Err(format!("no merge function for tab: {:?}", tab.to_string())),
+    }
+}
@@ -0,0 +1,193 @@
+use std::hash::Hasher;
+use std::sync::Arc;
+
+use fnv::FnvHasher;
+use lmdb::Environment;
+
+use pi_db::db::Bin;
+
+use atom::Atom;
+
+use crate::snapshot::{self, Frame};
+
+// 最大深度，叶子按键的前两个字节划分
+const MAX_DEPTH: u8 = 16;
+
+/**
+* 表内容的Merkle树，叶子按键的前两个字节(不足补0)均分为2^depth个键范围，叶子的哈希为范围内所有键值的哈希
+* 内部节点的哈希为两个子节点哈希的哈希，哈希算法为FNV-1a，在不同版本和平台间一致
+* 两个副本交换根哈希，不同时逐层对比子树的哈希即可找到不一致的键范围，不需要全表对比
+* 键范围按字节序划分，使用自定义比较函数的表，范围内的键在表中不一定连续
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerkleTree {
+    depth: u8,
+    records: u64,
+    nodes: Vec<u64>,    //按层存放的节点哈希，下标1为根，节点i的子节点为2i和2i+1，叶子从2^depth开始
+}
+
+// 计算一组字节串的哈希，每个字节串前加长度以区分边界
+fn hash_parts(parts: &[&[u8]]) -> u64 {
+    let mut h = FnvHasher::default();
+    for part in parts {
+        h.write(&(part.len() as u64).to_be_bytes());
+        h.write(part);
+    }
+    h.finish()
+}
+
+// 逐条累积叶子哈希，不需要保留记录
+struct Builder {
+    depth: u8,
+    count: u64,
+    leaves: Vec<Option<FnvHasher>>,
+}
+
+impl Builder {
+    fn new(depth: u8) -> Self {
+        let depth = depth.min(MAX_DEPTH);
+        Builder {
+            depth,
+            count: 0,
+            leaves: (0..1usize << depth).map(|_| None).collect(),
+        }
+    }
+
+    fn push(&mut self, key: &[u8], value: &[u8]) {
+        let h = self.leaves[MerkleTree::leaf_of(self.depth, key)].get_or_insert_with(FnvHasher::default);
+        h.write(&hash_parts(&[key, value]).to_be_bytes());
+        self.count += 1;
+    }
+
+    fn finish(self) -> MerkleTree {
+        let leaves = self.leaves.len();
+        // 空叶子的哈希为0，整个子树为空时内部节点的哈希也为0
+        let mut nodes = vec![0u64; leaves * 2];
+        for (i, h) in self.leaves.into_iter().enumerate() {
+            nodes[leaves + i] = h.map_or(0, |h| h.finish());
+        }
+        for i in (1..leaves).rev() {
+            let (l, r) = (nodes[i * 2], nodes[i * 2 + 1]);
+            nodes[i] = if l == 0 && r == 0 { 0 } else { hash_parts(&[&l.to_be_bytes(), &r.to_be_bytes()]) };
+        }
+        MerkleTree { depth: self.depth, records: self.count, nodes }
+    }
+}
+
+impl MerkleTree {
+    /**
+    * 由按表顺序排列的键值构造
+    * @param depth 树的深度，叶子数为2^depth，最大为16
+    * @param records 键值
+    */
+    pub fn build<I: IntoIterator<Item = (Bin, Bin)>>(depth: u8, records: I) -> Self {
+        let mut builder = Builder::new(depth);
+        for (k, v) in records {
+            builder.push(&k, &v);
+        }
+        builder.finish()
+    }
+
+    // 键所在的叶子序号
+    fn leaf_of(depth: u8, key: &[u8]) -> usize {
+        let prefix = (*key.get(0).unwrap_or(&0) as usize) << 8 | *key.get(1).unwrap_or(&0) as usize;
+        prefix >> (MAX_DEPTH - depth)
+    }
+
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    // 构造时的记录数
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    pub fn root(&self) -> u64 {
+        self.nodes[1]
+    }
+
+    /**
+    * 子树的哈希
+    * @param level 层，0为根，depth为叶子
+    * @param index 层中的序号，从0开始
+    * @returns 层或序号越界返回None
+    */
+    pub fn node(&self, level: u8, index: usize) -> Option<u64> {
+        if level > self.depth || index >= 1 << level {
+            return None;
+        }
+        Some(self.nodes[(1 << level) + index])
+    }
+
+    // 一层的所有子树哈希，用于一次交换一层
+    pub fn level(&self, level: u8) -> Option<&[u64]> {
+        if level > self.depth {
+            return None;
+        }
+        Some(&self.nodes[1 << level..2 << level])
+    }
+
+    /**
+    * 子树覆盖的键范围[start, end)，按字节序
+    * @returns 返回起始键和结束键，最后一个子树没有结束键
+    */
+    pub fn range(&self, level: u8, index: usize) -> (Bin, Option<Bin>) {
+        let bound = |i: usize| {
+            let prefix = ((i << (MAX_DEPTH - level)) as u16).to_be_bytes();
+            // 去掉末尾的0，较短的键也落在正确的范围中
+            let len = prefix.iter().rposition(|b| *b != 0).map_or(0, |p| p + 1);
+            Arc::new(prefix[..len].to_vec())
+        };
+        let end = if index + 1 >= 1 << level { None } else { Some(bound(index + 1)) };
+        (bound(index), end)
+    }
+
+    /**
+    * 与另一个副本的树对比，找出哈希不同的叶子的键范围，两棵树的深度必须相同
+    * @returns 返回不一致的键范围，相邻的范围已合并
+    */
+    pub fn diff(&self, other: &MerkleTree) -> Result<Vec<(Bin, Option<Bin>)>, String> {
+        if self.depth != other.depth {
+            return Err(format!("merkle depth mismatch, {} != {}", self.depth, other.depth));
+        }
+        let mut ranges: Vec<(Bin, Option<Bin>)> = Vec::new();
+        let mut stack = vec![1usize];
+        let first_leaf = 1usize << self.depth;
+        while let Some(i) = stack.pop() {
+            if self.nodes[i] == other.nodes[i] {
+                continue;
+            }
+            if i < first_leaf {
+                // 先压入右子树，保证按键的顺序输出
+                stack.push(i * 2 + 1);
+                stack.push(i * 2);
+                continue;
+            }
+            let (start, end) = self.range(self.depth, i - first_leaf);
+            match ranges.last_mut() {
+                Some((_, last_end)) if last_end.as_ref() == Some(&start) => *last_end = end,
+                _ => ranges.push((start, end)),
+            }
+        }
+        Ok(ranges)
+    }
+}
+
+/**
+* 在一个读事务中读取表的所有记录并构造Merkle树
+* @param env LMDB环境
+* @param env_id 环境id
+* @param tab 表名
+* @param depth 树的深度，最大为16
+*/
+pub fn build(env: &Environment, env_id: u64, tab: &Atom, depth: u8) -> Result<MerkleTree, String> {
+    let mut builder = Builder::new(depth);
+    snapshot::frames(env, env_id, tab, |frame| {
+        if let Frame::Record(k, v) = frame {
+            builder.push(&k, &v);
+        }
+        Ok(())
+    })?;
+    Ok(builder.finish())
+}
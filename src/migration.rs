@@ -0,0 +1,64 @@
+use lmdb::{Database, Environment, RwTransaction, Transaction, WriteFlags};
+
+use atom::Atom;
+
+use crate::schema::{self, TableVersion};
+
+// 在__meta表中记录已执行迁移版本的键
+const MIGRATION_KEY: &str = "__migration";
+
+/**
+* 数据迁移，在启动时按版本从小到大执行
+*/
+pub trait Migration {
+    // 迁移后的库版本，必须大于0且各迁移不重复
+    fn version(&self) -> u32;
+
+    // 执行迁移，失败时迁移所在的事务回滚
+    fn up(&self, txn: &mut RwTransaction) -> Result<(), String>;
+}
+
+/**
+* 执行未执行过的迁移，每个迁移在独立的事务中执行，并在同一事务中记录进度
+* 必须在环境的读写线程启动之前调用
+* @param env 数据库环境
+* @param meta __meta表
+* @param migrations 所有迁移
+* @returns 返回执行后的库版本，失败返回原因描述，已成功的迁移不会回滚
+*/
+pub fn run(env: &Environment, meta: Database, migrations: &[Box<Migration>]) -> Result<u32, String> {
+    let key = Atom::from(MIGRATION_KEY);
+    let mut current = {
+        let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+        let r = schema::read(&txn, meta, &key)?;
+        let _ = txn.commit();
+        r
+    };
+
+    let mut pending = migrations
+        .iter()
+        .filter(|m| current.as_ref().map_or(true, |c| m.version() > c.version))
+        .collect::<Vec<&Box<Migration>>>();
+    pending.sort_by_key(|m| m.version());
+
+    for m in pending {
+        let version = match current {
+            Some(ref c) => TableVersion { version: m.version(), created_at: c.created_at },
+            None => TableVersion::new(m.version()),
+        };
+
+        let mut txn = env.begin_rw_txn().map_err(|e| e.to_string())?;
+        if let Err(e) = m.up(&mut txn) {
+            txn.abort();
+            return Err(format!("migration {} failed: {}", m.version(), e));
+        }
+        txn.put(meta, &key.as_bytes(), &version.encode(), WriteFlags::empty())
+            .map_err(|e| e.to_string())?;
+        txn.commit().map_err(|e| e.to_string())?;
+        info!("migration {} applied", version.version);
+
+        current = Some(version);
+    }
+
+    Ok(current.map_or(0, |c| c.version))
+}
@@ -0,0 +1,232 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use pi_db::db::{Bin, DBResult, Filter, Iter, IterResult, KeyIterResult, NextResult, SResult, TabKV, TabTxn, TxCallback, TxQueryCallback, TxState, Txn, CommitResult};
+
+use atom::Atom;
+
+/**
+* 键命名空间，透明地为键加上前缀，读取和迭代时去掉前缀
+* 多个逻辑表可以共享同一个LMDB表，各命名空间的前缀不能互为前缀
+*/
+pub struct Namespace {
+    txn: Arc<TabTxn>,
+    prefix: Bin,
+}
+
+impl Namespace {
+    /**
+    * 构建命名空间
+    * @param txn 共享表的表事务
+    * @param prefix 键前缀
+    */
+    pub fn new(txn: Arc<TabTxn>, prefix: &[u8]) -> Self {
+        Namespace {
+            txn,
+            prefix: Arc::new(prefix.to_vec()),
+        }
+    }
+
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    fn add_prefix(&self, arr: &[TabKV]) -> Arc<Vec<TabKV>> {
+        Arc::new(arr.iter().map(|kv| TabKV {
+            ware: kv.ware.clone(),
+            tab: kv.tab.clone(),
+            key: prefixed(&self.prefix, &kv.key),
+            index: kv.index,
+            value: kv.value.clone(),
+        }).collect())
+    }
+}
+
+// 加上前缀的键
+fn prefixed(prefix: &[u8], key: &[u8]) -> Bin {
+    let mut k = Vec::with_capacity(prefix.len() + key.len());
+    k.extend_from_slice(prefix);
+    k.extend_from_slice(key);
+    Arc::new(k)
+}
+
+// 去掉查询结果中键的前缀
+fn strip_prefix(prefix: &[u8], arr: Vec<TabKV>) -> Vec<TabKV> {
+    arr.into_iter().map(|kv| TabKV {
+        key: Arc::new(kv.key[prefix.len()..].to_vec()),
+        ..kv
+    }).collect()
+}
+
+// 大于命名空间内所有键的最小键，前缀全为0xff时不存在
+fn successor(prefix: &[u8]) -> Option<Bin> {
+    let mut k = prefix.to_vec();
+    while let Some(last) = k.pop() {
+        if last < 0xff {
+            k.push(last + 1);
+            return Some(Arc::new(k));
+        }
+    }
+    None
+}
+
+impl Txn for Namespace {
+    fn get_state(&self) -> TxState {
+        self.txn.get_state()
+    }
+
+    fn prepare(&self, timeout: usize, cb: TxCallback) -> DBResult {
+        self.txn.prepare(timeout, cb)
+    }
+
+    fn commit(&self, cb: TxCallback) -> CommitResult {
+        self.txn.commit(cb)
+    }
+
+    fn rollback(&self, cb: TxCallback) -> DBResult {
+        self.txn.rollback(cb)
+    }
+}
+
+impl TabTxn for Namespace {
+    fn key_lock(&self, arr: Arc<Vec<TabKV>>, lock_time: usize, readonly: bool, cb: TxCallback) -> DBResult {
+        self.txn.key_lock(self.add_prefix(&arr), lock_time, readonly, cb)
+    }
+
+    fn query(
+        &self,
+        arr: Arc<Vec<TabKV>>,
+        lock_time: Option<usize>,
+        readonly: bool,
+        cb: TxQueryCallback,
+    ) -> Option<SResult<Vec<TabKV>>> {
+        let prefix = self.prefix.clone();
+        let r = self.txn.query(self.add_prefix(&arr), lock_time, readonly, Arc::new(move |r| {
+            cb(r.map(|v| strip_prefix(&prefix, v)))
+        }));
+        r.map(|r| r.map(|v| strip_prefix(&self.prefix, v)))
+    }
+
+    fn modify(&self, arr: Arc<Vec<TabKV>>, lock_time: Option<usize>, readonly: bool, cb: TxCallback) -> DBResult {
+        self.txn.modify(self.add_prefix(&arr), lock_time, readonly, cb)
+    }
+
+    fn iter(
+        &self,
+        tab: &Atom,
+        key: Option<Bin>,
+        descending: bool,
+        filter: Filter,
+        cb: Arc<Fn(IterResult)>,
+    ) -> Option<IterResult> {
+        // descending为true时从小到大迭代，否则从大到小迭代
+        let start = match (key, descending) {
+            (Some(k), _) => Some(prefixed(&self.prefix, &k)),
+            (None, true) => Some(self.prefix.clone()),
+            (None, false) => successor(&self.prefix),
+        };
+        let prefix = self.prefix.clone();
+        let r = self.txn.iter(tab, start, descending, filter, Arc::new(move |r: IterResult| {
+            cb(r.map(|it| NamespaceIter::new(it, prefix.clone(), descending)))
+        }));
+        r.map(|r| r.map(|it| NamespaceIter::new(it, self.prefix.clone(), descending)))
+    }
+
+    fn key_iter(
+        &self,
+        _key: Option<Bin>,
+        _descending: bool,
+        _filter: Filter,
+        _cb: Arc<Fn(KeyIterResult)>,
+    ) -> Option<KeyIterResult> {
+        None
+    }
+
+    fn index(
+        &self,
+        tab: &Atom,
+        index_key: &Atom,
+        key: Option<Bin>,
+        descending: bool,
+        filter: Filter,
+        cb: Arc<Fn(IterResult)>,
+    ) -> Option<IterResult> {
+        self.txn.index(tab, index_key, key, descending, filter, cb)
+    }
+
+    // 返回的是整个共享表的大小
+    fn tab_size(&self, cb: Arc<Fn(SResult<usize>)>) -> Option<SResult<usize>> {
+        self.txn.tab_size(cb)
+    }
+}
+
+struct NamespaceIterState {
+    inner: Mutex<Box<Iter<Item = (Bin, Bin)>>>,
+    prefix: Bin,
+    desc: bool,
+    first: AtomicBool,
+    done: AtomicBool,
+}
+
+/**
+* 命名空间迭代器，只返回命名空间内的键，并去掉前缀
+*/
+pub struct NamespaceIter(Arc<NamespaceIterState>);
+
+impl NamespaceIter {
+    fn new(inner: Box<Iter<Item = (Bin, Bin)>>, prefix: Bin, desc: bool) -> Box<Iter<Item = (Bin, Bin)>> {
+        Box::new(NamespaceIter(Arc::new(NamespaceIterState {
+            inner: Mutex::new(inner),
+            prefix,
+            desc,
+            first: AtomicBool::new(true),
+            done: AtomicBool::new(false),
+        })))
+    }
+}
+
+// 从内部迭代器取下一个元素
+fn fetch(state: Arc<NamespaceIterState>, cb: Arc<Fn(NextResult<(Bin, Bin)>)>) -> Option<NextResult<(Bin, Bin)>> {
+    let s = state.clone();
+    let cb1 = cb.clone();
+    let r = state.inner.lock().unwrap().next(Arc::new(move |r| {
+        if let Some(r) = on_item(s.clone(), r, cb1.clone()) {
+            cb1(r);
+        }
+    }));
+    r.and_then(|r| on_item(state, r, cb))
+}
+
+// 处理内部迭代器返回的元素，需要继续取下一个元素时返回None
+fn on_item(state: Arc<NamespaceIterState>, r: NextResult<(Bin, Bin)>, cb: Arc<Fn(NextResult<(Bin, Bin)>)>) -> Option<NextResult<(Bin, Bin)>> {
+    let first = state.first.swap(false, Ordering::SeqCst);
+    match r {
+        Ok(Some((k, v))) => {
+            if k.starts_with(&state.prefix) {
+                Some(Ok(Some((Arc::new(k[state.prefix.len()..].to_vec()), v))))
+            } else if first && !state.desc && k.as_slice() > state.prefix.as_slice() {
+                // 从大到小迭代时起始位置可能落在下一个命名空间的第一个键上
+                fetch(state, cb)
+            } else {
+                state.done.store(true, Ordering::SeqCst);
+                Some(Ok(None))
+            }
+        }
+        Ok(None) => {
+            state.done.store(true, Ordering::SeqCst);
+            Some(Ok(None))
+        }
+        Err(e) => Some(Err(e)),
+    }
+}
+
+impl Iter for NamespaceIter {
+    type Item = (Bin, Bin);
+
+    fn next(&mut self, cb: Arc<Fn(NextResult<Self::Item>)>) -> Option<NextResult<Self::Item>> {
+        if self.0.done.load(Ordering::SeqCst) {
+            return Some(Ok(None));
+        }
+        fetch(self.0.clone(), cb)
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use pi_db::db::Bin;
+
+// 分页令牌的格式版本
+const TOKEN_VERSION: u8 = 1;
+
+/**
+* 分页查询的一页
+*/
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub items: Vec<(Bin, Bin)>,     //本页的键值
+    pub next: Option<Bin>,          //下一页的令牌，没有下一页时为None
+}
+
+/**
+* 分页令牌，记录上一页的最后一个键和迭代方向，调用者只应把它当作不透明的字节串
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageToken {
+    pub descending: bool,   //与表事务的iter相同，为true时从小到大迭代
+    pub last_key: Bin,      //上一页的最后一个键，下一页从它之后开始
+}
+
+impl PageToken {
+    pub fn encode(&self) -> Bin {
+        let mut v = Vec::with_capacity(2 + self.last_key.len());
+        v.push(TOKEN_VERSION);
+        v.push(self.descending as u8);
+        v.extend_from_slice(&self.last_key);
+        Arc::new(v)
+    }
+
+    pub fn decode(token: &[u8]) -> Result<Self, String> {
+        if token.len() < 2 || token[0] != TOKEN_VERSION {
+            return Err("invalid page token".to_string());
+        }
+        Ok(PageToken {
+            descending: token[1] != 0,
+            last_key: Arc::new(token[2..].to_vec()),
+        })
+    }
+}
+
+/**
+* 根据本页的结果生成下一页的令牌，本页不满说明已经没有下一页
+*/
+pub fn next_token(items: &[(Bin, Bin)], descending: bool, limit: usize) -> Option<Bin> {
+    if items.len() < limit {
+        return None;
+    }
+    items.last().map(|(k, _)| PageToken {
+        descending,
+        last_key: k.clone(),
+    }.encode())
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lmdb::{Cursor, Database, DatabaseFlags, Environment, Error, RwTransaction, Transaction, WriteFlags};
+
+use pi_db::db::Bin;
+
+use atom::Atom;
+
+use crate::lmdb_file::with_txn;
+use crate::pool::lookup_db;
+use crate::quota::{self, Quota};
+use crate::schema::META_TAB;
+use crate::table_meta;
+
+// 记录键过期时间的影子表，每个环境一个
+pub const EXPIRES_TAB: &str = "_$expires";
+// 策略在保留表中的键前缀，键为前缀 + 表名哈希(8字节)
+const POLICY_KEY_PREFIX: &[u8] = b"__policy\0";
+// 策略的编码版本，版本2增加了软删除
+const POLICY_VERSION: u8 = 2;
+// 每次清理默认删除的最大键数
+const DEFAULT_EXPIRE_BATCH: usize = 1000;
+
+/**
+* 键的比较方式，只在表创建时生效，已存在的表保持创建时的比较方式
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparator {
+    Bytes,          //按字节序比较
+    ReverseBytes,   //从键的末尾开始按字节比较
+    Integer,        //按本机字节序的无符号整数比较，见int_key.rs
+}
+
+impl Comparator {
+    fn flags(&self) -> DatabaseFlags {
+        match self {
+            Comparator::Bytes => DatabaseFlags::empty(),
+            Comparator::ReverseBytes => DatabaseFlags::REVERSE_KEY,
+            Comparator::Integer => DatabaseFlags::INTEGER_KEY,
+        }
+    }
+
+    fn code(&self) -> u8 {
+        match self {
+            Comparator::Bytes => 0,
+            Comparator::ReverseBytes => 1,
+            Comparator::Integer => 2,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Comparator::Bytes),
+            1 => Some(Comparator::ReverseBytes),
+            2 => Some(Comparator::Integer),
+            _ => None,
+        }
+    }
+}
+
+/**
+* 表的存储策略，保存在环境的保留表中，库打开时载入，由写线程在写入时执行
+* 没有策略的表使用全局设置
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct TablePolicy {
+    pub compression: bool,      //是否用表的字典压缩小值，见dict.rs
+    pub checksum: bool,         //是否记录值的校验和，见checksum.rs
+    pub ttl: Option<Duration>,  //写入的键默认的存活时间，过期的键由清理任务删除，删除前仍可读到
+    pub quota: Option<Quota>,   //表的存储配额
+    pub comparator: Comparator, //键的比较方式
+    pub soft_delete: bool,      //删除时写入墓碑而不是删除记录，见tombstone.rs
+}
+
+impl Default for TablePolicy {
+    fn default() -> Self {
+        TablePolicy {
+            compression: true,
+            checksum: false,
+            ttl: None,
+            quota: None,
+            comparator: Comparator::Bytes,
+            soft_delete: false,
+        }
+    }
+}
+
+impl TablePolicy {
+    fn encode(&self) -> Vec<u8> {
+        let opt = |v: Option<u64>| {
+            let mut b = vec![v.is_some() as u8];
+            b.extend_from_slice(&v.unwrap_or(0).to_be_bytes());
+            b
+        };
+        let mut v = Vec::with_capacity(32);
+        v.push(POLICY_VERSION);
+        v.push(self.compression as u8);
+        v.push(self.checksum as u8);
+        v.extend(opt(self.ttl.map(|d| d.as_millis() as u64)));
+        v.extend(opt(self.quota.and_then(|q| q.max_bytes)));
+        v.extend(opt(self.quota.and_then(|q| q.max_entries)));
+        v.push(self.comparator.code());
+        v.push(self.soft_delete as u8);
+        v
+    }
+
+    fn decode(value: &[u8]) -> Option<Self> {
+        // 版本1没有软删除
+        match (value.get(0), value.len()) {
+            (Some(1), 31) | (Some(2), 32) => (),
+            _ => return None,
+        }
+        let opt = |pos: usize| {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&value[pos + 1..pos + 9]);
+            if value[pos] == 0 { None } else { Some(u64::from_be_bytes(b)) }
+        };
+        let (max_bytes, max_entries) = (opt(12), opt(21));
+        Some(TablePolicy {
+            compression: value[1] != 0,
+            checksum: value[2] != 0,
+            ttl: opt(3).map(Duration::from_millis),
+            quota: if max_bytes.is_none() && max_entries.is_none() { None } else { Some(Quota { max_bytes, max_entries }) },
+            comparator: Comparator::from_code(value[30])?,
+            soft_delete: value.get(31).map_or(false, |b| *b != 0),
+        })
+    }
+}
+
+lazy_static! {
+    // 已载入的表策略，键为表名哈希
+    static ref POLICIES: RwLock<HashMap<u64, TablePolicy>> = RwLock::new(HashMap::new());
+}
+
+fn policy_key(tab: u64) -> Vec<u8> {
+    let mut k = Vec::with_capacity(POLICY_KEY_PREFIX.len() + 8);
+    k.extend_from_slice(POLICY_KEY_PREFIX);
+    k.extend_from_slice(&tab.to_be_bytes());
+    k
+}
+
+fn expires_db(env_id: u64) -> Option<Database> {
+    lookup_db(env_id, &Atom::from(EXPIRES_TAB))
+}
+
+// 影子表的键: 表名哈希 + 原键
+fn expire_key(tab: &Atom, key: &[u8]) -> Vec<u8> {
+    let mut k = Vec::with_capacity(8 + key.len());
+    k.extend_from_slice(&(tab.get_hash() as u64).to_be_bytes());
+    k.extend_from_slice(key);
+    k
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// 在内存中生效，配额由quota.rs执行，比较方式在表创建时使用
+fn apply(tab: u64, policy: TablePolicy) {
+    let flags = table_meta::hash_db_flags(tab) - DatabaseFlags::REVERSE_KEY - DatabaseFlags::INTEGER_KEY;
+    table_meta::set_hash_db_flags(tab, flags | policy.comparator.flags());
+    quota::set_hash_quota(tab, policy.quota);
+    POLICIES.write().unwrap().insert(tab, policy);
+}
+
+/**
+* 载入保留表中所有表的策略并生效，在库打开时、打开表之前调用，比较方式才能在创建表时生效
+* @param env LMDB环境
+* @param meta 保留表
+* @returns 返回载入的策略数
+*/
+pub fn load(env: &Environment, meta: Database) -> Result<usize, String> {
+    let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+    let mut count = 0;
+    {
+        let mut cursor = txn.open_ro_cursor(meta).map_err(|e| e.to_string())?;
+        for (k, v) in cursor.iter_from(POLICY_KEY_PREFIX) {
+            if !k.starts_with(POLICY_KEY_PREFIX) {
+                break;
+            }
+            if k.len() != POLICY_KEY_PREFIX.len() + 8 {
+                continue;
+            }
+            let mut tab = [0u8; 8];
+            tab.copy_from_slice(&k[POLICY_KEY_PREFIX.len()..]);
+            match TablePolicy::decode(v) {
+                Some(policy) => {
+                    apply(u64::from_be_bytes(tab), policy);
+                    count += 1;
+                }
+                None => warn!("invalid policy of tab hash: {:?}", u64::from_be_bytes(tab)),
+            }
+        }
+    }
+    let _ = txn.commit();
+    Ok(count)
+}
+
+/**
+* 设置表的存储策略，在写线程的读写事务中保存到表所在环境的保留表，提交后生效
+* @param tab 表名
+* @param policy 策略
+*/
+pub fn set_policy(tab: &Atom, policy: TablePolicy) -> Result<(), String> {
+    let meta = Atom::from(META_TAB);
+    let key = policy_key(tab.get_hash() as u64);
+    let value = Arc::new(policy.encode());
+    with_txn(tab, move |ops| ops.put(&meta, &key, &value))?;
+    apply(tab.get_hash() as u64, policy);
+    Ok(())
+}
+
+// 表的存储策略，没有策略返回None
+pub fn policy(tab: &Atom) -> Option<TablePolicy> {
+    POLICIES.read().unwrap().get(&(tab.get_hash() as u64)).cloned()
+}
+
+/**
+* 表是否记录校验和
+* @returns 没有策略返回None，使用全局设置
+*/
+pub fn checksum(tab: &Atom) -> Option<bool> {
+    POLICIES.read().unwrap().get(&(tab.get_hash() as u64)).map(|p| p.checksum)
+}
+
+// 表是否软删除，没有策略的表直接删除
+pub fn soft_delete(tab: &Atom) -> bool {
+    POLICIES.read().unwrap().get(&(tab.get_hash() as u64)).map_or(false, |p| p.soft_delete)
+}
+
+// 表是否允许字典压缩，没有策略的表允许
+pub fn compression(tab: &Atom) -> bool {
+    POLICIES.read().unwrap().get(&(tab.get_hash() as u64)).map_or(true, |p| p.compression)
+}
+
+/**
+* 写入或删除键后调用，有默认存活时间的表记录键的过期时间，删除的键清除过期时间
+* 每次写入都从当前时间重新计算过期时间
+*/
+pub fn on_write(txn: &mut RwTransaction, env_id: u64, tab: &Atom, key: &[u8], deleted: bool) -> Result<(), Error> {
+    let db = match expires_db(env_id) {
+        Some(db) => db,
+        None => return Ok(()),
+    };
+    // 没有策略的表不会有过期时间
+    let ttl = match POLICIES.read().unwrap().get(&(tab.get_hash() as u64)) {
+        Some(policy) => policy.ttl,
+        None => return Ok(()),
+    };
+    match ttl {
+        Some(ttl) if !deleted => {
+            let at = now_millis() + ttl.as_millis() as u64;
+            txn.put(db, &expire_key(tab, key), &at.to_be_bytes(), WriteFlags::empty())
+        }
+        _ => match txn.del(db, &expire_key(tab, key), None) {
+            Ok(_) | Err(Error::NotFound) => Ok(()),
+            Err(e) => Err(e),
+        },
+    }
+}
+
+/**
+* 删除表中已过期的键，先在读事务中找出过期的键，再在写线程的读写事务中删除
+* @param env LMDB环境
+* @param env_id 环境id
+* @param tab 表名
+* @param limit 最多删除的键数，0表示默认值
+* @returns 返回删除的键数，等于limit时可能还有过期的键
+*/
+pub fn expire(env: &Environment, env_id: u64, tab: &Atom, limit: usize) -> Result<usize, String> {
+    let db = expires_db(env_id).ok_or_else(|| "expires table not opened".to_string())?;
+    let limit = if limit == 0 { DEFAULT_EXPIRE_BATCH } else { limit };
+    let prefix = (tab.get_hash() as u64).to_be_bytes();
+    let now = now_millis();
+    let mut keys: Vec<Bin> = Vec::new();
+    {
+        let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+        {
+            let mut cursor = txn.open_ro_cursor(db).map_err(|e| e.to_string())?;
+            for (k, v) in cursor.iter_from(&prefix[..]) {
+                if !k.starts_with(&prefix) || keys.len() >= limit {
+                    break;
+                }
+                if v.len() != 8 {
+                    continue;
+                }
+                let mut at = [0u8; 8];
+                at.copy_from_slice(v);
+                if u64::from_be_bytes(at) <= now {
+                    keys.push(Arc::new(k[8..].to_vec()));
+                }
+            }
+        }
+        let _ = txn.commit();
+    }
+    if keys.is_empty() {
+        return Ok(0);
+    }
+
+    let count = keys.len();
+    let keys = Arc::new(keys);
+    let tab1 = tab.clone();
+    with_txn(tab, move |ops| {
+        for key in keys.iter() {
+            ops.del(&tab1, key)?;
+        }
+        Ok(())
+    })?;
+    debug!("tab: {:?} expired {} keys", tab.to_string(), count);
+    Ok(count)
+}
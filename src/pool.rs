@@ -1,465 +1,1350 @@
-use crossbeam_channel::{unbounded, Sender};
-use std::collections::HashMap;
-use std::sync::Arc;
+use crossbeam_channel::{bounded, select, Receiver, RecvError, RecvTimeoutError, SendError, Sender, TrySendError};
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
 use std::sync::RwLock;
-use std::sync::atomic::{Ordering, AtomicU64};
+use std::sync::atomic::{Ordering, AtomicBool, AtomicU64, AtomicUsize};
 use std::thread;
-use std::time::{Instant, Duration};
+use std::time::{Instant, Duration, SystemTime, UNIX_EPOCH};
 
-use lmdb::{Cursor, Database, DatabaseFlags, Environment, Error, Transaction, WriteFlags, RwTransaction};
+use tracing::{debug_span, field};
+use lmdb::{Cursor, Database, DatabaseFlags, Environment, Error, Transaction, WriteFlags, RwTransaction, RoTransaction};
+use lmdb_sys as ffi;
 
 use worker::impls::cast_store_task;
 use worker::task::TaskType;
 
+const MDB_GET_CURRENT: u32 = 4;
 const MDB_SET_KEY: u32 = 16;
 const MDB_SET_RANGE: u32 = 17;
 const MDB_PREV: u32 = 12;
 const MDB_NEXT: u32 = 8;
 const MDB_FIRST: u32 = 0;
 const MDB_LAST: u32 = 6;
+// 复制表时每批读取的记录数
+const COPY_BATCH: usize = 1000;
 
-const SLOW_TIME: u64 = 10;
+// 默认慢操作阈值(毫秒)
+const DEFAULT_SLOW_TIME: u64 = 50;
 
-use pi_db::db::{Bin, NextResult, TabKV, TxCallback, TxQueryCallback};
+use pi_db::db::{Bin, NextResult, SResult, TabKV};
 
 use atom::Atom;
 
+use crate::audit;
+use crate::backend::{self, Backend, Pump};
+use crate::bloom;
+use crate::cdc;
+use crate::fault;
+use crate::flusher::{self, SyncPolicy};
+use crate::changelog;
+use crate::checksum;
+use crate::chunk;
+use crate::mem_store::MemStore;
+use crate::merge;
+use crate::page::{self, Page};
+use crate::policy;
+use crate::quota;
+use crate::read_cache;
+use crate::readers::{self, ReaderSlot};
+use crate::retry;
+use crate::rocks_store::RocksStore;
+use crate::scan_filter::ScanFilter;
+use crate::txn_stats::{self, CommitStats};
+use crate::table_meta;
+use crate::tiering;
+use crate::tombstone;
+use crate::versions;
+use crate::view::{ValueView, ViewCallback};
+
+// 带版本查询的回调，返回每个键的值和版本
+pub type VersionedQueryCallback = SendCallback<SResult<Vec<(TabKV, u64)>>>;
+
+// 消息中的回调，在工作线程或任务池中调用，必须可以在线程间传递
+pub type SendCallback<T> = Arc<Fn(T) + Send + Sync>;
+// 查询回调
+pub type QueryCallback = SendCallback<SResult<Vec<TabKV>>>;
+// 写入、提交和回滚的回调
+pub type WriteCallback = SendCallback<SResult<()>>;
+// 提交回调，返回本次提交写入的统计
+pub type CommitCallback = SendCallback<SResult<CommitStats>>;
+// 迭代器取下一个键值的回调
+pub type NextCallback = SendCallback<NextResult<(Bin, Bin)>>;
+
+/**
+* 将pi_db接口传入的回调转换为可以放入消息的回调，只在实现pi_db接口的边界调用
+* pi_db的回调没有声明Send，包装后放入消息，工作线程得到结果时把回调和结果一起投递到任务池执行，回调不在工作线程中执行或释放
+* 回调最多执行一次，回调的所有副本被释放而没有结果时，以错误执行回调，避免调用者一直等待
+* 本库自己的接口直接要求Send + Sync的回调，不经过这里
+* @param cb pi_db的回调
+* @returns 返回可以在线程间传递的回调
+*/
+pub fn pi_db_callback<V: Send + 'static>(cb: Arc<Fn(SResult<V>)>) -> SendCallback<SResult<V>> {
+    let reply = Arc::new(PiDbCallback(Mutex::new(Some(cb))));
+    Arc::new(move |r| reply.reply(r))
+}
+
+// pi_db的回调，只在任务池中执行和释放
+struct PiDbCallback<V: Send + 'static>(Mutex<Option<Arc<Fn(SResult<V>)>>>);
+
+unsafe impl<V: Send + 'static> Send for PiDbCallback<V> {}
+unsafe impl<V: Send + 'static> Sync for PiDbCallback<V> {}
+
+impl<V: Send + 'static> PiDbCallback<V> {
+    // 取出回调，和结果一起投递到任务池执行，之后的结果被忽略
+    fn reply(&self, r: SResult<V>) {
+        if let Some(cb) = self.0.lock().unwrap().take() {
+            let t = Box::new(move |_: Option<isize>| cb(r));
+            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("pi_db callback"));
+        }
+    }
+}
+
+impl<V: Send + 'static> Drop for PiDbCallback<V> {
+    fn drop(&mut self) {
+        // 工作线程退出或丢弃了消息，所有副本都已释放而没有结果
+        self.reply(Err("lmdb worker disconnected before reply".to_string()));
+    }
+}
+
 pub enum ReaderMsg {
-    Query(Arc<Vec<TabKV>>, TxQueryCallback),
+    Query(Arc<Vec<TabKV>>, QueryCallback, Option<CancelToken>),
+    // 查询键的值和版本
+    QueryVersioned(Arc<Vec<TabKV>>, VersionedQueryCallback),
+    // 零拷贝查询，回调在读事务中同步执行，直接访问内存映射中的值
+    QueryView(Arc<Vec<TabKV>>, ViewCallback),
     CreateItemIter(bool, Atom, Option<Bin>, Sender<Option<Bin>>),
     NextItem(
         bool,
         Atom,
         Option<Bin>,
-        Arc<Fn(NextResult<(Bin, Bin)>)>,
+        NextCallback,
+        Sender<Option<Bin>>,
+    ),
+    // 带过滤条件的迭代器取下一个满足条件的键值，不满足条件的键值在读线程中跳过
+    NextMatch(
+        bool,
+        Atom,
+        Bin,
+        Arc<ScanFilter>,
+        NextCallback,
         Sender<Option<Bin>>,
     ),
-    Commit(TxCallback),
-    Rollback(TxCallback),
+    // 键迭代器取下一个键，只移动游标，不读取值
+    NextKey(bool, Atom, Bin, Sender<Option<Bin>>),
+    // 重新定位迭代器，为false时定位到第一个大于等于键的位置，为true时定位到最后一个小于等于键的位置
+    Seek(Atom, Bin, bool, Sender<Option<Bin>>),
+    // 分页查询，从上一页最后一个键之后开始，最多返回指定数量的键值
+    ScanPage(Atom, Option<Bin>, bool, usize, SendCallback<SResult<Page>>),
+    // 表中的记录数
+    TableSize(Atom, SendCallback<SResult<usize>>),
+    // 表中键在[start, end)范围内的记录数，None表示不限制
+    CountRange(Atom, Option<Bin>, Option<Bin>, SendCallback<SResult<usize>>),
+    Commit(WriteCallback),
+    Rollback(WriteCallback),
+    // 在读线程中打开独立的读事务，返回读事务id，一个读线程可以同时持有多个读事务
+    OpenRo(Sender<Result<u64, String>>),
+    // 在已打开的读事务中查询
+    RoQuery(u64, Arc<Vec<TabKV>>, QueryCallback),
+    // 在已打开的读事务中从当前键开始取迭代方向上的下一个键值，返回键值和之后的键
+    RoNext(u64, Atom, Bin, bool, SendCallback<SResult<(Option<(Bin, Bin)>, Option<Bin>)>>),
+    // 结束已打开的读事务
+    CloseRo(u64),
 }
 
-unsafe impl Send for ReaderMsg {}
+impl ReaderMsg {
+    // 消息类型名，用于日志和追踪
+    pub fn op_name(&self) -> &'static str {
+        match self {
+            ReaderMsg::Query(..) => "query",
+            ReaderMsg::QueryVersioned(..) => "query_versioned",
+            ReaderMsg::QueryView(..) => "query_view",
+            ReaderMsg::CreateItemIter(..) => "create_item_iter",
+            ReaderMsg::NextItem(..) => "next_item",
+            ReaderMsg::NextMatch(..) => "next_match",
+            ReaderMsg::NextKey(..) => "next_key",
+            ReaderMsg::Seek(_, _, false, _) => "seek",
+            ReaderMsg::Seek(_, _, true, _) => "seek_for_prev",
+            ReaderMsg::ScanPage(..) => "scan_page",
+            ReaderMsg::TableSize(..) => "table_size",
+            ReaderMsg::CountRange(..) => "count_range",
+            ReaderMsg::Commit(..) => "commit",
+            ReaderMsg::Rollback(..) => "rollback",
+            ReaderMsg::OpenRo(..) => "open_ro",
+            ReaderMsg::RoQuery(..) => "ro_query",
+            ReaderMsg::RoNext(..) => "ro_next",
+            ReaderMsg::CloseRo(..) => "close_ro",
+        }
+    }
+
+    // 消息操作的表，批量查询取第一个表
+    pub fn tab(&self) -> Option<&Atom> {
+        match self {
+            ReaderMsg::Query(queries, ..) | ReaderMsg::QueryVersioned(queries, _) | ReaderMsg::QueryView(queries, _) => queries.first().map(|q| &q.tab),
+            ReaderMsg::CreateItemIter(_, tab, _, _) => Some(tab),
+            ReaderMsg::NextItem(_, tab, _, _, _) => Some(tab),
+            ReaderMsg::Seek(tab, _, _, _) | ReaderMsg::NextKey(_, tab, _, _) => Some(tab),
+            ReaderMsg::NextMatch(_, tab, _, _, _, _) => Some(tab),
+            ReaderMsg::ScanPage(tab, _, _, _, _) => Some(tab),
+            ReaderMsg::TableSize(tab, _) | ReaderMsg::CountRange(tab, _, _, _) => Some(tab),
+            ReaderMsg::RoQuery(_, queries, _) => queries.first().map(|q| &q.tab),
+            ReaderMsg::RoNext(_, tab, _, _, _) => Some(tab),
+            _ => None,
+        }
+    }
+
+    // 消息涉及的键数量
+    pub fn key_count(&self) -> usize {
+        match self {
+            ReaderMsg::Query(queries, ..) | ReaderMsg::QueryVersioned(queries, _) | ReaderMsg::QueryView(queries, _) | ReaderMsg::RoQuery(_, queries, _) => queries.len(),
+            ReaderMsg::CreateItemIter(..) | ReaderMsg::NextItem(..) | ReaderMsg::NextMatch(..) | ReaderMsg::NextKey(..) | ReaderMsg::Seek(..) | ReaderMsg::RoNext(..) => 1,
+            ReaderMsg::ScanPage(_, _, _, limit, _) => *limit,
+            _ => 0,
+        }
+    }
+}
 
 pub enum WriterMsg {
-    Query(Arc<Vec<TabKV>>, TxQueryCallback),
+    Query(Arc<Vec<TabKV>>, QueryCallback, Option<CancelToken>),
     CreateItemIter(bool, Atom, Option<Bin>, Sender<Option<Bin>>),
     NextItem(
         bool,
         Atom,
         Option<Bin>,
-        Arc<Fn(NextResult<(Bin, Bin)>)>,
+        NextCallback,
         Sender<Option<Bin>>,
     ),
-    Modify(TxCallback),
-    Commit(Arc<Vec<TabKV>>, TxCallback),
-    Rollback(TxCallback),
+    Modify(WriteCallback),
+    // 合并写入，不属于任何事务，与其它合并写入在同一个读写事务中提交，提交后调用各自的回调
+    Coalesce(Arc<Vec<TabKV>>, WriteCallback),
+    // 用表的合并函数将操作数合并到当前值，TabKV的value为操作数
+    Merge(Arc<Vec<TabKV>>, WriteCallback),
+    // 在写线程的独立读写事务中执行闭包，闭包返回成功则提交，否则回滚
+    Exec(u64, TxnFn, Sender<Result<(), String>>),
+    // 预提交，校验前置条件并将修改写入读写事务但不提交，失败则回滚读写事务
+    Prepare(u64, Arc<Vec<TabKV>>, Arc<Vec<Condition>>, WriteCallback),
+    // 提交已预提交的读写事务
+    CommitPrepared(u64, WriteCallback),
+    // 提交事务，提交前校验所有前置条件，任一条件不满足则整个事务回滚，成功时回调参数为提交的统计，包括已预提交的修改
+    Commit(u64, Arc<Vec<TabKV>>, Arc<Vec<Condition>>, CommitCallback),
+    Rollback(u64, WriteCallback),
+    // 重命名表，在一个读写事务中创建新表、复制所有记录并删除旧表，附带的修改如表的元信息在同一个事务中写入
+    RenameDb(Atom, Atom, Arc<Vec<TabKV>>, WriteCallback),
+    // 复制表的所有记录到另一个表，目标表不存在则创建，已存在则必须为空，附带的修改在同一个事务中写入
+    CopyDb(Atom, Atom, Arc<Vec<TabKV>>, WriteCallback),
+    // 按指定的写入标志写入一批键值，不属于任何事务，在独立的读写事务中提交
+    Put(Arc<Vec<TabKV>>, WriteFlags, WriteCallback),
+    // 批量导入按键升序排列的键值，从通道中读取直到发送端关闭，每导入指定数量的记录提交一次，回调参数为导入的记录数
+    BulkLoad(Atom, Receiver<(Bin, Bin)>, usize, SendCallback<SResult<usize>>),
 }
 
-unsafe impl Send for WriterMsg {}
+impl WriterMsg {
+    // 消息类型名，用于日志和追踪
+    pub fn op_name(&self) -> &'static str {
+        match self {
+            WriterMsg::Query(..) => "query",
+            WriterMsg::CreateItemIter(..) => "create_item_iter",
+            WriterMsg::NextItem(..) => "next_item",
+            WriterMsg::Modify(..) => "modify",
+            WriterMsg::Coalesce(..) => "coalesce",
+            WriterMsg::Merge(..) => "merge",
+            WriterMsg::Exec(..) => "exec",
+            WriterMsg::Prepare(..) => "prepare",
+            WriterMsg::CommitPrepared(..) => "commit_prepared",
+            WriterMsg::Commit(..) => "commit",
+            WriterMsg::Rollback(..) => "rollback",
+            WriterMsg::RenameDb(..) => "rename_db",
+            WriterMsg::CopyDb(..) => "copy_db",
+            WriterMsg::Put(..) => "put",
+            WriterMsg::BulkLoad(..) => "bulk_load",
+        }
+    }
 
-pub struct LmdbService {
-    env: Option<Arc<Environment>>,
-    // how many threads to serve db read, only 1 writer thread
-    readers_count: usize,
-    readers: Vec<Sender<ReaderMsg>>,
-    writer: Option<Sender<WriterMsg>>,
-}
+    // 消息所属的会话或事务id，不属于事务的消息为0
+    pub fn session(&self) -> u64 {
+        match self {
+            WriterMsg::Exec(id, ..)
+            | WriterMsg::Prepare(id, ..)
+            | WriterMsg::CommitPrepared(id, ..)
+            | WriterMsg::Commit(id, ..)
+            | WriterMsg::Rollback(id, ..) => *id,
+            _ => 0,
+        }
+    }
 
-impl LmdbService {
-    pub fn new(readers_count: usize) -> LmdbService {
-        Self {
-            env: None,
-            readers_count,
-            readers: vec![],
-            writer: None,
+    // 消息操作的表，批量操作取第一个表
+    pub fn tab(&self) -> Option<&Atom> {
+        match self {
+            WriterMsg::Query(queries, ..) => queries.first().map(|q| &q.tab),
+            WriterMsg::CreateItemIter(_, tab, _, _) => Some(tab),
+            WriterMsg::NextItem(_, tab, _, _, _) => Some(tab),
+            WriterMsg::Merge(operands, _) | WriterMsg::Coalesce(operands, _) => operands.first().map(|m| &m.tab),
+            WriterMsg::Put(modifies, _, _) => modifies.first().map(|m| &m.tab),
+            WriterMsg::Prepare(_, modifies, _, _) => modifies.first().map(|m| &m.tab),
+            WriterMsg::Commit(_, modifies, _, _) => modifies.first().map(|m| &m.tab),
+            WriterMsg::RenameDb(old, ..) | WriterMsg::CopyDb(old, ..) => Some(old),
+            WriterMsg::BulkLoad(tab, ..) => Some(tab),
+            _ => None,
         }
     }
 
-    pub fn set_env(&mut self, env: Arc<Environment>) {
-        self.env = Some(env);
+    // 消息涉及的键数量
+    pub fn key_count(&self) -> usize {
+        match self {
+            WriterMsg::Query(queries, ..) => queries.len(),
+            WriterMsg::CreateItemIter(..) | WriterMsg::NextItem(..) => 1,
+            WriterMsg::Merge(operands, _) | WriterMsg::Coalesce(operands, _) => operands.len(),
+            WriterMsg::Put(modifies, _, _) => modifies.len(),
+            WriterMsg::Prepare(_, modifies, _, _) => modifies.len(),
+            WriterMsg::Commit(_, modifies, _, _) => modifies.len(),
+            _ => 0,
+        }
     }
+}
 
-    pub fn get_env(&self) -> Arc<Environment> {
-        self.env.clone().unwrap()
+// 存储错误，通过to_string传给上层回调
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoreError {
+    // 读写事务空闲超时，已被写线程回滚
+    TxnTimedOut,
+    // 工作线程队列已满，调用者应稍后重试
+    Busy,
+    // 操作已被调用者取消
+    Cancelled,
+    // 值与记录的校验和不一致，数据可能已损坏
+    ChecksumMismatch,
+    // 提交时前置条件不满足，事务中的修改都未写入
+    PreconditionFailed,
+    // 以NO_OVERWRITE写入的键已存在，同一批的修改都未写入
+    KeyExist,
+    // 表超出存储配额，事务中的修改都未写入
+    QuotaExceeded,
+    // 读槽已用完，附带当时的读槽列表，调用者应稍后重试或增大max_readers
+    ReadersFull(Vec<ReaderSlot>),
+    // 重试次数用完后仍然失败的临时性错误，如数据库文件被其它进程扩大或系统调用被中断
+    Transient(String),
+    // 数据库文件损坏或与库的版本不兼容
+    Corrupted(String),
+    // LMDB内部错误
+    Internal(String),
+}
+
+/**
+* 错误的分类，上层只应自动重试可重试的错误
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorClass {
+    Retryable,  //重新执行整个操作可能成功，操作的修改都未写入
+    Permanent,  //重新执行也会失败，或重新执行前需要调用者处理
+}
+
+impl StoreError {
+    // 错误的分类，前置条件不满足时需要调用者重新读取后再决定是否写入，不能直接重试
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            StoreError::TxnTimedOut
+            | StoreError::Busy
+            | StoreError::ReadersFull(_)
+            | StoreError::Transient(_) => ErrorClass::Retryable,
+            _ => ErrorClass::Permanent,
+        }
     }
+}
 
-    pub fn start(&mut self) {
-        self.spawn_readers();
-        self.spawn_writer();
+impl From<Error> for StoreError {
+    fn from(e: Error) -> Self {
+        match e {
+            e if retry::is_transient(&e) => StoreError::Transient(e.to_string()),
+            Error::Corrupted | Error::PageNotFound | Error::Panic | Error::VersionMismatch | Error::Invalid => StoreError::Corrupted(e.to_string()),
+            Error::KeyExist => StoreError::KeyExist,
+            Error::Other(quota::QUOTA_CODE) => StoreError::QuotaExceeded,
+            e => StoreError::Internal(e.to_string()),
+        }
     }
+}
 
-    pub fn ro_sender(&self, tab: &Atom) -> Option<Sender<ReaderMsg>> {
-        Some(self.readers[(tab.get_hash() as usize) % self.readers_count].clone())
+/**
+* 对回调中的错误分类，回调的错误为StoreError的to_string，其它错误视为不可重试
+* @param err 回调返回的错误
+* @returns 返回错误的分类
+*/
+pub fn classify(err: &str) -> ErrorClass {
+    const RETRYABLE: [&str; 4] = ["TxnTimedOut", "Busy", "ReadersFull", "Transient"];
+    if RETRYABLE.iter().any(|prefix| err.starts_with(prefix)) {
+        ErrorClass::Retryable
+    } else {
+        ErrorClass::Permanent
     }
+}
 
-    pub fn rw_sender(&self) -> Option<Sender<WriterMsg>> {
-        self.writer.clone()
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::TxnTimedOut => write!(f, "TxnTimedOut"),
+            StoreError::Busy => write!(f, "Busy"),
+            StoreError::Cancelled => write!(f, "Cancelled"),
+            StoreError::ChecksumMismatch => write!(f, "ChecksumMismatch"),
+            StoreError::PreconditionFailed => write!(f, "PreconditionFailed"),
+            StoreError::KeyExist => write!(f, "KeyExist"),
+            StoreError::QuotaExceeded => write!(f, "QuotaExceeded"),
+            StoreError::ReadersFull(slots) => {
+                write!(f, "ReadersFull, {} readers", slots.len())?;
+                for slot in slots.iter().filter(|slot| slot.txnid.is_some()) {
+                    write!(f, "; pid: {}, thread: {:x}, txnid: {:?}, lag: {}, age: {:?}", slot.pid, slot.thread, slot.txnid, slot.lag, slot.age)?;
+                }
+                Ok(())
+            }
+            StoreError::Transient(e) => write!(f, "Transient, {}", e),
+            StoreError::Corrupted(e) => write!(f, "Corrupted, {}", e),
+            StoreError::Internal(e) => write!(f, "lmdb internal error: {}", e),
+        }
     }
+}
 
-    fn spawn_readers(&mut self) {
-        (0..self.readers_count).for_each(|i| {
-            let env = self.env.clone();
-            let (tx, rx) = unbounded();
+// 在写线程中执行的事务闭包
+pub type TxnFn = Box<FnMut(&mut TxnOps) -> Result<(), String> + Send>;
 
-            let _ = thread::Builder::new().name(format!("Lmdb Reader {:?}", i)).spawn(move ||
-            loop {
-                match rx.recv() {
-                    Ok(ReaderMsg::Commit(cb)) => {
-                        let start_time = Instant::now();
-                        let t = Box::new(move |_| {
-                            cb(Ok(()));
-                        });
-                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader commit"));
+/**
+* 事务闭包中的读写操作，直接在写线程的读写事务中执行
+*/
+pub struct TxnOps<'a, 'env: 'a> {
+    txn: &'a mut RwTransaction<'env>,
+    env_id: u64,
+    written: Vec<TabKV>,
+}
 
-                        let elapsed = start_time.elapsed();
-                        if elapsed > Duration::from_millis(SLOW_TIME) {
-                            println!("===> Slow reader/writer commit, time: {:?}", elapsed);
-                        }
-                    }
-                    Ok(ReaderMsg::Query(queries, cb)) => {
-                        let start_time = Instant::now();
-                        let mut qr = vec![];
-                        let mut query_error = false;
-                        let txn = env
-                            .as_ref()
-                            .unwrap()
-                            .begin_ro_txn()
-                            .expect("Fatal error: Lmdb can't create ro txn");
-                        for q in queries.iter() {
-                            let db = get_db(q.tab.get_hash() as u64);
-                            match txn.get(db, q.key.as_ref()) {
-                                Ok(v) => {
-                                    qr.push(TabKV {
-                                        ware: q.ware.clone(),
-                                        tab: q.tab.clone(),
-                                        key: q.key.clone(),
-                                        index: q.index,
-                                        value: Some(Arc::new(Vec::from(v))),
-                                    });
-                                }
+impl<'a, 'env> TxnOps<'a, 'env> {
+    fn tabkv(tab: &Atom, key: &[u8], value: Option<&[u8]>) -> TabKV {
+        TabKV {
+            ware: Atom::from(""),
+            tab: tab.clone(),
+            key: Arc::new(key.to_vec()),
+            index: 0,
+            value: value.map(|v| Arc::new(v.to_vec())),
+        }
+    }
 
-                                Err(Error::NotFound) => {
-                                    qr.push(TabKV {
-                                        ware: q.ware.clone(),
-                                        tab: q.tab.clone(),
-                                        key: q.key.clone(),
-                                        index: q.index,
-                                        value: None,
-                                    });
-                                }
+    fn check_tab(&self, tab: &Atom) -> Result<(), String> {
+        if lookup_db(self.env_id, tab).is_some() {
+            Ok(())
+        } else {
+            Err(format!("tab not opened: {:?}", tab.to_string()))
+        }
+    }
 
-                                Err(_) => {
-                                    query_error = true;
-                                    break;
-                                }
-                            }
-                        }
+    // 读写事务所在的环境
+    pub fn env_id(&self) -> u64 {
+        self.env_id
+    }
 
-                        if query_error {
-                            let t = Box::new(move |_| {
-                                cb(Err(format!("lmdb query internal error")));
-                            });
-                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader query error"));
-                            warn!("queries error: {:?}", queries);
-                        } else {
-                            debug!("lmdb query success: {:?}", qr);
-                            let t = Box::new(move |_| {
-                                cb(Ok(qr));
-                            });
-                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader query ok"));
-                        }
+    // 读取键的值，可以读到本事务中已写入的值
+    pub fn get(&self, tab: &Atom, key: &[u8]) -> Result<Option<Bin>, String> {
+        self.check_tab(tab)?;
+        let q = TxnOps::tabkv(tab, key, None);
+        query_in_txn(self.env_id, &*self.txn, &[q], None, None)
+            .map(|mut r| r.pop().and_then(|kv| kv.value))
+            .map_err(|e| e.to_string())
+    }
 
-                        match txn.commit() {
-                            Ok(_) => {}
-                            Err(e) => panic!("query txn commit error: {:?}", e.to_string()),
-                        }
+    // 写入键值
+    pub fn put(&mut self, tab: &Atom, key: &[u8], value: &[u8]) -> Result<(), String> {
+        self.check_tab(tab)?;
+        let kv = TxnOps::tabkv(tab, key, Some(value));
+        bloom::insert(self.env_id, &[kv.clone()]);
+        read_cache::invalidate(self.env_id, &[kv.clone()]);
+        write_kv(self.txn, self.env_id, &kv).map_err(|e| StoreError::from(e).to_string())?;
+        self.written.push(kv);
+        Ok(())
+    }
 
-                        let elapsed = start_time.elapsed();
-                        if elapsed > Duration::from_millis(SLOW_TIME) {
-                            let tabs = queries.iter().map(|q| q.tab.clone().to_string()).collect::<Vec<String>>();
-                            let qsize: usize = queries.iter().map(|q| q.key.clone().len()).sum();
-                            println!("===> Slow reader query, time: {:?}, tabs: {:?}, qsize: {:?}", elapsed, tabs, qsize);
-                        }
-                    }
-                    Ok(ReaderMsg::CreateItemIter(descending, tab, start_key, sndr)) => {
-                        let start_time = Instant::now();
-                        let txn = env
-                            .as_ref()
-                            .unwrap()
-                            .begin_ro_txn()
-                            .expect("Fatal error: Lmdb can't create ro txn");
-                        let db = get_db(tab.get_hash() as u64);
-                        let cursor = txn
-                            .open_ro_cursor(db)
-                            .expect(&format!("Fatal error: open cursor for db: {:?} failed", db));
+    // 删除键
+    pub fn del(&mut self, tab: &Atom, key: &[u8]) -> Result<(), String> {
+        self.check_tab(tab)?;
+        let kv = TxnOps::tabkv(tab, key, None);
+        read_cache::invalidate(self.env_id, &[kv.clone()]);
+        write_kv(self.txn, self.env_id, &kv).map_err(|e| StoreError::from(e).to_string())?;
+        self.written.push(kv);
+        Ok(())
+    }
 
-                        match (descending, start_key) {
-                            (true, None) => match cursor.get(None, None, MDB_FIRST) {
-                                Ok(val) => {
-                                    let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
-                                }
-                                Err(Error::NotFound) => {
-                                    let _ = sndr.send(None);
-                                }
+    /**
+    * 永久删除软删除留下的墓碑，删除已在写入墓碑时记录，这里不再记录修改
+    * @param before 只删除在该时间(毫秒)之前写入的墓碑
+    * @returns 键不是墓碑或墓碑较新时不删除，返回false
+    */
+    pub fn purge(&mut self, tab: &Atom, key: &[u8], before: u64) -> Result<bool, String> {
+        let db = get_db(self.env_id, tab).map_err(|e| e.to_string())?;
+        match (&*self.txn).get(db, &key) {
+            Ok(v) if tombstone::deleted_at(v).map_or(false, |t| t <= before) => (),
+            Ok(_) | Err(Error::NotFound) => return Ok(false),
+            Err(e) => return Err(e.to_string()),
+        }
+        self.txn.del(db, &key, None).map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+}
 
-                                Err(_) => {}
-                            },
-                            // MDB_SET_RANGE 会找到第一个大于或者等于 sk 的 key
-                            (true, Some(sk)) => {
-                                match cursor.get(Some(sk.as_ref()), None, MDB_SET_RANGE) {
-                                    Ok(val) => {
-                                        let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
-                                    }
-                                    Err(Error::NotFound) => {
-                                        let _ = sndr.send(None);
-                                    }
-                                    Err(_) => {}
-                                }
-                            }
-                            (false, Some(sk)) => {
-                                match cursor.get(Some(sk.as_ref()), None, MDB_SET_RANGE) {
-                                    Ok(val) => {
-                                        let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
-                                    }
-                                    Err(Error::NotFound) => {
-                                        // 降序迭代起始 key 超过最大 key 则定位到表中最后一个元素
-                                        match cursor.get(None, None, MDB_LAST) {
-                                            Ok(val) => {
-                                                let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
-                                            }
-                                            Err(Error::NotFound) => {
-                                                let _ = sndr.send(None);
-                                            }
-                                            Err(_) => {}
-                                        }
-                                    }
-                                    Err(_) => {}
-                                }
-                            }
-                            (false, None) => match cursor.get(None, None, MDB_LAST) {
-                                Ok(val) => {
-                                    let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
-                                }
-                                Err(Error::NotFound) => {
-                                    let _ = sndr.send(None);
-                                }
-                                Err(_) => {}
-                            },
-                        }
+// 写入前键需要满足的条件
+#[derive(Debug, Clone, PartialEq)]
+pub enum Precondition {
+    // 键不存在
+    MustNotExist,
+    // 键存在
+    MustExist,
+    // 键的当前值等于指定值
+    MustEqual(Bin),
+    // 键的当前版本等于指定版本，版本0表示键不存在
+    Version(u64),
+}
 
-                        drop(cursor);
-                        match txn.commit() {
-                            Ok(_) => {}
-                            Err(e) => panic!("create iter txn commit error: {:?}", e.to_string()),
-                        }
+// 提交时校验的前置条件
+#[derive(Debug, Clone)]
+pub struct Condition {
+    pub tab: Atom,
+    pub key: Bin,
+    pub expect: Precondition,
+}
 
-                        let elapsed = start_time.elapsed();
-                        if elapsed > Duration::from_millis(SLOW_TIME) {
-                            println!("===> Slow reader createItemIter, time: {:?}, tab: {:?}", elapsed, tab);
-                        }
-                    }
-                    Ok(ReaderMsg::NextItem(descending, tab, cur_key, cb, sndr)) => {
-                        let start_time = Instant::now();
-                        let txn = env
-                            .as_ref()
-                            .unwrap()
-                            .begin_ro_txn()
-                            .expect("Fatal error: Lmdb can't create ro txn");
-                        let db = get_db(tab.get_hash() as u64);
-                        let cursor = txn
-                            .open_ro_cursor(db)
-                            .expect(&format!("Fatal error: open cursor for db: {:?} failed", db));
+impl Condition {
+    // 当前值和版本是否满足条件
+    pub fn check(&self, current: Option<&[u8]>, version: u64) -> bool {
+        match (&self.expect, current) {
+            (Precondition::MustNotExist, v) => v.is_none(),
+            (Precondition::MustExist, v) => v.is_some(),
+            (Precondition::MustEqual(expect), Some(v)) => expect.as_slice() == v,
+            (Precondition::MustEqual(_), None) => false,
+            (Precondition::Version(expect), _) => *expect == version,
+        }
+    }
+}
 
-                        match (descending, cur_key.clone()) {
-                            (true, Some(ck)) => {
-                                let cb1 = cb.clone();
-                                let ck1 = ck.clone();
-                                match cursor.get(Some(ck.as_ref()), None, MDB_SET_KEY) {
-                                    Ok(val) => {
-                                        let v = val.1.to_vec();
-                                        debug!("iter next item descendin key: {:?}, value: {:?}", ck.clone(), v.clone());
-                                        let t = Box::new(move |_: Option<isize>| {
-                                            cb1(Ok(Some((ck1, Arc::new(v)))));
-                                        });
-                                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader get next item"));
-                                    }
-                                    Err(Error::NotFound) => {}
-                                    Err(_) => {}
-                                }
+// 持有写线程读写事务的信息
+#[derive(Debug, Clone)]
+pub struct RwTxnInfo {
+    pub env_id: u64,                //环境id
+    pub txid: u64,                  //事务id
+    pub tab: Option<Atom>,          //最近操作的表
+    pub last_op: &'static str,      //最近的操作
+    pub age: Duration,              //事务已打开的时间
+}
 
-                                // get next key
-                                match cursor.get(Some(ck.as_ref()), None, MDB_NEXT) {
-                                    Ok(val) => {
-                                        debug!("iter next key descending: item: {:?}", val.clone());
-                                        let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
-                                    }
+// 写线程状态
+#[derive(Debug, Clone)]
+pub struct WriterStatus {
+    pub holders: Vec<RwTxnInfo>,    //各环境写线程中持有读写事务的事务
+    pub waiting: usize,             //等待写线程的事务数量
+    pub stalled: bool,              //是否停滞，即有事务在等待且读写事务打开时间超过阈值
+}
 
-                                    Err(Error::NotFound) => {
-                                        debug!("iter next key descending: NotFound");
-                                        let _ = sndr.send(None);
-                                    }
+// 工作线程的运行统计
+#[derive(Debug, Clone)]
+pub struct WorkerStats {
+    pub name: String,               //线程名
+    pub alive: bool,                //是否在运行，空闲回收的读线程退出后为false
+    pub busy: bool,                 //是否正在处理消息
+    pub queue_depth: usize,         //通道中待处理的消息数量
+    pub processed: u64,             //已处理的消息数量
+    pub last_active: u64,           //最近一次处理完消息的时间(毫秒时间戳)，0表示还未处理过消息
+}
 
-                                    Err(e) => {
-                                        let t = Box::new(move |_: Option<isize>| {
-                                            cb(Err(format!("lmdb iter internal error: {:?}", e)));
-                                        });
-                                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader get next item error")); 
-                                    }
-                                }
-                            }
-                            (false, Some(ck)) => {
-                                let cb1 = cb.clone();
-                                let ck1 = ck.clone();
-                                let cb2 = cb.clone();
-                                match cursor.get(Some(ck.as_ref()), None, MDB_SET_KEY) {
-                                    Ok(val) => {
-                                        let v = val.1.to_vec();
-                                        debug!("iter next item ascending key: {:?}, value: {:?}", ck.clone(), v.clone());
-                                        let t = Box::new(move |_: Option<isize>| {
-                                            cb1(Ok(Some((ck1, Arc::new(v)))));
-                                        });
-                                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader get next item"));
-                                    }
-                                    Err(Error::NotFound) => {}
-                                    Err(_) => {}
-                                }
+// 环境的工作线程池统计
+#[derive(Debug, Clone)]
+pub struct PoolStats {
+    pub env_id: u64,                    //环境id
+    pub total: usize,                   //工作线程总数，包括写线程
+    pub idle: usize,                    //运行中且空闲的工作线程数
+    pub busy: usize,                    //正在处理消息的工作线程数
+    pub workers: Vec<WorkerStats>,      //各工作线程的统计，写线程在最后
+    pub open_txn_age: Option<Duration>, //写线程中读写事务已打开的时间
+}
 
-                                // get next key
-                                match cursor.get(Some(ck.as_ref()), None, MDB_PREV) {
-                                    Ok(val) => {
-                                        debug!("iter next item ascending item: {:?}", val);
-                                        let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
-                                    }
+// 工作线程与统计共享的活动记录，只使用原子变量，读取统计时不需要加锁
+#[derive(Debug, Default)]
+struct WorkerActivity {
+    busy: AtomicBool,
+    processed: AtomicU64,
+    last_active: AtomicU64,
+}
 
-                                    Err(Error::NotFound) => {
-                                        debug!("iter next item ascending item: NotFound");
-                                        let _ = sndr.send(None);
-                                    }
+impl WorkerActivity {
+    // 开始处理消息，返回的守卫释放时记录处理完成
+    fn begin(&self) -> BusyGuard {
+        self.busy.store(true, Ordering::Relaxed);
+        BusyGuard(self)
+    }
+}
 
-                                    Err(e) => {
-                                        let t = Box::new(move |_: Option<isize>| {
-                                            cb2(Err(format!("Lmdb reader lmdb next item error: {:?}", e)));
-                                        });
-                                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader query error"));
-                                    }
-                                }
-                            }
+struct BusyGuard<'a>(&'a WorkerActivity);
 
-                            _ => (),
-                        }
+impl<'a> Drop for BusyGuard<'a> {
+    fn drop(&mut self) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+        self.0.last_active.store(now, Ordering::Relaxed);
+        self.0.processed.fetch_add(1, Ordering::Relaxed);
+        self.0.busy.store(false, Ordering::Relaxed);
+    }
+}
 
-                        drop(cursor);
-                        match txn.commit() {
-                            Ok(_) => {},
-                            Err(e) => panic!("Next item txn commit error: {:?}", e.to_string()),
-                        }
+/**
+* 取消令牌，提交耗时操作时返回给调用者
+* 工作线程在处理每个键之前检查令牌，已取消则提前结束并以Cancelled错误调用回调
+*/
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
 
-                        let elapsed = start_time.elapsed();
-                        if elapsed > Duration::from_millis(SLOW_TIME) {
-                            println!("===> Slow reader itemIter, time: {:?}, tab: {:?}, iter_key: {:?}", elapsed, tab, cur_key);
-                        }
-                    }
-                    Ok(ReaderMsg::Rollback(cb)) => {
-                        let t = Box::new(move |_: Option<isize>| {
-                            cb(Ok(()));
-                        });
-                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader rollback error"));
-                    }
-                    Err(_) => (),
-                }
-            });
-            self.readers.push(tx);
-        })
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken(Arc::new(AtomicBool::new(false)))
     }
 
-    fn spawn_writer(&mut self) {
-        let env = self.env.clone();
-        let (tx, rx) = unbounded();
+    // 取消操作
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
 
-        let _ = thread::Builder::new().name("Lmdb writer".to_string()).spawn(move || {
-            let mut rw_txn: Option<RwTransaction> = None;
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
 
-            loop {
-                match rx.recv() {
-                    Ok(WriterMsg::Query(queries, cb)) => {
-                        let start_time = Instant::now();
-                        let mut qr = vec![];
-                        let mut query_error = false;
-                        if rw_txn.is_none() {
-                            rw_txn = Some(env
-                            .as_ref()
-                            .unwrap()
-                            .begin_rw_txn()
-                            .expect("Fatal error: failed to begin rw txn"));
-                        }
+// 读消息优先级
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Priority {
+    High,   //延迟敏感的小查询
+    Low,    //迭代和大批量查询
+}
 
-                        for q in queries.iter() {
-                            let db = get_db(q.tab.get_hash() as u64);
-                            match rw_txn.as_ref().unwrap().get(db, q.key.as_ref()) {
-                                Ok(v) => {
-                                    qr.push(TabKV {
-                                        ware: q.ware.clone(),
-                                        tab: q.tab.clone(),
-                                        key: q.key.clone(),
-                                        index: q.index,
-                                        value: Some(Arc::new(Vec::from(v))),
-                                    });
-                                }
+// 批量查询超过该数量的键时使用低优先级
+pub const LOW_PRIORITY_QUERY_SIZE: usize = 64;
 
-                                Err(Error::NotFound) => {
-                                    qr.push(TabKV {
-                                        ware: q.ware.clone(),
-                                        tab: q.tab.clone(),
-                                        key: q.key.clone(),
-                                        index: q.index,
-                                        value: None,
-                                    });
-                                }
+// 读消息的分发方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dispatch {
+    TabHash,        //按表名哈希固定到一个读线程
+    LeastLoaded,    //分发到队列最短的读线程，读消息不依赖线程状态，可以在任意读线程执行
+}
 
-                                Err(_) => {
-                                    query_error = true;
-                                    break;
-                                }
+// 存储后端类型
+#[derive(Clone)]
+pub enum StoreKind {
+    Lmdb,
+    Mem(MemStore),
+    Rocks(RocksStore),
+}
+
+pub struct LmdbService {
+    env: Option<Arc<Environment>>,
+    // 环境id，同一个池中的每个环境有独立的读写线程
+    env_id: u64,
+    // how many threads to serve db read, only 1 writer thread
+    readers_count: usize,
+    readers: Vec<Sender<ReaderMsg>>,
+    // 读线程的低优先级通道，用于迭代和大批量查询
+    readers_low: Vec<Sender<ReaderMsg>>,
+    writer: Option<Sender<WriterMsg>>,
+    // 使用非LMDB后端时不需要LMDB环境
+    kind: StoreKind,
+    dispatch: Dispatch,
+    // 每个工作线程通道的容量，0表示不限制
+    queue_capacity: usize,
+    // 是否将工作线程绑定到CPU核
+    pin_cores: bool,
+    // 固定到按表名哈希的读线程的表，不受分发方式影响
+    affinity_tabs: HashSet<u64>,
+    // 清理失效读槽的间隔(毫秒)，0表示不清理
+    reader_check_interval: u64,
+    // 后台刷盘策略，用于以NO_SYNC打开的环境
+    sync_policy: Option<SyncPolicy>,
+    // 可空闲退出的读线程，非LMDB后端为空
+    idle_readers: Vec<Arc<IdleReader>>,
+    // 读线程空闲多少秒后退出，0表示不退出
+    idle_timeout: u64,
+    // 空闲时保留的最少读线程数
+    min_readers: usize,
+    // 各读线程和写线程的活动记录
+    reader_activity: Vec<Arc<WorkerActivity>>,
+    writer_activity: Arc<WorkerActivity>,
+    // 是否使用同步执行模式
+    inline: bool,
+    // 同步执行模式的执行器
+    pump: Option<Arc<Pump>>,
+}
+
+/**
+* 事务句柄，绑定事务使用的读线程和写线程，事务的查询、修改、提交和回滚都发送到同一个工作线程
+* 只能通过LmdbService::txn_handle创建，不会把提交或回滚发送给没有持有该事务的工作线程
+*/
+#[derive(Clone)]
+pub struct TxnHandle {
+    txid: u64,
+    reader: WorkerSender<ReaderMsg>,            //绑定的读线程的高优先级通道
+    reader_low: WorkerSender<ReaderMsg>,        //绑定的读线程的低优先级通道
+    writer: Option<WorkerSender<WriterMsg>>,    //读写事务绑定的写线程
+}
+
+impl TxnHandle {
+    pub fn txid(&self) -> u64 {
+        self.txid
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    // 读写事务的写线程，只读事务返回错误
+    fn writer(&self) -> Result<&WorkerSender<WriterMsg>, StoreError> {
+        self.writer.as_ref().ok_or_else(|| StoreError::Internal(format!("txn: {:?} is read only", self.txid)))
+    }
+
+    /**
+    * 查询，读写事务在写线程的读写事务中查询，只读事务在绑定的读线程中查询
+    * @returns 读线程队列已满返回Busy
+    */
+    pub fn query(&self, queries: Arc<Vec<TabKV>>, cb: QueryCallback, token: Option<CancelToken>) -> Result<(), StoreError> {
+        match &self.writer {
+            Some(writer) => writer.send(WriterMsg::Query(queries, cb, token)).map_err(|_| StoreError::Busy),
+            None => {
+                let reader = if queries.len() > LOW_PRIORITY_QUERY_SIZE { &self.reader_low } else { &self.reader };
+                reader.try_send(ReaderMsg::Query(queries, cb, token)).map_err(|_| StoreError::Busy)
+            }
+        }
+    }
+
+    // 确认修改，修改在提交时才写入
+    pub fn modify(&self, cb: WriteCallback) -> Result<(), StoreError> {
+        self.writer()?.send(WriterMsg::Modify(cb)).map_err(|_| StoreError::Busy)
+    }
+
+    // 预提交，将修改写入写线程的读写事务但不提交
+    pub fn prepare(&self, modifies: Arc<Vec<TabKV>>, conditions: Arc<Vec<Condition>>, cb: WriteCallback) -> Result<(), StoreError> {
+        self.writer()?.send(WriterMsg::Prepare(self.txid, modifies, conditions, cb)).map_err(|_| StoreError::Busy)
+    }
+
+    // 提交已预提交的读写事务
+    pub fn commit_prepared(&self, cb: WriteCallback) -> Result<(), StoreError> {
+        self.writer()?.send(WriterMsg::CommitPrepared(self.txid, cb)).map_err(|_| StoreError::Busy)
+    }
+
+    // 提交表事务，由绑定的读线程确认，修改由库事务统一提交
+    pub fn commit(&self, cb: WriteCallback) -> Result<(), StoreError> {
+        self.reader.send(ReaderMsg::Commit(cb)).map_err(|_| StoreError::Busy)
+    }
+
+    // 回滚，读写事务由写线程回滚并释放写线程的使用权
+    pub fn rollback(&self, cb: WriteCallback) -> Result<(), StoreError> {
+        match &self.writer {
+            Some(writer) => writer.send(WriterMsg::Rollback(self.txid, cb)).map_err(|_| StoreError::Busy),
+            None => self.reader.send(ReaderMsg::Rollback(cb)).map_err(|_| StoreError::Busy),
+        }
+    }
+}
+
+/**
+* 读线程中的独立读事务，所有查询和迭代都在打开时的同一个快照中执行
+* 一个读线程可以同时持有多个读事务，不同调用者的迭代器不需要各自占用一个读线程
+* 读事务持有读槽并阻止空闲页回收，使用完应尽快释放，释放时自动结束
+*/
+pub struct RoTxn {
+    id: u64,
+    reader: WorkerSender<ReaderMsg>,    //持有读事务的读线程
+}
+
+impl RoTxn {
+    // 在指定的读线程中打开读事务，等待读线程打开后返回，不能在持有全局锁时调用
+    pub(crate) fn open(reader: WorkerSender<ReaderMsg>) -> Result<RoTxn, String> {
+        let (tx, rx) = bounded(1);
+        reader.send(ReaderMsg::OpenRo(tx)).map_err(|e| e.to_string())?;
+        let id = rx.recv().map_err(|e| e.to_string())??;
+        Ok(RoTxn { id, reader })
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /**
+    * 在读事务中查询
+    * @param queries 查询的键
+    * @param cb 查询回调
+    */
+    pub fn query(&self, queries: Arc<Vec<TabKV>>, cb: QueryCallback) -> Result<(), StoreError> {
+        self.reader.send(ReaderMsg::RoQuery(self.id, queries, cb)).map_err(|_| StoreError::Busy)
+    }
+
+    /**
+    * 在读事务中从当前键开始取迭代方向上的下一个键值，当前键存在时返回当前键
+    * @param tab 表名
+    * @param cur_key 当前键
+    * @param descending 为true时从小到大迭代
+    * @param cb 返回找到的键值和之后的键，之后的键作为下一次的当前键，为None表示迭代结束
+    */
+    pub fn next(&self, tab: &Atom, cur_key: Bin, descending: bool, cb: SendCallback<SResult<(Option<(Bin, Bin)>, Option<Bin>)>>) -> Result<(), StoreError> {
+        self.reader.send(ReaderMsg::RoNext(self.id, tab.clone(), cur_key, descending, cb)).map_err(|_| StoreError::Busy)
+    }
+}
+
+impl Drop for RoTxn {
+    fn drop(&mut self) {
+        let _ = self.reader.send(ReaderMsg::CloseRo(self.id));
+    }
+}
+
+/**
+* 可空闲退出的读线程，读线程退出后通道保留，下次向读线程发送消息时重新启动
+* 发送消息和读线程退出在同一个锁中进行：读线程只在锁中确认通道为空后退出，发送者在锁中发现读线程已退出时先重新启动再发送
+*/
+pub(crate) struct IdleReader {
+    alive: Mutex<bool>,                             //读线程是否在运行
+    spawn: Box<Fn(Arc<IdleReader>) + Send + Sync>,  //启动读线程，继续使用原来的通道
+}
+
+impl IdleReader {
+    fn is_alive(&self) -> bool {
+        *self.alive.lock().unwrap()
+    }
+
+    // 读线程空闲超时后调用，在锁中确认通道为空后标记退出，返回是否可以退出
+    fn try_exit(&self, rx: &Receiver<ReaderMsg>, low_rx: &Receiver<ReaderMsg>) -> bool {
+        let mut alive = self.alive.lock().unwrap();
+        if rx.is_empty() && low_rx.is_empty() {
+            *alive = false;
+        }
+        !*alive
+    }
+}
+
+// 在读线程的锁中发送消息，读线程已因空闲退出时先重新启动，通道中积压的消息由新线程继续处理
+fn send_to_reader<R, F: FnOnce() -> R>(slot: &Arc<IdleReader>, send: F) -> R {
+    let mut alive = slot.alive.lock().unwrap();
+    if !*alive {
+        *alive = true;
+        (slot.spawn)(slot.clone());
+    }
+    send()
+}
+
+/**
+* 工作线程的发送端，同步执行模式下发送后立即在发送者的线程中执行消息
+* 读线程的发送端每次发送前确认读线程在运行，事务句柄等缓存的发送端在读线程空闲退出后仍然可用
+*/
+pub struct WorkerSender<T> {
+    sender: Sender<T>,
+    pump: Option<Arc<Pump>>,
+    reader: Option<Arc<IdleReader>>,
+}
+
+impl<T> Clone for WorkerSender<T> {
+    fn clone(&self) -> Self {
+        WorkerSender {
+            sender: self.sender.clone(),
+            pump: self.pump.clone(),
+            reader: self.reader.clone(),
+        }
+    }
+}
+
+impl<T> WorkerSender<T> {
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        match &self.reader {
+            Some(slot) => send_to_reader(slot, || self.sender.send(msg))?,
+            None => self.sender.send(msg)?,
+        }
+        if let Some(pump) = &self.pump {
+            pump.pump();
+        }
+        Ok(())
+    }
+
+    pub fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+        match &self.reader {
+            Some(slot) => send_to_reader(slot, || self.sender.try_send(msg))?,
+            None => self.sender.try_send(msg)?,
+        }
+        if let Some(pump) = &self.pump {
+            pump.pump();
+        }
+        Ok(())
+    }
+
+    // 待处理的消息数量
+    pub fn len(&self) -> usize {
+        self.sender.len()
+    }
+}
+
+impl LmdbService {
+    pub fn new(readers_count: usize) -> LmdbService {
+        Self {
+            env: None,
+            env_id: 0,
+            readers_count,
+            readers: vec![],
+            readers_low: vec![],
+            writer: None,
+            kind: StoreKind::Lmdb,
+            dispatch: Dispatch::LeastLoaded,
+            queue_capacity: 0,
+            pin_cores: false,
+            affinity_tabs: HashSet::new(),
+            reader_check_interval: readers::DEFAULT_READER_CHECK_INTERVAL,
+            sync_policy: None,
+            idle_readers: vec![],
+            idle_timeout: 0,
+            min_readers: 0,
+            reader_activity: vec![],
+            writer_activity: Arc::new(WorkerActivity::default()),
+            inline: false,
+            pump: None,
+        }
+    }
+
+    /**
+    * 设置表的线程亲和性，开启后该表的所有读消息都由按表名哈希确定的同一个读线程处理
+    * 写消息总是由唯一的写线程处理，不需要设置
+    * @param tab 表名
+    * @param affinity 是否开启
+    */
+    pub fn set_tab_affinity(&mut self, tab: &Atom, affinity: bool) {
+        let hash = tab.get_hash() as u64;
+        if affinity {
+            self.affinity_tabs.insert(hash);
+        } else {
+            self.affinity_tabs.remove(&hash);
+        }
+    }
+
+    // 设置是否将工作线程绑定到CPU核，必须在start之前调用
+    pub fn set_pin_cores(&mut self, pin: bool) {
+        self.pin_cores = pin;
+    }
+
+    // 设置清理失效读槽的间隔(毫秒)，为0则不清理，必须在start之前调用
+    pub fn set_reader_check_interval(&mut self, millis: u64) {
+        self.reader_check_interval = millis;
+    }
+
+    /**
+    * 设置后台刷盘，环境以NO_SYNC打开时提交不刷盘，由后台线程按间隔或提交次数刷盘
+    * 必须在start之前调用
+    * @param policy 刷盘策略，为None则不启动后台刷盘
+    */
+    pub fn set_background_sync(&mut self, policy: Option<SyncPolicy>) {
+        self.sync_policy = policy;
+    }
+
+    /**
+    * 设置空闲读线程的回收，多余的读线程空闲超时后退出，在所有运行中的读线程都繁忙时按需重新启动
+    * 必须在start之前调用，写线程不会被回收
+    * @param idle_secs 空闲多少秒后退出，为0则不回收
+    * @param min_readers 保留的最少读线程数
+    */
+    pub fn set_idle_scale_down(&mut self, idle_secs: u64, min_readers: usize) {
+        self.idle_timeout = idle_secs;
+        self.min_readers = min_readers.max(1);
+    }
+
+    // 设置每个工作线程通道的容量，必须在start之前调用
+    pub fn set_queue_capacity(&mut self, capacity: usize) {
+        self.queue_capacity = capacity;
+    }
+
+    // 每个工作线程通道的容量，0表示不限制
+    pub fn queue_capacity(&self) -> usize {
+        self.queue_capacity
+    }
+
+    // 尝试向读线程发送消息，队列已满时不阻塞，返回Busy
+    pub fn try_ro_send(&self, tab: &Atom, priority: Priority, msg: ReaderMsg) -> Result<(), StoreError> {
+        let sender = self.ro_sender_with_priority(tab, priority).ok_or(StoreError::Busy)?;
+        sender.try_send(msg).map_err(|_| StoreError::Busy)
+    }
+
+    // 尝试向写线程发送消息，队列已满时不阻塞，返回Busy
+    pub fn try_rw_send(&self, msg: WriterMsg) -> Result<(), StoreError> {
+        let sender = self.rw_sender().ok_or(StoreError::Busy)?;
+        sender.try_send(msg).map_err(|_| StoreError::Busy)
+    }
+
+    /**
+    * 设置同步执行模式，不启动工作线程，消息在发送者的线程中按发送顺序执行，回调也在发送者的线程中调用
+    * 用于编写确定性的测试，只支持非LMDB后端，必须在start之前调用
+    * 事务管理器的最终提交仍由提交线程发出，提交消息在提交线程中执行
+    */
+    pub fn set_inline(&mut self, inline: bool) {
+        self.inline = inline;
+    }
+
+    // 设置读消息的分发方式
+    pub fn set_dispatch(&mut self, dispatch: Dispatch) {
+        self.dispatch = dispatch;
+    }
+
+    // 使用纯内存后端，必须在start之前调用
+    pub fn use_mem_store(&mut self) {
+        self.kind = StoreKind::Mem(MemStore::new());
+    }
+
+    // 使用RocksDB后端，必须在start之前调用
+    pub fn use_rocks_store(&mut self, path: &str) -> Result<(), String> {
+        self.kind = StoreKind::Rocks(RocksStore::open(path)?);
+        Ok(())
+    }
+
+    pub fn is_lmdb(&self) -> bool {
+        match self.kind {
+            StoreKind::Lmdb => true,
+            _ => false,
+        }
+    }
+
+    pub fn set_env(&mut self, env: Arc<Environment>) {
+        self.env = Some(env);
+    }
+
+    pub fn env_id(&self) -> u64 {
+        self.env_id
+    }
+
+    pub fn get_env(&self) -> Arc<Environment> {
+        self.env.clone().unwrap()
+    }
+
+    pub fn start(&mut self) {
+        match self.kind.clone() {
+            StoreKind::Lmdb => {
+                if self.inline {
+                    warn!("inline mode not supported by lmdb, start worker threads");
+                }
+                self.spawn_readers();
+                self.spawn_writer();
+                if self.reader_check_interval > 0 {
+                    readers::spawn_checker(self.env_id, self.get_env(), Duration::from_millis(self.reader_check_interval));
+                }
+                if let Some(policy) = self.sync_policy {
+                    flusher::spawn(self.env_id, self.get_env(), policy);
+                }
+            }
+            StoreKind::Mem(store) => self.spawn_backend(store),
+            StoreKind::Rocks(store) => self.spawn_backend(store),
+        }
+    }
+
+    fn spawn_backend<B: Backend + Sync>(&mut self, store: B) {
+        if self.inline {
+            let (reader, writer, pump) = backend::inline(store, self.env_id);
+            self.readers = vec![reader; self.readers_count];
+            self.readers_low = self.readers.clone();
+            self.writer = Some(writer);
+            self.pump = Some(pump);
+            return;
+        }
+
+        let capacity = self.queue_capacity;
+        self.readers = (0..self.readers_count).map(|i| backend::spawn_reader(store.clone(), i, capacity)).collect();
+        // 非LMDB后端只用于测试，不区分优先级
+        self.readers_low = self.readers.clone();
+        self.writer = Some(backend::spawn_writer(store, self.env_id, capacity));
+    }
+
+    pub(crate) fn ro_sender(&self, tab: &Atom) -> Option<WorkerSender<ReaderMsg>> {
+        self.ro_sender_with_priority(tab, Priority::High)
+    }
+
+    // 按优先级获取读线程的通道，读线程总是先处理高优先级通道中的消息
+    pub(crate) fn ro_sender_with_priority(&self, tab: &Atom, priority: Priority) -> Option<WorkerSender<ReaderMsg>> {
+        if self.readers.is_empty() {
+            return None;
+        }
+        let index = self.reader_index(tab);
+        Some(self.reader_sender(index, priority))
+    }
+
+    // 指定读线程指定优先级的通道
+    fn reader_sender(&self, index: usize, priority: Priority) -> WorkerSender<ReaderMsg> {
+        let readers = match priority {
+            Priority::High => &self.readers,
+            Priority::Low => &self.readers_low,
+        };
+        WorkerSender {
+            sender: readers[index].clone(),
+            pump: self.pump.clone(),
+            reader: self.idle_readers.get(index).cloned(),
+        }
+    }
+
+    // 按分发策略为表选择读线程，选中的读线程已退出时在发送时重新启动
+    fn reader_index(&self, tab: &Atom) -> usize {
+        let hashed = (tab.get_hash() as usize) % self.readers_count;
+        let index = match self.dispatch {
+            Dispatch::TabHash => hashed,
+            _ if self.affinity_tabs.contains(&(tab.get_hash() as u64)) => hashed,
+            // 队列长度相同时优先选择按表名哈希的读线程，运行中的读线程都繁忙时才选择已退出的读线程
+            Dispatch::LeastLoaded => (0..self.readers_count)
+                .map(|i| (hashed + i) % self.readers_count)
+                .min_by_key(|&i| match (self.is_reader_alive(i), self.queue_len(i)) {
+                    (true, 0) => (0, 0),
+                    (false, len) => (1, len),
+                    (true, len) => (2, len),
+                })
+                .unwrap_or(hashed),
+        };
+        index
+    }
+
+    /**
+    * 在为表选择的读线程中打开独立的读事务，等待读线程打开后返回
+    * @param tab 表名，用于选择读线程，读事务可以读取同一环境中的所有表
+    * @returns 返回读事务，读槽用完或工作线程未启动时返回错误
+    */
+    pub fn open_ro(&self, tab: &Atom) -> Result<RoTxn, String> {
+        if self.readers.is_empty() {
+            return Err("lmdb readers not started".to_string());
+        }
+        RoTxn::open(self.reader_sender(self.reader_index(tab), Priority::High))
+    }
+
+    /**
+    * 为事务选择工作线程，事务的所有消息都通过返回的句柄发送到同一个读线程，读写事务同时绑定写线程
+    * @param tab 表名，用于选择读线程
+    * @param txid 事务id
+    * @param writable 是否为读写事务
+    * @returns 返回事务句柄，工作线程未启动时返回None
+    */
+    pub fn txn_handle(&self, tab: &Atom, txid: u64, writable: bool) -> Option<TxnHandle> {
+        if self.readers.is_empty() {
+            return None;
+        }
+        let writer = match writable {
+            true => Some(self.rw_sender()?),
+            false => None,
+        };
+        let index = self.reader_index(tab);
+        Some(TxnHandle {
+            txid,
+            reader: self.reader_sender(index, Priority::High),
+            reader_low: self.reader_sender(index, Priority::Low),
+            writer,
+        })
+    }
+
+    // 读线程两个优先级通道中待处理的消息数量
+    fn queue_len(&self, index: usize) -> usize {
+        self.readers[index].len() + self.readers_low[index].len()
+    }
+
+    pub(crate) fn rw_sender(&self) -> Option<WorkerSender<WriterMsg>> {
+        self.writer.clone().map(|sender| WorkerSender {
+            sender,
+            pump: self.pump.clone(),
+            reader: None,
+        })
+    }
+
+    fn spawn_readers(&mut self) {
+        (0..self.readers_count).for_each(|i| {
+            let (tx, rx) = channel(self.queue_capacity);
+            let (low_tx, low_rx) = channel(self.queue_capacity);
+            let activity = Arc::new(WorkerActivity::default());
+            let (env, env_id, pin, idle, activity1) = (self.env.clone(), self.env_id, self.pin_cores, self.reader_idle(i), activity.clone());
+            let slot = Arc::new(IdleReader {
+                alive: Mutex::new(true),
+                spawn: Box::new(move |slot| {
+                    spawn_reader(env.clone(), env_id, i, rx.clone(), low_rx.clone(), pin, idle, slot, activity1.clone());
+                }),
+            });
+            (slot.spawn)(slot.clone());
+            self.readers.push(tx);
+            self.readers_low.push(low_tx);
+            self.idle_readers.push(slot);
+            self.reader_activity.push(activity);
+        })
+    }
+
+    // 读线程的空闲超时，序号小于最少读线程数的读线程不会退出
+    fn reader_idle(&self, index: usize) -> Option<Duration> {
+        if self.idle_timeout == 0 || index < self.min_readers {
+            None
+        } else {
+            Some(Duration::from_secs(self.idle_timeout))
+        }
+    }
+
+    // 读线程是否在运行，非LMDB后端的读线程总是在运行
+    fn is_reader_alive(&self, index: usize) -> bool {
+        self.idle_readers.get(index).map_or(true, |slot| slot.is_alive())
+    }
+
+    /**
+    * 获取工作线程池的统计，只读取原子变量和通道长度，不会阻塞工作线程
+    * 非LMDB后端的工作线程不记录活动，只有队列长度
+    */
+    pub fn stats(&self) -> PoolStats {
+        let mut workers = (0..self.readers.len())
+            .map(|i| {
+                let activity = self.reader_activity.get(i);
+                WorkerStats {
+                    name: reader_name(i),
+                    alive: self.is_reader_alive(i),
+                    busy: activity.map_or(false, |a| a.busy.load(Ordering::Relaxed)),
+                    queue_depth: self.queue_len(i),
+                    processed: activity.map_or(0, |a| a.processed.load(Ordering::Relaxed)),
+                    last_active: activity.map_or(0, |a| a.last_active.load(Ordering::Relaxed)),
+                }
+            })
+            .collect::<Vec<WorkerStats>>();
+        if let Some(writer) = &self.writer {
+            workers.push(WorkerStats {
+                name: writer_name(),
+                alive: true,
+                busy: self.writer_activity.busy.load(Ordering::Relaxed),
+                queue_depth: writer.len(),
+                processed: self.writer_activity.processed.load(Ordering::Relaxed),
+                last_active: self.writer_activity.last_active.load(Ordering::Relaxed),
+            });
+        }
+        let busy = workers.iter().filter(|w| w.busy).count();
+        let idle = workers.iter().filter(|w| w.alive && !w.busy).count();
+        let open_txn_age = RW_TXN_HOLDERS.lock().unwrap().get(&self.env_id).map(|h| h.3.elapsed());
+
+        PoolStats {
+            env_id: self.env_id,
+            total: workers.len(),
+            idle,
+            busy,
+            workers,
+            open_txn_age,
+        }
+    }
+
+    fn spawn_writer(&mut self) {
+        let env = self.env.clone();
+        let env_id = self.env_id;
+        let (tx, rx) = channel(self.queue_capacity);
+
+        let pin = self.pin_cores;
+        let readers_count = self.readers_count;
+        let activity = self.writer_activity.clone();
+        let _ = thread::Builder::new().name(writer_name()).spawn(move || {
+            if pin {
+                // 写线程使用读线程之后的核
+                pin_to_core(readers_count);
+            }
+            let mut rw_txn: Option<RwTransaction> = None;
+            // 已直接写入读写事务的键(合并和预提交)，提交时使读缓存失效
+            let mut staged: Vec<TabKV> = Vec::new();
+            // 等待一起提交的合并写入
+            let mut coalescer = Coalescer::new();
+            // 读写事务未结束时收到的带标志写入，事务结束后依次提交
+            let mut pending_puts: Vec<(Arc<Vec<TabKV>>, WriteFlags, WriteCallback)> = Vec::new();
+
+            loop {
+                // 合并写入不能与事务的读写事务混在一起，只在写线程空闲时刷新
+                if rw_txn.is_none() && coalescer.due() {
+                    audit::set_session(env_id, 0);
+                    flush_coalesced(env.as_ref().unwrap(), env_id, coalescer.take());
+                }
+                if rw_txn.is_none() {
+                    for (modifies, flags, cb) in pending_puts.drain(..) {
+                        put_with_flags(env.as_ref().unwrap(), env_id, modifies, flags, cb);
+                    }
+                }
+
+                let msg = match txn_idle_timeout() {
+                    // 有未结束的读写事务时，空闲超时后回滚事务，避免阻塞所有写操作
+                    Some(timeout) if rw_txn.is_some() => match rx.recv_timeout(timeout) {
+                        Ok(msg) => msg,
+                        Err(RecvTimeoutError::Timeout) => {
+                            let txid = take_writer(env_id).unwrap_or(0);
+                            warn!("rw txn idle timeout, txid: {:?}, timeout: {:?}, abort it", txid, timeout);
+                            if let Some(txn) = rw_txn.take() {
+                                txn.abort();
                             }
+                            TIMED_OUT_TXS.lock().unwrap().insert(txid);
+                            RW_TXN_HOLDERS.lock().unwrap().remove(&env_id);
+                            staged.clear();
+                            aborted(env_id);
+                            continue;
                         }
-                        if query_error {
-                            let t = Box::new(move |_| {
-                                cb(Err(format!("lmdb rw query internal error")));
-                            });
-                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer query error"));
-                            warn!("queries error: {:?}", queries);
-                        } else {
-                            debug!("lmdb rw query success: {:?}", qr);
-                            let t = Box::new(move |_| {
-                                cb(Ok(qr));
-                            });
-                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer query ok"));
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    },
+                    // 有等待的合并写入时最多等到刷新时间
+                    _ if rw_txn.is_none() && !coalescer.is_empty() => match rx.recv_timeout(coalescer.remaining()) {
+                        Ok(msg) => msg,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    },
+                    _ => match rx.recv() {
+                        Ok(msg) => msg,
+                        Err(_) => break,
+                    },
+                };
+                // 持有读写事务的事务的守卫已全部释放而没有提交或回滚，回滚遗留的读写事务，避免其它事务写入其中
+                // 该事务自己的提交和回滚消息仍按原读写事务处理
+                if rw_txn.is_some() {
+                    let holder = RW_TXN_HOLDERS.lock().unwrap().get(&env_id).map(|h| h.0);
+                    if holder.is_some() && holder != writer_owner(env_id) && holder != Some(msg.session()) {
+                        warn!("rw txn released without commit or rollback, txid: {:?}, abort it", holder);
+                        if let Some(txn) = rw_txn.take() {
+                            txn.abort();
+                        }
+                        RW_TXN_HOLDERS.lock().unwrap().remove(&env_id);
+                        staged.clear();
+                        aborted(env_id);
+                    }
+                }
+                let span = debug_span!("lmdb_writer", op = msg.op_name(), tab = ?msg.tab(), keys = msg.key_count(), outcome = field::Empty);
+                let _enter = span.enter();
+                let _busy = activity.begin();
+                fault::on_message("writer");
+                let mut outcome = "ok";
+
+                let op = msg.op_name();
+                let op_tab = msg.tab().cloned();
+                audit::set_session(env_id, msg.session());
+
+                match msg {
+                    WriterMsg::Query(queries, cb, token) => {
+                        let start_time = Instant::now();
+                        if rw_txn.is_none() {
+                            rw_txn = Some(begin_rw(env.as_ref().unwrap())
+                            .expect("Fatal error: failed to begin rw txn"));
                         }
-                        let elapsed = start_time.elapsed();
-                        if elapsed > Duration::from_millis(SLOW_TIME) {
-                            let tabs = queries.iter().map(|q| q.tab.clone().to_string()).collect::<Vec<String>>();
-                            let qsize: usize = queries.iter().map(|q| q.key.clone().len()).sum();
-                            println!("===> Slow writer query, time: {:?}, tabs: {:?}, qsize: {:?}", elapsed, tabs, qsize);
+
+                        match query_in_txn(env_id, rw_txn.as_ref().unwrap(), &queries, token.as_ref(), None) {
+                            Ok(qr) => {
+                                debug!("lmdb rw query success: {:?}", qr);
+                                let t = Box::new(move |_| {
+                                    cb(Ok(qr));
+                                });
+                                cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer query ok"));
+                            }
+                            Err(e) => {
+                                outcome = "error";
+                                warn!("rw queries error: {:?}, {:?}", e, queries);
+                                let t = Box::new(move |_| {
+                                    cb(Err(e.to_string()));
+                                });
+                                cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer query error"));
+                            }
                         }
+                        log_slow("writer query", start_time, &queries, queries.len());
                     }
 
-                    Ok(WriterMsg::CreateItemIter(descending, tab, start_key, sndr)) => {
+                    WriterMsg::CreateItemIter(descending, tab, start_key, sndr) => {
                         let start_time = Instant::now();
                         if rw_txn.is_none() {
-                            rw_txn = Some(env
-                            .as_ref()
-                            .unwrap()
-                            .begin_rw_txn()
+                            rw_txn = Some(begin_rw(env.as_ref().unwrap())
                             .expect("Fatal error: failed to begin rw txn"));
                         }
 
-                        let db = get_db(tab.get_hash() as u64);
-                        let cursor = rw_txn.as_mut().unwrap()
-                            .open_rw_cursor(db)
-                            .expect(&format!("Fatal error: open rw cursor for db: {:?} failed", db));
+                        let cursor = get_db(env_id, &tab)
+                            .and_then(|db| rw_txn.as_mut().unwrap().open_rw_cursor(db))
+                            .expect(&format!("Fatal error: open rw cursor for tab: {:?} failed", tab));
 
                         match (descending, start_key) {
-                            (true, None) => match cursor.get(None, None, MDB_FIRST) {
+                            (true, None) => match skip_tombstones(&cursor, &tab, cursor.get(None, None, MDB_FIRST), MDB_NEXT) {
                                 Ok(val) => {
                                     let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
                                 }
@@ -471,7 +1356,7 @@ impl LmdbService {
                             },
                             // MDB_SET_RANGE 会找到第一个大于或者等于 sk 的 key
                             (true, Some(sk)) => {
-                                match cursor.get(Some(sk.as_ref()), None, MDB_SET_RANGE) {
+                                match skip_tombstones(&cursor, &tab, cursor.get(Some(sk.as_ref()), None, MDB_SET_RANGE), MDB_NEXT) {
                                     Ok(val) => {
                                         let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
                                     }
@@ -482,13 +1367,13 @@ impl LmdbService {
                                 }
                             }
                             (false, Some(sk)) => {
-                                match cursor.get(Some(sk.as_ref()), None, MDB_SET_RANGE) {
+                                match skip_tombstones(&cursor, &tab, cursor.get(Some(sk.as_ref()), None, MDB_SET_RANGE), MDB_PREV) {
                                     Ok(val) => {
                                         let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
                                     }
                                     Err(Error::NotFound) => {
                                         // 降序迭代起始 key 超过最大 key 则定位到表中最后一个元素
-                                        match cursor.get(None, None, MDB_LAST) {
+                                        match skip_tombstones(&cursor, &tab, cursor.get(None, None, MDB_LAST), MDB_PREV) {
                                             Ok(val) => {
                                                 let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
                                             }
@@ -501,7 +1386,7 @@ impl LmdbService {
                                     Err(_) => {}
                                 }
                             }
-                            (false, None) => match cursor.get(None, None, MDB_LAST) {
+                            (false, None) => match skip_tombstones(&cursor, &tab, cursor.get(None, None, MDB_LAST), MDB_PREV) {
                                 Ok(val) => {
                                     let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
                                 }
@@ -514,45 +1399,45 @@ impl LmdbService {
 
                         drop(cursor);
 
-                        let elapsed = start_time.elapsed();
-                        if elapsed > Duration::from_millis(SLOW_TIME) {
-                            println!("===> Slow writer createItemIter, time: {:?}, tab: {:?}", elapsed, tab);
-                        }
+                        log_slow_tab("writer createItemIter", start_time, &tab);
                     }
 
-                    Ok(WriterMsg::NextItem(descending, tab, cur_key, cb, sndr)) => {
+                    WriterMsg::NextItem(descending, tab, cur_key, cb, sndr) => {
                         let start_time = Instant::now();
                         if rw_txn.is_none() {
-                            rw_txn = Some(env
-                            .as_ref()
-                            .unwrap()
-                            .begin_rw_txn()
+                            rw_txn = Some(begin_rw(env.as_ref().unwrap())
                             .expect("Fatal error: failed to begin rw txn"));
                         }
-                        let db = get_db(tab.get_hash() as u64);
-                        let cursor = rw_txn.as_mut().unwrap()
-                            .open_rw_cursor(db)
-                            .expect(&format!("Fatal error: open rw cursor for db: {:?} failed", db));
+                        let cursor = get_db(env_id, &tab)
+                            .and_then(|db| rw_txn.as_ref().unwrap().open_ro_cursor(db))
+                            .expect(&format!("Fatal error: open cursor for tab: {:?} failed", tab));
 
                         match (descending, cur_key.clone()) {
                             (true, Some(ck)) => {
                                 let cb1 = cb.clone();
                                 let ck1 = ck.clone();
                                 match cursor.get(Some(ck.as_ref()), None, MDB_SET_KEY) {
-                                    Ok(val) => {
-                                        let v = val.1.to_vec();
-                                        debug!("iter next item descendin key: {:?}, value: {:?}", ck.clone(), v.clone());
-                                        let t = Box::new(move |_: Option<isize>| {
-                                            cb1(Ok(Some((ck1, Arc::new(v)))));
-                                        });
-                                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader get next item"));
-                                    }
+                                    // 软删除的键不返回
+                                    Ok((_, v)) if tombstone::is_deleted(&tab, v) => {}
+                                    Ok(val) => match chunk::read(rw_txn.as_ref().unwrap(), env_id, &tab, &ck, val.1) {
+                                        Ok(v) => {
+                                            debug!("iter next item descendin key: {:?}, value: {:?}", ck.clone(), v.clone());
+                                            let t = Box::new(move |_: Option<isize>| {
+                                                cb1(Ok(Some((ck1, Arc::new(v)))));
+                                            });
+                                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader get next item"));
+                                        }
+                                        Err(e) => {
+                                            outcome = "error";
+                                            warn!("read chunked value failed: {:?}", e.to_string());
+                                        }
+                                    },
                                     Err(Error::NotFound) => {}
                                     Err(_) => {}
                                 }
 
                                 // get next key
-                                match cursor.get(Some(ck.as_ref()), None, MDB_NEXT) {
+                                match skip_tombstones(&cursor, &tab, cursor.get(Some(ck.as_ref()), None, MDB_NEXT), MDB_NEXT) {
                                     Ok(val) => {
                                         debug!("rw iter next key descending: item: {:?}", val.clone());
                                         let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
@@ -564,6 +1449,7 @@ impl LmdbService {
                                     }
 
                                     Err(e) => {
+                                        outcome = "error";
                                         let t = Box::new(move |_: Option<isize>| {
                                             cb(Err(format!("lmdb rw iter internal error: {:?}", e)));
                                         });
@@ -576,20 +1462,27 @@ impl LmdbService {
                                 let ck1 = ck.clone();
                                 let cb2 = cb.clone();
                                 match cursor.get(Some(ck.as_ref()), None, MDB_SET_KEY) {
-                                    Ok(val) => {
-                                        let v = val.1.to_vec();
-                                        debug!("rw iter next item ascending key: {:?}, value: {:?}", ck.clone(), v.clone());
-                                        let t = Box::new(move |_: Option<isize>| {
-                                            cb1(Ok(Some((ck1, Arc::new(v)))));
-                                        });
-                                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer get next item"));
-                                    }
+                                    // 软删除的键不返回
+                                    Ok((_, v)) if tombstone::is_deleted(&tab, v) => {}
+                                    Ok(val) => match chunk::read(rw_txn.as_ref().unwrap(), env_id, &tab, &ck, val.1) {
+                                        Ok(v) => {
+                                            debug!("rw iter next item ascending key: {:?}, value: {:?}", ck.clone(), v.clone());
+                                            let t = Box::new(move |_: Option<isize>| {
+                                                cb1(Ok(Some((ck1, Arc::new(v)))));
+                                            });
+                                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer get next item"));
+                                        }
+                                        Err(e) => {
+                                            outcome = "error";
+                                            warn!("read chunked value failed: {:?}", e.to_string());
+                                        }
+                                    },
                                     Err(Error::NotFound) => {}
                                     Err(_) => {}
                                 }
 
                                 // get next key
-                                match cursor.get(Some(ck.as_ref()), None, MDB_PREV) {
+                                match skip_tombstones(&cursor, &tab, cursor.get(Some(ck.as_ref()), None, MDB_PREV), MDB_PREV) {
                                     Ok(val) => {
                                         debug!("rw iter next item ascending item: {:?}", val);
                                         let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
@@ -601,6 +1494,7 @@ impl LmdbService {
                                     }
 
                                     Err(e) => {
+                                        outcome = "error";
                                         let t = Box::new(move |_: Option<isize>| {
                                             cb2(Err(format!("Lmdb writer lmdb next item error: {:?}", e)));
                                         });
@@ -614,112 +1508,2208 @@ impl LmdbService {
 
                         drop(cursor);
 
-                        let elapsed = start_time.elapsed();
-                        if elapsed > Duration::from_millis(SLOW_TIME) {
-                            println!("===> Slow writer itemIter, time: {:?}, tab: {:?}, iter_key: {:?}", elapsed, tab, cur_key);
-                        }
+                        log_slow_tab("writer nextItem", start_time, &tab);
                     }
 
-                    Ok(WriterMsg::Modify(cb)) => {
+                    WriterMsg::Modify(cb) => {
                         let t = Box::new(move |_: Option<isize>| {
                             cb(Ok(()));
                         });
                         cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer modify"));
                     }
-                    Ok(WriterMsg::Commit(modifies, cb)) => {
+                    WriterMsg::Coalesce(modifies, cb) => {
+                        coalescer.push(modifies, cb);
+                    }
+                    WriterMsg::BulkLoad(tab, stream, every, cb) => {
                         let start_time = Instant::now();
-                        if rw_txn.is_none() {
-                            rw_txn = Some(env
-                            .as_ref()
-                            .unwrap()
-                            .begin_rw_txn()
-                            .expect("Fatal error: failed to begin rw txn"));
+                        let r = if rw_txn.is_some() {
+                            Err(StoreError::Busy.to_string())
+                        } else {
+                            bulk_load(env.as_ref().unwrap(), env_id, &tab, &stream, every)
+                        };
+                        if r.is_err() {
+                            outcome = "error";
                         }
+                        let t = Box::new(move |_: Option<isize>| {
+                            cb(r.clone());
+                        });
+                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer bulk load"));
 
-                        let mut modify_error = false;
+                        log_slow_tab("writer bulk load", start_time, &tab);
+                    }
+                    WriterMsg::Put(modifies, flags, cb) => {
+                        if rw_txn.is_some() {
+                            pending_puts.push((modifies, flags, cb));
+                        } else {
+                            put_with_flags(env.as_ref().unwrap(), env_id, modifies, flags, cb);
+                        }
+                    }
+                    WriterMsg::Exec(txid, mut f, sndr) => {
+                        let start_time = Instant::now();
+                        // 写线程的读写事务被其它事务持有，由调用者重试
+                        if rw_txn.is_some() {
+                            outcome = "error";
+                            let _ = sndr.send(Err(StoreError::Busy.to_string()));
+                            continue;
+                        }
 
-                        for m in modifies.iter() {
-                            let db = get_db(m.tab.get_hash() as u64);
-                            // value is some, insert data
-                            if m.value.is_some() {
-                                match rw_txn.as_mut().unwrap().put(
-                                    db,
-                                    m.key.as_ref(),
-                                    m.value.clone().unwrap().as_ref(),
-                                    WriteFlags::empty(),
-                                ) {
-                                    Ok(_) => {}
-                                    Err(_) => modify_error = true,
-                                }
-                            // value is None, delete data
-                            } else {
-                                match rw_txn.as_mut().unwrap().del(db, m.key.as_ref(), None) {
-                                    Ok(_) => {}
-                                    Err(Error::NotFound) => {
-                                        // TODO: when not found?
+                        let r = match begin_rw(env.as_ref().unwrap()) {
+                            Ok(mut txn) => {
+                                let (r, written) = {
+                                    let mut ops = TxnOps {
+                                        txn: &mut txn,
+                                        env_id,
+                                        written: Vec::new(),
+                                    };
+                                    (f(&mut ops), ops.written)
+                                };
+                                match r {
+                                    Ok(_) => {
+                                        let r = commit_rw(env_id, txn).map_err(|e| StoreError::from(e).to_string());
+                                        read_cache::invalidate(env_id, &written);
+                                        match r {
+                                            Ok(_) => committed(env_id),
+                                            Err(_) => aborted(env_id),
+                                        }
+                                        r
+                                    }
+                                    Err(e) => {
+                                        txn.abort();
+                                        read_cache::invalidate(env_id, &written);
+                                        aborted(env_id);
+                                        Err(e)
                                     }
-                                    Err(_) => modify_error = true,
                                 }
                             }
+                            Err(e) => Err(StoreError::from(e).to_string()),
+                        };
+                        if r.is_err() {
+                            outcome = "error";
                         }
-                        let cb1 = cb.clone();
-                        if modify_error {
-                            let t = Box::new(move |_: Option<isize>| {
-                                cb(Err("modify error".to_string()));
-                            });
-                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer error"));
-                            warn!("lmdb modify error");
+                        release_writer(env_id, txid);
+                        let _ = sndr.send(r);
+
+                        log_slow("writer exec", start_time, &[], 0);
+                    }
+                    WriterMsg::RenameDb(old, new, extra, cb) => {
+                        let start_time = Instant::now();
+                        // 写线程的读写事务被其它事务持有，由调用者重试
+                        let r = if rw_txn.is_some() {
+                            Err(StoreError::Busy.to_string())
+                        } else {
+                            rename_db(env.as_ref().unwrap(), env_id, &old, &new, &extra)
+                        };
+                        if r.is_err() {
+                            outcome = "error";
                         }
+                        let t = Box::new(move |_: Option<isize>| {
+                            cb(r.clone());
+                        });
+                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer rename db"));
 
-                        match rw_txn.take().unwrap().commit() {
+                        log_slow_tab("writer rename db", start_time, &old);
+                    }
+                    WriterMsg::CopyDb(src, dst, extra, cb) => {
+                        let start_time = Instant::now();
+                        let r = if rw_txn.is_some() {
+                            Err(StoreError::Busy.to_string())
+                        } else {
+                            copy_db(env.as_ref().unwrap(), env_id, &src, &dst, &extra)
+                        };
+                        if r.is_err() {
+                            outcome = "error";
+                        }
+                        let t = Box::new(move |_: Option<isize>| {
+                            cb(r.clone());
+                        });
+                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer copy db"));
+
+                        log_slow_tab("writer copy db", start_time, &src);
+                    }
+                    // 合并直接写入读写事务，在事务提交时与缓存的修改一起提交，先于缓存的修改写入
+                    WriterMsg::Merge(operands, cb) => {
+                        let start_time = Instant::now();
+                        if rw_txn.is_none() {
+                            rw_txn = Some(begin_rw(env.as_ref().unwrap())
+                            .expect("Fatal error: failed to begin rw txn"));
+                        }
+
+                        let r = merge_in_txn(rw_txn.as_mut().unwrap(), env_id, &operands);
+                        if r.is_err() {
+                            outcome = "error";
+                        }
+                        staged.extend(operands.iter().cloned());
+                        let t = Box::new(move |_: Option<isize>| {
+                            cb(r.clone());
+                        });
+                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer merge"));
+
+                        log_slow("writer merge", start_time, &operands, operands.len());
+                    }
+                    WriterMsg::Prepare(txid, modifies, conditions, cb) => {
+                        let start_time = Instant::now();
+                        if rw_txn.is_none() {
+                            rw_txn = Some(begin_rw(env.as_ref().unwrap())
+                            .expect("Fatal error: failed to begin rw txn"));
+                        }
+
+                        let r = check_conditions(rw_txn.as_ref().unwrap(), env_id, &conditions)
+                            .map_err(|e| e.to_string())
+                            .and_then(|_| {
+                                bloom::insert(env_id, &modifies);
+                                for m in modifies.iter() {
+                                    write_kv(rw_txn.as_mut().unwrap(), env_id, m).map_err(|e| StoreError::from(e).to_string())?;
+                                }
+                                Ok(())
+                            });
+                        match r {
+                            Ok(_) => staged.extend(modifies.iter().cloned()),
+                            Err(_) => {
+                                outcome = "error";
+                                rw_txn.take().unwrap().abort();
+                                staged.clear();
+                                aborted(env_id);
+                                release_writer(env_id, txid);
+                            }
+                        }
+                        let t = Box::new(move |_: Option<isize>| {
+                            cb(r.clone());
+                        });
+                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer prepare"));
+
+                        log_slow("writer prepare", start_time, &modifies, modifies.len());
+                    }
+                    WriterMsg::CommitPrepared(txid, cb) => {
+                        let start_time = Instant::now();
+                        let owned = RW_TXN_HOLDERS.lock().unwrap().get(&env_id).map_or(false, |h| h.0 == txid)
+                            || writer_owner(env_id) == Some(txid);
+                        let r = match rw_txn.take() {
+                            // 超时的事务已被回滚，不能提交超时后的部分修改
+                            txn if clear_timed_out(txid) => {
+                                rw_txn = txn;
+                                Err(StoreError::TxnTimedOut.to_string())
+                            }
+                            Some(txn) if owned => {
+                                let r = commit_rw(env_id, txn).map_err(|e| StoreError::from(e).to_string());
+                                read_cache::invalidate(env_id, &staged);
+                                staged.clear();
+                                match r {
+                                    Ok(_) => committed(env_id),
+                                    Err(_) => aborted(env_id),
+                                }
+                                release_writer(env_id, txid);
+                                r
+                            }
+                            txn => {
+                                rw_txn = txn;
+                                Err(format!("no prepared txn for txid: {:?}", txid))
+                            }
+                        };
+                        if r.is_err() {
+                            outcome = "error";
+                        }
+                        let t = Box::new(move |_: Option<isize>| {
+                            cb(r.clone());
+                        });
+                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer commit prepared"));
+
+                        log_slow("writer commit prepared", start_time, &[], 0);
+                    }
+                    // 超时的事务已被回滚，不能提交超时后的部分修改
+                    WriterMsg::Commit(txid, _, _, cb) if clear_timed_out(txid) => {
+                        outcome = "error";
+                        release_writer(env_id, txid);
+                        let t = Box::new(move |_: Option<isize>| {
+                            cb(Err(StoreError::TxnTimedOut.to_string()));
+                        });
+                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer timed out commit"));
+                    }
+                    // 没有修改且没有打开的读写事务，不需要提交
+                    WriterMsg::Commit(txid, modifies, conditions, cb) if modifies.is_empty() && conditions.is_empty() && rw_txn.is_none() => {
+                        release_writer(env_id, txid);
+                        let t = Box::new(move |_: Option<isize>| {
+                            cb(Ok(CommitStats::default()));
+                        });
+                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer empty txn commit"));
+                    }
+                    WriterMsg::Commit(txid, modifies, conditions, cb) => {
+                        let start_time = Instant::now();
+                        if rw_txn.is_none() {
+                            rw_txn = Some(begin_rw(env.as_ref().unwrap())
+                            .expect("Fatal error: failed to begin rw txn"));
+                        }
+
+                        if let Err(e) = check_conditions(rw_txn.as_ref().unwrap(), env_id, &conditions) {
+                            outcome = "error";
+                            rw_txn.take().unwrap().abort();
+                            staged.clear();
+                            aborted(env_id);
+                            release_writer(env_id, txid);
+                            let t = Box::new(move |_: Option<isize>| {
+                                cb(Err(e.to_string()));
+                            });
+                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer precondition failed"));
+                            log_slow("writer commit", start_time, &modifies, modifies.len());
+                            continue;
+                        }
+
+                        // 同一环境中所有表的修改在一个读写事务中提交，任一修改失败则全部回滚
+                        bloom::insert(env_id, &modifies);
+                        let mut modify_error = None;
+                        for m in modifies.iter() {
+                            if let Err(e) = write_kv(rw_txn.as_mut().unwrap(), env_id, m) {
+                                warn!("lmdb modify tab: {:?} error: {:?}", m.tab.to_string(), e.to_string());
+                                modify_error = Some(StoreError::from(e).to_string());
+                                break;
+                            }
+                        }
+                        if let Some(e) = modify_error {
+                            outcome = "error";
+                            rw_txn.take().unwrap().abort();
+                            read_cache::invalidate(env_id, &staged);
+                            staged.clear();
+                            aborted(env_id);
+                            release_writer(env_id, txid);
+                            let t = Box::new(move |_: Option<isize>| {
+                                cb(Err(e.clone()));
+                            });
+                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer error"));
+                            log_slow("writer commit", start_time, &modifies, modifies.len());
+                            continue;
+                        }
+
+                        // 提交后读写事务失效，提交前统计
+                        let mut stats = CommitStats::of(&staged);
+                        stats.merge(&CommitStats::of(&modifies));
+                        stats.dirty_pages = dirty_pages(rw_txn.as_ref().unwrap(), env_id, &staged, &modifies);
+                        let cb1 = cb.clone();
+                        match commit_rw(env_id, rw_txn.take().unwrap()) {
                             Ok(_) => {
+                                committed(env_id);
                                 let t = Box::new(move |_: Option<isize>| {
-                                    cb1(Ok(()));
+                                    cb1(Ok(stats));
                                 });
                                 cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer normal txn commit"));
                             }
                             Err(e) => {
+                                outcome = "error";
+                                aborted(env_id);
                                 let t = Box::new(move |_: Option<isize>| {
-                                    cb1(Err(format!("commit failed with error: {:?}", e.to_string())));
+                                    cb1(Err(StoreError::from(e).to_string()));
                                 });
                                 cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer normal txn commit error"));
                             }
                         }
-                        IN_PROGRESS_TX.store(0, Ordering::SeqCst);
+                        read_cache::invalidate(env_id, &modifies);
+                        read_cache::invalidate(env_id, &staged);
+                        staged.clear();
+                        release_writer(env_id, txid);
 
-                        let elapsed = start_time.elapsed();
-                        if elapsed > Duration::from_millis(SLOW_TIME) {
-                            let tabs = modifies.iter().map(|m| m.tab.clone().to_string()).collect::<Vec<String>>();
-                            let msize: usize = modifies.iter().filter(|m|m.value.is_some()).map(|m| m.value.clone().unwrap().len()).sum();
-                            println!("===> Slow writer commit, time: {:?}, tab: {:?}, commit and modify size: {:?}", elapsed, tabs, msize);
-                        }
+                        log_slow("writer commit", start_time, &modifies, modifies.len());
                     }
-                    Ok(WriterMsg::Rollback(cb)) => {
+                    WriterMsg::Rollback(txid, cb) => {
+                        clear_timed_out(txid);
+                        // 只回滚该事务自己持有的读写事务
+                        let owned = RW_TXN_HOLDERS.lock().unwrap().get(&env_id).map_or(false, |h| h.0 == txid);
+                        if owned || writer_owner(env_id) == Some(txid) {
+                            if let Some(txn) = rw_txn.take() {
+                                txn.abort();
+                            }
+                            staged.clear();
+                            aborted(env_id);
+                            release_writer(env_id, txid);
+                        }
                         let t = Box::new(move |_: Option<isize>| {
                             cb(Ok(()));
                         });
                         cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer rollback txn commit"));
-                        IN_PROGRESS_TX.store(0, Ordering::SeqCst);
                     }
-                    Err(_) => (),
+                }
+                span.record("outcome", &outcome);
+
+                let mut holders = RW_TXN_HOLDERS.lock().unwrap();
+                if rw_txn.is_none() {
+                    holders.remove(&env_id);
+                } else {
+                    let txid = writer_owner(env_id).unwrap_or(0);
+                    match holders.get_mut(&env_id) {
+                        Some(h) if h.0 == txid => {
+                            h.1 = op_tab.or(h.1.take());
+                            h.2 = op;
+                        }
+                        _ => {
+                            holders.insert(env_id, (txid, op_tab, op, Instant::now()));
+                        }
+                    }
                 }
             }
+            // 所有发送端都已释放，写线程退出前写入等待中的合并写入
+            if rw_txn.is_none() && !coalescer.is_empty() {
+                flush_coalesced(env.as_ref().unwrap(), env_id, coalescer.take());
+            }
         });
         self.writer = Some(tx);
     }
 }
 
+// 合并写入默认的刷新阈值
+const DEFAULT_COALESCE_BYTES: usize = 1024 * 1024;
+const DEFAULT_COALESCE_RECORDS: usize = 1024;
+
+/**
+* 写线程中等待一起提交的合并写入
+*/
+struct Coalescer {
+    batches: Vec<(Arc<Vec<TabKV>>, WriteCallback)>,
+    bytes: usize,
+    records: usize,
+    since: Instant,
+}
+
+impl Coalescer {
+    fn new() -> Self {
+        Coalescer {
+            batches: Vec::new(),
+            bytes: 0,
+            records: 0,
+            since: Instant::now(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    fn push(&mut self, modifies: Arc<Vec<TabKV>>, cb: WriteCallback) {
+        if self.batches.is_empty() {
+            self.since = Instant::now();
+        }
+        self.records += modifies.len();
+        self.bytes += modifies.iter().map(|kv| kv.key.len() + kv.value.as_ref().map_or(0, |v| v.len())).sum::<usize>();
+        self.batches.push((modifies, cb));
+    }
+
+    // 是否达到字节数、记录数或时间阈值
+    fn due(&self) -> bool {
+        !self.batches.is_empty()
+            && (self.bytes >= COALESCE_BYTES.load(Ordering::Relaxed)
+                || self.records >= COALESCE_RECORDS.load(Ordering::Relaxed)
+                || self.remaining() == Duration::from_millis(0))
+    }
+
+    // 距离刷新时间的剩余时间
+    fn remaining(&self) -> Duration {
+        let interval = Duration::from_millis(COALESCE_INTERVAL.load(Ordering::Relaxed));
+        interval.checked_sub(self.since.elapsed()).unwrap_or(Duration::from_millis(0))
+    }
+
+    fn take(&mut self) -> Vec<(Arc<Vec<TabKV>>, WriteCallback)> {
+        self.bytes = 0;
+        self.records = 0;
+        std::mem::replace(&mut self.batches, Vec::new())
+    }
+}
+
+// 在一个读写事务中提交所有合并写入，提交后调用各批的回调
+fn flush_coalesced(env: &Environment, env_id: u64, batches: Vec<(Arc<Vec<TabKV>>, WriteCallback)>) {
+    let start_time = Instant::now();
+    let modifies = batches.iter().flat_map(|(m, _)| m.iter().cloned()).collect::<Vec<TabKV>>();
+    bloom::insert(env_id, &modifies);
+    let r = write_txn(env, env_id, "coalesced write", |txn| {
+        for m in modifies.iter() {
+            if let Err(e) = write_kv(txn, env_id, m) {
+                warn!("modify tab: {:?} error: {:?}", m.tab.to_string(), e.to_string());
+                return Err(e);
+            }
+        }
+        Ok(())
+    }).map_err(|e| StoreError::from(e).to_string());
+    read_cache::invalidate(env_id, &modifies);
+    match r {
+        Ok(_) => committed(env_id),
+        Err(ref e) => warn!("lmdb coalesced write failed: {:?}", e),
+    }
+    for (_, cb) in batches {
+        let r = r.clone();
+        let t = Box::new(move |_: Option<isize>| {
+            cb(r.clone());
+        });
+        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer coalesced commit"));
+    }
+
+    log_slow("writer coalesced commit", start_time, &modifies, modifies.len());
+}
+
+// 在独立的读写事务中按写入标志写入一批键值，任一写入失败则整批回滚
+fn put_with_flags(env: &Environment, env_id: u64, modifies: Arc<Vec<TabKV>>, flags: WriteFlags, cb: WriteCallback) {
+    let start_time = Instant::now();
+    bloom::insert(env_id, &modifies);
+    let r = write_txn(env, env_id, "put", |txn| {
+        for m in modifies.iter() {
+            if let Err(e) = write_kv_flags(txn, env_id, m, flags) {
+                debug!("modify tab: {:?} error: {:?}", m.tab.to_string(), e.to_string());
+                return Err(e);
+            }
+        }
+        Ok(())
+    }).map_err(|e| StoreError::from(e).to_string());
+    read_cache::invalidate(env_id, &modifies);
+    if r.is_ok() {
+        committed(env_id);
+    }
+    let t = Box::new(move |_: Option<isize>| {
+        cb(r.clone());
+    });
+    cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb writer put with flags"));
+
+    log_slow("writer put", start_time, &modifies, modifies.len());
+}
+
+/**
+* 设置合并写入的刷新策略，达到任一阈值时刷新
+* @param max_bytes 累计的字节数
+* @param max_records 累计的记录数
+* @param interval_millis 第一批写入后等待的最长时间，为0则每批立即提交
+*/
+pub fn set_write_coalescing(max_bytes: usize, max_records: usize, interval_millis: u64) {
+    COALESCE_BYTES.store(max_bytes, Ordering::Relaxed);
+    COALESCE_RECORDS.store(max_records, Ordering::Relaxed);
+    COALESCE_INTERVAL.store(interval_millis, Ordering::Relaxed);
+}
+
 lazy_static! {
-    // all opened dbs in this env
-    pub static ref OPENED_TABLES: Arc<RwLock<HashMap<u64, Database>>> = Arc::new(RwLock::new(HashMap::new()));
-    pub static ref IN_PROGRESS_TX: AtomicU64 = AtomicU64::new(0);
-}
-
-fn get_db(tab: u64) -> Database {
-    OPENED_TABLES
-        .read()
-        .unwrap()
-        .get(&tab)
-        .unwrap()
-        .clone()
+    // all opened dbs, keyed by (env id, tab hash)
+    static ref OPENED_TABLES: Arc<RwLock<HashMap<(u64, u64), Database>>> = Arc::new(RwLock::new(HashMap::new()));
+    // 已登记的表的表名，按表名哈希查找时确认表名，避免哈希冲突时使用其它表的句柄
+    static ref OPENED_NAMES: RwLock<HashMap<(u64, u64), Atom>> = RwLock::new(HashMap::new());
+    // 各环境写线程的使用权，值为持有的事务id和该事务持有的守卫数
+    static ref WRITER_OWNERS: Mutex<HashMap<u64, (u64, usize)>> = Mutex::new(HashMap::new());
+    // 写线程的使用权归还时唤醒等待的事务
+    static ref WRITER_RELEASED: Condvar = Condvar::new();
+    // 读线程中打开的独立读事务的id
+    static ref RO_TXN_ID: AtomicU64 = AtomicU64::new(1);
+    // 慢操作阈值(毫秒)，超过该时间的操作会输出警告日志
+    static ref SLOW_TIME: AtomicU64 = AtomicU64::new(DEFAULT_SLOW_TIME);
+    // 读写事务空闲超时(毫秒)，0表示不超时
+    static ref TXN_IDLE_TIMEOUT: AtomicU64 = AtomicU64::new(0);
+    // 因空闲超时被回滚的事务
+    static ref TIMED_OUT_TXS: Mutex<HashSet<u64>> = Mutex::new(HashSet::new());
+    // 各环境写线程中持有读写事务的事务id、最近操作的表、最近的操作和事务打开时间
+    static ref RW_TXN_HOLDERS: Mutex<HashMap<u64, (u64, Option<Atom>, &'static str, Instant)>> = Mutex::new(HashMap::new());
+    // 等待写线程的事务数量
+    static ref RW_WAITERS: AtomicUsize = AtomicUsize::new(0);
+    // 合并写入的刷新阈值
+    static ref COALESCE_BYTES: AtomicUsize = AtomicUsize::new(DEFAULT_COALESCE_BYTES);
+    static ref COALESCE_RECORDS: AtomicUsize = AtomicUsize::new(DEFAULT_COALESCE_RECORDS);
+    static ref COALESCE_INTERVAL: AtomicU64 = AtomicU64::new(0);
+}
+
+// 读写事务打开超过该时间且有事务在等待，则认为写线程停滞
+const STALL_TIME: Duration = Duration::from_secs(1);
+// 等待写线程使用权的超时时间
+const ACQUIRE_WRITER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/**
+* 写线程使用权的守卫，同一环境同一时间只有一个事务可以使用写线程，守卫全部释放时归还使用权
+* 同一事务可以多次取得，写线程在事务提交、回滚或超时后直接归还，之后剩余的守卫释放时不影响其它事务
+* 守卫全部释放而事务没有提交或回滚时，写线程在处理下一条消息前回滚遗留的读写事务
+*/
+pub struct WriterGuard {
+    env_id: u64,
+    txid: u64,
+}
+
+impl WriterGuard {
+    /**
+    * 尝试取得环境的写线程的使用权，不等待
+    * @param env_id 环境id
+    * @param txid 事务id，不能为0
+    * @returns 事务已持有或取得成功返回守卫
+    */
+    pub fn try_checkout(env_id: u64, txid: u64) -> Option<WriterGuard> {
+        if txid == 0 {
+            return None;
+        }
+        checkout_locked(&mut WRITER_OWNERS.lock().unwrap(), env_id, txid)
+    }
+
+    /**
+    * 等待取得环境的写线程的使用权，使用权归还时被唤醒，不轮询
+    * @param env_id 环境id
+    * @param txid 事务id，不能为0
+    * @returns 取得成功返回守卫，超时返回None
+    */
+    pub fn checkout(env_id: u64, txid: u64) -> Option<WriterGuard> {
+        if txid == 0 {
+            return None;
+        }
+        RW_WAITERS.fetch_add(1, Ordering::SeqCst);
+        let deadline = Instant::now() + ACQUIRE_WRITER_TIMEOUT;
+        let mut owners = WRITER_OWNERS.lock().unwrap();
+        let guard = loop {
+            if let Some(guard) = checkout_locked(&mut owners, env_id, txid) {
+                break Some(guard);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                break None;
+            }
+            owners = WRITER_RELEASED.wait_timeout(owners, deadline - now).unwrap().0;
+        };
+        drop(owners);
+        RW_WAITERS.fetch_sub(1, Ordering::SeqCst);
+
+        if guard.is_none() {
+            warn!("acquire lmdb writer timeout, env: {:?}, txid: {:?}, writer status: {:?}", env_id, txid, writer_status());
+        }
+        guard
+    }
+
+    pub fn env_id(&self) -> u64 {
+        self.env_id
+    }
+
+    pub fn txid(&self) -> u64 {
+        self.txid
+    }
+}
+
+// 在已加锁的使用权表中取得写线程的使用权
+fn checkout_locked(owners: &mut HashMap<u64, (u64, usize)>, env_id: u64, txid: u64) -> Option<WriterGuard> {
+    let owner = owners.entry(env_id).or_insert((txid, 0));
+    if owner.0 != txid {
+        return None;
+    }
+    owner.1 += 1;
+    Some(WriterGuard { env_id, txid })
+}
+
+impl Drop for WriterGuard {
+    fn drop(&mut self) {
+        let mut owners = WRITER_OWNERS.lock().unwrap();
+        let last = match owners.get_mut(&self.env_id) {
+            Some(owner) if owner.0 == self.txid => {
+                owner.1 = owner.1.saturating_sub(1);
+                owner.1 == 0
+            }
+            _ => false,
+        };
+        if last {
+            owners.remove(&self.env_id);
+            WRITER_RELEASED.notify_all();
+        }
+    }
+}
+
+/**
+* 事务结束后由写线程归还写线程的使用权，只有持有者才能归还，避免其它事务的提交或回滚误释放
+* @param env_id 环境id
+* @param txid 事务id
+* @returns 归还成功返回true
+*/
+pub(crate) fn release_writer(env_id: u64, txid: u64) -> bool {
+    let mut owners = WRITER_OWNERS.lock().unwrap();
+    match owners.get(&env_id) {
+        Some(owner) if owner.0 == txid => {
+            owners.remove(&env_id);
+            WRITER_RELEASED.notify_all();
+            true
+        }
+        _ => false,
+    }
+}
+
+// 环境的写线程当前的持有者
+pub(crate) fn writer_owner(env_id: u64) -> Option<u64> {
+    WRITER_OWNERS.lock().unwrap().get(&env_id).map(|owner| owner.0)
+}
+
+// 读写事务超时时收回写线程的使用权，返回原持有者
+fn take_writer(env_id: u64) -> Option<u64> {
+    let owner = WRITER_OWNERS.lock().unwrap().remove(&env_id).map(|owner| owner.0);
+    WRITER_RELEASED.notify_all();
+    owner
+}
+
+/**
+* 获取写线程状态，用于诊断写操作停滞
+* @returns 返回持有读写事务的事务信息、等待数量和是否停滞
+*/
+pub fn writer_status() -> WriterStatus {
+    let holders = RW_TXN_HOLDERS.lock().unwrap().iter().map(|(env_id, (txid, tab, last_op, start))| RwTxnInfo {
+        env_id: *env_id,
+        txid: *txid,
+        tab: tab.clone(),
+        last_op: *last_op,
+        age: start.elapsed(),
+    }).collect::<Vec<RwTxnInfo>>();
+    let waiting = RW_WAITERS.load(Ordering::SeqCst);
+    let stalled = waiting > 0 && holders.iter().any(|h| h.age > STALL_TIME);
+
+    WriterStatus {
+        holders,
+        waiting,
+        stalled,
+    }
+}
+
+/**
+* 设置读写事务空闲超时
+* @param millis 超时毫秒数，为0则不超时
+*/
+pub fn set_txn_idle_timeout(millis: u64) {
+    TXN_IDLE_TIMEOUT.store(millis, Ordering::Relaxed);
+}
+
+fn txn_idle_timeout() -> Option<Duration> {
+    match TXN_IDLE_TIMEOUT.load(Ordering::Relaxed) {
+        0 => None,
+        millis => Some(Duration::from_millis(millis)),
+    }
+}
+
+// 检查事务是否因空闲超时被回滚，记录保留到事务提交或回滚，之前的所有操作都失败
+pub fn is_timed_out(txid: u64) -> bool {
+    TIMED_OUT_TXS.lock().unwrap().contains(&txid)
+}
+
+// 事务提交或回滚时清除超时记录，返回事务是否曾超时
+pub(crate) fn clear_timed_out(txid: u64) -> bool {
+    TIMED_OUT_TXS.lock().unwrap().remove(&txid)
+}
+
+/**
+* 设置慢操作阈值
+* @param millis 阈值毫秒数，为0则关闭慢操作日志
+*/
+pub fn set_slow_time(millis: u64) {
+    SLOW_TIME.store(millis, Ordering::Relaxed);
+}
+
+// 获取慢操作阈值，关闭时返回None
+fn slow_time() -> Option<Duration> {
+    match SLOW_TIME.load(Ordering::Relaxed) {
+        0 => None,
+        millis => Some(Duration::from_millis(millis)),
+    }
+}
+
+// 记录涉及多个表的慢操作，包括消息类型、键数量、涉及的表和耗时
+fn log_slow(op: &str, start_time: Instant, items: &[TabKV], key_count: usize) {
+    let elapsed = start_time.elapsed();
+    match slow_time() {
+        Some(limit) if elapsed > limit => {
+            let mut tabs = items.iter().map(|kv| kv.tab.to_string()).collect::<Vec<String>>();
+            tabs.dedup();
+            let bytes: usize = items.iter().map(|kv| kv.key.len() + kv.value.as_ref().map_or(0, |v| v.len())).sum();
+            warn!("===> Slow lmdb {}, time: {:?}, keys: {:?}, bytes: {:?}, tabs: {:?}", op, elapsed, key_count, bytes, tabs);
+        }
+        _ => {}
+    }
+}
+
+// 记录单表的慢操作
+fn log_slow_tab(op: &str, start_time: Instant, tab: &Atom) {
+    let elapsed = start_time.elapsed();
+    match slow_time() {
+        Some(limit) if elapsed > limit => {
+            warn!("===> Slow lmdb {}, time: {:?}, keys: 1, tab: {:?}", op, elapsed, tab);
+        }
+        _ => {}
+    }
+}
+
+/**
+* Lmdb服务池，一个进程可以同时服务多个环境(例如每个库或每块磁盘一个环境)
+* 每个环境有独立的读写线程，表通过绑定找到所属的环境
+* 表名在整个池中必须唯一，元信息表除外，它按库名路由
+*/
+pub struct LmdbPool {
+    services: HashMap<u64, LmdbService>,
+    tab_envs: HashMap<u64, u64>,
+    default_env: Option<u64>,
+}
+
+impl LmdbPool {
+    pub fn new() -> Self {
+        LmdbPool {
+            services: HashMap::new(),
+            tab_envs: HashMap::new(),
+            default_env: None,
+        }
+    }
+
+    /**
+    * 注册环境的服务并启动读写线程，第一个注册的环境为默认环境
+    * @param env_id 环境id
+    * @param service 已设置好环境和配置的服务
+    */
+    pub fn add_service(&mut self, env_id: u64, mut service: LmdbService) {
+        service.env_id = env_id;
+        service.start();
+        self.services.insert(env_id, service);
+        if self.default_env.is_none() {
+            self.default_env = Some(env_id);
+        }
+    }
+
+    // 将表绑定到环境
+    pub fn bind_tab(&mut self, tab: &Atom, env_id: u64) {
+        self.tab_envs.insert(tab.get_hash() as u64, env_id);
+    }
+
+    // 获取表所属的环境，未绑定的表属于默认环境
+    pub fn env_of(&self, tab: &Atom) -> Option<u64> {
+        self.tab_envs.get(&(tab.get_hash() as u64)).cloned().or(self.default_env)
+    }
+
+    // 获取表所属环境的服务
+    pub fn service(&self, tab: &Atom) -> Option<&LmdbService> {
+        self.env_of(tab).and_then(|env_id| self.services.get(&env_id))
+    }
+
+    pub fn service_by_env(&self, env_id: u64) -> Option<&LmdbService> {
+        self.services.get(&env_id)
+    }
+
+    pub fn services(&self) -> impl Iterator<Item = (&u64, &LmdbService)> {
+        self.services.iter()
+    }
+}
+
+/**
+* 打开读事务，临时性错误按重试策略重试，读槽一直用完时返回ReadersFull，附带读槽列表用于诊断长时间未结束的读事务
+*/
+fn begin_ro(env_id: u64, env: &Environment) -> Result<RoTransaction, StoreError> {
+    match retry::retry(env, "begin ro txn", || env.begin_ro_txn()) {
+        Ok(txn) => Ok(txn),
+        Err(Error::ReadersFull) => {
+            let slots = readers::reader_list(env_id, env).unwrap_or_default();
+            let e = StoreError::ReadersFull(slots);
+            error!("lmdb begin ro txn failed, env: {:?}, {}", env_id, e);
+            Err(e)
+        }
+        Err(e) => Err(StoreError::from(e)),
+    }
+}
+
+// 打开读写事务，临时性错误按重试策略重试
+fn begin_rw(env: &Environment) -> Result<RwTransaction, Error> {
+    retry::retry(env, "begin rw txn", || env.begin_rw_txn())
+}
+
+/**
+* 在独立的读写事务中执行写入并提交，临时性错误按重试策略重新执行整个事务，每次失败都回滚事务中的表创建和删除
+* @param env LMDB环境
+* @param env_id 环境id
+* @param op 操作名，用于日志
+* @param f 在读写事务中执行的写入
+* @returns 提交成功返回Ok，重试次数用完时返回最后一次的错误
+*/
+fn write_txn<F>(env: &Environment, env_id: u64, op: &str, mut f: F) -> Result<(), Error>
+    where F: FnMut(&mut RwTransaction) -> Result<(), Error> {
+    let max_retries = retry::retry_policy().max_retries;
+    let mut attempts = 0;
+    retry::retry(env, op, || {
+        let mut txn = env.begin_rw_txn()?;
+        let r = match f(&mut txn) {
+            Ok(_) => commit_rw(env_id, txn),
+            Err(e) => {
+                txn.abort();
+                Err(e)
+            }
+        };
+        if let Err(ref e) = r {
+            if attempts < max_retries && retry::is_transient(e) {
+                txn_stats::retried(env_id);
+            }
+            aborted(env_id);
+        }
+        attempts += 1;
+        r
+    })
+}
+
+/**
+* 启动读线程，设置了空闲超时的读线程空闲超时后退出，下次发送消息时由send_to_reader重新启动
+* 读线程退出时通道保留在读线程的槽中，读线程只在锁中确认通道为空后退出，之后发送的消息总会重新启动读线程
+*/
+fn spawn_reader(
+    env: Option<Arc<Environment>>,
+    env_id: u64,
+    i: usize,
+    rx: Receiver<ReaderMsg>,
+    low_rx: Receiver<ReaderMsg>,
+    pin: bool,
+    idle: Option<Duration>,
+    slot: Arc<IdleReader>,
+    activity: Arc<WorkerActivity>,
+) {
+    let _ = thread::Builder::new().name(reader_name(i)).spawn(move || {
+        if pin {
+            pin_to_core(i);
+        }
+        // 本读线程持有的独立读事务，键为读事务id
+        let mut ro_txns: HashMap<u64, RoTransaction> = HashMap::new();
+        loop {
+            let msg = match recv_prioritized_timeout(&rx, &low_rx, idle) {
+                Ok(Some(msg)) => msg,
+                // 持有读事务时不能退出
+                Ok(None) if !ro_txns.is_empty() => continue,
+                Ok(None) => {
+                    // 空闲超时后退出，通道不为空时继续处理
+                    if slot.try_exit(&rx, &low_rx) {
+                        debug!("lmdb reader {} idle, stopped", i);
+                        break;
+                    }
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            let span = debug_span!("lmdb_reader", worker = i, op = msg.op_name(), tab = ?msg.tab(), keys = msg.key_count(), outcome = field::Empty);
+            let _enter = span.enter();
+            let _busy = activity.begin();
+            fault::on_message("reader");
+            let mut outcome = "ok";
+
+            match msg {
+                ReaderMsg::Commit(cb) => {
+                    let start_time = Instant::now();
+                    let t = Box::new(move |_| {
+                        cb(Ok(()));
+                    });
+                    cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader commit"));
+
+                    log_slow("reader commit", start_time, &[], 0);
+                }
+                ReaderMsg::OpenRo(sndr) => {
+                    let r = begin_ro(env_id, env.as_ref().unwrap()).map(|txn| {
+                        let id = RO_TXN_ID.fetch_add(1, Ordering::SeqCst);
+                        ro_txns.insert(id, txn);
+                        id
+                    });
+                    if r.is_err() {
+                        outcome = "error";
+                    }
+                    let _ = sndr.send(r.map_err(|e| e.to_string()));
+                }
+                ReaderMsg::RoQuery(id, queries, cb) => {
+                    let start_time = Instant::now();
+                    let r = match ro_txns.get(&id) {
+                        Some(txn) => query_in_txn(env_id, txn, &queries, None, None).map_err(|e| e.to_string()),
+                        None => Err(format!("ro txn: {:?} not opened in reader: {}", id, i)),
+                    };
+                    if r.is_err() {
+                        outcome = "error";
+                    }
+                    let t = Box::new(move |_| {
+                        cb(r.clone());
+                    });
+                    cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader ro txn query"));
+
+                    log_slow("reader ro txn query", start_time, &queries, queries.len());
+                }
+                ReaderMsg::RoNext(id, tab, cur_key, descending, cb) => {
+                    let start_time = Instant::now();
+                    let r = match ro_txns.get(&id) {
+                        Some(txn) => next_match(txn, env_id, &tab, &cur_key, descending, &ScanFilter::default()).map_err(|e| format!("lmdb iter internal error: {:?}", e)),
+                        None => Err(format!("ro txn: {:?} not opened in reader: {}", id, i)),
+                    };
+                    if r.is_err() {
+                        outcome = "error";
+                    }
+                    let t = Box::new(move |_: Option<isize>| {
+                        cb(r.clone());
+                    });
+                    cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader ro txn next"));
+
+                    log_slow_tab("reader ro txn next", start_time, &tab);
+                }
+                ReaderMsg::CloseRo(id) => {
+                    if let Some(txn) = ro_txns.remove(&id) {
+                        let _ = txn.commit();
+                    }
+                }
+                ReaderMsg::Query(queries, cb, token) => {
+                    let start_time = Instant::now();
+                    let epoch = read_cache::epoch();
+                    let txn = match begin_ro(env_id, env.as_ref().unwrap()) {
+                        Ok(txn) => txn,
+                        Err(e) => {
+                            let t = Box::new(move |_| {
+                                cb(Err(e.to_string()));
+                            });
+                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader query error"));
+                            span.record("outcome", &"error");
+                            continue;
+                        }
+                    };
+
+                    match query_in_txn(env_id, &txn, &queries, token.as_ref(), Some(epoch)) {
+                        Ok(qr) => {
+                            debug!("lmdb query success: {:?}", qr);
+                            let t = Box::new(move |_| {
+                                cb(Ok(qr));
+                            });
+                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader query ok"));
+                        }
+                        Err(e) => {
+                            outcome = "error";
+                            warn!("queries error: {:?}, {:?}", e, queries);
+                            let t = Box::new(move |_| {
+                                cb(Err(e.to_string()));
+                            });
+                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader query error"));
+                        }
+                    }
+
+                    match txn.commit() {
+                        Ok(_) => {}
+                        Err(e) => panic!("query txn commit error: {:?}", e.to_string()),
+                    }
+
+                    log_slow("reader query", start_time, &queries, queries.len());
+                }
+                ReaderMsg::NextMatch(descending, tab, cur_key, filter, cb, sndr) => {
+                    let start_time = Instant::now();
+                    let txn = match begin_ro(env_id, env.as_ref().unwrap()) {
+                        Ok(txn) => txn,
+                        Err(e) => {
+                            let t = Box::new(move |_: Option<isize>| {
+                                cb(Err(e.to_string()));
+                            });
+                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader get next match"));
+                            let _ = sndr.send(None);
+                            span.record("outcome", &"error");
+                            continue;
+                        }
+                    };
+
+                    let r = next_match(&txn, env_id, &tab, &cur_key, descending, &filter);
+                    let next = match &r {
+                        Ok((_, next)) => next.clone(),
+                        Err(_) => None,
+                    };
+                    let item = r.map(|(item, _)| item).map_err(|e| {
+                        outcome = "error";
+                        format!("lmdb iter internal error: {:?}", e)
+                    });
+                    let t = Box::new(move |_: Option<isize>| {
+                        cb(item.clone());
+                    });
+                    cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader get next match"));
+                    let _ = sndr.send(next);
+
+                    let _ = txn.commit();
+
+                    log_slow_tab("reader nextMatch", start_time, &tab);
+                }
+                ReaderMsg::NextKey(descending, tab, cur_key, sndr) => {
+                    let start_time = Instant::now();
+                    let txn = match begin_ro(env_id, env.as_ref().unwrap()) {
+                        Ok(txn) => txn,
+                        Err(e) => {
+                            let _ = sndr.send(None);
+                            span.record("outcome", &"error");
+                            continue;
+                        }
+                    };
+
+                    match get_db(env_id, &tab).and_then(|db| next_key(&txn, db, &cur_key, descending, policy::soft_delete(&tab))) {
+                        Ok(k) => {
+                            let _ = sndr.send(k);
+                        }
+                        Err(e) => {
+                            outcome = "error";
+                            warn!("next key of tab: {:?} error: {:?}", tab, e);
+                            let _ = sndr.send(None);
+                        }
+                    }
+
+                    let _ = txn.commit();
+
+                    log_slow_tab("reader nextKey", start_time, &tab);
+                }
+                ReaderMsg::Seek(tab, key, for_prev, sndr) => {
+                    let start_time = Instant::now();
+                    let txn = match begin_ro(env_id, env.as_ref().unwrap()) {
+                        Ok(txn) => txn,
+                        Err(e) => {
+                            let _ = sndr.send(None);
+                            span.record("outcome", &"error");
+                            continue;
+                        }
+                    };
+
+                    match get_db(env_id, &tab).and_then(|db| seek(&txn, db, &key, for_prev)) {
+                        Ok(k) => {
+                            let _ = sndr.send(k);
+                        }
+                        Err(e) => {
+                            outcome = "error";
+                            warn!("seek tab: {:?} error: {:?}", tab, e);
+                            let _ = sndr.send(None);
+                        }
+                    }
+
+                    let _ = txn.commit();
+
+                    log_slow_tab("reader seek", start_time, &tab);
+                }
+                ReaderMsg::ScanPage(tab, after, descending, limit, cb) => {
+                    let start_time = Instant::now();
+                    let txn = match begin_ro(env_id, env.as_ref().unwrap()) {
+                        Ok(txn) => txn,
+                        Err(e) => {
+                            let t = Box::new(move |_| {
+                                cb(Err(e.to_string()));
+                            });
+                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader scan page"));
+                            span.record("outcome", &"error");
+                            continue;
+                        }
+                    };
+
+                    let r = scan_page(&txn, env_id, &tab, after.as_ref(), descending, limit).map(|items| Page {
+                        next: page::next_token(&items, descending, limit),
+                        items,
+                    });
+                    if r.is_err() {
+                        outcome = "error";
+                    }
+                    let t = Box::new(move |_| {
+                        cb(r.clone());
+                    });
+                    cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader scan page"));
+
+                    let _ = txn.commit();
+
+                    log_slow_tab("reader scan page", start_time, &tab);
+                }
+                ReaderMsg::TableSize(tab, cb) => {
+                    let start_time = Instant::now();
+                    let txn = match begin_ro(env_id, env.as_ref().unwrap()) {
+                        Ok(txn) => txn,
+                        Err(e) => {
+                            let t = Box::new(move |_| {
+                                cb(Err(e.to_string()));
+                            });
+                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader table size"));
+                            span.record("outcome", &"error");
+                            continue;
+                        }
+                    };
+
+                    let r = get_db(env_id, &tab).map_err(|e| e.to_string()).and_then(|db| table_size(&txn, &tab, db));
+                    if r.is_err() {
+                        outcome = "error";
+                    }
+                    let t = Box::new(move |_| {
+                        cb(r.clone());
+                    });
+                    cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader table size"));
+
+                    let _ = txn.commit();
+
+                    log_slow_tab("reader table size", start_time, &tab);
+                }
+                ReaderMsg::CountRange(tab, start, end, cb) => {
+                    let start_time = Instant::now();
+                    let txn = match begin_ro(env_id, env.as_ref().unwrap()) {
+                        Ok(txn) => txn,
+                        Err(e) => {
+                            let t = Box::new(move |_| {
+                                cb(Err(e.to_string()));
+                            });
+                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader count range"));
+                            span.record("outcome", &"error");
+                            continue;
+                        }
+                    };
+
+                    let r = get_db(env_id, &tab).map_err(|e| e.to_string()).and_then(|db| count_range(&txn, &tab, db, start.as_ref(), end.as_ref()));
+                    if r.is_err() {
+                        outcome = "error";
+                    }
+                    let t = Box::new(move |_| {
+                        cb(r.clone());
+                    });
+                    cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader count range"));
+
+                    let _ = txn.commit();
+
+                    log_slow_tab("reader count range", start_time, &tab);
+                }
+                ReaderMsg::QueryView(queries, mut cb) => {
+                    let start_time = Instant::now();
+                    let txn = match begin_ro(env_id, env.as_ref().unwrap()) {
+                        Ok(txn) => txn,
+                        Err(e) => {
+                            cb(Err(e.to_string()));
+                            span.record("outcome", &"error");
+                            continue;
+                        }
+                    };
+
+                    let r = query_view(env_id, &txn, &queries);
+                    if r.is_err() {
+                        outcome = "error";
+                    }
+                    // 视图只在读事务中有效，必须在提交读事务之前调用回调
+                    cb(r.map_err(|e| e.to_string()));
+
+                    let _ = txn.commit();
+
+                    log_slow("reader query view", start_time, &queries, queries.len());
+                }
+                ReaderMsg::QueryVersioned(queries, cb) => {
+                    let start_time = Instant::now();
+                    let epoch = read_cache::epoch();
+                    let txn = match begin_ro(env_id, env.as_ref().unwrap()) {
+                        Ok(txn) => txn,
+                        Err(e) => {
+                            let t = Box::new(move |_| {
+                                cb(Err(e.to_string()));
+                            });
+                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader query versioned"));
+                            span.record("outcome", &"error");
+                            continue;
+                        }
+                    };
+
+                    let r = query_in_txn(env_id, &txn, &queries, None, Some(epoch)).and_then(|qr| {
+                        let mut vr = Vec::with_capacity(qr.len());
+                        for kv in qr.into_iter() {
+                            let version = versions::get(&txn, env_id, &kv.tab, &kv.key).map_err(StoreError::from)?;
+                            vr.push((kv, version));
+                        }
+                        Ok(vr)
+                    });
+                    if r.is_err() {
+                        outcome = "error";
+                    }
+                    let t = Box::new(move |_| {
+                        cb(r.clone().map_err(|e| e.to_string()));
+                    });
+                    cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader query versioned"));
+
+                    let _ = txn.commit();
+
+                    log_slow("reader query versioned", start_time, &queries, queries.len());
+                }
+                ReaderMsg::CreateItemIter(descending, tab, start_key, sndr) => {
+                    let start_time = Instant::now();
+                    let txn = match begin_ro(env_id, env.as_ref().unwrap()) {
+                        Ok(txn) => txn,
+                        Err(e) => {
+                            let _ = sndr.send(None);
+                            span.record("outcome", &"error");
+                            continue;
+                        }
+                    };
+                    let cursor = get_db(env_id, &tab)
+                        .and_then(|db| txn.open_ro_cursor(db))
+                        .expect(&format!("Fatal error: open cursor for tab: {:?} failed", tab));
+
+                    match (descending, start_key) {
+                        (true, None) => match skip_tombstones(&cursor, &tab, cursor.get(None, None, MDB_FIRST), MDB_NEXT) {
+                            Ok(val) => {
+                                let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
+                            }
+                            Err(Error::NotFound) => {
+                                let _ = sndr.send(None);
+                            }
+
+                            Err(_) => {}
+                        },
+                        // MDB_SET_RANGE 会找到第一个大于或者等于 sk 的 key
+                        (true, Some(sk)) => {
+                            match skip_tombstones(&cursor, &tab, cursor.get(Some(sk.as_ref()), None, MDB_SET_RANGE), MDB_NEXT) {
+                                Ok(val) => {
+                                    let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
+                                }
+                                Err(Error::NotFound) => {
+                                    let _ = sndr.send(None);
+                                }
+                                Err(_) => {}
+                            }
+                        }
+                        (false, Some(sk)) => {
+                            match skip_tombstones(&cursor, &tab, cursor.get(Some(sk.as_ref()), None, MDB_SET_RANGE), MDB_PREV) {
+                                Ok(val) => {
+                                    let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
+                                }
+                                Err(Error::NotFound) => {
+                                    // 降序迭代起始 key 超过最大 key 则定位到表中最后一个元素
+                                    match skip_tombstones(&cursor, &tab, cursor.get(None, None, MDB_LAST), MDB_PREV) {
+                                        Ok(val) => {
+                                            let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
+                                        }
+                                        Err(Error::NotFound) => {
+                                            let _ = sndr.send(None);
+                                        }
+                                        Err(_) => {}
+                                    }
+                                }
+                                Err(_) => {}
+                            }
+                        }
+                        (false, None) => match skip_tombstones(&cursor, &tab, cursor.get(None, None, MDB_LAST), MDB_PREV) {
+                            Ok(val) => {
+                                let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
+                            }
+                            Err(Error::NotFound) => {
+                                let _ = sndr.send(None);
+                            }
+                            Err(_) => {}
+                        },
+                    }
+
+                    drop(cursor);
+                    match txn.commit() {
+                        Ok(_) => {}
+                        Err(e) => panic!("create iter txn commit error: {:?}", e.to_string()),
+                    }
+
+                    log_slow_tab("reader createItemIter", start_time, &tab);
+                }
+                ReaderMsg::NextItem(descending, tab, cur_key, cb, sndr) => {
+                    let start_time = Instant::now();
+                    let txn = match begin_ro(env_id, env.as_ref().unwrap()) {
+                        Ok(txn) => txn,
+                        Err(e) => {
+                            let t = Box::new(move |_: Option<isize>| {
+                                cb(Err(e.to_string()));
+                            });
+                            cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader get next item error"));
+                            let _ = sndr.send(None);
+                            span.record("outcome", &"error");
+                            continue;
+                        }
+                    };
+                    let cursor = get_db(env_id, &tab)
+                        .and_then(|db| txn.open_ro_cursor(db))
+                        .expect(&format!("Fatal error: open cursor for tab: {:?} failed", tab));
+
+                    match (descending, cur_key.clone()) {
+                        (true, Some(ck)) => {
+                            let cb1 = cb.clone();
+                            let ck1 = ck.clone();
+                            match cursor.get(Some(ck.as_ref()), None, MDB_SET_KEY) {
+                                // 软删除的键不返回
+                                Ok((_, v)) if tombstone::is_deleted(&tab, v) => {}
+                                Ok(val) => match chunk::read(&txn, env_id, &tab, &ck, val.1) {
+                                    Ok(v) => {
+                                        debug!("iter next item descendin key: {:?}, value: {:?}", ck.clone(), v.clone());
+                                        let t = Box::new(move |_: Option<isize>| {
+                                            cb1(Ok(Some((ck1, Arc::new(v)))));
+                                        });
+                                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader get next item"));
+                                    }
+                                    Err(e) => {
+                                        outcome = "error";
+                                        warn!("read chunked value failed: {:?}", e.to_string());
+                                    }
+                                },
+                                Err(Error::NotFound) => {}
+                                Err(_) => {}
+                            }
+
+                            // get next key
+                            match skip_tombstones(&cursor, &tab, cursor.get(Some(ck.as_ref()), None, MDB_NEXT), MDB_NEXT) {
+                                Ok(val) => {
+                                    debug!("iter next key descending: item: {:?}", val.clone());
+                                    let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
+                                }
+
+                                Err(Error::NotFound) => {
+                                    debug!("iter next key descending: NotFound");
+                                    let _ = sndr.send(None);
+                                }
+
+                                Err(e) => {
+                                    outcome = "error";
+                                    let t = Box::new(move |_: Option<isize>| {
+                                        cb(Err(format!("lmdb iter internal error: {:?}", e)));
+                                    });
+                                    cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader get next item error")); 
+                                }
+                            }
+                        }
+                        (false, Some(ck)) => {
+                            let cb1 = cb.clone();
+                            let ck1 = ck.clone();
+                            let cb2 = cb.clone();
+                            match cursor.get(Some(ck.as_ref()), None, MDB_SET_KEY) {
+                                // 软删除的键不返回
+                                Ok((_, v)) if tombstone::is_deleted(&tab, v) => {}
+                                Ok(val) => match chunk::read(&txn, env_id, &tab, &ck, val.1) {
+                                    Ok(v) => {
+                                        debug!("iter next item ascending key: {:?}, value: {:?}", ck.clone(), v.clone());
+                                        let t = Box::new(move |_: Option<isize>| {
+                                            cb1(Ok(Some((ck1, Arc::new(v)))));
+                                        });
+                                        cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader get next item"));
+                                    }
+                                    Err(e) => {
+                                        outcome = "error";
+                                        warn!("read chunked value failed: {:?}", e.to_string());
+                                    }
+                                },
+                                Err(Error::NotFound) => {}
+                                Err(_) => {}
+                            }
+
+                            // get next key
+                            match skip_tombstones(&cursor, &tab, cursor.get(Some(ck.as_ref()), None, MDB_PREV), MDB_PREV) {
+                                Ok(val) => {
+                                    debug!("iter next item ascending item: {:?}", val);
+                                    let _ = sndr.send(Some(Arc::new(val.0.unwrap().to_vec())));
+                                }
+
+                                Err(Error::NotFound) => {
+                                    debug!("iter next item ascending item: NotFound");
+                                    let _ = sndr.send(None);
+                                }
+
+                                Err(e) => {
+                                    outcome = "error";
+                                    let t = Box::new(move |_: Option<isize>| {
+                                        cb2(Err(format!("Lmdb reader lmdb next item error: {:?}", e)));
+                                    });
+                                    cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader query error"));
+                                }
+                            }
+                        }
+
+                        _ => (),
+                    }
+
+                    drop(cursor);
+                    match txn.commit() {
+                        Ok(_) => {},
+                        Err(e) => panic!("Next item txn commit error: {:?}", e.to_string()),
+                    }
+
+                    log_slow_tab("reader nextItem", start_time, &tab);
+                }
+                ReaderMsg::Rollback(cb) => {
+                    let t = Box::new(move |_: Option<isize>| {
+                        cb(Ok(()));
+                    });
+                    cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb reader rollback error"));
+                }
+            }
+            span.record("outcome", &outcome);
+        }
+    });
+}
+
+// 读线程名
+pub fn reader_name(index: usize) -> String {
+    format!("pi_store-reader-{}", index)
+}
+
+// 写线程名
+pub fn writer_name() -> String {
+    "pi_store-writer".to_string()
+}
+
+// 将当前线程绑定到指定序号的CPU核，序号超过核数时取模
+fn pin_to_core(index: usize) {
+    match core_affinity::get_core_ids() {
+        Some(cores) if !cores.is_empty() => {
+            let core = cores[index % cores.len()];
+            if !core_affinity::set_for_current(core) {
+                warn!("pin thread {:?} to core {:?} failed", thread::current().name(), core.id);
+            }
+        }
+        _ => warn!("get core ids failed, thread {:?} not pinned", thread::current().name()),
+    }
+}
+
+// 创建工作线程的通道，容量为0表示不限制
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    match capacity {
+        0 => unbounded(),
+        cap => bounded(cap),
+    }
+}
+
+// 优先接收高优先级通道中的消息，高优先级通道为空时同时等待两个通道
+fn recv_prioritized<T>(high: &Receiver<T>, low: &Receiver<T>) -> Result<T, RecvError> {
+    if let Ok(msg) = high.try_recv() {
+        return Ok(msg);
+    }
+
+    select! {
+        recv(high) -> msg => msg,
+        recv(low) -> msg => msg,
+    }
+}
+
+// 带超时地按优先级接收消息，超时返回None，没有超时则一直等待
+fn recv_prioritized_timeout<T>(high: &Receiver<T>, low: &Receiver<T>, timeout: Option<Duration>) -> Result<Option<T>, RecvError> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return recv_prioritized(high, low).map(Some),
+    };
+    if let Ok(msg) = high.try_recv() {
+        return Ok(Some(msg));
+    }
+
+    select! {
+        recv(high) -> msg => msg.map(Some),
+        recv(low) -> msg => msg.map(Some),
+        default(timeout) => Ok(None),
+    }
+}
+
+/**
+* 在事务中批量查询，不存在的键返回值为None
+* 读写事务中有未提交的修改，长期持有的读事务是旧的快照，都不能使用读缓存
+* @param cache 读缓存的版本，启用读缓存时先查缓存并缓存查到的值，为None则直接查询LMDB
+*/
+fn query_in_txn<T: Transaction>(env_id: u64, txn: &T, queries: &[TabKV], token: Option<&CancelToken>, cache: Option<u64>) -> Result<Vec<TabKV>, StoreError> {
+    let mut qr = Vec::with_capacity(queries.len());
+    for q in queries.iter() {
+        if token.map_or(false, |t| t.is_cancelled()) {
+            return Err(StoreError::Cancelled);
+        }
+
+        let tab = q.tab.get_hash() as u64;
+        let value = match cache.and_then(|_| read_cache::get(env_id, tab, &q.key)) {
+            Some(v) => Some(v),
+            // 布隆过滤器确定不存在的键不访问LMDB，迁移到冷表的键可能已不在过滤器中
+            None if !bloom::may_contain(env_id, tab, &q.key) => None,
+            None => match get_db(env_id, &q.tab).and_then(|db| txn.get(db, q.key.as_ref())) {
+                // 软删除的键视为不存在，也不查询冷表
+                Ok(v) if tombstone::is_deleted(&q.tab, v) => {
+                    qr.push(TabKV { value: None, ..q.clone() });
+                    continue;
+                }
+                Ok(v) => {
+                    let v = Arc::new(chunk::read(txn, env_id, &q.tab, &q.key, v).map_err(StoreError::from)?);
+                    checksum::verify(txn, env_id, &q.tab, &q.key, &v)?;
+                    if let Some(epoch) = cache {
+                        read_cache::put(epoch, env_id, tab, &q.key, &v);
+                    }
+                    Some(v)
+                }
+                Err(Error::NotFound) => None,
+                Err(e) => return Err(StoreError::from(e)),
+            },
+        };
+        // 分层的表在热表中找不到时查询冷表
+        let value = match value {
+            Some(v) => {
+                tiering::touch(env_id, tab, &q.key);
+                Some(v)
+            }
+            None => match tiering::get(txn, env_id, tab, &q.key).map_err(StoreError::from)? {
+                Some(v) => {
+                    checksum::verify(txn, env_id, &q.tab, &q.key, &v)?;
+                    Some(Arc::new(v))
+                }
+                None => None,
+            },
+        };
+        qr.push(TabKV {
+            ware: q.ware.clone(),
+            tab: q.tab.clone(),
+            key: q.key.clone(),
+            index: q.index,
+            value: value,
+        });
+    }
+    Ok(qr)
+}
+
+// 从当前键开始在迭代方向上找到第一个满足过滤条件的键值，并返回它之后的下一个键
+fn next_match<T: Transaction>(
+    txn: &T,
+    env_id: u64,
+    tab: &Atom,
+    cur_key: &Bin,
+    descending: bool,
+    filter: &ScanFilter,
+) -> Result<(Option<(Bin, Bin)>, Option<Bin>), Error> {
+    let cursor = txn.open_ro_cursor(get_db(env_id, tab)?)?;
+    let step = if descending { MDB_NEXT } else { MDB_PREV };
+    let mut item = cursor.get(Some(cur_key.as_ref()), None, MDB_SET_RANGE);
+    if let (false, Ok((Some(k), _))) = (descending, &item) {
+        // 从大到小迭代时当前键已被删除，定位到的是更大的键
+        if *k != cur_key.as_slice() {
+            item = cursor.get(None, None, MDB_PREV);
+        }
+    }
+    if let (false, Err(Error::NotFound)) = (descending, &item) {
+        item = cursor.get(None, None, MDB_LAST);
+    }
+    loop {
+        let (k, v) = match item {
+            Ok((Some(k), v)) => (k, v),
+            Ok((None, _)) | Err(Error::NotFound) => return Ok((None, None)),
+            Err(e) => return Err(e),
+        };
+        if filter.past_end(k, descending) {
+            return Ok((None, None));
+        }
+        if tombstone::is_deleted(tab, v) {
+            item = cursor.get(None, None, step);
+            continue;
+        }
+        let value = chunk::read(txn, env_id, tab, k, v)?;
+        if filter.matches(k, &value) {
+            let found = (Arc::new(k.to_vec()), Arc::new(value));
+            let next = match cursor.get(None, None, step) {
+                Ok((k, _)) => k.map(|k| Arc::new(k.to_vec())),
+                Err(Error::NotFound) => None,
+                Err(e) => return Err(e),
+            };
+            return Ok((Some(found), next));
+        }
+        item = cursor.get(None, None, step);
+    }
+}
+
+// 从游标定位到的记录开始，沿迭代方向跳过软删除的键，未开启软删除的表原样返回
+fn skip_tombstones<'txn, C: Cursor<'txn>>(cursor: &C, tab: &Atom, mut item: Result<(Option<&'txn [u8]>, &'txn [u8]), Error>, step: u32) -> Result<(Option<&'txn [u8]>, &'txn [u8]), Error> {
+    if !policy::soft_delete(tab) {
+        return item;
+    }
+    loop {
+        match item {
+            Ok((Some(_), v)) if tombstone::is_tombstone(v) => item = cursor.get(None, None, step),
+            r => return r,
+        }
+    }
+}
+
+// 取迭代方向上的下一个键，只比较和复制键，值所在的页不会被复制，也不会重组分块
+// skip_tombstones为true时跳过软删除的键，需要读取值的头部
+fn next_key<T: Transaction>(txn: &T, db: Database, cur_key: &Bin, descending: bool, skip_tombstones: bool) -> Result<Option<Bin>, Error> {
+    let cursor = txn.open_ro_cursor(db)?;
+    // 当前键可能已被删除，定位到第一个大于等于它的键
+    let on_cur = match cursor.get(Some(cur_key.as_ref()), None, MDB_SET_RANGE) {
+        Ok((Some(k), _)) => k == cur_key.as_slice(),
+        Ok((None, _)) => false,
+        Err(Error::NotFound) => {
+            return match descending {
+                true => Ok(None),
+                false => match cursor.get(None, None, MDB_LAST) {
+                    Ok((k, _)) => Ok(k.map(|k| Arc::new(k.to_vec()))),
+                    Err(Error::NotFound) => Ok(None),
+                    Err(e) => Err(e),
+                },
+            };
+        }
+        Err(e) => return Err(e),
+    };
+    let step = if descending { MDB_NEXT } else { MDB_PREV };
+    let mut r = match (descending, on_cur) {
+        (true, false) => cursor.get(None, None, MDB_GET_CURRENT),
+        _ => cursor.get(None, None, step),
+    };
+    while skip_tombstones {
+        match r {
+            Ok((Some(_), v)) if tombstone::is_tombstone(v) => r = cursor.get(None, None, step),
+            _ => break,
+        }
+    }
+    match r {
+        Ok((k, _)) => Ok(k.map(|k| Arc::new(k.to_vec()))),
+        Err(Error::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// 定位到第一个大于等于键的位置，或者最后一个小于等于键的位置
+fn seek<T: Transaction>(txn: &T, db: Database, key: &Bin, for_prev: bool) -> Result<Option<Bin>, Error> {
+    let cursor = txn.open_ro_cursor(db)?;
+    let found = match cursor.get(Some(key.as_ref()), None, MDB_SET_RANGE) {
+        Ok(val) => Some(val.0.unwrap().to_vec()),
+        Err(Error::NotFound) => None,
+        Err(e) => return Err(e),
+    };
+    let r = match (found, for_prev) {
+        (found, false) => found,
+        (Some(k), true) if k.as_slice() == key.as_slice() => Some(k),
+        // 第一个大于键的位置的前一个位置
+        (Some(_), true) => match cursor.get(None, None, MDB_PREV) {
+            Ok(val) => Some(val.0.unwrap().to_vec()),
+            Err(Error::NotFound) => None,
+            Err(e) => return Err(e),
+        },
+        // 所有键都小于键时定位到表尾
+        (None, true) => match cursor.get(None, None, MDB_LAST) {
+            Ok(val) => Some(val.0.unwrap().to_vec()),
+            Err(Error::NotFound) => None,
+            Err(e) => return Err(e),
+        },
+    };
+    Ok(r.map(Arc::new))
+}
+
+// 读取一页键值，不包括after，descending为true时从小到大
+fn scan_page<T: Transaction>(txn: &T, env_id: u64, tab: &Atom, after: Option<&Bin>, descending: bool, limit: usize) -> Result<Vec<(Bin, Bin)>, String> {
+    let db = get_db(env_id, tab).map_err(|e| e.to_string())?;
+    let cursor = txn.open_ro_cursor(db).map_err(|e| e.to_string())?;
+    let mut items = Vec::with_capacity(limit);
+    let mut item = match (after, descending) {
+        (None, true) => cursor.get(None, None, MDB_FIRST),
+        (None, false) => cursor.get(None, None, MDB_LAST),
+        (Some(k), true) => match cursor.get(Some(k.as_ref()), None, MDB_SET_RANGE) {
+            Ok((Some(found), _)) if found == k.as_slice() => cursor.get(None, None, MDB_NEXT),
+            r => r,
+        },
+        (Some(k), false) => match cursor.get(Some(k.as_ref()), None, MDB_SET_RANGE) {
+            Ok(_) => cursor.get(None, None, MDB_PREV),
+            Err(Error::NotFound) => cursor.get(None, None, MDB_LAST),
+            Err(e) => Err(e),
+        },
+    };
+    while items.len() < limit {
+        match item {
+            Ok((Some(_), v)) if tombstone::is_deleted(tab, v) => (),
+            Ok((Some(k), v)) => {
+                let v = chunk::read(txn, env_id, tab, k, v).map_err(|e| e.to_string())?;
+                items.push((Arc::new(k.to_vec()), Arc::new(v)));
+            }
+            Ok((None, _)) | Err(Error::NotFound) => break,
+            Err(e) => return Err(e.to_string()),
+        }
+        item = cursor.get(None, None, if descending { MDB_NEXT } else { MDB_PREV });
+    }
+    Ok(items)
+}
+
+// 表中的记录数，由LMDB的表统计直接得到，不需要遍历
+fn table_entries<T: Transaction>(txn: &T, db: Database) -> Result<usize, String> {
+    let mut stat: ffi::MDB_stat = unsafe { mem::zeroed() };
+    match unsafe { ffi::mdb_stat(txn.txn(), db.dbi(), &mut stat) } {
+        ffi::MDB_SUCCESS => Ok(stat.ms_entries as usize),
+        code => Err(Error::from_err_code(code).to_string()),
+    }
+}
+
+// 估计提交的脏页数，每个修改的表按从根到叶子的路径计，再加上写入字节数占用的页数
+fn dirty_pages(txn: &RwTransaction, env_id: u64, staged: &[TabKV], modifies: &[TabKV]) -> u64 {
+    let mut tabs: HashMap<u64, &Atom> = HashMap::new();
+    let mut bytes = 0;
+    for m in staged.iter().chain(modifies.iter()) {
+        tabs.insert(m.tab.get_hash() as u64, &m.tab);
+        bytes += m.key.len() + m.value.as_ref().map_or(0, |v| v.len());
+    }
+    let mut pages = 0;
+    let mut psize = 0;
+    for tab in tabs.values() {
+        let db = match lookup_db(env_id, tab) {
+            Some(db) => db,
+            None => continue,
+        };
+        let mut stat: ffi::MDB_stat = unsafe { mem::zeroed() };
+        if unsafe { ffi::mdb_stat(txn.txn(), db.dbi(), &mut stat) } == ffi::MDB_SUCCESS {
+            pages += cmp::max(stat.ms_depth, 1) as u64;
+            psize = stat.ms_psize as usize;
+        }
+    }
+    if psize > 0 {
+        pages += ((bytes + psize - 1) / psize) as u64;
+    }
+    pages
+}
+
+// 遍历游标统计键在[start, end)范围内的记录数，不包括软删除的键
+fn count_range<T: Transaction>(txn: &T, tab: &Atom, db: Database, start: Option<&Bin>, end: Option<&Bin>) -> Result<usize, String> {
+    let mut cursor = txn.open_ro_cursor(db).map_err(|e| e.to_string())?;
+    let iter = match start {
+        Some(k) => cursor.iter_from(k.as_slice()),
+        None => cursor.iter_start(),
+    };
+    Ok(iter
+        .take_while(|(k, _)| end.map_or(true, |e| *k < e.as_slice()))
+        .filter(|(_, v)| !tombstone::is_deleted(tab, v))
+        .count())
+}
+
+// 表中的记录数，软删除的表需要遍历以排除墓碑
+fn table_size<T: Transaction>(txn: &T, tab: &Atom, db: Database) -> Result<usize, String> {
+    if policy::soft_delete(tab) {
+        count_range(txn, tab, db, None, None)
+    } else {
+        table_entries(txn, db)
+    }
+}
+
+// 在读事务中查询值的视图，不分块的值直接指向内存映射，不经过读缓存
+fn query_view<'txn, T: Transaction>(env_id: u64, txn: &'txn T, queries: &[TabKV]) -> Result<Vec<Option<ValueView<'txn>>>, StoreError> {
+    let mut qr = Vec::with_capacity(queries.len());
+    for q in queries.iter() {
+        let tab = q.tab.get_hash() as u64;
+        if !bloom::may_contain(env_id, tab, &q.key) {
+            qr.push(None);
+            continue;
+        }
+        match get_db(env_id, &q.tab).and_then(|db| txn.get(db, q.key.as_ref())) {
+            Ok(v) if tombstone::is_deleted(&q.tab, v) => qr.push(None),
+            Ok(v) => {
+                let view = if chunk::is_chunked(v) {
+                    ValueView::owned(chunk::read(txn, env_id, &q.tab, &q.key, v).map_err(StoreError::from)?)
+                } else {
+                    ValueView::borrowed(v)
+                };
+                checksum::verify(txn, env_id, &q.tab, &q.key, &view)?;
+                qr.push(Some(view));
+            }
+            Err(Error::NotFound) => qr.push(None),
+            Err(e) => return Err(StoreError::from(e)),
+        }
+    }
+    Ok(qr)
+}
+
+// 在读写事务中校验前置条件
+fn check_conditions<T: Transaction>(txn: &T, env_id: u64, conditions: &[Condition]) -> Result<(), StoreError> {
+    for c in conditions.iter() {
+        let current = match get_db(env_id, &c.tab).and_then(|db| txn.get(db, c.key.as_ref())) {
+            Ok(v) if tombstone::is_deleted(&c.tab, v) => None,
+            Ok(v) => Some(chunk::read(txn, env_id, &c.tab, &c.key, v).map_err(StoreError::from)?),
+            Err(Error::NotFound) => None,
+            Err(e) => return Err(StoreError::from(e)),
+        };
+        let version = match c.expect {
+            Precondition::Version(_) => versions::get(txn, env_id, &c.tab, &c.key).map_err(StoreError::from)?,
+            _ => 0,
+        };
+        if !c.check(current.as_ref().map(|v| v.as_slice()), version) {
+            debug!("precondition failed, tab: {:?}, key: {:?}, expect: {:?}", c.tab, c.key, c.expect);
+            return Err(StoreError::PreconditionFailed);
+        }
+    }
+    Ok(())
+}
+
+// 在读写事务中合并操作数
+fn merge_in_txn(txn: &mut RwTransaction, env_id: u64, operands: &[TabKV]) -> Result<(), String> {
+    for m in operands.iter() {
+        let operand = m.value.as_ref().ok_or_else(|| format!("merge without operand: {:?}", m.tab.to_string()))?;
+        let current = match get_db(env_id, &m.tab).and_then(|db| (&*txn).get(db, m.key.as_ref())) {
+            Ok(v) if tombstone::is_deleted(&m.tab, v) => None,
+            Ok(v) => Some(chunk::read(&*txn, env_id, &m.tab, &m.key, v).map_err(|e| e.to_string())?),
+            Err(Error::NotFound) => None,
+            Err(e) => return Err(e.to_string()),
+        };
+        let value = merge::apply(&m.tab, current.as_ref().map(|v| v.as_slice()), operand)?;
+        let kv = TabKV {
+            value: value.map(Arc::new),
+            ..m.clone()
+        };
+        bloom::insert(env_id, &[kv.clone()]);
+        write_kv(txn, env_id, &kv).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/**
+* 重命名表，LMDB不支持重命名，在一个读写事务中创建新表、逐条移动记录并删除旧表
+* 记录原样移动，分块、版本、校验和与过期时间随记录一起迁移，修改日志、变更数据和审计只记录一次重命名
+* @returns 失败时事务回滚，旧表保持不变
+*/
+fn rename_db(env: &Environment, env_id: u64, old: &Atom, new: &Atom, extra: &[TabKV]) -> Result<(), String> {
+    let old_key = (env_id, old.get_hash() as u64);
+    let new_key = (env_id, new.get_hash() as u64);
+    let old_db = lookup_db(env_id, old).ok_or_else(|| format!("tab not opened: {:?}", old.to_string()))?;
+    if OPENED_TABLES.read().unwrap().contains_key(&new_key) {
+        return Err(format!("tab already exists: {:?}", new.to_string()));
+    }
+
+    let mut txn = begin_rw(env).map_err(|e| e.to_string())?;
+    // 新表沿用旧表的数据库标志
+    let new_db = match txn.db_flags(old_db).and_then(|flags| unsafe { txn.create_db(Some(new.as_str()), flags) }) {
+        Ok(db) => db,
+        Err(e) => {
+            txn.abort();
+            return Err(e.to_string());
+        }
+    };
+    // write_kv按表名查找表，新表在提交前先登记，失败时移除
+    if let Err(e) = register_db(env_id, new, new_db) {
+        txn.abort();
+        return Err(e);
+    }
+
+    let mut written = Vec::new();
+    let r = (|| -> Result<(), Error> {
+        let records = {
+            let mut cursor = txn.open_ro_cursor(old_db)?;
+            cursor.iter_start().map(|(k, v)| (k.to_vec(), v.to_vec())).collect::<Vec<(Vec<u8>, Vec<u8>)>>()
+        };
+        for (k, v) in records {
+            move_kv(&mut txn, env_id, (old, old_db), (new, new_db), &k, &v)?;
+            let key = Arc::new(k);
+            written.push(TabKV { ware: Atom::from(""), tab: old.clone(), key: key.clone(), index: 0, value: None });
+            written.push(TabKV { ware: Atom::from(""), tab: new.clone(), key, index: 0, value: None });
+        }
+        for m in extra.iter() {
+            write_kv(&mut txn, env_id, m)?;
+        }
+        changelog::assign_seq(&mut txn, env_id)?;
+        cdc::capture_rename(&txn, env_id, old, new)?;
+        audit::capture_rename(env_id, old, new);
+        unsafe { txn.drop_db(old_db) }
+    })();
+    let r = r.and_then(|_| commit_rw(env_id, txn));
+    read_cache::invalidate(env_id, &written);
+    read_cache::invalidate(env_id, extra);
+
+    match r {
+        Ok(_) => {
+            committed(env_id);
+            unregister_db(env_id, old_key.1);
+            bloom::rebuild(env_id, new.get_hash() as u64, env, new_db);
+            info!("rename tab: {:?} to {:?}, records: {}", old.to_string(), new.to_string(), written.len() / 2);
+            Ok(())
+        }
+        Err(e) => {
+            aborted(env_id);
+            unregister_db(env_id, new_key.1);
+            Err(format!("rename tab: {:?} failed: {:?}", old.to_string(), e.to_string()))
+        }
+    }
+}
+
+// 重命名时把一条记录原样移到新表，不经过修改日志、变更数据和审计
+fn move_kv(txn: &mut RwTransaction, env_id: u64, from: (&Atom, Database), to: (&Atom, Database), key: &[u8], raw: &[u8]) -> Result<(), Error> {
+    let deleted = tombstone::is_deleted(from.0, raw);
+    if deleted {
+        txn.put(to.1, &key, &raw, WriteFlags::empty())?;
+    } else {
+        let value = chunk::read(&*txn, env_id, from.0, key, raw)?;
+        chunk::put(txn, env_id, to.1, to.0, key, &value)?;
+        checksum::put(txn, env_id, to.0, key, &value)?;
+    }
+    chunk::del(txn, env_id, from.1, from.0, key)?;
+    checksum::del(txn, env_id, from.0, key)?;
+    versions::rename(txn, env_id, from.0, to.0, key)?;
+    policy::on_write(txn, env_id, from.0, key, true)?;
+    policy::on_write(txn, env_id, to.0, key, deleted)
+}
+
+/**
+* 复制表，目标表为空，源表的记录按键的顺序用APPEND写入，不需要查找插入位置
+* 分块的值重组后重新分块写入，修改日志、版本和校验和与普通写入相同，目标表不是软删除时不复制墓碑
+* @returns 失败时事务回滚，新建的目标表被丢弃，提交成功后才登记目标表
+*/
+fn copy_db(env: &Environment, env_id: u64, src: &Atom, dst: &Atom, extra: &[TabKV]) -> Result<(), String> {
+    let src_db = lookup_db(env_id, src).ok_or_else(|| format!("tab not opened: {:?}", src.to_string()))?;
+    let opened = lookup_db(env_id, dst);
+    if opened.is_none() && OPENED_TABLES.read().unwrap().contains_key(&(env_id, dst.get_hash() as u64)) {
+        return Err(format!("tab: {:?} hash conflicts with opened tab", dst.to_string()));
+    }
+
+    let mut txn = begin_rw(env).map_err(|e| e.to_string())?;
+    let dst_db = match opened {
+        Some(db) => match table_entries(&txn, db) {
+            Ok(0) => Ok(db),
+            Ok(_) => Err(format!("copy target tab not empty: {:?}", dst.to_string())),
+            Err(e) => Err(e),
+        },
+        None => txn.db_flags(src_db).and_then(|flags| unsafe { txn.create_db(Some(dst.as_str()), flags) }).map_err(|e| e.to_string()),
+    };
+    let dst_db = match dst_db {
+        Ok(db) => db,
+        Err(e) => {
+            txn.abort();
+            aborted(env_id);
+            return Err(e);
+        }
+    };
+    // 源表软删除的墓碑只在目标表也是软删除时复制
+    let skip_tombstones = policy::soft_delete(src) && !policy::soft_delete(dst);
+
+    let mut count = 0;
+    let r = (|| -> Result<(), Error> {
+        let mut last: Option<Vec<u8>> = None;
+        loop {
+            // 游标与写入不能同时借用事务，每次读取一批记录后再写入
+            let mut batch = Vec::with_capacity(COPY_BATCH);
+            {
+                let mut cursor = txn.open_ro_cursor(src_db)?;
+                let mut item = match &last {
+                    Some(k) => cursor.get(Some(k.as_slice()), None, MDB_SET_RANGE).and_then(|_| cursor.get(None, None, MDB_NEXT)),
+                    None => cursor.get(None, None, MDB_FIRST),
+                };
+                while batch.len() < COPY_BATCH {
+                    match item {
+                        Ok((Some(_), v)) if skip_tombstones && tombstone::is_tombstone(v) => {}
+                        Ok((Some(k), v)) => batch.push((k.to_vec(), chunk::read(&txn, env_id, src, k, v)?)),
+                        Ok((None, _)) | Err(Error::NotFound) => break,
+                        Err(e) => return Err(e),
+                    }
+                    item = cursor.get(None, None, MDB_NEXT);
+                }
+            }
+            if batch.is_empty() {
+                return Ok(());
+            }
+            last = batch.last().map(|(k, _)| k.clone());
+            for (k, v) in batch {
+                append_kv(&mut txn, env_id, dst_db, dst, Arc::new(k), Arc::new(v))?;
+                count += 1;
+            }
+        }
+    })();
+    let r = r
+        .and_then(|_| extra.iter().try_for_each(|m| write_kv(&mut txn, env_id, m)))
+        .and_then(|_| commit_rw(env_id, txn));
+    read_cache::invalidate(env_id, extra);
+
+    match r {
+        Ok(_) => {
+            committed(env_id);
+            // 目标表在提交成功后才登记，失败时不会留下无效的表句柄
+            if opened.is_none() {
+                register_db(env_id, dst, dst_db)?;
+            }
+            bloom::rebuild(env_id, dst.get_hash() as u64, env, dst_db);
+            info!("copy tab: {:?} to {:?}, records: {}", src.to_string(), dst.to_string(), count);
+            Ok(())
+        }
+        Err(e) => {
+            aborted(env_id);
+            Err(format!("copy tab: {:?} failed: {:?}", src.to_string(), e.to_string()))
+        }
+    }
+}
+
+// 读写事务提交后发布修改，并使事务中的表创建和删除生效
+fn committed(env_id: u64) {
+    table_meta::publish(env_id);
+    cdc::publish(env_id);
+    audit::publish(env_id);
+    flusher::on_commit(env_id);
+    txn_stats::committed(env_id);
+}
+
+// 读写事务回滚后丢弃修改，并撤销事务中的表创建和删除
+fn aborted(env_id: u64) {
+    table_meta::discard(env_id);
+    cdc::discard(env_id);
+    audit::discard(env_id);
+    txn_stats::aborted(env_id);
+}
+
+// 按表的比较函数比较两个键，整数键的表按数值比较
+pub fn key_cmp<T: Transaction>(txn: &T, db: Database, a: &[u8], b: &[u8]) -> cmp::Ordering {
+    let a = ffi::MDB_val { mv_size: a.len(), mv_data: a.as_ptr() as *mut _ };
+    let b = ffi::MDB_val { mv_size: b.len(), mv_data: b.as_ptr() as *mut _ };
+    unsafe { ffi::mdb_cmp(txn.txn(), db.dbi(), &a, &b) }.cmp(&0)
+}
+
+// 按键的顺序追加写入键值，修改日志、版本和校验和与普通写入相同
+fn append_kv(txn: &mut RwTransaction, env_id: u64, db: Database, tab: &Atom, key: Bin, value: Bin) -> Result<(), Error> {
+    let m = TabKV { ware: Atom::from(""), tab: tab.clone(), key, index: 0, value: Some(value) };
+    changelog::record(txn, env_id, &m)?;
+    cdc::capture(txn, env_id, db, &m)?;
+    audit::capture(env_id, &m);
+    let value = m.value.as_ref().unwrap();
+    chunk::append(txn, env_id, db, tab, &m.key, value)?;
+    versions::bump(txn, env_id, tab, &m.key)?;
+    policy::on_write(txn, env_id, tab, &m.key, false)?;
+    checksum::put(txn, env_id, tab, &m.key, value)
+}
+
+/**
+* 批量导入，键必须严格升序且大于表中已有的键，用APPEND写入，不需要查找插入位置
+* 每导入every条记录提交一次，失败时已提交的记录保留，错误中包含已提交的记录数
+* 失败后继续读取并丢弃流中剩余的记录，直到发送端关闭
+* @returns 返回导入的记录数
+*/
+fn bulk_load(env: &Environment, env_id: u64, tab: &Atom, stream: &Receiver<(Bin, Bin)>, every: usize) -> Result<usize, String> {
+    let mut committed_count = 0;
+    match bulk_append(env, env_id, tab, stream, every.max(1), &mut committed_count) {
+        Ok(count) => {
+            info!("bulk load tab: {:?}, records: {}", tab.to_string(), count);
+            Ok(count)
+        }
+        Err(e) => {
+            aborted(env_id);
+            let dropped = stream.iter().count();
+            warn!("bulk load tab: {:?} failed, dropped {} records left in stream", tab.to_string(), dropped);
+            Err(format!("bulk load tab: {:?} failed after {} records: {}", tab.to_string(), committed_count, e))
+        }
+    }
+}
+
+// 按批追加写入流中的记录，每提交一批更新已提交的记录数
+fn bulk_append(env: &Environment, env_id: u64, tab: &Atom, stream: &Receiver<(Bin, Bin)>, every: usize, committed_count: &mut usize) -> Result<usize, String> {
+    let db = lookup_db(env_id, tab).ok_or_else(|| format!("tab not opened: {:?}", tab.to_string()))?;
+    let mut txn = begin_rw(env).map_err(|e| e.to_string())?;
+    let mut last: Option<Bin> = {
+        let mut cursor = txn.open_ro_cursor(db).map_err(|e| e.to_string())?;
+        match cursor.get(None, None, MDB_LAST) {
+            Ok((Some(k), _)) => Some(Arc::new(k.to_vec())),
+            Ok((None, _)) | Err(Error::NotFound) => None,
+            Err(e) => return Err(e.to_string()),
+        }
+    };
+
+    let mut count = 0;
+    for (key, value) in stream.iter() {
+        if last.as_ref().map_or(false, |l| key_cmp(&txn, db, &key, l) != cmp::Ordering::Greater) {
+            return Err(format!("keys not ascending at record {}", count));
+        }
+        bloom::insert(env_id, &[TabKV { ware: Atom::from(""), tab: tab.clone(), key: key.clone(), index: 0, value: None }]);
+        append_kv(&mut txn, env_id, db, tab, key.clone(), value).map_err(|e| e.to_string())?;
+        last = Some(key);
+        count += 1;
+        if count % every == 0 {
+            commit_rw(env_id, txn).map_err(|e| e.to_string())?;
+            committed(env_id);
+            *committed_count = count;
+            txn = begin_rw(env).map_err(|e| e.to_string())?;
+        }
+    }
+    commit_rw(env_id, txn).map_err(|e| e.to_string())?;
+    committed(env_id);
+    *committed_count = count;
+    Ok(count)
+}
+
+// 提交写线程的读写事务，注入提交失败时回滚事务
+fn commit_rw(env_id: u64, txn: RwTransaction) -> Result<(), Error> {
+    if let Err(e) = fault::on_commit() {
+        txn.abort();
+        return Err(e);
+    }
+    let r = txn.commit();
+    if let Err(ref e) = r {
+        txn_stats::commit_failed(env_id, e);
+    }
+    r
+}
+
+// 在读写事务中写入一条修改，值为None表示删除，表未打开返回BadDbi
+fn write_kv(txn: &mut RwTransaction, env_id: u64, m: &TabKV) -> Result<(), Error> {
+    write_kv_flags(txn, env_id, m, WriteFlags::empty())
+}
+
+// 按写入标志写入键值，只支持NO_OVERWRITE、APPEND和APPEND_DUP，删除时忽略标志
+fn write_kv_flags(txn: &mut RwTransaction, env_id: u64, m: &TabKV, flags: WriteFlags) -> Result<(), Error> {
+    txn_stats::touch(env_id, &m.tab);
+    let r = put_kv(txn, env_id, m, flags);
+    if let Err(ref e) = r {
+        txn_stats::put_failed(env_id, &m.tab, e);
+    }
+    r
+}
+
+// 记录修改日志、变更数据和审计，变更数据需要读取原值，在写入前调用
+fn capture(txn: &mut RwTransaction, env_id: u64, db: Database, m: &TabKV) -> Result<(), Error> {
+    changelog::record(txn, env_id, m)?;
+    cdc::capture(txn, env_id, db, m)?;
+    audit::capture(env_id, m);
+    Ok(())
+}
+
+fn put_kv(txn: &mut RwTransaction, env_id: u64, m: &TabKV, flags: WriteFlags) -> Result<(), Error> {
+    fault::on_put()?;
+    table_meta::apply(txn, env_id, m)?;
+    let db = get_db(env_id, &m.tab)?;
+    match &m.value {
+        // value is some, insert data
+        Some(v) => {
+            let flags = flags & (WriteFlags::NO_OVERWRITE | WriteFlags::APPEND | WriteFlags::APPEND_DUP);
+            // 键可能已迁移到冷表，在两层中检查是否存在
+            if flags.contains(WriteFlags::NO_OVERWRITE) {
+                match txn.get(db, m.key.as_ref()) {
+                    Ok(v) if !tombstone::is_deleted(&m.tab, v) => return Err(Error::KeyExist),
+                    Ok(_) | Err(Error::NotFound) => (),
+                    Err(e) => return Err(e),
+                }
+                if tiering::get(&*txn, env_id, m.tab.get_hash() as u64, &m.key)?.is_some() {
+                    return Err(Error::KeyExist);
+                }
+            }
+            capture(txn, env_id, db, m)?;
+            tiering::on_write(txn, env_id, &m.tab, &m.key)?;
+            chunk::put_with_flags(txn, env_id, db, &m.tab, &m.key, v, flags)?;
+            versions::bump(txn, env_id, &m.tab, &m.key)?;
+            policy::on_write(txn, env_id, &m.tab, &m.key, false)?;
+            checksum::put(txn, env_id, &m.tab, &m.key, v)?;
+            quota::check(&*txn, db, &m.tab)
+        }
+        // value is None, delete data
+        None => {
+            // 删除不存在或已软删除的键没有修改，不记录
+            let exists = match txn.get(db, m.key.as_ref()) {
+                Ok(v) => !tombstone::is_deleted(&m.tab, v),
+                Err(Error::NotFound) => false,
+                Err(e) => return Err(e),
+            };
+            if exists || tiering::get(&*txn, env_id, m.tab.get_hash() as u64, &m.key)?.is_some() {
+                capture(txn, env_id, db, m)?;
+            }
+            tiering::on_write(txn, env_id, &m.tab, &m.key)?;
+            // 软删除的表写入墓碑，由tombstone::purge在保留时间后永久删除
+            let r = if policy::soft_delete(&m.tab) {
+                tombstone::put(txn, env_id, db, &m.tab, &m.key)
+            } else {
+                chunk::del(txn, env_id, db, &m.tab, &m.key)
+            };
+            match r {
+                Ok(_) | Err(Error::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+            versions::del(txn, env_id, &m.tab, &m.key)?;
+            policy::on_write(txn, env_id, &m.tab, &m.key, true)?;
+            checksum::del(txn, env_id, &m.tab, &m.key)
+        }
+    }
+}
+
+/**
+* 登记已打开的表，表只在打开时解析一次，所有工作线程共享同一个表句柄
+* @param env_id 环境id
+* @param tab 表名
+* @param db 表句柄
+* @returns 表名哈希与已登记的其它表冲突时返回错误
+*/
+pub fn register_db(env_id: u64, tab: &Atom, db: Database) -> Result<(), String> {
+    let key = (env_id, tab.get_hash() as u64);
+    let mut names = OPENED_NAMES.write().unwrap();
+    if let Some(name) = names.get(&key) {
+        if name != tab {
+            return Err(format!("tab: {:?} hash conflicts with opened tab: {:?}", tab.to_string(), name.to_string()));
+        }
+    }
+    names.insert(key, tab.clone());
+    OPENED_TABLES.write().unwrap().insert(key, db);
+    Ok(())
+}
+
+// 注销已登记的表
+pub fn unregister_db(env_id: u64, tab: u64) {
+    let mut names = OPENED_NAMES.write().unwrap();
+    names.remove(&(env_id, tab));
+    OPENED_TABLES.write().unwrap().remove(&(env_id, tab));
+}
+
+// 按表名查找已登记的表，表名与登记时不一致视为未打开
+pub fn lookup_db(env_id: u64, tab: &Atom) -> Option<Database> {
+    let key = (env_id, tab.get_hash() as u64);
+    let names = OPENED_NAMES.read().unwrap();
+    if names.get(&key).map_or(false, |name| name != tab) {
+        return None;
+    }
+    OPENED_TABLES.read().unwrap().get(&key).cloned()
+}
+
+// 消息中的表未打开时返回BadDbi，与键不存在的NotFound区分
+fn get_db(env_id: u64, tab: &Atom) -> Result<Database, Error> {
+    lookup_db(env_id, tab).ok_or_else(|| {
+        warn!("tab not opened: {:?}, env: {:?}", tab.to_string(), env_id);
+        Error::BadDbi
+    })
 }
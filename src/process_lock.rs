@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// 写进程的锁文件，内容为持有者的进程id
+pub const WRITER_LOCK_FILE: &str = "writer.lock";
+
+lazy_static! {
+    // 本进程持有的写锁，键为环境id，库打开期间一直持有
+    static ref WRITER_LOCKS: Mutex<HashMap<u64, WriterLock>> = Mutex::new(HashMap::new());
+}
+
+/**
+* 多进程打开同一个环境时，只有一个进程可以写入
+* LMDB的写锁在写事务开始时阻塞等待，不会报告持有者；写锁文件在打开环境时检查，另一个进程正在写入时立即返回错误
+* 设置NO_LOCK时LMDB不做任何加锁，写锁文件是唯一的保护，读进程需要自行保证不与写入同时进行
+*/
+#[derive(Debug)]
+pub struct WriterLock {
+    path: PathBuf,
+}
+
+impl Drop for WriterLock {
+    fn drop(&mut self) {
+        if holder(self.path.parent().unwrap_or(Path::new("."))) == Some(process::id()) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/**
+* 获取写锁文件的持有者
+* @param dir 数据库目录
+* @returns 返回持有者的进程id，锁文件不存在或无法解析返回None
+*/
+pub fn holder(dir: &Path) -> Option<u32> {
+    fs::read_to_string(dir.join(WRITER_LOCK_FILE)).ok().and_then(|s| s.trim().parse::<u32>().ok())
+}
+
+// 进程是否存活，无法判断的平台视为存活
+#[cfg(target_os = "linux")]
+pub fn is_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn is_alive(_pid: u32) -> bool {
+    true
+}
+
+// 锁文件是否刚被创建
+fn is_fresh(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.elapsed().ok())
+        .map_or(false, |age| age < Duration::from_secs(1))
+}
+
+/**
+* 获取环境的写锁，持有者已退出的锁文件视为失效并被替换
+* @param dir 数据库目录，不存在时创建
+* @returns 返回写锁，另一个存活的进程持有写锁时返回错误
+*/
+pub fn acquire(dir: &Path) -> Result<WriterLock, String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let path = dir.join(WRITER_LOCK_FILE);
+    // 最多重试一次，第一次失败时清理失效的锁文件
+    for _ in 0..2 {
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(process::id().to_string().as_bytes()).map_err(|e| e.to_string())?;
+                file.sync_all().map_err(|e| e.to_string())?;
+                return Ok(WriterLock { path });
+            }
+            Err(ref e) if e.kind() == ErrorKind::AlreadyExists => match holder(dir) {
+                Some(pid) if pid == process::id() => {
+                    return Err(format!("db: {:?} already opened for write by this process", dir));
+                }
+                Some(pid) if is_alive(pid) => {
+                    return Err(format!("db: {:?} is locked for write by process: {}", dir, pid));
+                }
+                // 另一个进程刚创建锁文件，还未写入进程id
+                None if is_fresh(&path) => {
+                    return Err(format!("db: {:?} writer lock contended", dir));
+                }
+                pid => {
+                    warn!("remove stale writer lock of db: {:?}, holder: {:?}", dir, pid);
+                    fs::remove_file(&path).map_err(|e| e.to_string())?;
+                }
+            },
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Err(format!("db: {:?} writer lock contended", dir))
+}
+
+// 保存环境的写锁，库打开期间持有
+pub fn hold(env_id: u64, lock: WriterLock) {
+    WRITER_LOCKS.lock().unwrap().insert(env_id, lock);
+}
+
+// 释放环境的写锁
+pub fn release(env_id: u64) {
+    WRITER_LOCKS.lock().unwrap().remove(&env_id);
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use worker::impls::cast_store_task;
+use worker::task::TaskType;
+
+use atom::Atom;
+
+use crate::cdc::ChangeEvent;
+
+// 回调未确认时的最大重新投递次数
+const MAX_REDELIVERY: usize = 3;
+
+/**
+* 键前缀订阅的回调，参数为一次提交中匹配前缀的所有修改
+* 投递语义为至少一次: 只在提交成功后投递，缓存中未提交的修改不会投递；
+* 回调返回false表示未处理，会重新投递，因此同一次提交可能被投递多次，回调必须是幂等的
+* 重新投递超过次数后放弃，进程崩溃时已提交但未投递的修改会丢失，需要结合提交序号重新同步
+*/
+pub type PrefixCallback = Arc<Fn(Arc<Vec<ChangeEvent>>) -> bool>;
+
+struct Subscription {
+    tab: Atom,
+    prefix: Vec<u8>,
+    cb: PrefixCallback,
+}
+
+lazy_static! {
+    static ref SUBSCRIPTION_ID: AtomicU64 = AtomicU64::new(1);
+    // 所有键前缀订阅
+    static ref SUBSCRIPTIONS: RwLock<HashMap<u64, Subscription>> = RwLock::new(HashMap::new());
+    static ref SUBSCRIPTION_COUNT: AtomicUsize = AtomicUsize::new(0);
+}
+
+/**
+* 订阅表中键前缀的修改
+* @param tab 表名
+* @param prefix 键前缀，为空则订阅整个表
+* @param cb 回调，每次提交最多调用一次(不计重新投递)
+* @returns 返回订阅id，用于取消订阅
+*/
+pub fn subscribe(tab: &Atom, prefix: &[u8], cb: PrefixCallback) -> u64 {
+    let id = SUBSCRIPTION_ID.fetch_add(1, Ordering::SeqCst);
+    SUBSCRIPTIONS.write().unwrap().insert(id, Subscription {
+        tab: tab.clone(),
+        prefix: prefix.to_vec(),
+        cb,
+    });
+    SUBSCRIPTION_COUNT.fetch_add(1, Ordering::SeqCst);
+    id
+}
+
+// 取消订阅，已经开始的投递不受影响
+pub fn unsubscribe(id: u64) {
+    if SUBSCRIPTIONS.write().unwrap().remove(&id).is_some() {
+        SUBSCRIPTION_COUNT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// 是否有订阅
+pub fn has_subscribers() -> bool {
+    SUBSCRIPTION_COUNT.load(Ordering::SeqCst) > 0
+}
+
+/**
+* 提交成功后通知匹配的订阅，由写线程调用
+* @param events 提交中的所有修改
+*/
+pub fn notify(events: &[ChangeEvent]) {
+    if !has_subscribers() {
+        return;
+    }
+    for (id, s) in SUBSCRIPTIONS.read().unwrap().iter() {
+        let matched = events
+            .iter()
+            .filter(|e| e.tab == s.tab && e.key.starts_with(&s.prefix))
+            .cloned()
+            .collect::<Vec<ChangeEvent>>();
+        if !matched.is_empty() {
+            deliver(*id, s.cb.clone(), Arc::new(matched), 0);
+        }
+    }
+}
+
+// 异步投递，回调未确认时重新投递
+fn deliver(id: u64, cb: PrefixCallback, events: Arc<Vec<ChangeEvent>>, times: usize) {
+    let t = Box::new(move |_| {
+        if cb(events.clone()) {
+            return;
+        }
+        if times < MAX_REDELIVERY && SUBSCRIPTIONS.read().unwrap().contains_key(&id) {
+            deliver(id, cb.clone(), events.clone(), times + 1);
+        } else {
+            warn!("prefix subscription {:?} not acked, drop {:?} changes", id, events.len());
+        }
+    });
+    cast_store_task(TaskType::Async(false), 100, None, t, Atom::from("Lmdb prefix notify"));
+}
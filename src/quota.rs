@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::RwLock;
+
+use lmdb::{Database, Error, Transaction};
+use lmdb_sys as ffi;
+
+use atom::Atom;
+
+// 超出配额时写入返回的错误码
+pub const QUOTA_CODE: i32 = -30001;
+
+/**
+* 表的存储配额，任一上限为None表示不限制
+* 占用的字节数按表的页数计算，不包括分块存储的大值在分块表中占用的页
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Quota {
+    pub max_bytes: Option<u64>,     //表占用的最大字节数
+    pub max_entries: Option<u64>,   //表的最大记录数
+}
+
+/**
+* 表的存储使用情况
+*/
+#[derive(Debug, Clone)]
+pub struct TabUsage {
+    pub tab: Atom,              //表名
+    pub entries: u64,           //记录数
+    pub bytes: u64,             //表占用的字节数，包括分支页、叶子页和溢出页
+    pub quota: Option<Quota>,   //表的配额
+}
+
+lazy_static! {
+    // 表的配额，键为表名哈希
+    static ref QUOTAS: RwLock<HashMap<u64, Quota>> = RwLock::new(HashMap::new());
+}
+
+/**
+* 设置表的存储配额，为None则取消配额，对之后的写入生效，已超出配额的表只能删除
+*/
+pub fn set_quota(tab: &Atom, quota: Option<Quota>) {
+    set_hash_quota(tab.get_hash() as u64, quota);
+}
+
+// 按表名哈希设置配额，用于载入只保存了表名哈希的表策略
+pub(crate) fn set_hash_quota(tab: u64, quota: Option<Quota>) {
+    match quota {
+        Some(quota) => QUOTAS.write().unwrap().insert(tab, quota),
+        None => QUOTAS.write().unwrap().remove(&tab),
+    };
+}
+
+pub fn quota(tab: &Atom) -> Option<Quota> {
+    QUOTAS.read().unwrap().get(&(tab.get_hash() as u64)).cloned()
+}
+
+// 读取表在事务中的记录数和占用的字节数
+fn stat<T: Transaction>(txn: &T, db: Database) -> Result<(u64, u64), Error> {
+    let mut stat: ffi::MDB_stat = unsafe { mem::zeroed() };
+    match unsafe { ffi::mdb_stat(txn.txn(), db.dbi(), &mut stat) } {
+        ffi::MDB_SUCCESS => {
+            let pages = stat.ms_branch_pages + stat.ms_leaf_pages + stat.ms_overflow_pages;
+            Ok((stat.ms_entries as u64, (pages * stat.ms_psize as usize) as u64))
+        }
+        code => Err(Error::from_err_code(code)),
+    }
+}
+
+/**
+* 写入后检查表是否超出配额，在写入所在的读写事务中调用，超出时返回错误使整个事务回滚
+* @param txn 读写事务
+* @param db 表
+* @param tab 表名
+* @returns 超出配额返回Error::Other(QUOTA_CODE)
+*/
+pub fn check<T: Transaction>(txn: &T, db: Database, tab: &Atom) -> Result<(), Error> {
+    let quota = match quota(tab) {
+        Some(quota) => quota,
+        None => return Ok(()),
+    };
+    let (entries, bytes) = stat(txn, db)?;
+    if quota.max_entries.map_or(false, |max| entries > max) || quota.max_bytes.map_or(false, |max| bytes > max) {
+        warn!("tab: {:?} quota exceeded, entries: {}, bytes: {}, quota: {:?}", tab.to_string(), entries, bytes, quota);
+        return Err(Error::Other(QUOTA_CODE));
+    }
+    Ok(())
+}
+
+/**
+* 读取表的存储使用情况
+* @param txn 读事务
+* @param db 表
+* @param tab 表名
+*/
+pub fn usage<T: Transaction>(txn: &T, db: Database, tab: &Atom) -> Result<TabUsage, Error> {
+    let (entries, bytes) = stat(txn, db)?;
+    Ok(TabUsage {
+        tab: tab.clone(),
+        entries,
+        bytes,
+        quota: quota(tab),
+    })
+}
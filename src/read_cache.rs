@@ -0,0 +1,62 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use lru::LruCache;
+
+use pi_db::db::{Bin, TabKV};
+
+// 缓存键: 环境id、表名哈希、键
+type CacheKey = (u64, u64, Bin);
+
+lazy_static! {
+    // 热点键的读缓存，为None时不启用
+    static ref READ_CACHE: Mutex<Option<LruCache<CacheKey, Bin>>> = Mutex::new(None);
+    // 缓存版本，每次失效时递增，用于丢弃失效前读到的旧值
+    static ref CACHE_EPOCH: AtomicU64 = AtomicU64::new(0);
+}
+
+/**
+* 设置LMDB读缓存的容量，缓存在查询时先于txn.get查找，提交修改时失效
+* 只用于单次查询的只读事务，读写事务和长期持有的读事务直接查询LMDB
+* @param capacity 最多缓存的键数量，为0则关闭缓存
+*/
+pub fn set_read_cache(capacity: usize) {
+    *READ_CACHE.lock().unwrap() = if capacity == 0 {
+        None
+    } else {
+        Some(LruCache::new(capacity))
+    };
+    CACHE_EPOCH.fetch_add(1, Ordering::SeqCst);
+}
+
+// 当前缓存版本，必须在开始读事务之前获取
+pub fn epoch() -> u64 {
+    CACHE_EPOCH.load(Ordering::SeqCst)
+}
+
+// 查找缓存的值
+pub fn get(env_id: u64, tab: u64, key: &Bin) -> Option<Bin> {
+    match READ_CACHE.lock().unwrap().as_mut() {
+        Some(cache) => cache.get(&(env_id, tab, key.clone())).cloned(),
+        None => None,
+    }
+}
+
+// 缓存从LMDB读到的值，读取期间缓存已失效则丢弃
+pub fn put(epoch: u64, env_id: u64, tab: u64, key: &Bin, value: &Bin) {
+    if let Some(cache) = READ_CACHE.lock().unwrap().as_mut() {
+        if CACHE_EPOCH.load(Ordering::SeqCst) == epoch {
+            cache.put((env_id, tab, key.clone()), value.clone());
+        }
+    }
+}
+
+// 提交修改后使修改的键失效
+pub fn invalidate(env_id: u64, modifies: &[TabKV]) {
+    if let Some(cache) = READ_CACHE.lock().unwrap().as_mut() {
+        CACHE_EPOCH.fetch_add(1, Ordering::SeqCst);
+        for m in modifies.iter() {
+            cache.pop(&(env_id, m.tab.get_hash() as u64, m.key.clone()));
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_void};
+use std::process;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lmdb::{Environment, Error};
+use lmdb_sys as ffi;
+
+// 默认清理失效读槽的间隔(毫秒)
+pub const DEFAULT_READER_CHECK_INTERVAL: u64 = 60 * 1000;
+
+lazy_static! {
+    // 各读槽第一次被观察到的时间，键为(环境id, 进程id, 线程id, 读事务id)
+    static ref FIRST_SEEN: Mutex<HashMap<(u64, u32, u64, u64), Instant>> = Mutex::new(HashMap::new());
+    // 长时间读事务的告警策略，为None则不检查
+    static ref LONG_READER_POLICY: RwLock<Option<LongReaderPolicy>> = RwLock::new(None);
+    // 本进程中登记的读事务，键为(环境id, 读事务id)
+    static ref TRACKED: Mutex<HashMap<(u64, u64), TrackedRead>> = Mutex::new(HashMap::new());
+    // 各环境最近一次检查发现的长时间读事务
+    static ref LONG_READERS: Mutex<HashMap<u64, Vec<LongReader>>> = Mutex::new(HashMap::new());
+}
+
+/**
+* 环境中的读槽
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReaderSlot {
+    pub pid: u32,               //持有读槽的进程id
+    pub thread: u64,            //持有读槽的线程id
+    pub txnid: Option<u64>,     //读事务快照的事务id，读槽空闲时为None
+    pub lag: u64,               //读事务落后于最新写事务的事务数，落后越多越会阻止空闲页回收
+    pub age: Duration,          //读事务已打开的时间，从第一次被观察到开始计算，是实际时间的下限
+}
+
+/**
+* 长时间读事务的告警策略，由清理失效读槽的线程按清理间隔检查，间隔应小于max_age
+* 旧的读事务会阻止空闲页回收，使数据文件不断增大
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct LongReaderPolicy {
+    pub max_age: Duration,      //读事务打开超过该时间时告警
+    pub force_abort: bool,      //是否通知本进程中登记的超时读事务中止，其它进程的读事务只告警
+}
+
+/**
+* 超过告警时间的读事务
+*/
+#[derive(Debug, Clone)]
+pub struct LongReader {
+    pub slot: ReaderSlot,           //读槽，包括持有者的进程id和线程id
+    pub holder: Option<String>,     //本进程中登记的持有者，格式为操作名@线程名，未登记为None
+    pub aborted: bool,              //是否已通知持有者中止
+}
+
+// 本进程中登记的读事务
+struct TrackedRead {
+    op: &'static str,
+    thread: String,
+    abort: Arc<AtomicBool>,
+}
+
+/**
+* 登记的读事务，长时间读取的操作在每批读取之间检查是否被要求中止，释放时取消登记
+*/
+pub struct ReadGuard {
+    key: (u64, u64),
+    abort: Arc<AtomicBool>,
+}
+
+impl ReadGuard {
+    // 是否被长时间读事务策略要求中止，为true时应尽快结束读事务
+    pub fn is_aborted(&self) -> bool {
+        self.abort.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for ReadGuard {
+    fn drop(&mut self) {
+        TRACKED.lock().unwrap().remove(&self.key);
+    }
+}
+
+/**
+* 设置长时间读事务的告警策略，为None则关闭
+*/
+pub fn set_long_reader_policy(policy: Option<LongReaderPolicy>) {
+    *LONG_READER_POLICY.write().unwrap() = policy;
+}
+
+/**
+* 登记本进程中可能长时间打开的读事务，告警时报告持有者
+* @param env_id 环境id
+* @param txnid 读事务id
+* @param op 操作名
+* @returns 返回登记的读事务，读事务结束前不能释放
+*/
+pub fn track(env_id: u64, txnid: u64, op: &'static str) -> ReadGuard {
+    let abort = Arc::new(AtomicBool::new(false));
+    let thread = thread::current().name().unwrap_or("unnamed").to_string();
+    TRACKED.lock().unwrap().insert((env_id, txnid), TrackedRead { op, thread, abort: abort.clone() });
+    ReadGuard { key: (env_id, txnid), abort }
+}
+
+/**
+* 获取环境最近一次检查发现的长时间读事务
+*/
+pub fn long_readers(env_id: u64) -> Vec<LongReader> {
+    LONG_READERS.lock().unwrap().get(&env_id).cloned().unwrap_or_default()
+}
+
+/**
+* 检查环境中超过告警时间的读事务，报告持有者和已打开的时间，按策略通知本进程中的持有者中止
+* @param env_id 环境id
+* @param env LMDB环境
+* @param policy 告警策略
+* @returns 返回超过告警时间的读事务
+*/
+pub fn check_long_readers(env_id: u64, env: &Environment, policy: &LongReaderPolicy) -> Result<Vec<LongReader>, Error> {
+    let pid = process::id();
+    let tracked = TRACKED.lock().unwrap();
+    let long = reader_list(env_id, env)?.into_iter()
+        .filter(|slot| slot.txnid.is_some() && slot.age >= policy.max_age)
+        .map(|slot| {
+            let read = if slot.pid == pid { tracked.get(&(env_id, slot.txnid.unwrap())) } else { None };
+            let aborted = match read {
+                Some(read) if policy.force_abort => {
+                    read.abort.store(true, Ordering::SeqCst);
+                    true
+                }
+                _ => false,
+            };
+            let holder = read.map(|read| format!("{}@{}", read.op, read.thread));
+            warn!("long lived lmdb read txn, env: {:?}, pid: {}, thread: {:x}, holder: {:?}, txnid: {:?}, lag: {}, age: {:?}, aborted: {}",
+                env_id, slot.pid, slot.thread, holder, slot.txnid, slot.lag, slot.age, aborted);
+            LongReader { slot, holder, aborted }
+        })
+        .collect::<Vec<LongReader>>();
+    LONG_READERS.lock().unwrap().insert(env_id, long.clone());
+    Ok(long)
+}
+
+/**
+* 清理已崩溃进程遗留的读槽，这些读槽持有的旧快照会阻止空闲页回收
+* @param env LMDB环境
+* @returns 返回清理的读槽数量
+*/
+pub fn reader_check(env: &Environment) -> Result<usize, Error> {
+    let mut dead: c_int = 0;
+    match unsafe { ffi::mdb_reader_check(env.env(), &mut dead) } {
+        ffi::MDB_SUCCESS => Ok(dead as usize),
+        code => Err(Error::from_err_code(code)),
+    }
+}
+
+/**
+* 列出环境中所有的读槽
+* @param env_id 环境id
+* @param env LMDB环境
+* @returns 返回读槽列表，包括空闲的读槽
+*/
+pub fn reader_list(env_id: u64, env: &Environment) -> Result<Vec<ReaderSlot>, Error> {
+    let mut info: ffi::MDB_envinfo = unsafe { mem::zeroed() };
+    match unsafe { ffi::mdb_env_info(env.env(), &mut info) } {
+        ffi::MDB_SUCCESS => (),
+        code => return Err(Error::from_err_code(code)),
+    }
+    let last_txnid = info.me_last_txnid as u64;
+
+    let mut lines = String::new();
+    match unsafe { ffi::mdb_reader_list(env.env(), Some(collect_line), &mut lines as *mut String as *mut c_void) } {
+        code if code >= 0 => (),
+        code => return Err(Error::from_err_code(code)),
+    }
+
+    let now = Instant::now();
+    let mut first_seen = FIRST_SEEN.lock().unwrap();
+    let mut seen = Vec::new();
+    let slots = lines.lines().filter_map(parse_line).map(|(pid, thread, txnid)| {
+        let age = match txnid {
+            Some(id) => {
+                let key = (env_id, pid, thread, id);
+                seen.push(key);
+                now.duration_since(*first_seen.entry(key).or_insert(now))
+            }
+            None => Duration::from_secs(0),
+        };
+        ReaderSlot {
+            pid,
+            thread,
+            txnid,
+            lag: txnid.map_or(0, |id| last_txnid.saturating_sub(id)),
+            age,
+        }
+    }).collect::<Vec<ReaderSlot>>();
+    // 已结束的读事务不再记录
+    first_seen.retain(|key, _| key.0 != env_id || seen.contains(key));
+
+    Ok(slots)
+}
+
+// mdb_reader_list的输出回调，每次输出一行
+unsafe extern "C" fn collect_line(msg: *const c_char, ctx: *mut c_void) -> c_int {
+    let lines = &mut *(ctx as *mut String);
+    lines.push_str(&CStr::from_ptr(msg).to_string_lossy());
+    0
+}
+
+// 解析读槽行，格式为: 进程id 线程id(十六进制) 事务id，空闲读槽的事务id为-
+fn parse_line(line: &str) -> Option<(u32, u64, Option<u64>)> {
+    let mut fields = line.split_whitespace();
+    let pid = fields.next()?.parse::<u32>().ok()?;
+    let thread = u64::from_str_radix(fields.next()?, 16).ok()?;
+    let txnid = match fields.next()? {
+        "-" => None,
+        id => Some(id.parse::<u64>().ok()?),
+    };
+    Some((pid, thread, txnid))
+}
+
+/**
+* 启动定期清理失效读槽的线程，设置了长时间读事务策略时同时检查长时间读事务，环境关闭后线程退出
+* @param env_id 环境id
+* @param env LMDB环境
+* @param interval 清理间隔
+*/
+pub fn spawn_checker(env_id: u64, env: Arc<Environment>, interval: Duration) {
+    let _ = thread::Builder::new().name("pi_store-reader-check".to_string()).spawn(move || loop {
+        thread::sleep(interval);
+        // 只剩本线程持有环境，说明环境已关闭
+        if Arc::strong_count(&env) == 1 {
+            break;
+        }
+        match reader_check(&env) {
+            Ok(0) => (),
+            Ok(dead) => warn!("cleared {:?} stale lmdb reader slots, env: {:?}", dead, env_id),
+            Err(e) => warn!("lmdb reader check failed, env: {:?}, err: {:?}", env_id, e),
+        }
+        let policy = *LONG_READER_POLICY.read().unwrap();
+        if let Some(policy) = policy {
+            if let Err(e) = check_long_readers(env_id, &env, &policy) {
+                warn!("lmdb long reader check failed, env: {:?}, err: {:?}", env_id, e);
+            }
+        }
+    });
+}
@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use lmdb::{Environment, Error, Transaction};
+use lmdb_sys as ffi;
+
+use atom::Atom;
+
+use crate::changelog;
+use crate::checksum;
+use crate::chunk;
+use crate::pool::lookup_db;
+
+lazy_static! {
+    // 打开库时是否执行恢复校验
+    static ref VERIFY_ON_OPEN: AtomicBool = AtomicBool::new(false);
+}
+
+/**
+* 设置打开库时是否执行恢复校验，校验在工作线程启动前完成，校验失败时库打开失败
+* 校验需要遍历最近一次提交的日志，库较大或日志较多时会延长启动时间
+*/
+pub fn set_verify_on_open(enabled: bool) {
+    VERIFY_ON_OPEN.store(enabled, Ordering::SeqCst);
+}
+
+pub fn verify_on_open() -> bool {
+    VERIFY_ON_OPEN.load(Ordering::SeqCst)
+}
+
+/**
+* 恢复校验的结果
+*/
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryReport {
+    pub tabs: usize,        //校验的表数量
+    pub last_seq: u64,      //元信息中最近一次提交的序号
+    pub log_seq: u64,       //修改日志中最大的提交序号
+    pub replayed: usize,    //按日志重放的修改数量
+    pub truncated: usize,   //丢弃的没有对应提交的日志数量
+}
+
+/**
+* 恢复校验，在单个读写事务中完成，只能在工作线程启动前调用
+* 逐个打开已知的表并读取统计信息，确认表可以访问
+* 比较元信息中的提交序号和修改日志中的序号，丢弃序号之后的日志，并按日志重放最近一次提交中与表中不一致的修改
+* @param env LMDB环境
+* @param env_id 环境id
+* @param tabs 已知的表
+* @returns 返回校验结果，表无法访问时返回错误
+*/
+pub fn verify(env: &Environment, env_id: u64, tabs: &[Atom]) -> Result<RecoveryReport, String> {
+    let mut report = RecoveryReport::default();
+    let mut txn = env.begin_rw_txn().map_err(|e| e.to_string())?;
+
+    for tab in tabs {
+        let db = lookup_db(env_id, tab).ok_or_else(|| format!("recovery: tab not opened: {:?}", tab.to_string()))?;
+        let mut stat: ffi::MDB_stat = unsafe { std::mem::zeroed() };
+        match unsafe { ffi::mdb_stat(txn.txn(), db.dbi(), &mut stat) } {
+            ffi::MDB_SUCCESS => report.tabs += 1,
+            code => return Err(format!("recovery: tab: {:?} unreadable: {}", tab.to_string(), Error::from_err_code(code))),
+        }
+    }
+
+    report.last_seq = changelog::last_seq(&txn, env_id).map_err(|e| e.to_string())?;
+    report.log_seq = changelog::last_logged_seq(&txn, env_id).map_err(|e| e.to_string())?;
+    if report.log_seq > report.last_seq {
+        report.truncated = changelog::truncate_after(&mut txn, env_id, report.last_seq).map_err(|e| e.to_string())?;
+        warn!("recovery: env: {:?} dropped {} log entries after seq: {}", env_id, report.truncated, report.last_seq);
+    } else if report.log_seq < report.last_seq && changelog::is_enabled() && report.log_seq > 0 {
+        // 日志已被备份后裁剪或日志曾被关闭，缺失的提交无法重放
+        warn!("recovery: env: {:?} log ends at seq: {}, last commit seq: {}", env_id, report.log_seq, report.last_seq);
+    }
+
+    if report.last_seq > 0 && report.log_seq >= report.last_seq {
+        let changes = changelog::changes(&txn, env_id, report.last_seq - 1, report.last_seq).map_err(|e| e.to_string())?;
+        for change in changes {
+            let db = match lookup_db(env_id, &change.tab) {
+                Some(db) => db,
+                // 表已被删除
+                None => continue,
+            };
+            let current = match (&txn).get(db, &change.key.as_slice()) {
+                Ok(v) => Some(chunk::read(&txn, env_id, &change.tab, &change.key, v).map_err(|e| e.to_string())?),
+                Err(Error::NotFound) => None,
+                Err(e) => return Err(e.to_string()),
+            };
+            if current.as_ref().map(|v| v.as_slice()) == change.value.as_ref().map(|v| v.as_slice()) {
+                continue;
+            }
+            let r = match &change.value {
+                Some(value) => chunk::put(&mut txn, env_id, db, &change.tab, &change.key, value)
+                    .and_then(|_| checksum::put(&mut txn, env_id, &change.tab, &change.key, value)),
+                None => chunk::del(&mut txn, env_id, db, &change.tab, &change.key)
+                    .and_then(|_| checksum::del(&mut txn, env_id, &change.tab, &change.key)),
+            };
+            r.map_err(|e| e.to_string())?;
+            report.replayed += 1;
+        }
+    }
+
+    txn.commit().map_err(|e| e.to_string())?;
+    info!("recovery: env: {:?} verified, {:?}", env_id, report);
+    Ok(report)
+}
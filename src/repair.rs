@@ -0,0 +1,205 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use lmdb::{Cursor, Database, DatabaseFlags, Environment, EnvironmentFlags, Error, RoTransaction, Transaction, WriteFlags};
+
+use pi_db::db::Bin;
+
+// 游标操作，与lmdb_sys中的定义相同
+const MDB_FIRST: u32 = 0;
+const MDB_LAST: u32 = 6;
+const MDB_NEXT: u32 = 8;
+const MDB_PREV: u32 = 12;
+// 修复时最多打开的表数量
+const MAX_DBS: u32 = 1024;
+// 写入新环境时每个事务写入的记录数
+const REPAIR_BATCH: usize = 10000;
+
+/**
+* 单个表的修复结果
+*/
+#[derive(Debug, Clone)]
+pub struct TableRepair {
+    pub tab: String,                                //表名
+    pub salvaged: usize,                            //抢救出的记录数
+    pub lost: Option<(Option<Bin>, Option<Bin>)>,   //丢失的键范围，不包括两端，None表示没有丢失
+    pub error: Option<String>,                      //读取时遇到的错误
+}
+
+/**
+* 修复报告
+*/
+#[derive(Debug, Clone, Default)]
+pub struct RepairReport {
+    pub tables: Vec<TableRepair>,           //已抢救的表
+    pub unreadable: Vec<(String, String)>,  //无法打开的表和原因
+}
+
+impl RepairReport {
+    // 是否完整抢救了所有数据
+    pub fn is_complete(&self) -> bool {
+        self.unreadable.is_empty() && self.tables.iter().all(|t| t.lost.is_none())
+    }
+}
+
+// 写入新环境，每写入一批提交一次，避免单个事务过大
+struct Writer<'a> {
+    env: &'a Environment,
+    db: Database,
+    pending: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl<'a> Writer<'a> {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), String> {
+        self.pending.push((key.to_vec(), value.to_vec()));
+        if self.pending.len() >= REPAIR_BATCH {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        let mut txn = self.env.begin_rw_txn().map_err(|e| e.to_string())?;
+        for (k, v) in self.pending.drain(..) {
+            txn.put(self.db, &k, &v, WriteFlags::empty()).map_err(|e| e.to_string())?;
+        }
+        txn.commit().map_err(|e| e.to_string())
+    }
+}
+
+// 列出主库中的所有表名，主库损坏时返回已读到的表名和错误
+fn table_names(txn: &RoTransaction, main: Database) -> (Vec<String>, Option<String>) {
+    let mut names = Vec::new();
+    let mut cursor = match txn.open_ro_cursor(main) {
+        Ok(cursor) => cursor,
+        Err(e) => return (names, Some(e.to_string())),
+    };
+    let mut op = MDB_FIRST;
+    loop {
+        match cursor.get(None, None, op) {
+            Ok((Some(k), _)) => names.push(String::from_utf8_lossy(k).to_string()),
+            Ok((None, _)) | Err(Error::NotFound) => return (names, None),
+            Err(e) => return (names, Some(e.to_string())),
+        }
+        op = MDB_NEXT;
+    }
+}
+
+/**
+* 抢救一个表，先从头向后读，遇到损坏的页后再从尾向前读，直到与已读到的键相遇或再次遇到损坏
+* 两次读取之间的键范围视为丢失
+*/
+fn salvage_table(txn: &RoTransaction, db: Database, tab: &str, writer: &mut Writer) -> Result<TableRepair, String> {
+    let mut cursor = txn.open_ro_cursor(db).map_err(|e| e.to_string())?;
+    let mut repair = TableRepair {
+        tab: tab.to_string(),
+        salvaged: 0,
+        lost: None,
+        error: None,
+    };
+
+    let mut last: Option<Bin> = None;
+    let mut op = MDB_FIRST;
+    loop {
+        match cursor.get(None, None, op) {
+            Ok((Some(k), v)) => {
+                writer.put(k, v)?;
+                repair.salvaged += 1;
+                last = Some(Arc::new(k.to_vec()));
+            }
+            Ok((None, _)) | Err(Error::NotFound) => return Ok(repair),
+            Err(e) => {
+                repair.error = Some(e.to_string());
+                break;
+            }
+        }
+        op = MDB_NEXT;
+    }
+
+    let mut first: Option<Bin> = None;
+    let mut op = MDB_LAST;
+    loop {
+        match cursor.get(None, None, op) {
+            Ok((Some(k), v)) => {
+                if last.as_ref().map_or(false, |l| k <= l.as_slice()) {
+                    break;
+                }
+                writer.put(k, v)?;
+                repair.salvaged += 1;
+                first = Some(Arc::new(k.to_vec()));
+            }
+            Ok((None, _)) | Err(Error::NotFound) => break,
+            Err(e) => {
+                warn!("repair tab: {:?} read backward failed: {:?}", tab, e.to_string());
+                break;
+            }
+        }
+        op = MDB_PREV;
+    }
+    repair.lost = Some((last, first));
+    Ok(repair)
+}
+
+/**
+* 从损坏的环境中抢救所有可读的记录到新环境，跳过无法读取的页并报告丢失的范围
+* 修复期间损坏的环境不能被其它进程打开，抢救出的值按原样复制，包括分块和内部表
+* @param path 损坏的环境所在的目录
+* @param dest 新环境所在的目录，不存在则创建，必须为空
+* @param map_size 新环境的内存映射大小，不能小于损坏的环境
+* @returns 返回修复报告
+*/
+pub fn repair(path: &str, dest: &str, map_size: usize) -> Result<RepairReport, String> {
+    let src = Environment::new()
+        .set_max_dbs(MAX_DBS)
+        .set_flags(EnvironmentFlags::READ_ONLY | EnvironmentFlags::NO_LOCK | EnvironmentFlags::NO_TLS)
+        .open(Path::new(path))
+        .map_err(|e| e.to_string())?;
+    fs::create_dir_all(dest).map_err(|e| e.to_string())?;
+    let env = Environment::new()
+        .set_max_dbs(MAX_DBS)
+        .set_map_size(map_size)
+        .set_flags(EnvironmentFlags::NO_TLS)
+        .open(Path::new(dest))
+        .map_err(|e| e.to_string())?;
+
+    let mut report = RepairReport::default();
+    let main = src.open_db(None).map_err(|e| e.to_string())?;
+    let txn = src.begin_ro_txn().map_err(|e| e.to_string())?;
+    let (names, err) = table_names(&txn, main);
+    let _ = txn.commit();
+    if let Some(e) = err {
+        report.unreadable.push((String::new(), format!("main db: {}", e)));
+    }
+
+    // 表必须在读事务开始前打开，之后开始的读事务才能使用
+    let mut dbs = Vec::with_capacity(names.len());
+    for name in names {
+        match src.open_db(Some(&name)) {
+            Ok(db) => dbs.push((name, db)),
+            Err(e) => report.unreadable.push((name, e.to_string())),
+        }
+    }
+
+    let txn = src.begin_ro_txn().map_err(|e| e.to_string())?;
+    for (name, db) in dbs {
+        let mut writer = Writer {
+            env: &env,
+            db: env.create_db(Some(&name), DatabaseFlags::empty()).map_err(|e| e.to_string())?,
+            pending: Vec::new(),
+        };
+        match salvage_table(&txn, db, &name, &mut writer).and_then(|r| writer.flush().map(|_| r)) {
+            Ok(r) => {
+                if r.lost.is_some() {
+                    warn!("repair tab: {:?} lost keys in {:?}", name, r.lost);
+                }
+                report.tables.push(r);
+            }
+            Err(e) => report.unreadable.push((name, e)),
+        }
+    }
+    let _ = txn.commit();
+    env.sync(true).map_err(|e| e.to_string())?;
+
+    Ok(report)
+}
@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use lmdb::{Cursor, Environment, EnvironmentFlags, Transaction};
+
+use pi_db::db::{Bin, TabKV};
+
+use atom::Atom;
+
+use crate::changelog::{self, Change};
+use crate::chunk::{self, CHUNKS_TAB};
+use crate::pool::{register_db, unregister_db};
+use crate::schema::META_TAB;
+use crate::snapshot;
+use crate::tombstone;
+
+/**
+* 恢复的时间点
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestorePoint {
+    Seq(u64),   //恢复到该提交序号之后的状态
+    Time(u64),  //恢复到该时间(毫秒)之前最后一次提交之后的状态
+}
+
+/**
+* 用全量备份加修改日志重建表在历史时间点的内容
+* 全量备份是compact生成的副本，日志必须从备份的提交序号之后连续记录
+* @param env 库所在的环境
+* @param ware 库名
+* @param tab 表名
+* @param point 恢复的时间点
+* @param base 全量备份所在的目录或快照文件，为None则从空表开始重放全部日志
+* @returns 返回表在该时间点的所有记录，按键排序
+*/
+pub fn restore_to(env: &Environment, ware: &Atom, tab: &Atom, point: RestorePoint, base: Option<&str>, max_dbs: u32) -> Result<Vec<TabKV>, String> {
+    let (base_seq, mut rows) = match base {
+        Some(path) => load_base(path, tab, max_dbs)?,
+        None => (0, BTreeMap::new()),
+    };
+    if let RestorePoint::Seq(seq) = point {
+        if seq < base_seq {
+            return Err(format!("restore point {:?} before base snapshot seq {:?}", seq, base_seq));
+        }
+    }
+
+    let env_id = ware.get_hash() as u64;
+    let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+    let last = changelog::last_seq(&txn, env_id).map_err(|e| e.to_string())?;
+    let until = match point {
+        RestorePoint::Seq(seq) => seq.min(last),
+        RestorePoint::Time(_) => last,
+    };
+    let changes = changelog::changes(&txn, env_id, base_seq, until).map_err(|e| e.to_string())?;
+    let _ = txn.commit();
+
+    let mut expect = base_seq + 1;
+    for (seq, commit) in group_by_seq(changes) {
+        // 提交序号不连续说明日志已被清理或记录时未开启
+        if seq != expect {
+            return Err(format!("change log incomplete, missing seq {:?}", expect));
+        }
+        expect = seq + 1;
+        if let RestorePoint::Time(t) = point {
+            if commit.first().map_or(false, |c| c.time > t) {
+                break;
+            }
+        }
+        for c in commit.into_iter().filter(|c| &c.tab == tab) {
+            rows.insert(c.key.to_vec(), c.value);
+        }
+    }
+    if let RestorePoint::Seq(_) = point {
+        if expect <= until {
+            return Err(format!("change log incomplete, missing seq {:?}", expect));
+        }
+    }
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(k, v)| v.map(|v| TabKV {
+            ware: ware.clone(),
+            tab: tab.clone(),
+            key: Arc::new(k),
+            index: 0,
+            value: Some(v),
+        }))
+        .collect())
+}
+
+// 按提交序号分组，日志已按序号排序
+fn group_by_seq(changes: Vec<Change>) -> Vec<(u64, Vec<Change>)> {
+    let mut groups: Vec<(u64, Vec<Change>)> = Vec::new();
+    for c in changes {
+        match groups.last_mut() {
+            Some((seq, commit)) if *seq == c.seq => commit.push(c),
+            _ => groups.push((c.seq, vec![c])),
+        }
+    }
+    groups
+}
+
+// 读取全量备份中表的所有记录和备份的提交序号，备份为文件时按快照文件读取
+fn load_base(path: &str, tab: &Atom, max_dbs: u32) -> Result<(u64, BTreeMap<Vec<u8>, Option<Bin>>), String> {
+    if Path::new(path).is_file() {
+        return load_snapshot(path);
+    }
+    let env = Environment::new()
+        .set_max_dbs(max_dbs)
+        .set_flags(EnvironmentFlags::READ_ONLY | EnvironmentFlags::NO_TLS)
+        .open(Path::new(path))
+        .map_err(|e| e.to_string())?;
+    let db = env.open_db(Some(&tab.to_string())).map_err(|e| e.to_string())?;
+    let meta = env.open_db(Some(META_TAB)).map_err(|e| e.to_string())?;
+
+    // 备份的表以备份路径为环境id临时注册，以便读取分块存储的值
+    let base_id = Atom::from(path).get_hash() as u64;
+    let mut registered = vec![Atom::from(META_TAB)];
+    register_db(base_id, &registered[0], meta)?;
+    if let Ok(chunks) = env.open_db(Some(CHUNKS_TAB)) {
+        let chunks_tab = Atom::from(CHUNKS_TAB);
+        if let Err(e) = register_db(base_id, &chunks_tab, chunks) {
+            unregister_db(base_id, registered[0].get_hash() as u64);
+            return Err(e);
+        }
+        registered.push(chunks_tab);
+    }
+
+    let r = (|| {
+        let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+        let seq = changelog::last_seq(&txn, base_id).map_err(|e| e.to_string())?;
+        let mut rows = BTreeMap::new();
+        {
+            let mut cursor = txn.open_ro_cursor(db).map_err(|e| e.to_string())?;
+            for (k, v) in cursor.iter_start() {
+                // 备份中软删除的键视为不存在
+                if tombstone::is_deleted(tab, v) {
+                    continue;
+                }
+                let v = chunk::read(&txn, base_id, tab, k, v).map_err(|e| e.to_string())?;
+                rows.insert(k.to_vec(), Some(Arc::new(v)));
+            }
+        }
+        let _ = txn.commit();
+        Ok((seq, rows))
+    })();
+
+    for name in registered.iter() {
+        unregister_db(base_id, name.get_hash() as u64);
+    }
+    r
+}
+
+// 读取write_snapshot生成的快照文件
+fn load_snapshot(path: &str) -> Result<(u64, BTreeMap<Vec<u8>, Option<Bin>>), String> {
+    let mut input = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let mut rows = BTreeMap::new();
+    let header = snapshot::read_snapshot(&mut input, |k, v| {
+        rows.insert(k.to_vec(), Some(v));
+        Ok(())
+    })?;
+    if header.version < 2 {
+        return Err(format!("snapshot: {:?} has no change log seq, can not be used as restore base", path));
+    }
+    Ok((header.seq, rows))
+}
@@ -0,0 +1,91 @@
+use std::io;
+use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
+
+use lmdb::{Environment, Error};
+use lmdb_sys as ffi;
+
+/**
+* 工作线程遇到临时性错误时的重试策略，每次重试前等待的时间加倍，直到最大等待时间
+* 重试次数用完后将最后一次的错误返回给回调
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,       //最大重试次数，0表示不重试
+    pub backoff: Duration,      //第一次重试前的等待时间
+    pub max_backoff: Duration,  //每次重试前的最大等待时间
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+lazy_static! {
+    // 所有环境共用的重试策略
+    static ref RETRY_POLICY: RwLock<RetryPolicy> = RwLock::new(RetryPolicy::default());
+}
+
+/**
+* 设置工作线程的重试策略，对之后开始的操作生效
+*/
+pub fn set_retry_policy(policy: RetryPolicy) {
+    *RETRY_POLICY.write().unwrap() = policy;
+}
+
+pub fn retry_policy() -> RetryPolicy {
+    *RETRY_POLICY.read().unwrap()
+}
+
+/**
+* 是否为临时性错误，重新执行操作可能成功
+* MDB_MAP_RESIZED为其它进程扩大了数据库文件，MDB_READERS_FULL为读槽暂时用完，被信号中断或资源暂时不可用的系统错误
+*/
+pub fn is_transient(e: &Error) -> bool {
+    match e {
+        Error::MapResized | Error::ReadersFull => true,
+        Error::Other(code) if *code > 0 => match io::Error::from_raw_os_error(*code).kind() {
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/**
+* 执行操作，遇到临时性错误时按重试策略重新执行，操作必须可以重复执行，失败时不能留下部分修改
+* @param env 操作的LMDB环境，MDB_MAP_RESIZED时用于采用新的文件大小
+* @param op 操作名，用于日志
+* @param f 操作
+* @returns 返回操作的结果，重试次数用完时返回最后一次的错误
+*/
+pub fn retry<T, F>(env: &Environment, op: &str, mut f: F) -> Result<T, Error>
+    where F: FnMut() -> Result<T, Error> {
+    let policy = retry_policy();
+    let mut backoff = policy.backoff;
+    let mut retries = 0;
+    loop {
+        match f() {
+            Err(ref e) if retries < policy.max_retries && is_transient(e) => {
+                retries += 1;
+                warn!("lmdb {} failed with transient error: {:?}, retry: {}/{}", op, e, retries, policy.max_retries);
+                if let Error::MapResized = e {
+                    // 大小为0时采用其它进程设置的文件大小
+                    let code = unsafe { ffi::mdb_env_set_mapsize(env.env(), 0) };
+                    if code != ffi::MDB_SUCCESS {
+                        return Err(Error::from_err_code(code));
+                    }
+                }
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(policy.max_backoff);
+            }
+            r => return r,
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use rocksdb::{Direction, IteratorMode, Options, WriteBatch, DB as RocksDB};
+
+use pi_db::db::{Bin, TabKV};
+
+use atom::Atom;
+
+use crate::backend::Backend;
+
+/**
+* RocksDB存储后端，适合写密集的场景
+* 所有表共享一个默认列族，键为 表名哈希(8字节大端) + 原始键，保证同一个表的键连续有序
+*/
+#[derive(Clone)]
+pub struct RocksStore(Arc<RocksDB>);
+
+impl RocksStore {
+    /**
+    * 打开RocksDB存储
+    * @param path 数据库目录，不存在则创建
+    * @returns 返回RocksDB存储，失败返回原因描述
+    */
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        RocksDB::open(&opts, path)
+            .map(|db| RocksStore(Arc::new(db)))
+            .map_err(|e| e.to_string())
+    }
+}
+
+// 表的键前缀
+fn tab_prefix(tab: &Atom) -> Vec<u8> {
+    (tab.get_hash() as u64).to_be_bytes().to_vec()
+}
+
+// 表中键在RocksDB中的实际键
+fn tab_key(prefix: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut k = Vec::with_capacity(prefix.len() + key.len());
+    k.extend_from_slice(prefix);
+    k.extend_from_slice(key);
+    k
+}
+
+// 去掉键前缀，不属于该表返回None
+fn strip_prefix(prefix: &[u8], key: &[u8]) -> Option<Bin> {
+    if key.starts_with(prefix) {
+        Some(Arc::new(key[prefix.len()..].to_vec()))
+    } else {
+        None
+    }
+}
+
+impl RocksStore {
+    // 表的第一个键
+    fn first(&self, prefix: &[u8]) -> Option<Bin> {
+        self.0
+            .iterator(IteratorMode::From(prefix, Direction::Forward))
+            .next()
+            .and_then(|(k, _)| strip_prefix(prefix, &k))
+    }
+
+    // 大于等于指定键的第一个键
+    fn seek(&self, prefix: &[u8], key: &[u8]) -> Option<Bin> {
+        self.0
+            .iterator(IteratorMode::From(&tab_key(prefix, key), Direction::Forward))
+            .next()
+            .and_then(|(k, _)| strip_prefix(prefix, &k))
+    }
+
+    // 表的最后一个键
+    fn last(&self, prefix: &[u8]) -> Option<Bin> {
+        let mut iter = match u64::from_be_bytes(Self::prefix_array(prefix)).checked_add(1) {
+            Some(next) => self.0.iterator(IteratorMode::From(&next.to_be_bytes(), Direction::Reverse)),
+            None => self.0.iterator(IteratorMode::End),
+        };
+        iter.find(|(k, _)| k.as_ref() < prefix || k.starts_with(prefix))
+            .and_then(|(k, _)| strip_prefix(prefix, &k))
+    }
+
+    fn prefix_array(prefix: &[u8]) -> [u8; 8] {
+        let mut a = [0u8; 8];
+        a.copy_from_slice(&prefix[0..8]);
+        a
+    }
+}
+
+impl Backend for RocksStore {
+    fn name(&self) -> &'static str {
+        "Rocks"
+    }
+
+    fn query(&self, queries: &[TabKV]) -> Result<Vec<TabKV>, String> {
+        let mut qr = Vec::with_capacity(queries.len());
+        for q in queries.iter() {
+            let value = self.0
+                .get(tab_key(&tab_prefix(&q.tab), &q.key))
+                .map_err(|e| format!("rocksdb query internal error: {:?}", e.to_string()))?;
+            qr.push(TabKV {
+                ware: q.ware.clone(),
+                tab: q.tab.clone(),
+                key: q.key.clone(),
+                index: q.index,
+                value: value.map(Arc::new),
+            });
+        }
+        Ok(qr)
+    }
+
+    fn first_key(&self, tab: &Atom, descending: bool, start_key: Option<Bin>) -> Option<Bin> {
+        let prefix = tab_prefix(tab);
+        match (descending, start_key) {
+            (true, None) => self.first(&prefix),
+            (true, Some(sk)) => self.seek(&prefix, &sk),
+            // 降序迭代起始 key 超过最大 key 则定位到表中最后一个元素
+            (false, Some(sk)) => self.seek(&prefix, &sk).or_else(|| self.last(&prefix)),
+            (false, None) => self.last(&prefix),
+        }
+    }
+
+    fn next_item(&self, tab: &Atom, descending: bool, cur_key: &Bin) -> (Option<Bin>, Option<Bin>) {
+        let prefix = tab_prefix(tab);
+        let ck = tab_key(&prefix, cur_key);
+        let value = match self.0.get(&ck) {
+            Ok(v) => v.map(Arc::new),
+            Err(e) => {
+                warn!("rocksdb next item error: {:?}", e.to_string());
+                None
+            }
+        };
+        let direction = if descending { Direction::Forward } else { Direction::Reverse };
+        let next = self.0
+            .iterator(IteratorMode::From(&ck, direction))
+            .find(|(k, _)| k.as_ref() != ck.as_slice())
+            .and_then(|(k, _)| strip_prefix(&prefix, &k));
+        (value, next)
+    }
+
+    fn commit(&self, modifies: &[TabKV]) -> Result<(), String> {
+        let mut batch = WriteBatch::default();
+        for m in modifies.iter() {
+            let key = tab_key(&tab_prefix(&m.tab), &m.key);
+            match &m.value {
+                Some(v) => batch.put(key, v.as_ref()),
+                None => batch.delete(key),
+            }
+        }
+        self.0
+            .write(batch)
+            .map_err(|e| format!("commit failed with error: {:?}", e.to_string()))
+    }
+}
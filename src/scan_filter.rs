@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use pi_db::db::Bin;
+
+use atom::Atom;
+
+/**
+* 命名谓词，参数为键和值，返回true表示保留
+*/
+pub type Predicate = Arc<Fn(&[u8], &[u8]) -> bool + Send + Sync>;
+
+lazy_static! {
+    // 已注册的命名谓词，键为谓词名哈希
+    static ref PREDICATES: RwLock<HashMap<u64, Predicate>> = RwLock::new(HashMap::new());
+}
+
+/**
+* 注册命名谓词，迭代时由读线程调用，谓词中不能访问数据库
+* @param name 谓词名
+* @param f 谓词，为None则取消注册
+*/
+pub fn register_predicate(name: &Atom, f: Option<Predicate>) {
+    let mut predicates = PREDICATES.write().unwrap();
+    match f {
+        Some(f) => {
+            predicates.insert(name.get_hash() as u64, f);
+        }
+        None => {
+            predicates.remove(&(name.get_hash() as u64));
+        }
+    }
+}
+
+/**
+* 迭代时在读线程中执行的过滤条件，各条件同时满足才返回给调用者
+*/
+#[derive(Debug, Clone, Default)]
+pub struct ScanFilter {
+    pub prefix: Option<Bin>,        //键前缀
+    pub start: Option<Bin>,         //键范围的下界，包括该键
+    pub end: Option<Bin>,           //键范围的上界，不包括该键
+    pub min_value: Option<usize>,   //值的最小长度
+    pub max_value: Option<usize>,   //值的最大长度
+    pub predicate: Option<Atom>,    //已注册的命名谓词
+}
+
+impl ScanFilter {
+    pub fn new() -> Self {
+        ScanFilter::default()
+    }
+
+    pub fn prefix(mut self, prefix: &[u8]) -> Self {
+        self.prefix = Some(Arc::new(prefix.to_vec()));
+        self
+    }
+
+    pub fn range(mut self, start: Option<Bin>, end: Option<Bin>) -> Self {
+        self.start = start;
+        self.end = end;
+        self
+    }
+
+    pub fn value_size(mut self, min: Option<usize>, max: Option<usize>) -> Self {
+        self.min_value = min;
+        self.max_value = max;
+        self
+    }
+
+    pub fn predicate(mut self, name: Atom) -> Self {
+        self.predicate = Some(name);
+        self
+    }
+
+    /**
+    * 迭代的起始键，由前缀和键范围收窄调用者给出的起始键
+    * @param key 调用者给出的起始键
+    * @param descending 与表事务的iter相同，为true时从小到大迭代
+    */
+    pub fn start_key(&self, key: Option<Bin>, descending: bool) -> Option<Bin> {
+        let mut bounds = vec![key];
+        if descending {
+            bounds.push(self.prefix.clone());
+            bounds.push(self.start.clone());
+            bounds.into_iter().filter_map(|k| k).max()
+        } else {
+            // 从大到小迭代时读线程定位到第一个大于等于起始键的位置，过大的键由past_end之前的过滤跳过
+            bounds.push(self.end.clone());
+            bounds.into_iter().filter_map(|k| k).min()
+        }
+    }
+
+    /**
+    * 键是否已越过过滤范围，越过后不会再有满足条件的键，迭代可以提前结束
+    */
+    pub fn past_end(&self, key: &[u8], descending: bool) -> bool {
+        if descending {
+            if let Some(end) = &self.end {
+                if key >= end.as_slice() {
+                    return true;
+                }
+            }
+            if let Some(prefix) = &self.prefix {
+                if key > prefix.as_slice() && !key.starts_with(prefix) {
+                    return true;
+                }
+            }
+        } else {
+            if let Some(start) = &self.start {
+                if key < start.as_slice() {
+                    return true;
+                }
+            }
+            if let Some(prefix) = &self.prefix {
+                if key < prefix.as_slice() {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    // 键值是否满足所有条件
+    pub fn matches(&self, key: &[u8], value: &[u8]) -> bool {
+        if self.prefix.as_ref().map_or(false, |p| !key.starts_with(p)) {
+            return false;
+        }
+        if self.start.as_ref().map_or(false, |s| key < s.as_slice()) {
+            return false;
+        }
+        if self.end.as_ref().map_or(false, |e| key >= e.as_slice()) {
+            return false;
+        }
+        if self.min_value.map_or(false, |min| value.len() < min) || self.max_value.map_or(false, |max| value.len() > max) {
+            return false;
+        }
+        match &self.predicate {
+            Some(name) => match PREDICATES.read().unwrap().get(&(name.get_hash() as u64)) {
+                Some(f) => f(key, value),
+                // 谓词未注册时不返回任何键值，避免调用者误以为已过滤
+                None => false,
+            },
+            None => true,
+        }
+    }
+}
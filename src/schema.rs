@@ -0,0 +1,85 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lmdb::{Database, Error, Transaction};
+
+use pi_db::db::TabKV;
+
+use atom::Atom;
+
+// 保存各表数据格式版本的保留表，每个环境一个
+pub const META_TAB: &str = "__meta";
+
+/**
+* 表的数据格式版本，上层在数据布局变化时据此执行迁移
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableVersion {
+    pub version: u32,       //数据格式版本，新建的表为0
+    pub created_at: u64,    //表的创建时间，单位毫秒
+}
+
+impl TableVersion {
+    // 新建表的版本
+    pub fn new(version: u32) -> Self {
+        TableVersion {
+            version,
+            created_at: now_millis(),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(12);
+        v.extend_from_slice(&self.version.to_be_bytes());
+        v.extend_from_slice(&self.created_at.to_be_bytes());
+        v
+    }
+
+    pub fn decode(value: &[u8]) -> Option<Self> {
+        if value.len() != 12 {
+            return None;
+        }
+        let mut version = [0u8; 4];
+        version.copy_from_slice(&value[0..4]);
+        let mut created_at = [0u8; 8];
+        created_at.copy_from_slice(&value[4..12]);
+        Some(TableVersion {
+            version: u32::from_be_bytes(version),
+            created_at: u64::from_be_bytes(created_at),
+        })
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/**
+* 构建写入表版本的修改，在事务中与其它修改一起提交
+* @param ware 表所在的库名，用于路由到库所在环境的保留表
+* @param tab 表名
+* @param version 表版本
+*/
+pub fn version_kv(ware: &Atom, tab: &Atom, version: &TableVersion) -> TabKV {
+    TabKV {
+        ware: ware.clone(),
+        tab: Atom::from(META_TAB),
+        key: Arc::new(tab.as_bytes().to_vec()),
+        index: 0,
+        value: Some(Arc::new(version.encode())),
+    }
+}
+
+// 在事务中读取表的版本
+pub fn read<T: Transaction>(txn: &T, db: Database, tab: &Atom) -> Result<Option<TableVersion>, String> {
+    match txn.get(db, &tab.as_bytes()) {
+        Ok(v) => TableVersion::decode(v)
+            .map(Some)
+            .ok_or_else(|| format!("invalid table version for {:?}", tab.to_string())),
+        Err(Error::NotFound) => Ok(None),
+        Err(e) => Err(e.to_string()),
+    }
+}
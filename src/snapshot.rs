@@ -0,0 +1,368 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+use crc32fast::Hasher;
+use futures::io::{AsyncWrite, AsyncWriteExt};
+use lmdb::{Cursor, Environment, Transaction};
+
+use pi_db::db::Bin;
+
+use atom::Atom;
+
+use crate::changelog;
+use crate::chunk;
+use crate::pool::lookup_db;
+use crate::readers;
+use crate::tombstone;
+
+// 快照流的格式版本
+const SNAPSHOT_VERSION: u8 = 1;
+// 快照文件的魔数和格式版本，版本1为没有文件头的快照流
+const SNAPSHOT_MAGIC: &[u8; 4] = b"PISN";
+const SNAPSHOT_FILE_VERSION: u8 = 2;
+// 帧类型
+const FRAME_HEADER: u8 = 1;
+const FRAME_RECORD: u8 = 2;
+const FRAME_END: u8 = 3;
+// 单个帧的最大长度，防止读取损坏的流时分配过大的内存
+pub(crate) const MAX_FRAME_LEN: usize = 1 << 30;
+
+/**
+* 快照流中的帧，每个帧以4字节大端长度为前缀，内容的第一个字节为帧类型
+* 流由一个头帧、任意个记录帧和一个尾帧组成
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Header(Atom, u64),  //表名和快照所在的事务id
+    Record(Bin, Bin),   //键值
+    End(u64),           //记录数，用于校验流是否完整
+}
+
+impl Frame {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        match self {
+            Frame::Header(tab, txnid) => {
+                payload.push(FRAME_HEADER);
+                payload.push(SNAPSHOT_VERSION);
+                payload.extend_from_slice(&txnid.to_be_bytes());
+                payload.extend_from_slice(tab.as_bytes());
+            }
+            Frame::Record(key, value) => {
+                payload.push(FRAME_RECORD);
+                payload.extend_from_slice(&(key.len() as u32).to_be_bytes());
+                payload.extend_from_slice(key);
+                payload.extend_from_slice(value);
+            }
+            Frame::End(count) => {
+                payload.push(FRAME_END);
+                payload.extend_from_slice(&count.to_be_bytes());
+            }
+        }
+        let mut frame = Vec::with_capacity(4 + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    // 解析帧的内容，不包括长度前缀
+    pub fn decode(payload: &[u8]) -> Result<Self, String> {
+        let u64_at = |pos: usize| -> Result<u64, String> {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(payload.get(pos..pos + 8).ok_or_else(|| "truncated snapshot frame".to_string())?);
+            Ok(u64::from_be_bytes(b))
+        };
+        match payload.first() {
+            Some(&FRAME_HEADER) => {
+                if payload.get(1) != Some(&SNAPSHOT_VERSION) {
+                    return Err("unsupported snapshot version".to_string());
+                }
+                let txnid = u64_at(2)?;
+                let tab = String::from_utf8(payload[10..].to_vec()).map_err(|e| e.to_string())?;
+                Ok(Frame::Header(Atom::from(tab), txnid))
+            }
+            Some(&FRAME_RECORD) => {
+                let mut b = [0u8; 4];
+                b.copy_from_slice(payload.get(1..5).ok_or_else(|| "truncated snapshot frame".to_string())?);
+                let klen = u32::from_be_bytes(b) as usize;
+                let key = payload.get(5..5 + klen).ok_or_else(|| "truncated snapshot frame".to_string())?;
+                Ok(Frame::Record(Arc::new(key.to_vec()), Arc::new(payload[5 + klen..].to_vec())))
+            }
+            Some(&FRAME_END) => Ok(Frame::End(u64_at(1)?)),
+            _ => Err("invalid snapshot frame".to_string()),
+        }
+    }
+}
+
+/**
+* 从流中读取下一个帧，流已结束返回None
+*/
+pub fn read_frame<R: Read>(input: &mut R) -> Result<Option<Frame>, String> {
+    let mut len = [0u8; 4];
+    match input.read_exact(&mut len) {
+        Ok(_) => (),
+        Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(format!("snapshot frame too large: {}", len));
+    }
+    let mut payload = vec![0u8; len];
+    input.read_exact(&mut payload).map_err(|e| e.to_string())?;
+    Frame::decode(&payload).map(Some)
+}
+
+// 在同一个读事务中依次生成表的所有帧，由写出函数写出
+pub(crate) fn frames<F: FnMut(Frame) -> Result<(), String>>(env: &Environment, env_id: u64, tab: &Atom, write: F) -> Result<u64, String> {
+    scan(env, env_id, tab, write).map(|(count, _)| count)
+}
+
+// 生成表的所有帧，返回记录数和读事务所见的修改日志提交序号
+fn scan<F: FnMut(Frame) -> Result<(), String>>(env: &Environment, env_id: u64, tab: &Atom, mut write: F) -> Result<(u64, u64), String> {
+    let db = lookup_db(env_id, tab).ok_or_else(|| format!("tab not opened: {:?}", tab.to_string()))?;
+    let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+    let txnid = unsafe { lmdb_sys::mdb_txn_id(txn.txn()) } as u64;
+    let guard = readers::track(env_id, txnid, "snapshot export");
+    let seq = changelog::last_seq(&txn, env_id).map_err(|e| e.to_string())?;
+    write(Frame::Header(tab.clone(), txnid))?;
+
+    let mut count = 0u64;
+    {
+        let mut cursor = txn.open_ro_cursor(db).map_err(|e| e.to_string())?;
+        for (k, v) in cursor.iter_start() {
+            if guard.is_aborted() {
+                return Err(format!("snapshot export of tab: {:?} aborted, read txn too old", tab.to_string()));
+            }
+            // 软删除的键不导出
+            if tombstone::is_deleted(tab, v) {
+                continue;
+            }
+            let value = chunk::read(&txn, env_id, tab, k, v).map_err(|e| e.to_string())?;
+            write(Frame::Record(Arc::new(k.to_vec()), Arc::new(value)))?;
+            count += 1;
+        }
+    }
+    let _ = txn.commit();
+    write(Frame::End(count))?;
+    Ok((count, seq))
+}
+
+/**
+* 导出表的一致性快照，导出期间持有一个读事务，写入不受影响
+* @param env LMDB环境
+* @param env_id 环境id
+* @param tab 表名
+* @param out 输出，如文件或网络连接
+* @returns 返回导出的记录数
+*/
+pub fn export<W: Write>(env: &Environment, env_id: u64, tab: &Atom, out: &mut W) -> Result<u64, String> {
+    let count = frames(env, env_id, tab, |frame| out.write_all(&frame.encode()).map_err(|e| e.to_string()))?;
+    out.flush().map_err(|e| e.to_string())?;
+    Ok(count)
+}
+
+/**
+* 异步导出表的一致性快照，每次缓冲一批帧后再异步写出，缓冲期间持有读事务
+* 读事务不能跨线程，返回的future只能在当前线程中执行
+* @param batch 每批缓冲的帧数
+*/
+pub async fn export_async<W: AsyncWrite + Unpin>(env: &Environment, env_id: u64, tab: &Atom, out: &mut W, batch: usize) -> Result<u64, String> {
+    let mut buf: Vec<Vec<u8>> = Vec::with_capacity(batch);
+    let mut pending: Vec<Vec<Vec<u8>>> = Vec::new();
+    let count = frames(env, env_id, tab, |frame| {
+        buf.push(frame.encode());
+        if buf.len() >= batch.max(1) {
+            pending.push(std::mem::replace(&mut buf, Vec::with_capacity(batch)));
+        }
+        Ok(())
+    })?;
+    pending.push(buf);
+    for frames in pending {
+        for frame in frames {
+            out.write_all(&frame).await.map_err(|e| e.to_string())?;
+        }
+    }
+    out.flush().await.map_err(|e| e.to_string())?;
+    Ok(count)
+}
+
+/**
+* 快照文件头，文件格式与LMDB的版本和页格式无关
+* 文件由文件头、记录帧和尾帧组成，文件头为4字节魔数、1字节版本、8字节记录数、4字节校验和、
+* 8字节事务id、8字节修改日志提交序号、2字节表名长度和表名，整数都为大端
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotHeader {
+    pub version: u8,    //格式版本，1为没有文件头的快照流
+    pub tab: Atom,      //表名
+    pub txnid: u64,     //快照所在的事务id
+    pub seq: u64,       //快照所见的最后一次修改日志提交序号，未开启修改日志为0
+    pub records: u64,   //记录数
+    pub checksum: u32,  //所有记录帧的CRC32，版本1为0
+}
+
+impl SnapshotHeader {
+    fn encode(&self) -> Vec<u8> {
+        let tab = self.tab.as_bytes();
+        let mut buf = Vec::with_capacity(35 + tab.len());
+        buf.extend_from_slice(SNAPSHOT_MAGIC);
+        buf.push(self.version);
+        buf.extend_from_slice(&self.records.to_be_bytes());
+        buf.extend_from_slice(&self.checksum.to_be_bytes());
+        buf.extend_from_slice(&self.txnid.to_be_bytes());
+        buf.extend_from_slice(&self.seq.to_be_bytes());
+        buf.extend_from_slice(&(tab.len() as u16).to_be_bytes());
+        buf.extend_from_slice(tab);
+        buf
+    }
+
+    // 读取魔数之后的文件头
+    fn read<R: Read>(input: &mut R) -> Result<Self, String> {
+        let mut fixed = [0u8; 31];
+        input.read_exact(&mut fixed).map_err(|e| format!("truncated snapshot header, {}", e))?;
+        if fixed[0] != SNAPSHOT_FILE_VERSION {
+            return Err(format!("unsupported snapshot version: {}", fixed[0]));
+        }
+        let u64_at = |pos: usize| {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&fixed[pos..pos + 8]);
+            u64::from_be_bytes(b)
+        };
+        let mut checksum = [0u8; 4];
+        checksum.copy_from_slice(&fixed[9..13]);
+        let mut len = [0u8; 2];
+        len.copy_from_slice(&fixed[29..31]);
+        let mut tab = vec![0u8; u16::from_be_bytes(len) as usize];
+        input.read_exact(&mut tab).map_err(|e| format!("truncated snapshot header, {}", e))?;
+        Ok(SnapshotHeader {
+            version: fixed[0],
+            tab: Atom::from(String::from_utf8(tab).map_err(|e| e.to_string())?),
+            txnid: u64_at(13),
+            seq: u64_at(21),
+            records: u64_at(1),
+            checksum: u32::from_be_bytes(checksum),
+        })
+    }
+}
+
+/**
+* 将表的一致性快照写入快照文件，先写入占位的文件头，写完记录后回填事务id、记录数和校验和
+* 用于代替直接复制LMDB数据文件的备份，快照可以在不同LMDB版本和页大小之间读取
+* @param env LMDB环境
+* @param env_id 环境id
+* @param tab 表名
+* @param out 输出，需要可以回到文件头回填
+* @returns 返回快照的文件头
+*/
+pub fn write_snapshot<W: Write + Seek>(env: &Environment, env_id: u64, tab: &Atom, out: &mut W) -> Result<SnapshotHeader, String> {
+    let start = out.seek(SeekFrom::Current(0)).map_err(|e| e.to_string())?;
+    let mut header = SnapshotHeader {
+        version: SNAPSHOT_FILE_VERSION,
+        tab: tab.clone(),
+        txnid: 0,
+        seq: 0,
+        records: 0,
+        checksum: 0,
+    };
+    // 文件头长度固定，先写入占位，写完记录后整个回填
+    out.write_all(&header.encode()).map_err(|e| e.to_string())?;
+    let mut hasher = Hasher::new();
+    let (records, seq) = scan(env, env_id, tab, |frame| {
+        let bytes = match &frame {
+            Frame::Header(_, txnid) => {
+                header.txnid = *txnid;
+                return Ok(());
+            }
+            Frame::Record(..) => {
+                let bytes = frame.encode();
+                hasher.update(&bytes);
+                bytes
+            }
+            Frame::End(_) => frame.encode(),
+        };
+        out.write_all(&bytes).map_err(|e| e.to_string())
+    }).and_then(|r| {
+        out.flush().map_err(|e| e.to_string())?;
+        Ok(r)
+    })?;
+    header.records = records;
+    header.seq = seq;
+    header.checksum = hasher.finalize();
+
+    let end = out.seek(SeekFrom::Current(0)).map_err(|e| e.to_string())?;
+    let bytes = header.encode();
+    out.seek(SeekFrom::Start(start)).map_err(|e| e.to_string())?;
+    out.write_all(&bytes).map_err(|e| e.to_string())?;
+    out.seek(SeekFrom::Start(end)).map_err(|e| e.to_string())?;
+    out.flush().map_err(|e| e.to_string())?;
+    Ok(header)
+}
+
+/**
+* 读取快照文件，校验记录数和校验和，也可以读取没有文件头的快照流
+* 记录在校验前已交给回调，校验失败时调用者应丢弃已读取的记录
+* @param input 输入
+* @param f 每条记录的回调，返回错误则停止读取
+* @returns 返回快照的文件头
+*/
+pub fn read_snapshot<R: Read, F: FnMut(Bin, Bin) -> Result<(), String>>(input: &mut R, mut f: F) -> Result<SnapshotHeader, String> {
+    let mut magic = [0u8; 4];
+    input.read_exact(&mut magic).map_err(|e| format!("truncated snapshot, {}", e))?;
+    if &magic != SNAPSHOT_MAGIC {
+        // 没有文件头的快照流，读到的是第一个帧的长度
+        return read_stream(&mut (&magic[..]).chain(input), f);
+    }
+
+    let header = SnapshotHeader::read(input)?;
+    let mut hasher = Hasher::new();
+    let mut count = 0u64;
+    loop {
+        match read_frame(input)? {
+            Some(frame @ Frame::Record(..)) => {
+                hasher.update(&frame.encode());
+                if let Frame::Record(k, v) = frame {
+                    f(k, v)?;
+                }
+                count += 1;
+            }
+            Some(Frame::End(n)) if n == count && n == header.records => break,
+            Some(Frame::End(n)) => return Err(format!("snapshot record count mismatch, header: {}, end: {}, read: {}", header.records, n, count)),
+            Some(Frame::Header(..)) => return Err("unexpected snapshot header frame".to_string()),
+            None => return Err(format!("snapshot truncated after {} records", count)),
+        }
+    }
+    if hasher.finalize() != header.checksum {
+        return Err(format!("snapshot of tab: {:?} checksum mismatch", header.tab.to_string()));
+    }
+    Ok(header)
+}
+
+// 读取版本1的快照流
+fn read_stream<R: Read, F: FnMut(Bin, Bin) -> Result<(), String>>(input: &mut R, mut f: F) -> Result<SnapshotHeader, String> {
+    let (tab, txnid) = match read_frame(input)? {
+        Some(Frame::Header(tab, txnid)) => (tab, txnid),
+        _ => return Err("invalid snapshot, missing header".to_string()),
+    };
+    let mut count = 0u64;
+    loop {
+        match read_frame(input)? {
+            Some(Frame::Record(k, v)) => {
+                f(k, v)?;
+                count += 1;
+            }
+            Some(Frame::End(n)) if n == count => break,
+            Some(Frame::End(n)) => return Err(format!("snapshot record count mismatch, end: {}, read: {}", n, count)),
+            Some(Frame::Header(..)) => return Err("unexpected snapshot header frame".to_string()),
+            None => return Err(format!("snapshot truncated after {} records", count)),
+        }
+    }
+    Ok(SnapshotHeader {
+        version: SNAPSHOT_VERSION,
+        tab,
+        txnid,
+        seq: 0,
+        records: count,
+        checksum: 0,
+    })
+}
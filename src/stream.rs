@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use futures::stream::Stream;
+use futures::task::{Context, Poll, Waker};
+
+use pi_db::db::{Bin, Iter, NextResult, SResult};
+
+// 默认每批预取的元素数量
+const DEFAULT_BATCH: usize = 64;
+
+struct StreamState {
+    buf: VecDeque<(Bin, Bin)>,
+    fetching: bool,
+    done: bool,
+    error: Option<String>,
+    waker: Option<Waker>,
+}
+
+struct StreamShared {
+    inner: Mutex<Box<Iter<Item = (Bin, Bin)>>>,
+    state: Mutex<StreamState>,
+}
+
+/**
+* 将表迭代器包装为futures::Stream，内部按批预取
+* 迭代出错时返回一次错误后结束
+*/
+pub struct ItemStream {
+    shared: Arc<StreamShared>,
+    batch: usize,
+}
+
+impl ItemStream {
+    /**
+    * 构建迭代流
+    * @param iter 表事务的迭代器
+    * @param batch 每批预取的元素数量，为0则使用默认值
+    */
+    pub fn new(iter: Box<Iter<Item = (Bin, Bin)>>, batch: usize) -> Self {
+        ItemStream {
+            shared: Arc::new(StreamShared {
+                inner: Mutex::new(iter),
+                state: Mutex::new(StreamState {
+                    buf: VecDeque::new(),
+                    fetching: false,
+                    done: false,
+                    error: None,
+                    waker: None,
+                }),
+            }),
+            batch: if batch == 0 { DEFAULT_BATCH } else { batch },
+        }
+    }
+}
+
+// 从迭代器取下一个元素，上一个元素返回后才取下一个，保证顺序
+fn fetch(shared: Arc<StreamShared>, remaining: usize) {
+    let s = shared.clone();
+    let r = shared.inner.lock().unwrap().next(Arc::new(move |r| {
+        on_next(s.clone(), r, remaining);
+    }));
+    if let Some(r) = r {
+        on_next(shared, r, remaining);
+    }
+}
+
+fn on_next(shared: Arc<StreamShared>, r: NextResult<(Bin, Bin)>, remaining: usize) {
+    let (more, waker) = {
+        let mut state = shared.state.lock().unwrap();
+        let more = match r {
+            Ok(Some(kv)) => {
+                state.buf.push_back(kv);
+                remaining > 1
+            }
+            Ok(None) => {
+                state.done = true;
+                false
+            }
+            Err(e) => {
+                state.error = Some(e);
+                state.done = true;
+                false
+            }
+        };
+        if !more {
+            state.fetching = false;
+        }
+        (more, state.waker.take())
+    };
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+    if more {
+        fetch(shared, remaining - 1);
+    }
+}
+
+impl Stream for ItemStream {
+    type Item = SResult<(Bin, Bin)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let (r, start) = {
+            let mut state = self.shared.state.lock().unwrap();
+            let r = match state.buf.pop_front() {
+                Some(kv) => Poll::Ready(Some(Ok(kv))),
+                None => match state.error.take() {
+                    Some(e) => Poll::Ready(Some(Err(e))),
+                    None if state.done => Poll::Ready(None),
+                    None => {
+                        state.waker = Some(cx.waker().clone());
+                        Poll::Pending
+                    }
+                },
+            };
+            // 缓冲区取空后预取下一批
+            let start = state.buf.is_empty() && !state.fetching && !state.done;
+            if start {
+                state.fetching = true;
+            }
+            (r, start)
+        };
+        if start {
+            fetch(self.shared.clone(), self.batch);
+        }
+        r
+    }
+}
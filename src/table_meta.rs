@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+use lmdb::{Cursor, Database, DatabaseFlags, Environment, Error, RwTransaction, Transaction};
+
+use pi_db::db::{TabKV, TabMeta};
+use bon::{Decode, ReadBuffer};
+
+use atom::Atom;
+
+use crate::bloom;
+use crate::checksum;
+use crate::chunk;
+use crate::pool::{lookup_db, register_db, unregister_db};
+use crate::versions;
+
+// 保存表元信息的表，键为表名，值为pi_db的TabMeta
+pub const SINFO: &str = "_$sinfo";
+
+lazy_static! {
+    // 新建表时使用的数据库标志，键为表名哈希；已存在的表使用创建时保存在数据文件中的标志
+    static ref DB_FLAGS: RwLock<HashMap<u64, DatabaseFlags>> = RwLock::new(HashMap::new());
+    // 各环境当前读写事务中创建和删除的表，提交后生效，回滚后撤销；Some为创建，None为删除
+    static ref PENDING: Mutex<HashMap<u64, Vec<(u64, Option<Database>)>>> = Mutex::new(HashMap::new());
+}
+
+/**
+* 设置表创建时使用的数据库标志，如INTEGERKEY、DUP_SORT，必须在表创建前设置
+* 标志在表创建时保存在数据文件中，之后修改不影响已存在的表
+*/
+pub fn set_db_flags(tab: &Atom, flags: DatabaseFlags) {
+    DB_FLAGS.write().unwrap().insert(tab.get_hash() as u64, flags);
+}
+
+// 表创建时使用的数据库标志
+pub fn db_flags(tab: &Atom) -> DatabaseFlags {
+    hash_db_flags(tab.get_hash() as u64)
+}
+
+// 按表名哈希读取和设置表创建时使用的数据库标志，用于载入只保存了表名哈希的表策略
+pub(crate) fn hash_db_flags(tab: u64) -> DatabaseFlags {
+    DB_FLAGS.read().unwrap().get(&tab).cloned().unwrap_or_else(DatabaseFlags::empty)
+}
+
+pub(crate) fn set_hash_db_flags(tab: u64, flags: DatabaseFlags) {
+    DB_FLAGS.write().unwrap().insert(tab, flags);
+}
+
+fn decode_tab(key: &[u8]) -> Result<Atom, Error> {
+    Atom::decode(&mut ReadBuffer::new(key, 0)).map_err(|_| Error::Corrupted)
+}
+
+/**
+* 读取环境中所有表的元信息并打开对应的表，在库启动时调用，之后的读写可以直接使用这些表
+* 元信息存在但表不存在时创建空表，保证元信息与表一致
+* @param env LMDB环境
+* @param env_id 环境id
+* @param sinfo 元信息表
+* @returns 返回所有表名和元信息
+*/
+pub fn load(env: &Environment, env_id: u64, sinfo: Database) -> Result<Vec<(Atom, Arc<TabMeta>)>, String> {
+    let mut metas = Vec::new();
+    {
+        let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+        {
+            let mut cursor = txn.open_ro_cursor(sinfo).map_err(|e| e.to_string())?;
+            for (k, v) in cursor.iter_start() {
+                let tab = decode_tab(k).map_err(|e| e.to_string())?;
+                let meta = TabMeta::decode(&mut ReadBuffer::new(v, 0)).map_err(|_| format!("invalid meta of tab: {:?}", tab.to_string()))?;
+                metas.push((tab, Arc::new(meta)));
+            }
+        }
+        let _ = txn.commit();
+    }
+
+    for (tab, _) in metas.iter() {
+        let db = env.create_db(Some(tab.as_str()), db_flags(tab)).map_err(|e| e.to_string())?;
+        register_db(env_id, tab, db)?;
+        bloom::rebuild(env_id, tab.get_hash() as u64, env, db);
+    }
+    debug!("env: {:?} loaded {} tabs", env_id, metas.len());
+    Ok(metas)
+}
+
+/**
+* 打开环境中所有已存在的命名表并登记，包括没有元信息的内部表，如冷表和快照表
+* 读写事务中打开表有诸多限制，启动时一次打开后，各工作线程共享同一个表句柄
+* @param env LMDB环境
+* @param env_id 环境id
+* @param max_dbs 环境的最大表数量
+* @returns 返回新打开的表数量，已存在的表超过最大表数量时返回错误
+*/
+pub fn preopen(env: &Environment, env_id: u64, max_dbs: u32) -> Result<usize, String> {
+    let mut names = Vec::new();
+    {
+        let main = env.open_db(None).map_err(|e| e.to_string())?;
+        let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+        {
+            let mut cursor = txn.open_ro_cursor(main).map_err(|e| e.to_string())?;
+            for (k, _) in cursor.iter_start() {
+                match std::str::from_utf8(k) {
+                    Ok(name) => names.push(Atom::from(name)),
+                    Err(_) => warn!("env: {:?} skip invalid db name: {:?}", env_id, k),
+                }
+            }
+        }
+        let _ = txn.commit();
+    }
+    if names.len() > max_dbs as usize {
+        return Err(format!("env: {:?} has {} dbs, more than max dbs: {}", env_id, names.len(), max_dbs));
+    }
+    if names.len() * 10 >= max_dbs as usize * 9 {
+        warn!("env: {:?} has {} dbs, close to max dbs: {}", env_id, names.len(), max_dbs);
+    }
+
+    let mut count = 0;
+    for name in names {
+        if lookup_db(env_id, &name).is_some() {
+            continue;
+        }
+        let db = env.open_db(Some(name.as_str())).map_err(|e| e.to_string())?;
+        register_db(env_id, &name, db)?;
+        count += 1;
+    }
+    debug!("env: {:?} preopened {} dbs", env_id, count);
+    Ok(count)
+}
+
+/**
+* 写入元信息表时调用，在同一个读写事务中创建或清空表，使表的创建和删除与元信息一起提交或回滚
+* 新建的表立即登记，以便同一事务中的写入可以使用；删除的表在提交后才注销
+*/
+pub fn apply(txn: &mut RwTransaction, env_id: u64, m: &TabKV) -> Result<(), Error> {
+    if m.tab.as_str() != SINFO {
+        return Ok(());
+    }
+    let tab = decode_tab(&m.key)?;
+    let key = (env_id, tab.get_hash() as u64);
+    let opened = lookup_db(env_id, &tab);
+    match (&m.value, opened) {
+        (Some(_), Some(_)) => {
+            // 同一事务中删除后重建，取消删除
+            if let Some(pending) = PENDING.lock().unwrap().get_mut(&env_id) {
+                pending.retain(|(tab, db)| !(*tab == key.1 && db.is_none()));
+            }
+            Ok(())
+        }
+        (Some(_), None) => {
+            let db = unsafe { txn.create_db(Some(tab.as_str()), db_flags(&tab))? };
+            register_db(env_id, &tab, db).map_err(|e| {
+                warn!("{}", e);
+                Error::BadDbi
+            })?;
+            PENDING.lock().unwrap().entry(env_id).or_insert_with(Vec::new).push((key.1, Some(db)));
+            Ok(())
+        }
+        (None, Some(db)) => {
+            // 关闭表的句柄在回滚后无法恢复，删除时只清空表，连同分块、版本和校验和
+            let keys = {
+                let mut cursor = txn.open_ro_cursor(db)?;
+                cursor.iter_start().map(|(k, _)| k.to_vec()).collect::<Vec<Vec<u8>>>()
+            };
+            for k in keys.iter() {
+                chunk::del(txn, env_id, db, &tab, k)?;
+                versions::del(txn, env_id, &tab, k)?;
+                checksum::del(txn, env_id, &tab, k)?;
+            }
+            txn.clear_db(db)?;
+            PENDING.lock().unwrap().entry(env_id).or_insert_with(Vec::new).push((key.1, None));
+            Ok(())
+        }
+        (None, None) => Ok(()),
+    }
+}
+
+// 读写事务提交后调用，注销已删除的表
+pub fn publish(env_id: u64) {
+    if let Some(pending) = PENDING.lock().unwrap().remove(&env_id) {
+        for (tab, db) in pending {
+            if db.is_none() {
+                unregister_db(env_id, tab);
+            }
+        }
+    }
+}
+
+// 读写事务回滚后调用，注销本事务中新建的表
+pub fn discard(env_id: u64) {
+    if let Some(pending) = PENDING.lock().unwrap().remove(&env_id) {
+        for (tab, db) in pending {
+            if db.is_some() {
+                unregister_db(env_id, tab);
+            }
+        }
+    }
+}
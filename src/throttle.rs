@@ -0,0 +1,145 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/**
+* 令牌桶，令牌按固定速率补充，桶满后不再增加
+* 令牌不足时仍然扣除，余额为负表示已预支，调用者需要等待余额补回0
+*/
+#[derive(Debug)]
+struct TokenBucket {
+    rate: u64,          //每秒补充的令牌数
+    burst: u64,         //桶的容量
+    tokens: f64,        //当前余额
+    last: Instant,      //上次补充的时间
+}
+
+impl TokenBucket {
+    fn new(rate: u64, burst: u64) -> Self {
+        TokenBucket {
+            rate,
+            burst,
+            tokens: burst as f64,
+            last: Instant::now(),
+        }
+    }
+
+    // 扣除令牌，返回需要等待的时间
+    fn take(&mut self, n: u64) -> Option<Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last);
+        self.last = now;
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate as f64).min(self.burst as f64);
+        self.tokens -= n as f64;
+        if self.tokens >= 0.0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64(-self.tokens / self.rate as f64))
+        }
+    }
+}
+
+// 等待到期后执行的任务
+struct Delayed {
+    at: Instant,
+    seq: u64,
+    task: Box<FnOnce() + Send>,
+}
+
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        self.at == other.at && self.seq == other.seq
+    }
+}
+
+impl Eq for Delayed {}
+
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// 到期时间早的先执行，同时到期的按加入顺序执行
+impl Ord for Delayed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.at.cmp(&self.at).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+struct Scheduler {
+    queue: Mutex<(BinaryHeap<Delayed>, u64)>,
+    cond: Condvar,
+}
+
+lazy_static! {
+    // 写入字节数的令牌桶，为None则不限速
+    static ref WRITE_LIMIT: Mutex<Option<TokenBucket>> = Mutex::new(None);
+    // 延迟执行的任务，由限速线程在到期后执行
+    static ref SCHEDULER: Scheduler = {
+        let _ = thread::Builder::new().name("pi_store-throttle".to_string()).spawn(run);
+        Scheduler {
+            queue: Mutex::new((BinaryHeap::new(), 0)),
+            cond: Condvar::new(),
+        }
+    };
+}
+
+fn run() {
+    let scheduler = &*SCHEDULER;
+    let mut queue = scheduler.queue.lock().unwrap();
+    loop {
+        let now = Instant::now();
+        match queue.0.peek().map(|d| d.at) {
+            Some(at) if at <= now => {
+                let delayed = queue.0.pop().unwrap();
+                drop(queue);
+                (delayed.task)();
+                queue = scheduler.queue.lock().unwrap();
+            }
+            Some(at) => queue = scheduler.cond.wait_timeout(queue, at - now).unwrap().0,
+            None => queue = scheduler.cond.wait(queue).unwrap(),
+        }
+    }
+}
+
+/**
+* 设置所有环境共用的写入限速，限制修改的键值字节数，批量任务不能通过占满唯一的写线程饿死交互请求
+* @param bytes_per_sec 每秒允许写入的字节数，为0则不限速
+* @param burst 允许突发写入的字节数，小于bytes_per_sec时使用bytes_per_sec
+*/
+pub fn set_write_rate(bytes_per_sec: u64, burst: u64) {
+    *WRITE_LIMIT.lock().unwrap() = if bytes_per_sec == 0 {
+        None
+    } else {
+        Some(TokenBucket::new(bytes_per_sec, burst.max(bytes_per_sec)))
+    };
+}
+
+/**
+* 为写入扣除令牌，返回写入需要推迟的时间，未限速或令牌足够时返回None
+* @param bytes 写入的键值字节数
+*/
+pub fn reserve(bytes: u64) -> Option<Duration> {
+    WRITE_LIMIT.lock().unwrap().as_mut().and_then(|bucket| bucket.take(bytes))
+}
+
+/**
+* 在限速线程中推迟执行任务，任务应很快完成，如向工作线程发送消息
+* @param delay 推迟的时间
+* @param task 任务
+*/
+pub fn after<F: FnOnce() + Send + 'static>(delay: Duration, task: F) {
+    let scheduler = &*SCHEDULER;
+    let mut queue = scheduler.queue.lock().unwrap();
+    let seq = queue.1;
+    queue.1 += 1;
+    queue.0.push(Delayed {
+        at: Instant::now() + delay,
+        seq,
+        task: Box::new(task),
+    });
+    scheduler.cond.notify_one();
+}
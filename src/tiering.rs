@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use lmdb::{Cursor, Database, DatabaseFlags, Environment, Error, RwTransaction, Transaction, WriteFlags};
+
+use pi_db::db::Bin;
+
+use atom::Atom;
+
+use crate::chunk;
+use crate::pool::register_db;
+
+// 冷表名的后缀
+const COLD_SUFFIX: &str = "$cold";
+// 默认的压缩级别
+const DEFAULT_LEVEL: i32 = 3;
+
+/**
+* 表的分层配置，超过空闲时间未被访问的键从热表迁移到压缩的冷表
+*/
+#[derive(Debug, Clone)]
+pub struct TierConfig {
+    pub idle: Duration,     //键超过该时间未被读写则视为冷数据
+    pub level: i32,         //冷数据的zstd压缩级别，0表示默认级别
+}
+
+// 分层表的状态
+struct Tier {
+    config: TierConfig,
+    cold: Database,                     //冷表
+    since: Instant,                     //开启分层的时间，之前未被访问的键从该时间开始计算空闲
+    touched: HashMap<Bin, Instant>,     //热表中键最近一次被读写的时间
+}
+
+lazy_static! {
+    // 开启分层的表，键为环境id和热表的表名哈希
+    static ref TIERS: RwLock<HashMap<(u64, u64), Mutex<Tier>>> = RwLock::new(HashMap::new());
+}
+
+// 热表对应的冷表名
+pub fn cold_tab(tab: &Atom) -> Atom {
+    Atom::from(format!("{}{}", tab.as_str(), COLD_SUFFIX))
+}
+
+/**
+* 开启表的分层存储，冷表不存在则创建
+* 查询在热表中找不到的键时会继续查询冷表，范围迭代和扫描只覆盖热表
+* @param env LMDB环境
+* @param env_id 环境id
+* @param tab 热表名
+* @param config 分层配置
+*/
+pub fn enable(env: &Environment, env_id: u64, tab: &Atom, config: TierConfig) -> Result<(), String> {
+    let cold_name = cold_tab(tab);
+    let cold = env.create_db(Some(cold_name.as_str()), DatabaseFlags::empty()).map_err(|e| e.to_string())?;
+    register_db(env_id, &cold_name, cold)?;
+    TIERS.write().unwrap().insert((env_id, tab.get_hash() as u64), Mutex::new(Tier {
+        config,
+        cold,
+        since: Instant::now(),
+        touched: HashMap::new(),
+    }));
+    Ok(())
+}
+
+// 关闭表的分层存储，已迁移到冷表的键仍可通过迁回读取，见restore_all
+pub fn disable(env_id: u64, tab: &Atom) {
+    TIERS.write().unwrap().remove(&(env_id, tab.get_hash() as u64));
+}
+
+pub fn is_enabled(env_id: u64, tab: u64) -> bool {
+    TIERS.read().unwrap().contains_key(&(env_id, tab))
+}
+
+// 记录键被访问，未开启分层的表直接返回
+pub fn touch(env_id: u64, tab: u64, key: &Bin) {
+    if let Some(tier) = TIERS.read().unwrap().get(&(env_id, tab)) {
+        tier.lock().unwrap().touched.insert(key.clone(), Instant::now());
+    }
+}
+
+fn compress(value: &[u8], level: i32) -> Result<Vec<u8>, Error> {
+    zstd::block::compress(value, if level == 0 { DEFAULT_LEVEL } else { level }).map_err(|_| Error::Invalid)
+}
+
+fn decompress(value: &[u8]) -> Result<Vec<u8>, Error> {
+    // 冷数据的前8字节为原始长度
+    if value.len() < 8 {
+        return Err(Error::Corrupted);
+    }
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&value[..8]);
+    zstd::block::decompress(&value[8..], u64::from_be_bytes(len) as usize).map_err(|_| Error::Corrupted)
+}
+
+/**
+* 在冷表中查询键，未开启分层的表返回None，找到时记录访问，下次写入时迁回热表
+*/
+pub fn get<T: Transaction>(txn: &T, env_id: u64, tab: u64, key: &Bin) -> Result<Option<Vec<u8>>, Error> {
+    let tiers = TIERS.read().unwrap();
+    let tier = match tiers.get(&(env_id, tab)) {
+        Some(tier) => tier,
+        None => return Ok(None),
+    };
+    let cold = tier.lock().unwrap().cold;
+    match txn.get(cold, key.as_ref()) {
+        Ok(v) => {
+            let v = decompress(v)?;
+            tier.lock().unwrap().touched.insert(key.clone(), Instant::now());
+            Ok(Some(v))
+        }
+        Err(Error::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/**
+* 写入热表时调用，删除冷表中的旧值，保证同一个键只存在于一层
+*/
+pub fn on_write(txn: &mut RwTransaction, env_id: u64, tab: &Atom, key: &Bin) -> Result<(), Error> {
+    let tiers = TIERS.read().unwrap();
+    let tier = match tiers.get(&(env_id, tab.get_hash() as u64)) {
+        Some(tier) => tier,
+        None => return Ok(()),
+    };
+    let mut tier = tier.lock().unwrap();
+    tier.touched.insert(key.clone(), Instant::now());
+    match txn.del(tier.cold, key.as_ref(), None) {
+        Ok(_) | Err(Error::NotFound) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/**
+* 将热表中的冷键压缩后迁移到冷表，需要在持有写线程使用权时调用
+* 迁移不改变键的逻辑值，不记录修改日志，版本和校验和保持不变
+* @param txn 读写事务
+* @param env_id 环境id
+* @param tab 热表名
+* @param db 热表
+* @returns 返回迁移的键数量
+*/
+pub fn migrate(txn: &mut RwTransaction, env_id: u64, tab: &Atom, db: Database) -> Result<usize, String> {
+    let tiers = TIERS.read().unwrap();
+    let tier = tiers.get(&(env_id, tab.get_hash() as u64)).ok_or_else(|| format!("tiering not enabled: {:?}", tab.to_string()))?;
+    let (cold, cold_keys) = {
+        let now = Instant::now();
+        let tier = tier.lock().unwrap();
+        let is_cold = |key: &[u8]| {
+            let last = tier.touched.get(&key.to_vec()).cloned().unwrap_or(tier.since);
+            now.duration_since(last) >= tier.config.idle
+        };
+        let mut keys = Vec::new();
+        let mut cursor = txn.open_ro_cursor(db).map_err(|e| e.to_string())?;
+        for (k, v) in cursor.iter_start() {
+            if is_cold(k) {
+                let v = chunk::read(&*txn, env_id, tab, k, v).map_err(|e| e.to_string())?;
+                keys.push((k.to_vec(), v));
+            }
+        }
+        (tier.cold, keys)
+    };
+
+    let level = tier.lock().unwrap().config.level;
+    for (k, v) in cold_keys.iter() {
+        let mut packed = (v.len() as u64).to_be_bytes().to_vec();
+        packed.extend(compress(v, level).map_err(|e| e.to_string())?);
+        txn.put(cold, k, &packed, WriteFlags::empty()).map_err(|e| e.to_string())?;
+        chunk::del(txn, env_id, db, tab, k).map_err(|e| e.to_string())?;
+    }
+
+    let mut tier = tier.lock().unwrap();
+    for (k, _) in cold_keys.iter() {
+        tier.touched.remove(k);
+    }
+    debug!("tab: {:?} migrated {} cold keys", tab.to_string(), cold_keys.len());
+    Ok(cold_keys.len())
+}
+
+/**
+* 将冷表中的所有键迁回热表，用于关闭分层前恢复数据
+* @returns 返回迁回的键数量
+*/
+pub fn restore_all(txn: &mut RwTransaction, env_id: u64, tab: &Atom, db: Database) -> Result<usize, String> {
+    let cold = TIERS
+        .read()
+        .unwrap()
+        .get(&(env_id, tab.get_hash() as u64))
+        .map(|tier| tier.lock().unwrap().cold)
+        .ok_or_else(|| format!("tiering not enabled: {:?}", tab.to_string()))?;
+    let mut values = Vec::new();
+    {
+        let mut cursor = txn.open_ro_cursor(cold).map_err(|e| e.to_string())?;
+        for (k, v) in cursor.iter_start() {
+            values.push((k.to_vec(), decompress(v).map_err(|e| e.to_string())?));
+        }
+    }
+    for (k, v) in values.iter() {
+        chunk::put(txn, env_id, db, tab, k, v).map_err(|e| e.to_string())?;
+    }
+    txn.clear_db(cold).map_err(|e| e.to_string())?;
+    Ok(values.len())
+}
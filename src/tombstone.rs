@@ -0,0 +1,117 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lmdb::{Cursor, Database, Environment, Error, RwTransaction, Transaction, WriteFlags};
+
+use pi_db::db::Bin;
+
+use atom::Atom;
+
+use crate::chunk;
+use crate::lmdb_file::with_txn;
+use crate::policy;
+use crate::pool::lookup_db;
+
+// 墓碑值的魔数
+const TOMBSTONE_MAGIC: &[u8] = b"\0pi_store_tomb\0\0";
+// 墓碑值的长度: 魔数 + 删除时间(8字节毫秒)
+const TOMBSTONE_LEN: usize = 16 + 8;
+// 每次清除默认删除的最大键数
+const DEFAULT_PURGE_BATCH: usize = 1000;
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// 值是否是墓碑
+pub fn is_tombstone(value: &[u8]) -> bool {
+    value.len() == TOMBSTONE_LEN && value.starts_with(TOMBSTONE_MAGIC)
+}
+
+// 值在表中是否表示已软删除的键，未开启软删除的表中没有墓碑，同样格式的值是普通的值
+pub fn is_deleted(tab: &Atom, value: &[u8]) -> bool {
+    policy::soft_delete(tab) && is_tombstone(value)
+}
+
+// 墓碑的删除时间，单位毫秒，不是墓碑返回None
+pub fn deleted_at(value: &[u8]) -> Option<u64> {
+    if !is_tombstone(value) {
+        return None;
+    }
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&value[16..]);
+    Some(u64::from_be_bytes(b))
+}
+
+/**
+* 在读写事务中用墓碑代替删除，键不存在或已是墓碑时不修改，保留最初的删除时间
+* 墓碑直接写入表中，不分块、不去重也不压缩，原值的分块和附件引用被释放
+*/
+pub fn put(txn: &mut RwTransaction, env_id: u64, db: Database, tab: &Atom, key: &[u8]) -> Result<(), Error> {
+    match txn.get(db, &key) {
+        Ok(v) if is_tombstone(v) => return Ok(()),
+        Ok(_) => (),
+        Err(Error::NotFound) => return Ok(()),
+        Err(e) => return Err(e),
+    }
+    chunk::del(txn, env_id, db, tab, key)?;
+    let mut value = Vec::with_capacity(TOMBSTONE_LEN);
+    value.extend_from_slice(TOMBSTONE_MAGIC);
+    value.extend_from_slice(&now_millis().to_be_bytes());
+    txn.put(db, &key, &value, WriteFlags::empty())
+}
+
+/**
+* 永久删除表中超过保留时间的墓碑，先在读事务中找出墓碑，再在写线程的读写事务中删除
+* 删除前在读写事务中重新检查，期间被重新写入或重新删除的键不会被提前删除
+* @param env LMDB环境
+* @param env_id 环境id
+* @param tab 表名
+* @param retention 墓碑的保留时间
+* @param limit 最多删除的键数，0表示默认值
+* @returns 返回删除的键数，等于limit时可能还有可删除的墓碑
+*/
+pub fn purge(env: &Environment, env_id: u64, tab: &Atom, retention: Duration, limit: usize) -> Result<usize, String> {
+    let db = lookup_db(env_id, tab).ok_or_else(|| format!("tab not opened: {:?}", tab.to_string()))?;
+    if !policy::soft_delete(tab) {
+        return Ok(0);
+    }
+    let limit = if limit == 0 { DEFAULT_PURGE_BATCH } else { limit };
+    let before = now_millis().saturating_sub(retention.as_millis() as u64);
+    let mut keys: Vec<Bin> = Vec::new();
+    {
+        let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+        {
+            let mut cursor = txn.open_ro_cursor(db).map_err(|e| e.to_string())?;
+            for (k, v) in cursor.iter_start() {
+                if keys.len() >= limit {
+                    break;
+                }
+                if deleted_at(v).map_or(false, |t| t <= before) {
+                    keys.push(Arc::new(k.to_vec()));
+                }
+            }
+        }
+        let _ = txn.commit();
+    }
+    if keys.is_empty() {
+        return Ok(0);
+    }
+
+    let keys = Arc::new(keys);
+    let tab1 = tab.clone();
+    let count = with_txn(tab, move |ops| {
+        let mut count = 0;
+        for key in keys.iter() {
+            if ops.purge(&tab1, key, before)? {
+                count += 1;
+            }
+        }
+        Ok(count)
+    })?;
+    debug!("tab: {:?} purged {} tombstones", tab.to_string(), count);
+    Ok(count)
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use lmdb::Error;
+
+use pi_db::db::TabKV;
+
+use atom::Atom;
+
+/**
+* 表的读写事务失败统计，用于找出争用写线程或频繁失败的负载
+*/
+#[derive(Debug, Clone)]
+pub struct TabTxnStats {
+    pub tab: Atom,          //表名
+    pub aborted: u64,       //修改了该表且被回滚的读写事务数量，包括前置条件不满足和提交失败
+    pub failed_puts: u64,   //写入或删除失败的次数
+    pub map_full: u64,      //写入或提交因MDB_MAP_FULL失败的次数
+    pub retried: u64,       //修改了该表且因临时性错误重新执行的读写事务数量
+}
+
+/**
+* 一次提交写入的统计，由写线程在提交时计算并传给提交回调，用于按事务记录和计费IO
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CommitStats {
+    pub puts: u64,          //写入的键值数
+    pub deletes: u64,       //删除的键数
+    pub bytes: u64,         //写入的键和值的字节数，删除只计键的字节数
+    pub dirty_pages: u64,   //估计的脏页数，按每个表从根到叶子的路径加上写入字节数占用的页数估计
+}
+
+impl CommitStats {
+    /**
+    * 统计修改中的写入和删除，不包括脏页数
+    * @param modifies 提交的修改
+    */
+    pub fn of(modifies: &[TabKV]) -> Self {
+        let mut stats = CommitStats::default();
+        for m in modifies {
+            match &m.value {
+                Some(v) => {
+                    stats.puts += 1;
+                    stats.bytes += (m.key.len() + v.len()) as u64;
+                }
+                None => {
+                    stats.deletes += 1;
+                    stats.bytes += m.key.len() as u64;
+                }
+            }
+        }
+        stats
+    }
+
+    // 合并另一个环境的统计
+    pub fn merge(&mut self, other: &CommitStats) {
+        self.puts += other.puts;
+        self.deletes += other.deletes;
+        self.bytes += other.bytes;
+        self.dirty_pages += other.dirty_pages;
+    }
+}
+
+// 事务提交统计的观察者，参数为事务id和所有环境合并后的统计
+pub type CommitObserver = Arc<Fn(u64, &CommitStats) + Send + Sync>;
+
+// 表的计数器，只使用原子变量
+#[derive(Debug, Default)]
+struct Counters {
+    aborted: AtomicU64,
+    failed_puts: AtomicU64,
+    map_full: AtomicU64,
+    retried: AtomicU64,
+}
+
+lazy_static! {
+    // 表的计数器，键为(环境id, 表名哈希)
+    static ref STATS: RwLock<HashMap<(u64, u64), (Atom, Arc<Counters>)>> = RwLock::new(HashMap::new());
+    // 各环境当前读写事务修改的表，提交或回滚时清空
+    static ref PENDING: Mutex<HashMap<u64, HashMap<u64, Atom>>> = Mutex::new(HashMap::new());
+    // 库事务提交统计的观察者
+    static ref COMMIT_OBSERVER: RwLock<Option<CommitObserver>> = RwLock::new(None);
+}
+
+fn counters(env_id: u64, tab: &Atom) -> Arc<Counters> {
+    let key = (env_id, tab.get_hash() as u64);
+    if let Some((_, c)) = STATS.read().unwrap().get(&key) {
+        return c.clone();
+    }
+    STATS.write().unwrap().entry(key).or_insert_with(|| (tab.clone(), Arc::new(Counters::default()))).1.clone()
+}
+
+// 对当前读写事务修改的每个表计数
+fn each_pending<F: Fn(&Counters)>(env_id: u64, f: F) {
+    if let Some(tabs) = PENDING.lock().unwrap().get(&env_id) {
+        for tab in tabs.values() {
+            f(&counters(env_id, tab));
+        }
+    }
+}
+
+// 在写线程中写入键值前调用，记录当前读写事务修改的表
+pub fn touch(env_id: u64, tab: &Atom) {
+    PENDING.lock().unwrap().entry(env_id).or_insert_with(HashMap::new).entry(tab.get_hash() as u64).or_insert_with(|| tab.clone());
+}
+
+// 写入或删除键值失败后调用
+pub fn put_failed(env_id: u64, tab: &Atom, e: &Error) {
+    let c = counters(env_id, tab);
+    c.failed_puts.fetch_add(1, Ordering::Relaxed);
+    if let Error::MapFull = e {
+        c.map_full.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// 读写事务提交失败后调用，MDB_MAP_FULL计入当前读写事务修改的所有表
+pub fn commit_failed(env_id: u64, e: &Error) {
+    if let Error::MapFull = e {
+        each_pending(env_id, |c| {
+            c.map_full.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+}
+
+// 读写事务因临时性错误回滚并将重新执行时调用，在回滚之前调用
+pub fn retried(env_id: u64) {
+    each_pending(env_id, |c| {
+        c.retried.fetch_add(1, Ordering::Relaxed);
+    });
+}
+
+// 读写事务提交成功后调用
+pub fn committed(env_id: u64) {
+    PENDING.lock().unwrap().remove(&env_id);
+}
+
+// 读写事务回滚后调用
+pub fn aborted(env_id: u64) {
+    each_pending(env_id, |c| {
+        c.aborted.fetch_add(1, Ordering::Relaxed);
+    });
+    PENDING.lock().unwrap().remove(&env_id);
+}
+
+/**
+* 获取环境中各表的读写事务失败统计，只包括发生过失败的表
+* @param env_id 环境id
+* @returns 返回各表的统计，按回滚次数从多到少排列
+*/
+pub fn stats(env_id: u64) -> Vec<TabTxnStats> {
+    let mut stats = STATS.read().unwrap().iter()
+        .filter(|((id, _), _)| *id == env_id)
+        .map(|(_, (tab, c))| TabTxnStats {
+            tab: tab.clone(),
+            aborted: c.aborted.load(Ordering::Relaxed),
+            failed_puts: c.failed_puts.load(Ordering::Relaxed),
+            map_full: c.map_full.load(Ordering::Relaxed),
+            retried: c.retried.load(Ordering::Relaxed),
+        })
+        .collect::<Vec<TabTxnStats>>();
+    stats.sort_by(|a, b| b.aborted.cmp(&a.aborted));
+    stats
+}
+
+/**
+* 设置库事务提交统计的观察者，库事务在所有环境都提交成功后调用，为None则取消
+* 观察者在提交回调中调用，应很快返回
+*/
+pub fn set_commit_observer(observer: Option<CommitObserver>) {
+    *COMMIT_OBSERVER.write().unwrap() = observer;
+}
+
+// 库事务在所有环境都提交成功后调用
+pub fn observe_commit(txid: u64, stats: &CommitStats) {
+    if let Some(observer) = COMMIT_OBSERVER.read().unwrap().clone() {
+        observer(txid, stats);
+    }
+}
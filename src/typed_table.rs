@@ -0,0 +1,128 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use bon::{Decode, Encode, ReadBuffer, WriteBuffer};
+
+use pi_db::db::{Bin, DBResult, Iter, IterResult, NextResult, SResult, TabKV, TabTxn, TxCallback};
+
+use atom::Atom;
+
+/**
+* 类型化的表，用bon编解码键和值，调用者不需要手工打包Bin
+*/
+pub struct TypedTable<K, V> {
+    txn: Arc<TabTxn>,
+    ware: Atom,
+    tab: Atom,
+    _kv: PhantomData<(K, V)>,
+}
+
+impl<K: Encode + Decode + 'static, V: Encode + Decode + 'static> TypedTable<K, V> {
+    /**
+    * 构建类型化的表
+    * @param txn 表事务
+    * @param ware 库名
+    * @param tab 表名
+    */
+    pub fn new(txn: Arc<TabTxn>, ware: Atom, tab: Atom) -> Self {
+        TypedTable {
+            txn,
+            ware,
+            tab,
+            _kv: PhantomData,
+        }
+    }
+
+    fn tabkv(&self, key: &K, value: Option<&V>) -> TabKV {
+        TabKV {
+            ware: self.ware.clone(),
+            tab: self.tab.clone(),
+            key: encode(key),
+            index: 0,
+            value: value.map(encode),
+        }
+    }
+
+    // 读取键的值
+    pub fn get(&self, key: &K, cb: Arc<Fn(SResult<Option<V>>)>) -> Option<SResult<Option<V>>> {
+        let r = self.txn.query(Arc::new(vec![self.tabkv(key, None)]), None, false, Arc::new(move |r| {
+            cb(r.and_then(first_value))
+        }));
+        r.map(|r| r.and_then(first_value))
+    }
+
+    // 写入键值
+    pub fn put(&self, key: &K, value: &V, cb: TxCallback) -> DBResult {
+        self.txn.modify(Arc::new(vec![self.tabkv(key, Some(value))]), None, false, cb)
+    }
+
+    // 删除键
+    pub fn del(&self, key: &K, cb: TxCallback) -> DBResult {
+        self.txn.modify(Arc::new(vec![self.tabkv(key, None)]), None, false, cb)
+    }
+
+    /**
+    * 迭代表
+    * @param start 起始键，None从表头或表尾开始
+    * @param descending 与表事务的iter相同，为true时从小到大迭代
+    * @param cb 异步返回迭代器
+    */
+    pub fn scan(
+        &self,
+        start: Option<&K>,
+        descending: bool,
+        cb: Arc<Fn(SResult<TypedIter<K, V>>)>,
+    ) -> Option<SResult<TypedIter<K, V>>> {
+        let r = self.txn.iter(&self.tab, start.map(encode), descending, None, Arc::new(move |r: IterResult| {
+            cb(r.map(TypedIter::new))
+        }));
+        r.map(|r| r.map(TypedIter::new))
+    }
+}
+
+/**
+* 类型化的迭代器
+*/
+pub struct TypedIter<K, V> {
+    inner: Box<Iter<Item = (Bin, Bin)>>,
+    _kv: PhantomData<(K, V)>,
+}
+
+impl<K: Decode + 'static, V: Decode + 'static> TypedIter<K, V> {
+    fn new(inner: Box<Iter<Item = (Bin, Bin)>>) -> Self {
+        TypedIter {
+            inner,
+            _kv: PhantomData,
+        }
+    }
+
+    // 取下一个键值，迭代结束返回None
+    pub fn next(&mut self, cb: Arc<Fn(SResult<Option<(K, V)>>)>) -> Option<SResult<Option<(K, V)>>> {
+        let r = self.inner.next(Arc::new(move |r| cb(decode_item(r))));
+        r.map(decode_item)
+    }
+}
+
+fn encode<T: Encode>(t: &T) -> Bin {
+    let mut buf = WriteBuffer::new();
+    t.encode(&mut buf);
+    Arc::new(buf.unwrap())
+}
+
+fn decode<T: Decode>(bin: &[u8]) -> SResult<T> {
+    T::decode(&mut ReadBuffer::new(bin, 0)).map_err(|e| format!("decode failed: {:?}", e))
+}
+
+fn first_value<V: Decode>(r: Vec<TabKV>) -> SResult<Option<V>> {
+    match r.into_iter().next().and_then(|kv| kv.value) {
+        Some(v) => decode(&v).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn decode_item<K: Decode, V: Decode>(r: NextResult<(Bin, Bin)>) -> SResult<Option<(K, V)>> {
+    match r? {
+        Some((k, v)) => Ok(Some((decode(&k)?, decode(&v)?))),
+        None => Ok(None),
+    }
+}
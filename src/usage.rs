@@ -0,0 +1,128 @@
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::ptr;
+
+use lmdb::{Environment, Error, Transaction};
+use lmdb_sys as ffi;
+
+// LMDB的数据文件
+const DATA_FILE: &str = "data.mdb";
+// 空闲页列表所在的库
+const FREE_DBI: ffi::MDB_dbi = 0;
+
+/**
+* 环境的磁盘和内存映射使用情况
+*/
+#[derive(Debug, Clone)]
+pub struct DiskUsage {
+    pub map_size: usize,        //内存映射的大小，也是数据文件的上限
+    pub page_size: usize,       //页大小
+    pub used_pages: usize,      //已分配的页数，包括空闲页
+    pub free_pages: usize,      //空闲页列表中可以复用的页数
+    pub file_size: u64,         //数据文件在磁盘上的大小
+}
+
+impl DiskUsage {
+    // 内存映射的使用率，不计可以复用的空闲页，接近1时写入会因MDB_MAP_FULL失败
+    pub fn utilization(&self) -> f64 {
+        if self.map_size == 0 {
+            return 0.0;
+        }
+        (self.used_pages.saturating_sub(self.free_pages) * self.page_size) as f64 / self.map_size as f64
+    }
+}
+
+/**
+* LMDB内部空闲页列表的统计，空闲页列表持续增大说明旧的读事务阻止了空闲页回收
+*/
+#[derive(Debug, Clone, Default)]
+pub struct FreelistStats {
+    pub entries: usize,             //空闲页列表的记录数，每条记录对应一个释放过页的写事务
+    pub pages: usize,               //空闲页数
+    pub bytes: usize,               //空闲页占用的字节数
+    pub oldest_txnid: Option<u64>,  //最早释放页的写事务id，远落后于最新事务时说明空闲页长期未被复用
+    pub newest_txnid: Option<u64>,  //最近释放页的写事务id
+}
+
+/**
+* 内存映射的大小、页大小和已分配的页数
+*/
+pub fn map_usage(env: &Environment) -> Result<(usize, usize, usize), Error> {
+    let mut info: ffi::MDB_envinfo = unsafe { mem::zeroed() };
+    match unsafe { ffi::mdb_env_info(env.env(), &mut info) } {
+        ffi::MDB_SUCCESS => (),
+        code => return Err(Error::from_err_code(code)),
+    }
+    let mut stat: ffi::MDB_stat = unsafe { mem::zeroed() };
+    match unsafe { ffi::mdb_env_stat(env.env(), &mut stat) } {
+        ffi::MDB_SUCCESS => (),
+        code => return Err(Error::from_err_code(code)),
+    }
+    Ok((info.me_mapsize as usize, stat.ms_psize as usize, info.me_last_pgno as usize + 1))
+}
+
+// 遍历空闲页列表，每条记录的键是释放页的写事务id，值是页号数组，第一个元素为页数
+fn freelist<T: Transaction>(txn: &T) -> Result<FreelistStats, Error> {
+    let mut cursor: *mut ffi::MDB_cursor = ptr::null_mut();
+    match unsafe { ffi::mdb_cursor_open(txn.txn(), FREE_DBI, &mut cursor) } {
+        ffi::MDB_SUCCESS => (),
+        code => return Err(Error::from_err_code(code)),
+    }
+
+    let mut stats = FreelistStats::default();
+    let mut key: ffi::MDB_val = unsafe { mem::zeroed() };
+    let mut data: ffi::MDB_val = unsafe { mem::zeroed() };
+    let r = loop {
+        match unsafe { ffi::mdb_cursor_get(cursor, &mut key, &mut data, ffi::MDB_NEXT) } {
+            ffi::MDB_SUCCESS => {
+                stats.entries += 1;
+                if key.mv_size >= mem::size_of::<usize>() {
+                    let txnid = unsafe { ptr::read_unaligned(key.mv_data as *const usize) } as u64;
+                    stats.oldest_txnid.get_or_insert(txnid);
+                    stats.newest_txnid = Some(txnid);
+                }
+                if data.mv_size >= mem::size_of::<usize>() {
+                    stats.pages += unsafe { ptr::read_unaligned(data.mv_data as *const usize) };
+                }
+            }
+            ffi::MDB_NOTFOUND => break Ok(stats),
+            code => break Err(Error::from_err_code(code)),
+        }
+    };
+    unsafe { ffi::mdb_cursor_close(cursor) };
+    r
+}
+
+/**
+* 获取环境的磁盘和内存映射使用情况，只持有一个读事务
+* @param env LMDB环境
+* @param path 环境所在的目录
+*/
+pub fn disk_usage(env: &Environment, path: &str) -> Result<DiskUsage, String> {
+    let (map_size, page_size, used_pages) = map_usage(env).map_err(|e| e.to_string())?;
+    let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+    let free = freelist(&txn).map(|stats| stats.pages).map_err(|e| e.to_string());
+    txn.commit().map_err(|e| e.to_string())?;
+    let file_size = fs::metadata(Path::new(path).join(DATA_FILE)).map_err(|e| e.to_string())?.len();
+
+    Ok(DiskUsage {
+        map_size,
+        page_size,
+        used_pages,
+        free_pages: free?,
+        file_size,
+    })
+}
+
+/**
+* 获取环境的空闲页列表统计，只持有一个读事务，遍历整个空闲页列表
+* @param env LMDB环境
+*/
+pub fn freelist_stats(env: &Environment) -> Result<FreelistStats, String> {
+    let (_, page_size, _) = map_usage(env).map_err(|e| e.to_string())?;
+    let txn = env.begin_ro_txn().map_err(|e| e.to_string())?;
+    let r = freelist(&txn).map_err(|e| e.to_string());
+    txn.commit().map_err(|e| e.to_string())?;
+    r.map(|stats| FreelistStats { bytes: stats.pages * page_size, ..stats })
+}
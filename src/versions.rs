@@ -0,0 +1,95 @@
+use lmdb::{Database, Error, RwTransaction, Transaction, WriteFlags};
+
+use atom::Atom;
+
+use crate::pool::lookup_db;
+
+// 存放键版本的影子表，每个环境一个
+pub const VERSIONS_TAB: &str = "_$versions";
+
+// 影子表的键: 表名哈希 + 原键
+fn version_key(tab: &Atom, key: &[u8]) -> Vec<u8> {
+    let mut k = Vec::with_capacity(8 + key.len());
+    k.extend_from_slice(&(tab.get_hash() as u64).to_be_bytes());
+    k.extend_from_slice(key);
+    k
+}
+
+fn versions_db(env_id: u64) -> Option<Database> {
+    lookup_db(env_id, &Atom::from(VERSIONS_TAB))
+}
+
+// 已删除的键保留最后的版本，值为版本加删除标记，重新写入时从该版本继续递增
+const DELETED: u8 = 1;
+
+/**
+* 读取键的版本，不存在或已删除的键版本为0，每次写入和删除版本都加1
+* 删除后重新写入的键从删除时的版本继续递增，不会回到之前出现过的版本
+*/
+pub fn get<T: Transaction>(txn: &T, env_id: u64, tab: &Atom, key: &[u8]) -> Result<u64, Error> {
+    match last(txn, env_id, tab, key)? {
+        (_, true) => Ok(0),
+        (version, false) => Ok(version),
+    }
+}
+
+// 读取键最后的版本和是否已删除
+fn last<T: Transaction>(txn: &T, env_id: u64, tab: &Atom, key: &[u8]) -> Result<(u64, bool), Error> {
+    let db = match versions_db(env_id) {
+        Some(db) => db,
+        None => return Ok((0, false)),
+    };
+    match txn.get(db, &version_key(tab, key)) {
+        Ok(v) if v.len() == 8 || (v.len() == 9 && v[8] == DELETED) => {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&v[..8]);
+            Ok((u64::from_be_bytes(b), v.len() == 9))
+        }
+        Ok(_) => Err(Error::Corrupted),
+        Err(Error::NotFound) => Ok((0, false)),
+        Err(e) => Err(e),
+    }
+}
+
+// 写入键后增加版本
+pub fn bump(txn: &mut RwTransaction, env_id: u64, tab: &Atom, key: &[u8]) -> Result<u64, Error> {
+    let db = match versions_db(env_id) {
+        Some(db) => db,
+        None => return Ok(0),
+    };
+    let version = last(&*txn, env_id, tab, key)?.0 + 1;
+    txn.put(db, &version_key(tab, key), &version.to_be_bytes(), WriteFlags::empty())?;
+    Ok(version)
+}
+
+// 删除键后增加版本并标记为已删除，保留版本使重新写入的键不会重复之前的版本
+pub fn del(txn: &mut RwTransaction, env_id: u64, tab: &Atom, key: &[u8]) -> Result<(), Error> {
+    let db = match versions_db(env_id) {
+        Some(db) => db,
+        None => return Ok(()),
+    };
+    let (version, deleted) = last(&*txn, env_id, tab, key)?;
+    if deleted || version == 0 {
+        // 从未写入或已删除，版本不变
+        return Ok(());
+    }
+    let mut v = (version + 1).to_be_bytes().to_vec();
+    v.push(DELETED);
+    txn.put(db, &version_key(tab, key), &v, WriteFlags::empty())
+}
+
+// 重命名表时把键的版本移到新表名下，保留删除标记
+pub fn rename(txn: &mut RwTransaction, env_id: u64, old: &Atom, new: &Atom, key: &[u8]) -> Result<(), Error> {
+    let db = match versions_db(env_id) {
+        Some(db) => db,
+        None => return Ok(()),
+    };
+    let old_key = version_key(old, key);
+    let v = match txn.get(db, &old_key) {
+        Ok(v) => v.to_vec(),
+        Err(Error::NotFound) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    txn.put(db, &version_key(new, key), &v, WriteFlags::empty())?;
+    txn.del(db, &old_key, None)
+}
@@ -0,0 +1,50 @@
+use std::borrow::Cow;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use pi_db::db::{Bin, SResult};
+
+/**
+* 值的只读视图，直接指向LMDB的内存映射，生命周期受读事务约束，读事务结束后不能再访问
+* 分块存储的值需要重组，无法零拷贝，此时视图持有重组后的副本
+*/
+pub struct ValueView<'txn> {
+    data: Cow<'txn, [u8]>,
+}
+
+impl<'txn> ValueView<'txn> {
+    pub fn borrowed(data: &'txn [u8]) -> Self {
+        ValueView { data: Cow::Borrowed(data) }
+    }
+
+    pub fn owned(data: Vec<u8>) -> Self {
+        ValueView { data: Cow::Owned(data) }
+    }
+
+    // 是否直接指向内存映射
+    pub fn is_borrowed(&self) -> bool {
+        match self.data {
+            Cow::Borrowed(_) => true,
+            Cow::Owned(_) => false,
+        }
+    }
+
+    // 复制为可以在读事务结束后使用的值
+    pub fn to_bin(&self) -> Bin {
+        Arc::new(self.data.to_vec())
+    }
+}
+
+impl<'txn> Deref for ValueView<'txn> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/**
+* 零拷贝查询的回调，在读线程中同步调用，参数为每个查询键的值视图，不存在的键为None
+* 回调返回后读事务即结束，视图不能被保存；回调应尽快返回，不能阻塞读线程
+*/
+pub type ViewCallback = Box<for<'txn> FnMut(SResult<Vec<Option<ValueView<'txn>>>>) + Send>;
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use pi_db::db::Ware;
+
+use atom::Atom;
+
+use crate::env_builder::EnvBuilder;
+use crate::lmdb_file::DB;
+use crate::pool::LmdbService;
+
+// 默认的读线程数，与DB::new相同
+const DEFAULT_READERS: usize = 17;
+
+/**
+* 库的配置，每个库使用独立的环境和读写线程
+*/
+#[derive(Debug, Clone)]
+pub enum WareConfig {
+    Memory,                 //纯内存库，不创建文件
+    Lmdb {
+        path: String,           //数据库路径
+        db_size: usize,         //数据库文件的最大大小
+        readers: usize,         //读线程数，0表示使用默认值
+        queue_capacity: usize,  //每个读写线程通道的容量，0表示不限制
+        max_readers: u32,       //最大读事务数量，0表示使用默认值
+        max_dbs: u32,           //最大表数量，0表示使用默认值
+    },
+    Rocks(String),          //基于RocksDB的库，参数为数据库路径
+}
+
+// 已注册的库，由配置构建的库同时保存DB，以便调用DB特有的接口
+#[derive(Clone)]
+struct WareEntry {
+    ware: Arc<Ware>,
+    db: Option<DB>,
+}
+
+/**
+* 库的注册表，将pi_db的库名映射到库的实例，同一个实例中可以同时存在内存库、文件库和日志库等
+* 每个库的环境、线程池和配置相互独立
+*/
+#[derive(Clone, Default)]
+pub struct WareRegistry(Arc<RwLock<HashMap<Atom, WareEntry>>>);
+
+impl WareRegistry {
+    pub fn new() -> Self {
+        WareRegistry::default()
+    }
+
+    /**
+    * 按配置构建库并注册，同名的库已存在时返回错误
+    * @param name 库名，如"memory"、"file"
+    * @param config 库的配置
+    * @returns 返回构建的库，失败返回原因描述
+    */
+    pub fn open(&self, name: Atom, config: &WareConfig) -> Result<DB, String> {
+        if self.contains(&name) {
+            return Err(format!("ware already registered: {:?}", name.as_str()));
+        }
+        let db = match config {
+            WareConfig::Memory => DB::new_in_memory(name.clone())?,
+            WareConfig::Lmdb { path, db_size, readers, queue_capacity, max_readers, max_dbs } => {
+                let mut service = LmdbService::new(if *readers == 0 { DEFAULT_READERS } else { *readers });
+                service.set_queue_capacity(*queue_capacity);
+                let mut builder = EnvBuilder::new(path, *db_size);
+                if *max_readers > 0 {
+                    builder = builder.max_readers(*max_readers);
+                }
+                if *max_dbs > 0 {
+                    builder = builder.max_dbs(*max_dbs);
+                }
+                DB::new_with_env(builder, service)?
+            }
+            WareConfig::Rocks(path) => DB::new_rocksdb(Atom::from(path.as_str()))?,
+        };
+        self.insert(name, Arc::new(db.clone()), Some(db.clone()))?;
+        Ok(db)
+    }
+
+    /**
+    * 注册已构建的库，用于其它实现了Ware的库，如日志库
+    * @param name 库名
+    * @param ware 库
+    */
+    pub fn register(&self, name: Atom, ware: Arc<Ware>) -> Result<(), String> {
+        self.insert(name, ware, None)
+    }
+
+    fn insert(&self, name: Atom, ware: Arc<Ware>, db: Option<DB>) -> Result<(), String> {
+        let mut wares = self.0.write().unwrap();
+        if wares.contains_key(&name) {
+            return Err(format!("ware already registered: {:?}", name.as_str()));
+        }
+        debug!("register ware: {:?}", name.as_str());
+        wares.insert(name, WareEntry { ware, db });
+        Ok(())
+    }
+
+    // 注销库，不关闭库的环境，返回被注销的库
+    pub fn unregister(&self, name: &Atom) -> Option<Arc<Ware>> {
+        self.0.write().unwrap().remove(name).map(|e| e.ware)
+    }
+
+    // 获取指定名称的库
+    pub fn get(&self, name: &Atom) -> Option<Arc<Ware>> {
+        self.0.read().unwrap().get(name).map(|e| e.ware.clone())
+    }
+
+    // 获取按配置构建的库，注册的其它库返回None
+    pub fn get_db(&self, name: &Atom) -> Option<DB> {
+        self.0.read().unwrap().get(name).and_then(|e| e.db.clone())
+    }
+
+    pub fn contains(&self, name: &Atom) -> bool {
+        self.0.read().unwrap().contains_key(name)
+    }
+
+    // 列出所有库名
+    pub fn list(&self) -> Vec<Atom> {
+        self.0.read().unwrap().keys().cloned().collect()
+    }
+}
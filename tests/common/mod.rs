@@ -0,0 +1,142 @@
+// 各测试共用的夹具，每个测试文件只用到其中一部分
+#![allow(dead_code)]
+
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use lmdb::{DatabaseFlags, Environment};
+use tempdir::TempDir;
+
+use atom::Atom;
+use guid::GuidGen;
+use sinfo::EnumType;
+
+use pi_db::db::{Bin, SResult, TabKV, TabMeta};
+use pi_db::mgr::Mgr;
+
+use pi_store::lmdb_file::DB;
+use pi_store::pool::{register_db, Condition, LmdbPool, LmdbService, Precondition, SendCallback, StoreError, WriterGuard};
+
+pub fn bin(s: &str) -> Bin {
+    Arc::new(s.as_bytes().to_vec())
+}
+
+pub fn create_tabkv(ware: &str, tab: &str, key: &str, value: Option<&str>) -> TabKV {
+    TabKV {
+        ware: Atom::from(ware),
+        tab: Atom::from(tab),
+        key: bin(key),
+        index: 0,
+        value: value.map(bin),
+    }
+}
+
+pub fn condition(tab: &str, key: &str, expect: Precondition) -> Condition {
+    Condition {
+        tab: Atom::from(tab),
+        key: bin(key),
+        expect,
+    }
+}
+
+/**
+* 在临时目录中打开环境，创建并登记表后启动工作线程
+* 不经过全局的服务池，每个测试使用不同的环境id和表名
+*/
+pub fn open(dir: &TempDir, env_id: u64, tabs: &[&str], mut service: LmdbService) -> LmdbPool {
+    let env = Environment::new()
+        .set_max_dbs(16)
+        .set_map_size(1024 * 1024 * 10)
+        .open(dir.path())
+        .unwrap();
+    for tab in tabs {
+        let db = env.create_db(Some(tab), DatabaseFlags::empty()).unwrap();
+        register_db(env_id, &Atom::from(*tab), db).unwrap();
+    }
+    service.set_env(Arc::new(env));
+    let mut pool = LmdbPool::new();
+    pool.add_service(env_id, service);
+    pool
+}
+
+// 等待工作线程的回调
+pub fn wait<T: Send + 'static, F: FnOnce(SendCallback<T>) -> Result<(), StoreError>>(call: F) -> T {
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    call(Arc::new(move |r| {
+        let _ = tx.lock().unwrap().send(r);
+    }))
+    .unwrap();
+    rx.recv_timeout(Duration::from_secs(10)).expect("wait callback timeout")
+}
+
+// 等待pi_db接口同步返回或通过回调返回的结果
+pub fn wait_db<T: 'static, F: FnOnce(Arc<Fn(T)>) -> Option<T>>(call: F) -> T {
+    let slot = Arc::new(Mutex::new(None));
+    let slot1 = slot.clone();
+    if let Some(r) = call(Arc::new(move |r| *slot1.lock().unwrap() = Some(r))) {
+        return r;
+    }
+    for _ in 0..200 {
+        if let Some(r) = slot.lock().unwrap().take() {
+            return r;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("wait result timeout");
+}
+
+// 在读线程中查询
+pub fn get(pool: &LmdbPool, env_id: u64, tab: &str, key: &str) -> Option<Bin> {
+    let handle = pool.service_by_env(env_id).unwrap().txn_handle(&Atom::from(tab), 0, false).unwrap();
+    let r: SResult<Vec<TabKV>> = wait(|cb| handle.query(Arc::new(vec![create_tabkv("", tab, key, None)]), cb, None));
+    r.unwrap()[0].value.clone()
+}
+
+// 取得写线程的使用权，预提交并提交一个读写事务
+pub fn commit(pool: &LmdbPool, env_id: u64, txid: u64, modifies: Vec<TabKV>, conditions: Vec<Condition>) -> SResult<()> {
+    let handle = pool.service_by_env(env_id).unwrap().txn_handle(&modifies[0].tab, txid, true).unwrap();
+    let _writer = WriterGuard::checkout(env_id, txid).unwrap();
+    wait(|cb| handle.prepare(Arc::new(modifies), Arc::new(conditions), cb))?;
+    wait(|cb| handle.commit_prepared(cb))
+}
+
+/**
+* 在临时目录中打开库并注册到事务管理器，库名为临时目录的路径
+* @returns 返回事务管理器、库和库名
+*/
+pub fn open_ware(dir: &TempDir) -> (Arc<Mgr>, Arc<DB>, String) {
+    let ware = dir.path().to_string_lossy().to_string();
+    let mgr = Mgr::new(GuidGen::new(1, 1));
+    let db = Arc::new(DB::new(Atom::from(ware.as_str()), 1024 * 1024 * 10).unwrap());
+    mgr.register(Atom::from(ware.as_str()), db.clone());
+    (Arc::new(mgr), db, ware)
+}
+
+// 在新事务中创建表并提交
+pub fn create_tab(mgr: &Arc<Mgr>, ware: &str, tab: &str) {
+    let tr = mgr.transaction(true);
+    let meta = Arc::new(TabMeta {
+        k: EnumType::Str,
+        v: EnumType::Str,
+    });
+    tr.alter(&Atom::from(ware), &Atom::from(tab), Some(meta), Arc::new(|a| assert!(a.is_ok())));
+    thread::sleep(Duration::from_millis(500));
+    tr.prepare(Arc::new(|p| assert!(p.is_ok())));
+    thread::sleep(Duration::from_millis(500));
+    tr.commit(Arc::new(|c| assert!(c.is_ok())));
+    thread::sleep(Duration::from_millis(500));
+}
+
+// 在新事务中修改并提交
+pub fn modify(mgr: &Arc<Mgr>, arr: Vec<TabKV>) {
+    let tr = mgr.transaction(true);
+    tr.modify(Arc::new(arr), None, false, Arc::new(|m| assert!(m.is_ok())));
+    thread::sleep(Duration::from_millis(500));
+    tr.prepare(Arc::new(|p| assert!(p.is_ok())));
+    thread::sleep(Duration::from_millis(500));
+    tr.commit(Arc::new(|c| assert!(c.is_ok())));
+    thread::sleep(Duration::from_millis(500));
+}
@@ -0,0 +1,61 @@
+extern crate pi_db;
+extern crate pi_store;
+
+extern crate atom;
+
+mod common;
+
+use atom::Atom;
+
+use pi_store::backend::Backend;
+use pi_store::mem_store::MemStore;
+
+use common::{bin, create_tabkv};
+
+const WARE: &str = "memdb";
+
+#[test]
+fn test_mem_store_query_and_commit() {
+    let store = MemStore::new();
+    store.commit(&vec![
+        create_tabkv(WARE, "tab_a", "k1", Some("v1")),
+        create_tabkv(WARE, "tab_a", "k2", Some("v2")),
+        create_tabkv(WARE, "tab_b", "k1", Some("b1")),
+    ]).unwrap();
+
+    let qr = store.query(&vec![
+        create_tabkv(WARE, "tab_a", "k1", None),
+        create_tabkv(WARE, "tab_a", "k3", None),
+        create_tabkv(WARE, "tab_b", "k1", None),
+    ]).unwrap();
+    assert_eq!(qr[0].value, Some(bin("v1")));
+    assert_eq!(qr[1].value, None);
+    assert_eq!(qr[2].value, Some(bin("b1")));
+
+    store.commit(&vec![create_tabkv(WARE, "tab_a", "k1", None)]).unwrap();
+    let qr = store.query(&vec![create_tabkv(WARE, "tab_a", "k1", None)]).unwrap();
+    assert_eq!(qr[0].value, None);
+}
+
+#[test]
+fn test_mem_store_iter() {
+    let store = MemStore::new();
+    let tab = Atom::from("tab_iter");
+    store.commit(&vec![
+        create_tabkv(WARE, "tab_iter", "a", Some("1")),
+        create_tabkv(WARE, "tab_iter", "b", Some("2")),
+        create_tabkv(WARE, "tab_iter", "c", Some("3")),
+    ]).unwrap();
+
+    assert_eq!(store.first_key(&tab, true, None), Some(bin("a")));
+    assert_eq!(store.first_key(&tab, false, None), Some(bin("c")));
+    assert_eq!(store.first_key(&tab, true, Some(bin("bb"))), Some(bin("c")));
+
+    let (v, next) = store.next_item(&tab, true, &bin("a"));
+    assert_eq!(v, Some(bin("1")));
+    assert_eq!(next, Some(bin("b")));
+
+    let (v, next) = store.next_item(&tab, false, &bin("a"));
+    assert_eq!(v, Some(bin("1")));
+    assert_eq!(next, None);
+}
@@ -0,0 +1,347 @@
+extern crate lmdb;
+extern crate pi_db;
+extern crate pi_store;
+extern crate tempdir;
+
+extern crate atom;
+
+mod common;
+
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tempdir::TempDir;
+
+use atom::Atom;
+
+use pi_store::cdc;
+use pi_store::pool::{channel, lookup_db, set_write_coalescing, Dispatch, LmdbService, Precondition, StoreError, WriteCallback, WriterGuard, WriterMsg};
+use pi_store::quota::{self, Quota};
+
+use common::{bin, commit, condition, create_tabkv, get, open, wait};
+
+const WARE: &str = "pooldb";
+
+#[test]
+fn test_preconditions_fail_whole_batch() {
+    let dir = TempDir::new("pi_store_pool").unwrap();
+    let pool = open(&dir, 307, &["cond_tab"], LmdbService::new(2));
+    commit(&pool, 307, 1, vec![create_tabkv(WARE, "cond_tab", "a", Some("1"))], vec![]).unwrap();
+
+    // 任一条件不满足，同一批的修改都不写入
+    let r = commit(
+        &pool,
+        307,
+        2,
+        vec![create_tabkv(WARE, "cond_tab", "b", Some("2")), create_tabkv(WARE, "cond_tab", "a", Some("3"))],
+        vec![condition("cond_tab", "b", Precondition::MustNotExist), condition("cond_tab", "a", Precondition::MustNotExist)],
+    );
+    assert_eq!(r, Err(StoreError::PreconditionFailed.to_string()));
+    assert_eq!(get(&pool, 307, "cond_tab", "a"), Some(bin("1")));
+    assert_eq!(get(&pool, 307, "cond_tab", "b"), None);
+
+    // 所有条件都满足时写入
+    let r = commit(
+        &pool,
+        307,
+        3,
+        vec![create_tabkv(WARE, "cond_tab", "b", Some("2")), create_tabkv(WARE, "cond_tab", "a", Some("3"))],
+        vec![condition("cond_tab", "b", Precondition::MustNotExist), condition("cond_tab", "a", Precondition::MustEqual(bin("1")))],
+    );
+    assert_eq!(r, Ok(()));
+    assert_eq!(get(&pool, 307, "cond_tab", "a"), Some(bin("3")));
+    assert_eq!(get(&pool, 307, "cond_tab", "b"), Some(bin("2")));
+}
+
+#[test]
+fn test_version_survives_delete() {
+    let dir = TempDir::new("pi_store_pool").unwrap();
+    let pool = open(&dir, 315, &["version_tab", "_$versions"], LmdbService::new(2));
+    commit(&pool, 315, 1, vec![create_tabkv(WARE, "version_tab", "a", Some("1"))], vec![]).unwrap();
+    commit(&pool, 315, 2, vec![create_tabkv(WARE, "version_tab", "a", None)], vec![]).unwrap();
+
+    // 删除后的键版本为0，重新写入后版本从删除时继续递增，不会回到1
+    let r = commit(&pool, 315, 3, vec![create_tabkv(WARE, "version_tab", "a", Some("2"))], vec![condition("version_tab", "a", Precondition::Version(0))]);
+    assert_eq!(r, Ok(()));
+    let r = commit(&pool, 315, 4, vec![create_tabkv(WARE, "version_tab", "a", Some("3"))], vec![condition("version_tab", "a", Precondition::Version(1))]);
+    assert_eq!(r, Err(StoreError::PreconditionFailed.to_string()));
+    let r = commit(&pool, 315, 5, vec![create_tabkv(WARE, "version_tab", "a", Some("3"))], vec![condition("version_tab", "a", Precondition::Version(3))]);
+    assert_eq!(r, Ok(()));
+    assert_eq!(get(&pool, 315, "version_tab", "a"), Some(bin("3")));
+}
+
+#[test]
+fn test_prepare_then_commit_or_rollback() {
+    let dir = TempDir::new("pi_store_pool").unwrap();
+    let pool = open(&dir, 313, &["two_phase_tab"], LmdbService::new(2));
+    let service = pool.service_by_env(313).unwrap();
+
+    // 预提交的修改在提交前对读线程不可见
+    let handle = service.txn_handle(&Atom::from("two_phase_tab"), 1, true).unwrap();
+    let writer = WriterGuard::checkout(313, 1).unwrap();
+    let r = wait(|cb| handle.prepare(Arc::new(vec![create_tabkv(WARE, "two_phase_tab", "a", Some("1"))]), Arc::new(Vec::new()), cb));
+    assert_eq!(r, Ok(()));
+    assert_eq!(get(&pool, 313, "two_phase_tab", "a"), None);
+    assert_eq!(wait(|cb| handle.commit_prepared(cb)), Ok(()));
+    assert_eq!(get(&pool, 313, "two_phase_tab", "a"), Some(bin("1")));
+    drop(writer);
+
+    // 回滚预提交的修改
+    let handle = service.txn_handle(&Atom::from("two_phase_tab"), 2, true).unwrap();
+    let writer = WriterGuard::checkout(313, 2).unwrap();
+    let r = wait(|cb| handle.prepare(Arc::new(vec![create_tabkv(WARE, "two_phase_tab", "b", Some("2"))]), Arc::new(Vec::new()), cb));
+    assert_eq!(r, Ok(()));
+    assert_eq!(wait(|cb| handle.rollback(cb)), Ok(()));
+    assert_eq!(get(&pool, 313, "two_phase_tab", "b"), None);
+    drop(writer);
+
+    // 没有预提交的事务不能提交
+    let handle = service.txn_handle(&Atom::from("two_phase_tab"), 3, true).unwrap();
+    assert!(wait(|cb| handle.commit_prepared(cb)).is_err());
+}
+
+#[test]
+fn test_multi_table_txn_is_atomic() {
+    let dir = TempDir::new("pi_store_pool").unwrap();
+    let pool = open(&dir, 314, &["multi_tab_a", "multi_tab_b"], LmdbService::new(2));
+
+    // 一个读写事务写入两个表
+    let r = commit(
+        &pool,
+        314,
+        1,
+        vec![create_tabkv(WARE, "multi_tab_a", "k", Some("a1")), create_tabkv(WARE, "multi_tab_b", "k", Some("b1"))],
+        vec![],
+    );
+    assert_eq!(r, Ok(()));
+    assert_eq!(get(&pool, 314, "multi_tab_a", "k"), Some(bin("a1")));
+    assert_eq!(get(&pool, 314, "multi_tab_b", "k"), Some(bin("b1")));
+
+    // 另一个表的条件不满足，两个表都不写入
+    let r = commit(
+        &pool,
+        314,
+        2,
+        vec![create_tabkv(WARE, "multi_tab_a", "k", Some("a2")), create_tabkv(WARE, "multi_tab_b", "k", Some("b2"))],
+        vec![condition("multi_tab_b", "k", Precondition::MustNotExist)],
+    );
+    assert_eq!(r, Err(StoreError::PreconditionFailed.to_string()));
+    assert_eq!(get(&pool, 314, "multi_tab_a", "k"), Some(bin("a1")));
+    assert_eq!(get(&pool, 314, "multi_tab_b", "k"), Some(bin("b1")));
+}
+
+#[test]
+fn test_coalesced_writes_flush_together() {
+    let dir = TempDir::new("pi_store_pool").unwrap();
+    let pool = open(&dir, 330, &["coalesce_tab"], LmdbService::new(2));
+    let service = pool.service_by_env(330).unwrap();
+
+    // 达到记录数阈值前不提交，达到后一起提交并调用各批的回调
+    set_write_coalescing(1024 * 1024, 3, 60 * 1000);
+    let (tx, rx) = mpsc::channel();
+    let tx = Arc::new(Mutex::new(tx));
+    for key in ["a", "b"].iter() {
+        let tx = tx.clone();
+        let cb: WriteCallback = Arc::new(move |r| {
+            let _ = tx.lock().unwrap().send(r);
+        });
+        service.try_rw_send(WriterMsg::Coalesce(Arc::new(vec![create_tabkv(WARE, "coalesce_tab", key, Some("1"))]), cb)).unwrap();
+    }
+    thread::sleep(Duration::from_millis(200));
+    assert!(rx.try_recv().is_err());
+    assert_eq!(get(&pool, 330, "coalesce_tab", "a"), None);
+
+    let r = wait(|cb| service.try_rw_send(WriterMsg::Coalesce(Arc::new(vec![create_tabkv(WARE, "coalesce_tab", "c", Some("1"))]), cb)));
+    assert_eq!(r, Ok(()));
+    for _ in 0..2 {
+        assert_eq!(rx.recv_timeout(Duration::from_secs(10)).unwrap(), Ok(()));
+    }
+    for key in ["a", "b", "c"].iter() {
+        assert_eq!(get(&pool, 330, "coalesce_tab", key), Some(bin("1")));
+    }
+
+    // 未达到大小阈值时按时间刷新
+    set_write_coalescing(1024 * 1024, 1024, 100);
+    let r = wait(|cb| service.try_rw_send(WriterMsg::Coalesce(Arc::new(vec![create_tabkv(WARE, "coalesce_tab", "d", Some("1"))]), cb)));
+    assert_eq!(r, Ok(()));
+    assert_eq!(get(&pool, 330, "coalesce_tab", "d"), Some(bin("1")));
+}
+
+#[test]
+fn test_rename_table() {
+    let dir = TempDir::new("pi_store_pool").unwrap();
+    let pool = open(&dir, 343, &["rename_old", "rename_other"], LmdbService::new(2));
+    let service = pool.service_by_env(343).unwrap();
+    let r = commit(
+        &pool,
+        343,
+        1,
+        vec![create_tabkv(WARE, "rename_old", "a", Some("1")), create_tabkv(WARE, "rename_old", "b", Some("2"))],
+        vec![],
+    );
+    assert_eq!(r, Ok(()));
+
+    // 不能重命名为已存在的表
+    let r = wait(|cb| service.try_rw_send(WriterMsg::RenameDb(Atom::from("rename_old"), Atom::from("rename_other"), Arc::new(Vec::new()), cb)));
+    assert!(r.is_err());
+
+    let consumer = cdc::subscribe(0);
+    let r = wait(|cb| service.try_rw_send(WriterMsg::RenameDb(Atom::from("rename_old"), Atom::from("rename_new"), Arc::new(Vec::new()), cb)));
+    assert_eq!(r, Ok(()));
+    assert!(lookup_db(343, &Atom::from("rename_old")).is_none());
+    assert_eq!(get(&pool, 343, "rename_new", "a"), Some(bin("1")));
+    assert_eq!(get(&pool, 343, "rename_new", "b"), Some(bin("2")));
+
+    // 重命名只产生一个变更事件，不逐条发送记录的写入和删除
+    let events = loop {
+        let events = consumer.receiver().recv_timeout(Duration::from_secs(10)).unwrap();
+        if events.iter().any(|e| e.env_id == 343) {
+            break events;
+        }
+    };
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].tab, Atom::from("rename_new"));
+    assert_eq!(events[0].renamed_from, Some(Atom::from("rename_old")));
+}
+
+#[test]
+fn test_copy_table() {
+    let dir = TempDir::new("pi_store_pool").unwrap();
+    let pool = open(&dir, 344, &["copy_src", "copy_full"], LmdbService::new(2));
+    let service = pool.service_by_env(344).unwrap();
+    let r = commit(
+        &pool,
+        344,
+        1,
+        vec![
+            create_tabkv(WARE, "copy_src", "a", Some("1")),
+            create_tabkv(WARE, "copy_src", "b", Some("2")),
+            create_tabkv(WARE, "copy_full", "a", Some("x")),
+        ],
+        vec![],
+    );
+    assert_eq!(r, Ok(()));
+
+    // 目标表已存在时必须为空
+    let r = wait(|cb| service.try_rw_send(WriterMsg::CopyDb(Atom::from("copy_src"), Atom::from("copy_full"), Arc::new(Vec::new()), cb)));
+    assert!(r.is_err());
+    assert_eq!(get(&pool, 344, "copy_full", "b"), None);
+
+    // 目标表不存在则创建，源表保持不变
+    let r = wait(|cb| service.try_rw_send(WriterMsg::CopyDb(Atom::from("copy_src"), Atom::from("copy_dst"), Arc::new(Vec::new()), cb)));
+    assert_eq!(r, Ok(()));
+    for (key, value) in [("a", "1"), ("b", "2")].iter() {
+        assert_eq!(get(&pool, 344, "copy_src", key), Some(bin(value)));
+        assert_eq!(get(&pool, 344, "copy_dst", key), Some(bin(value)));
+    }
+}
+
+#[test]
+fn test_bulk_load_sorted() {
+    let dir = TempDir::new("pi_store_pool").unwrap();
+    let pool = open(&dir, 350, &["bulk_tab", "bulk_unsorted"], LmdbService::new(2));
+    let service = pool.service_by_env(350).unwrap();
+
+    let (tx, rx) = channel(0);
+    for i in 0..10 {
+        tx.send((bin(&format!("key{:02}", i)), bin(&i.to_string()))).unwrap();
+    }
+    drop(tx);
+    let r = wait(|cb| service.try_rw_send(WriterMsg::BulkLoad(Atom::from("bulk_tab"), rx, 3, cb)));
+    assert_eq!(r, Ok(10));
+    assert_eq!(get(&pool, 350, "bulk_tab", "key00"), Some(bin("0")));
+    assert_eq!(get(&pool, 350, "bulk_tab", "key09"), Some(bin("9")));
+
+    // 键不是升序时失败，已提交的批次保留，错误中包含已提交的记录数，之后的记录被丢弃
+    let (tx, rx) = channel(0);
+    for key in ["a", "b", "c", "b", "d", "e"].iter() {
+        tx.send((bin(key), bin("1"))).unwrap();
+    }
+    drop(tx);
+    let r = wait(|cb| service.try_rw_send(WriterMsg::BulkLoad(Atom::from("bulk_unsorted"), rx, 2, cb)));
+    let e = r.unwrap_err();
+    assert!(e.contains("failed after 2 records"));
+    assert!(e.contains("keys not ascending"));
+    assert_eq!(get(&pool, 350, "bulk_unsorted", "b"), Some(bin("1")));
+    assert_eq!(get(&pool, 350, "bulk_unsorted", "c"), None);
+    assert_eq!(get(&pool, 350, "bulk_unsorted", "d"), None);
+}
+
+#[test]
+fn test_quota_rejects_writes() {
+    let dir = TempDir::new("pi_store_pool").unwrap();
+    let pool = open(&dir, 367, &["quota_tab"], LmdbService::new(2));
+    let tab = Atom::from("quota_tab");
+    quota::set_quota(&tab, Some(Quota { max_bytes: None, max_entries: Some(2) }));
+
+    // 超出配额时整个事务不写入
+    let r = commit(
+        &pool,
+        367,
+        1,
+        vec![
+            create_tabkv(WARE, "quota_tab", "a", Some("1")),
+            create_tabkv(WARE, "quota_tab", "b", Some("2")),
+            create_tabkv(WARE, "quota_tab", "c", Some("3")),
+        ],
+        vec![],
+    );
+    assert_eq!(r, Err(StoreError::QuotaExceeded.to_string()));
+    assert_eq!(get(&pool, 367, "quota_tab", "a"), None);
+
+    let r = commit(&pool, 367, 2, vec![create_tabkv(WARE, "quota_tab", "a", Some("1")), create_tabkv(WARE, "quota_tab", "b", Some("2"))], vec![]);
+    assert_eq!(r, Ok(()));
+    let r = commit(&pool, 367, 3, vec![create_tabkv(WARE, "quota_tab", "c", Some("3"))], vec![]);
+    assert_eq!(r, Err(StoreError::QuotaExceeded.to_string()));
+
+    let env = pool.service_by_env(367).unwrap().get_env();
+    let txn = env.begin_ro_txn().unwrap();
+    let usage = quota::usage(&txn, lookup_db(367, &tab).unwrap(), &tab).unwrap();
+    assert_eq!(usage.entries, 2);
+    assert_eq!(usage.quota, Some(Quota { max_bytes: None, max_entries: Some(2) }));
+    quota::set_quota(&tab, None);
+}
+
+#[test]
+fn test_pinned_handle_after_scale_down() {
+    let dir = TempDir::new("pi_store_pool").unwrap();
+    let mut service = LmdbService::new(2);
+    service.set_dispatch(Dispatch::TabHash);
+    service.set_idle_scale_down(1, 1);
+    let pool = open(&dir, 370, &["pinned_tab"], service);
+    commit(&pool, 370, 1, vec![create_tabkv(WARE, "pinned_tab", "a", Some("1"))], vec![]).unwrap();
+
+    // 按表名哈希选择第二个读线程，该读线程空闲后会退出
+    let route = (0..)
+        .map(|i| Atom::from(format!("pinned_route{}", i).as_str()))
+        .find(|tab| tab.get_hash() as usize % 2 == 1)
+        .unwrap();
+    let service = pool.service_by_env(370).unwrap();
+    let handle = service.txn_handle(&route, 0, false).unwrap();
+    thread::sleep(Duration::from_millis(2500));
+    assert!(!service.stats().workers[1].alive);
+
+    // 句柄绑定的读线程已退出，发送时重新启动
+    let r = wait(|cb| handle.query(Arc::new(vec![create_tabkv(WARE, "pinned_tab", "a", None)]), cb, None));
+    assert_eq!(r.unwrap()[0].value, Some(bin("1")));
+    assert!(service.stats().workers[1].alive);
+}
+
+#[test]
+fn test_checkout_wakes_on_release() {
+    let writer = WriterGuard::checkout(296, 1).unwrap();
+    let waiter = thread::spawn(|| {
+        let start = Instant::now();
+        let guard = WriterGuard::checkout(296, 2);
+        (guard.map(|g| g.txid()), start.elapsed())
+    });
+    thread::sleep(Duration::from_millis(100));
+    drop(writer);
+
+    // 守卫释放后等待的事务立即被唤醒，不等到轮询间隔或超时
+    let (txid, elapsed) = waiter.join().unwrap();
+    assert_eq!(txid, Some(2));
+    assert!(elapsed < Duration::from_secs(1));
+}
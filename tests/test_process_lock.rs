@@ -0,0 +1,49 @@
+extern crate pi_store;
+extern crate tempdir;
+
+use std::fs;
+use std::process;
+
+use tempdir::TempDir;
+
+use pi_store::process_lock::{self, WRITER_LOCK_FILE};
+
+#[test]
+fn test_writer_lock_exclusive() {
+    let dir = TempDir::new("pi_store_lock").unwrap();
+    let lock = process_lock::acquire(dir.path()).unwrap();
+    assert_eq!(process_lock::holder(dir.path()), Some(process::id()));
+
+    // 同一个库只能有一个写者
+    assert!(process_lock::acquire(dir.path()).is_err());
+
+    drop(lock);
+    assert!(!dir.path().join(WRITER_LOCK_FILE).exists());
+    assert!(process_lock::acquire(dir.path()).is_ok());
+}
+
+#[test]
+fn test_writer_lock_held_by_other_process() {
+    let dir = TempDir::new("pi_store_lock").unwrap();
+    // 父进程一定存活
+    let parent = fs::read_to_string("/proc/self/stat")
+        .ok()
+        .and_then(|s| s.split_whitespace().nth(3).and_then(|pid| pid.parse::<u32>().ok()));
+    if let Some(pid) = parent {
+        fs::write(dir.path().join(WRITER_LOCK_FILE), pid.to_string()).unwrap();
+        let e = process_lock::acquire(dir.path()).unwrap_err();
+        assert!(e.contains(&pid.to_string()));
+        assert_eq!(process_lock::holder(dir.path()), Some(pid));
+    }
+}
+
+#[test]
+fn test_stale_writer_lock_replaced() {
+    let dir = TempDir::new("pi_store_lock").unwrap();
+    // 超出pid_max的进程id不可能存活
+    fs::write(dir.path().join(WRITER_LOCK_FILE), "4294967295").unwrap();
+    if !process_lock::is_alive(u32::max_value()) {
+        let _lock = process_lock::acquire(dir.path()).unwrap();
+        assert_eq!(process_lock::holder(dir.path()), Some(process::id()));
+    }
+}
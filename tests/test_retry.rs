@@ -0,0 +1,58 @@
+extern crate lmdb;
+extern crate pi_store;
+extern crate tempdir;
+
+use std::cell::Cell;
+use std::time::Duration;
+
+use lmdb::{Environment, Error};
+use tempdir::TempDir;
+
+use pi_store::pool::{classify, ErrorClass, StoreError};
+use pi_store::retry::{self, RetryPolicy};
+
+// 被信号中断的系统错误码
+const EINTR: i32 = 4;
+
+#[test]
+fn test_retry_transient_errors() {
+    let dir = TempDir::new("pi_store_retry").unwrap();
+    let env = Environment::new().open(dir.path()).unwrap();
+    retry::set_retry_policy(RetryPolicy {
+        max_retries: 3,
+        backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(4),
+    });
+
+    // 临时性错误重试后成功
+    let calls = Cell::new(0);
+    let r = retry::retry(&env, "test", || {
+        calls.set(calls.get() + 1);
+        match calls.get() {
+            1 => Err(Error::ReadersFull),
+            2 => Err(Error::Other(EINTR)),
+            n => Ok(n),
+        }
+    });
+    assert_eq!(r, Ok(3));
+
+    // 重试次数用完后返回最后一次的错误
+    let calls = Cell::new(0);
+    let r: Result<(), Error> = retry::retry(&env, "test", || {
+        calls.set(calls.get() + 1);
+        Err(Error::ReadersFull)
+    });
+    assert_eq!(r, Err(Error::ReadersFull));
+    assert_eq!(calls.get(), 4);
+    assert_eq!(classify(&StoreError::from(Error::ReadersFull).to_string()), ErrorClass::Retryable);
+
+    // 其它错误不重试
+    let calls = Cell::new(0);
+    let r: Result<(), Error> = retry::retry(&env, "test", || {
+        calls.set(calls.get() + 1);
+        Err(Error::Corrupted)
+    });
+    assert_eq!(r, Err(Error::Corrupted));
+    assert_eq!(calls.get(), 1);
+    assert_eq!(classify(&StoreError::from(Error::Corrupted).to_string()), ErrorClass::Permanent);
+}
@@ -0,0 +1,62 @@
+extern crate pi_db;
+extern crate pi_store;
+extern crate tempdir;
+
+extern crate atom;
+extern crate guid;
+
+mod common;
+
+use std::sync::Arc;
+
+use tempdir::TempDir;
+
+use atom::Atom;
+use guid::GuidGen;
+
+use pi_db::db::{Bin, SResult, TabKV, Ware, WareSnapshot};
+
+use pi_store::lmdb_file::{Session, DB};
+use pi_store::read_cache;
+
+use common::{bin, create_tab, create_tabkv, modify, open_ware, wait, wait_db};
+
+const TAB: &str = "session_tab";
+
+// 用单次查询的只读事务读取，启用读缓存时会缓存读到的值
+fn read_once(db: &DB, ware: &str, key: &str) -> Option<Bin> {
+    let txn = db.snapshot().tab_txn(&Atom::from(TAB), &GuidGen::new(1, 1).gen(0), false, Box::new(|_| {})).unwrap().unwrap();
+    let r: SResult<Vec<TabKV>> = wait_db(|cb| txn.query(Arc::new(vec![create_tabkv(ware, TAB, key, None)]), None, false, cb));
+    r.unwrap()[0].value.clone()
+}
+
+#[test]
+fn test_session_reads_own_writes() {
+    let dir = TempDir::new("pi_store_session").unwrap();
+    let (mgr, db, ware) = open_ware(&dir);
+    create_tab(&mgr, &ware, TAB);
+    read_cache::set_read_cache(16);
+
+    modify(&mgr, vec![create_tabkv(&ware, TAB, "k", Some("v1"))]);
+    assert_eq!(read_once(&db, &ware, "k"), Some(bin("v1")));
+
+    // 会话中的查询不使用读缓存，能读到自己未提交的修改
+    let session = Session::begin(&Atom::from(TAB)).unwrap();
+    let r = wait(|cb| {
+        session.modify(Arc::new(vec![create_tabkv(&ware, TAB, "k", Some("v2"))]), cb);
+        Ok(())
+    });
+    assert_eq!(r, Ok(()));
+    let r = wait(|cb| {
+        session.query(Arc::new(vec![create_tabkv(&ware, TAB, "k", None)]), cb);
+        Ok(())
+    });
+    assert_eq!(r.unwrap()[0].value, Some(bin("v2")));
+
+    // 回滚后其它事务仍然读到已提交的值
+    assert_eq!(wait(|cb| {
+        session.rollback(cb);
+        Ok(())
+    }), Ok(()));
+    assert_eq!(read_once(&db, &ware, "k"), Some(bin("v1")));
+}
@@ -0,0 +1,75 @@
+extern crate pi_db;
+extern crate pi_store;
+extern crate tempdir;
+
+extern crate atom;
+extern crate guid;
+extern crate sinfo;
+
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempdir::TempDir;
+
+use atom::Atom;
+
+use pi_store::policy::TablePolicy;
+
+use common::{bin, create_tab, create_tabkv, modify, open_ware, wait_db};
+
+const TAB: &str = "soft_delete_tab";
+
+#[test]
+fn test_soft_delete_iter_count_purge() {
+    let dir = TempDir::new("pi_store_soft_delete").unwrap();
+    let (mgr, db, ware) = open_ware(&dir);
+    create_tab(&mgr, &ware, TAB);
+
+    let mut policy = TablePolicy::default();
+    policy.soft_delete = true;
+    db.set_table_policy(&Atom::from(TAB), policy).unwrap();
+
+    modify(&mgr, (0..3).map(|i| create_tabkv(&ware, TAB, &format!("key{}", i), Some("value"))).collect());
+    // 删除一个存在的键和一个不存在的键，不存在的键不写墓碑
+    modify(&mgr, vec![create_tabkv(&ware, TAB, "key1", None), create_tabkv(&ware, TAB, "missing", None)]);
+
+    // 迭代不返回软删除的键
+    let tr = mgr.transaction(false);
+    let mut it = wait_db(|cb| tr.iter(&Atom::from(ware.as_str()), &Atom::from(TAB), None, true, None, cb)).unwrap();
+    let mut keys = Vec::new();
+    while let Some((k, _)) = wait_db(|cb| it.next(cb)).unwrap() {
+        keys.push(k);
+    }
+    assert_eq!(keys, vec![bin("key0"), bin("key2")]);
+
+    // 表的记录数和导出都不包括墓碑
+    assert_eq!(wait_db(|cb| tr.tab_size(&Atom::from(ware.as_str()), &Atom::from(TAB), cb)), Ok(2));
+    let mut out = Vec::new();
+    assert_eq!(db.export_snapshot(&Atom::from(TAB), &mut out).unwrap(), 2);
+
+    // 只有一个墓碑，清理后不再有墓碑
+    assert_eq!(db.purge_tombstones(&Atom::from(TAB), Duration::from_secs(0), 0).unwrap(), 1);
+    assert_eq!(db.purge_tombstones(&Atom::from(TAB), Duration::from_secs(0), 0).unwrap(), 0);
+    let mut out = Vec::new();
+    assert_eq!(db.export_snapshot(&Atom::from(TAB), &mut out).unwrap(), 2);
+}
+
+#[test]
+fn test_tombstone_value_in_plain_table() {
+    let dir = TempDir::new("pi_store_plain_tomb").unwrap();
+    let (mgr, _db, ware) = open_ware(&dir);
+    let tab = "plain_tomb_tab";
+    create_tab(&mgr, &ware, tab);
+
+    // 未开启软删除的表中，和墓碑格式相同的值也是普通的值
+    let value = &format!("{}{}", "\0pi_store_tomb\0\0", "00000000");
+    assert_eq!(value.len(), 24);
+    modify(&mgr, vec![create_tabkv(&ware, tab, "key", Some(value))]);
+
+    let tr = mgr.transaction(false);
+    let r = wait_db(|cb| tr.query(Arc::new(vec![create_tabkv(&ware, tab, "key", None)]), None, false, cb)).unwrap();
+    assert_eq!(r[0].value, Some(bin(value)));
+    assert_eq!(wait_db(|cb| tr.tab_size(&Atom::from(ware.as_str()), &Atom::from(tab), cb)), Ok(1));
+}
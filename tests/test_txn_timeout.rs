@@ -0,0 +1,55 @@
+extern crate lmdb;
+extern crate pi_db;
+extern crate pi_store;
+extern crate tempdir;
+
+extern crate atom;
+
+mod common;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use tempdir::TempDir;
+
+use atom::Atom;
+
+use pi_store::pool::{is_timed_out, set_txn_idle_timeout, LmdbService, StoreError, WriterGuard};
+
+use common::{bin, create_tabkv, get, open, wait};
+
+const WARE: &str = "timeout_db";
+const ENV_ID: u64 = 289;
+const TAB: &str = "timeout_tab";
+
+#[test]
+fn test_idle_txn_timeout_then_commit_fails() {
+    let dir = TempDir::new("pi_store_timeout").unwrap();
+    let pool = open(&dir, ENV_ID, &[TAB], LmdbService::new(2));
+    set_txn_idle_timeout(200);
+
+    let handle = pool.service_by_env(ENV_ID).unwrap().txn_handle(&Atom::from(TAB), 1, true).unwrap();
+    let guard = WriterGuard::checkout(ENV_ID, 1).unwrap();
+    let r = wait(|cb| handle.prepare(Arc::new(vec![create_tabkv(WARE, TAB, "k1", Some("v1"))]), Arc::new(Vec::new()), cb));
+    assert_eq!(r, Ok(()));
+
+    // 超时后写线程回滚事务并收回使用权，超时标记保留到提交或回滚
+    thread::sleep(Duration::from_millis(600));
+    assert!(is_timed_out(1));
+    assert!(WriterGuard::try_checkout(ENV_ID, 2).map(drop).is_some());
+
+    let r = wait(|cb| handle.commit_prepared(cb));
+    assert_eq!(r, Err(StoreError::TxnTimedOut.to_string()));
+    assert!(!is_timed_out(1));
+    drop(guard);
+    assert_eq!(get(&pool, ENV_ID, TAB, "k1"), None);
+
+    // 其它事务可以继续写入
+    let handle = pool.service_by_env(ENV_ID).unwrap().txn_handle(&Atom::from(TAB), 3, true).unwrap();
+    let _guard = WriterGuard::checkout(ENV_ID, 3).unwrap();
+    let r = wait(|cb| handle.prepare(Arc::new(vec![create_tabkv(WARE, TAB, "k2", Some("v2"))]), Arc::new(Vec::new()), cb));
+    assert_eq!(r, Ok(()));
+    assert_eq!(wait(|cb| handle.commit_prepared(cb)), Ok(()));
+    assert_eq!(get(&pool, ENV_ID, TAB, "k2"), Some(bin("v2")));
+}